@@ -11,6 +11,10 @@ pub struct ActionAgentsConfig {
   pub infolog: Option<InfoLogActionAgent>,
   #[cfg(feature = "webpush")]
   pub webpush: Option<super::webpush::WebPushActionAgentConfig>,
+  #[cfg(feature = "telegram")]
+  pub telegram: Option<super::telegram::TelegramActionAgentConfig>,
+  #[cfg(feature = "discord")]
+  pub discord: Option<super::discord::DiscordActionAgentConfig>,
 }
 
 impl ActionAgentsConfig {
@@ -23,6 +27,14 @@ impl ActionAgentsConfig {
     if let Some(agent) = self.webpush {
       actions.insert("webpush", Arc::new(agent.collect()?));
     }
+    #[cfg(feature = "telegram")]
+    if let Some(agent) = self.telegram {
+      actions.insert("telegram", Arc::new(agent.collect()?));
+    }
+    #[cfg(feature = "discord")]
+    if let Some(agent) = self.discord {
+      actions.insert("discord", Arc::new(agent.collect()?));
+    }
     if actions.is_empty() {
       log::warn!("at least one agent agent should be specified");
     }
@@ -0,0 +1,1069 @@
+#[cfg(feature = "renderer")]
+use std::path::Path;
+use std::{str::FromStr, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use r2d2_sqlite::rusqlite::{Connection, OptionalExtension};
+use rand::{distributions::Alphanumeric, Rng};
+#[cfg(feature = "renderer")]
+use reqwest::multipart::{Form, Part};
+use reqwest::{header::AUTHORIZATION, Client, RequestBuilder, Url};
+use rust_i18n::t;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+#[cfg(feature = "renderer")]
+use crate::renderer::RenderOptions;
+use crate::{
+  database::{
+    action::{CreateAction, DeleteAction},
+    dispatch, Database, DatabaseConnection, Language, TimeZone, Transaction,
+  },
+  splatnet::{Message, PvpMode, PvpSpiderItem},
+  Error, Result,
+};
+
+#[cfg(feature = "postgres")]
+use crate::database::postgres::PgConnection;
+
+#[cfg(feature = "renderer")]
+use super::format::render_card;
+use super::{
+  format::{body, headline, stage_name, truncate},
+  without_url, ActionAgent, ActionContext,
+};
+
+const REQUEST_TIMEOUT_SECS: u64 = 30;
+
+// how long a link code may be sent to the bot
+const LINK_EXPIRE_MINS: i64 = 10;
+const LINK_CODE_LEN: usize = 16;
+
+// direct messages of pending links are looked through for the code this often,
+// as far back as this many messages
+const LINK_POLL_SECS: u64 = 10;
+const LINK_SCAN_LIMIT: u32 = 10;
+
+// in characters, as limited by the api
+const MAX_TITLE_LEN: usize = 256;
+const MAX_DESCRIPTION_LEN: usize = 4096;
+const MAX_FIELD_LEN: usize = 1024;
+
+// json error codes of targets that are gone
+const UNKNOWN_CHANNEL: i64 = 10003;
+const UNKNOWN_USER: i64 = 10013;
+const UNKNOWN_WEBHOOK: i64 = 10015;
+const CANNOT_MESSAGE_USER: i64 = 50007;
+const INVALID_WEBHOOK_TOKEN: i64 = 50027;
+
+#[derive(Serialize, Deserialize)]
+pub struct DiscordActionAgentConfig {
+  // for direct messages, channel webhooks work without a bot
+  pub bot_token: Option<String>,
+  // the api server, e.g. a local mock
+  #[serde(default = "default_api_url")]
+  pub api_url: String,
+}
+
+fn default_api_url() -> String {
+  "https://discord.com/api/v10".into()
+}
+
+impl DiscordActionAgentConfig {
+  pub fn collect(self) -> Result<DiscordActionAgent> {
+    let client = Client::builder()
+      .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+      .build()?;
+    Ok(DiscordActionAgent {
+      bot_token: self.bot_token,
+      api_url: self.api_url.trim_end_matches('/').into(),
+      client,
+    })
+  }
+}
+
+#[derive(Serialize)]
+pub struct DiscordExtInfo {
+  // `dm` or `webhook`
+  pub kind: String,
+  pub user_id: Option<String>,
+  pub webhook_id: Option<String>,
+}
+
+// a code to prove the direct messages are of the user, by sending it to the bot
+// from there
+#[derive(Serialize)]
+pub struct DiscordLink {
+  pub code: String,
+  pub expire_time: i64,
+}
+
+pub struct DiscordActionAgent {
+  bot_token: Option<String>,
+  api_url: String,
+  client: Client,
+}
+
+impl std::fmt::Debug for DiscordActionAgent {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_tuple("DiscordActionAgent").finish()
+  }
+}
+
+#[async_trait]
+impl ActionAgent for DiscordActionAgent {
+  fn get_ext_info(
+    &self,
+    conn: &DatabaseConnection,
+    id: i64,
+  ) -> Result<Option<Box<dyn erased_serde::Serialize>>> {
+    let info = conn.lookup_discord_ext_info(id)?;
+    Ok(Some(Box::new(info)))
+  }
+
+  async fn emit(
+    self: Arc<Self>,
+    ctx: Arc<ActionContext>,
+    uid: i64,
+    id: i64,
+    msg: Arc<Message>,
+  ) -> Result<()> {
+    let (channel, language, time_zone) = lookup_target(&ctx.database, uid, id)?;
+    let embed = embed(msg.as_ref(), language.locale(), time_zone);
+    #[cfg(feature = "renderer")]
+    {
+      let img_opts = RenderOptions {
+        platform: "pc",
+        language,
+        time_zone,
+      };
+      if let Some(img_path) = render_card(&ctx, msg.as_ref(), &img_opts)? {
+        let mut embed = embed;
+        embed["image"] = json!({ "url": "attachment://card.jpg" });
+        let payload = json!({ "embeds": [embed] });
+        let img_path = Path::new(&ctx.renderer.out_dir()).join(img_path);
+        return self.post_with_card(&channel, payload, &img_path).await;
+      }
+    }
+    let payload = json!({ "embeds": [embed] });
+    self.post(&channel, payload).await
+  }
+
+  async fn test(self: Arc<Self>, db: Database, uid: i64, id: i64) -> Result<()> {
+    let (channel, language, _) = lookup_target(&db, uid, id)?;
+    let payload = json!({ "content": t!("test_notification", locale = language.locale()) });
+    self.post(&channel, payload).await
+  }
+
+  // binds the direct messages the pending link codes are sent in
+  async fn watch(self: Arc<Self>, ctx: Arc<ActionContext>) -> Result<()> {
+    let Some(token) = self.bot_token.as_deref() else {
+      return Ok(());
+    };
+    loop {
+      tokio::time::sleep(Duration::from_secs(LINK_POLL_SECS)).await;
+      let links = match ctx
+        .database
+        .get()
+        .and_then(|conn| conn.list_discord_links(Utc::now()))
+      {
+        Ok(links) => links,
+        Err(err) => {
+          log::warn!("list discord links failed: [{:?}]", err);
+          continue;
+        }
+      };
+      for link in links.into_iter() {
+        match self.check_link(&ctx.database, token, &link).await {
+          Ok(()) => {}
+          // no direct messages with the user, so the code is never to be seen
+          Err(Error::ActionExpired(reason)) => {
+            log::info!("discord link of [{}] dropped: [{}]", link.user_id, reason);
+            let ret = ctx
+              .database
+              .get()
+              .and_then(|conn| conn.take_discord_link(&link.code, Utc::now()));
+            if let Err(err) = ret {
+              log::warn!("drop discord link failed: [{:?}]", err);
+            }
+          }
+          Err(err) => log::warn!("check discord link failed: [{:?}]", err),
+        }
+      }
+    }
+  }
+}
+
+// pvp rotations get a field per detail, the rest the text of other agents
+fn embed(msg: &Message, locale: &str, time_zone: TimeZone) -> Value {
+  let mut embed = json!({
+    "title": truncate(&headline(msg, locale), MAX_TITLE_LEN),
+    "timestamp": msg.timestamp().to_rfc3339(),
+  });
+  match msg {
+    Message::Pvp(item) => {
+      embed["fields"] = pvp_fields(item, locale);
+      if let Some(color) = mode_color(item.mode) {
+        embed["color"] = json!(color);
+      }
+    }
+    _ => embed["description"] = json!(truncate(&body(msg, locale, time_zone), MAX_DESCRIPTION_LEN)),
+  }
+  embed
+}
+
+fn pvp_fields(item: &PvpSpiderItem, locale: &str) -> Value {
+  let stages: Vec<_> = item
+    .stages
+    .iter()
+    .map(|id| stage_name(*id, locale))
+    .collect();
+  // shown in the time zone of whoever reads it
+  let time = format!(
+    "<t:{}:f> - <t:{}:t>",
+    item.start_time.timestamp(),
+    item.end_time.timestamp()
+  );
+  json!([
+    {
+      "name": t!("discord.rule", locale = locale),
+      "value": item.rule.name(locale),
+      "inline": true,
+    },
+    {
+      "name": t!("discord.mode", locale = locale),
+      "value": item.mode.name(locale),
+      "inline": true,
+    },
+    {
+      "name": t!("discord.stages", locale = locale),
+      "value": truncate(&stages.join("\n"), MAX_FIELD_LEN),
+    },
+    {
+      "name": t!("discord.time", locale = locale),
+      "value": time,
+    },
+  ])
+}
+
+// the theme colors of the modes
+fn mode_color(mode: PvpMode) -> Option<u32> {
+  match mode {
+    PvpMode::Regular => Some(0x19d719),
+    PvpMode::Challenge | PvpMode::Open => Some(0xf54910),
+    PvpMode::X => Some(0x0fdb9b),
+    PvpMode::Fest => Some(0xeaff3d),
+    PvpMode::Event => Some(0xf02d7d),
+    PvpMode::Unknown => None,
+  }
+}
+
+// where notifications of an action are posted
+enum DiscordChannel {
+  // the direct message channel of the bot with the user
+  Dm { user_id: String },
+  Webhook { id: String, token: String },
+}
+
+// the channel and user settings a notification is sent with
+fn lookup_target(db: &Database, uid: i64, id: i64) -> Result<(DiscordChannel, Language, TimeZone)> {
+  let DiscordTarget {
+    kind,
+    user_id,
+    webhook_id,
+    webhook_token,
+    language,
+    time_zone,
+  } = db
+    .get()?
+    .lookup_discord_target(uid, id)?
+    .ok_or_else(|| Error::ActionExpired("channel not found".to_string()))?;
+  let channel = match (kind.as_str(), user_id, webhook_id, webhook_token) {
+    ("dm", Some(user_id), _, _) => DiscordChannel::Dm { user_id },
+    ("webhook", _, Some(id), Some(token)) => DiscordChannel::Webhook { id, token },
+    _ => {
+      return Err(Error::InternalServerError(
+        format!("malformed discord action #{}", id).into(),
+      ))
+    }
+  };
+  let language =
+    Language::from_str(&language).map_err(|err| Error::InternalServerError(Box::new(err)))?;
+  let time_zone =
+    TimeZone::from_str(&time_zone).map_err(|err| Error::InternalServerError(Box::new(err)))?;
+  Ok((channel, language, time_zone))
+}
+
+impl DiscordActionAgent {
+  // the recent messages the user sent the bot, which bind the direct messages
+  // if the code is among them
+  async fn check_link(&self, db: &Database, token: &str, link: &DiscordPendingLink) -> Result<()> {
+    let channel_id = self.open_dm(token, &link.user_id).await?;
+    let request = self
+      .client
+      .get(format!("{}/channels/{}/messages", self.api_url, channel_id))
+      .query(&[("limit", LINK_SCAN_LIMIT)])
+      .header(AUTHORIZATION, format!("Bot {}", token));
+    let messages: Vec<DmMessage> = self
+      .send(request)
+      .await?
+      .json()
+      .await
+      .map_err(without_url)?;
+    let sent = messages
+      .iter()
+      .any(|e| e.author.id == link.user_id && e.content.trim() == link.code);
+    if !sent {
+      return Ok(());
+    }
+    let linked = {
+      let mut conn = db.get()?;
+      let tx = conn.transaction()?;
+      let linked = tx.discord_link(&link.code, Utc::now())?;
+      tx.commit()?;
+      linked
+    };
+    let Some((uid, id)) = linked else {
+      return Ok(());
+    };
+    log::info!("discord user [{}] linked to uid[{}]", link.user_id, uid);
+    let (channel, language, _) = lookup_target(db, uid, id)?;
+    let payload = json!({ "content": t!("discord.linked", locale = language.locale()) });
+    self.post(&channel, payload).await
+  }
+
+  async fn post(&self, channel: &DiscordChannel, mut payload: Value) -> Result<()> {
+    payload["allowed_mentions"] = json!({ "parse": [] });
+    let request = self.request(channel).await?;
+    self.send(request.json(&payload)).await?;
+    Ok(())
+  }
+
+  // along with the image the embed refers to as `attachment://card.jpg`
+  #[cfg(feature = "renderer")]
+  async fn post_with_card(
+    &self,
+    channel: &DiscordChannel,
+    mut payload: Value,
+    img_path: &Path,
+  ) -> Result<()> {
+    payload["allowed_mentions"] = json!({ "parse": [] });
+    payload["attachments"] = json!([{ "id": 0, "filename": "card.jpg" }]);
+    let img = tokio::fs::read(img_path)
+      .await
+      .map_err(|err| Error::InternalServerError(Box::new(err)))?;
+    let form = Form::new().text("payload_json", payload.to_string()).part(
+      "files[0]",
+      Part::bytes(img)
+        .file_name("card.jpg")
+        .mime_str("image/jpeg")?,
+    );
+    let request = self.request(channel).await?;
+    self.send(request.multipart(form)).await?;
+    Ok(())
+  }
+
+  async fn request(&self, channel: &DiscordChannel) -> Result<RequestBuilder> {
+    let request = match channel {
+      DiscordChannel::Dm { user_id } => {
+        let token = self.bot_token()?;
+        let channel_id = self.open_dm(token, user_id).await?;
+        self
+          .client
+          .post(format!("{}/channels/{}/messages", self.api_url, channel_id))
+          .header(AUTHORIZATION, format!("Bot {}", token))
+      }
+      DiscordChannel::Webhook { id, token } => self
+        .client
+        .post(format!("{}/webhooks/{}/{}", self.api_url, id, token)),
+    };
+    Ok(request)
+  }
+
+  // the direct message channel with the user, which is the same one each time
+  async fn open_dm(&self, token: &str, user_id: &str) -> Result<String> {
+    let request = self
+      .client
+      .post(format!("{}/users/@me/channels", self.api_url))
+      .header(AUTHORIZATION, format!("Bot {}", token))
+      .json(&json!({ "recipient_id": user_id }));
+    let channel: DmChannel = self
+      .send(request)
+      .await?
+      .json()
+      .await
+      .map_err(without_url)?;
+    Ok(channel.id)
+  }
+
+  // the url of webhooks carries their token
+  async fn send(&self, request: RequestBuilder) -> Result<reqwest::Response> {
+    let resp = request.send().await.map_err(without_url)?;
+    let status = resp.status();
+    if status.is_success() {
+      return Ok(resp);
+    }
+    let err: ApiError = resp.json().await.unwrap_or_default();
+    Err(classify(status.as_u16(), err))
+  }
+
+  fn bot_token(&self) -> Result<&str> {
+    self
+      .bot_token
+      .as_deref()
+      .ok_or_else(|| Error::DeliveryRejected("no bot for direct messages".into()))
+  }
+}
+
+// channels the message can no longer be posted to expire the action, messages
+// refused for good are dropped, and the rest, e.g. hitting rate limits, are
+// retried
+fn classify(status: u16, err: ApiError) -> Error {
+  let description = format!(
+    "[{}] {}",
+    err.code.unwrap_or_default(),
+    err.message.unwrap_or_default()
+  );
+  match (status, err.code) {
+    // the user left the servers shared with the bot or closed direct
+    // messages, or the webhook was deleted
+    (
+      _,
+      Some(
+        UNKNOWN_CHANNEL
+        | UNKNOWN_USER
+        | UNKNOWN_WEBHOOK
+        | CANNOT_MESSAGE_USER
+        | INVALID_WEBHOOK_TOKEN,
+      ),
+    ) => Error::ActionExpired(description),
+    // the channel is out of reach of the bot or webhook for good
+    (403, _) => Error::ActionExpired(description),
+    // a revoked bot token refuses the messages of every action, which are
+    // dropped rather than expiring them all
+    (400 | 401 | 413, _) => Error::DeliveryRejected(description),
+    _ => Error::InternalServerError(format!("discord error [{}]: {}", status, description).into()),
+  }
+}
+
+#[derive(Deserialize, Default)]
+struct ApiError {
+  code: Option<i64>,
+  message: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DmChannel {
+  id: String,
+}
+
+#[derive(Deserialize)]
+struct DmMessage {
+  author: DmAuthor,
+  content: String,
+}
+
+#[derive(Deserialize)]
+struct DmAuthor {
+  id: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum DiscordSubscribeRequest {
+  // direct messages from the bot, who shares a server with the user, once the
+  // user sends the link code to the bot
+  Dm { user_id: String },
+  // posts to a channel through the url of its webhook
+  Webhook { url: String },
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum DiscordSubscribeResponse {
+  // the action created for the webhook
+  Action(i64),
+  // for direct messages, which are bound by the bot later on
+  Link(DiscordLink),
+}
+
+pub trait DiscordSubscribe {
+  fn discord_subscribe(
+    &self,
+    uid: i64,
+    request: DiscordSubscribeRequest,
+  ) -> Result<DiscordSubscribeResponse>;
+}
+
+impl DiscordSubscribe for Transaction<'_> {
+  fn discord_subscribe(
+    &self,
+    uid: i64,
+    request: DiscordSubscribeRequest,
+  ) -> Result<DiscordSubscribeResponse> {
+    let channel = match request {
+      // anyone could name the user, so nothing is sent until the user proves
+      // to own the account
+      DiscordSubscribeRequest::Dm { user_id } if is_snowflake(&user_id) => {
+        let code: String = rand::thread_rng()
+          .sample_iter(&Alphanumeric)
+          .take(LINK_CODE_LEN)
+          .map(char::from)
+          .collect();
+        let expire_time = Utc::now() + chrono::Duration::minutes(LINK_EXPIRE_MINS);
+        self.create_discord_link(&code, uid, &user_id, expire_time)?;
+        return Ok(DiscordSubscribeResponse::Link(DiscordLink {
+          code,
+          expire_time: expire_time.timestamp(),
+        }));
+      }
+      DiscordSubscribeRequest::Dm { user_id } => {
+        return Err(Error::InvalidParameter("discord::user_id", user_id))
+      }
+      DiscordSubscribeRequest::Webhook { url } => {
+        parse_webhook_url(&url).ok_or(Error::InvalidParameter("discord::url", url))?
+      }
+    };
+    let id = self.create_action(uid, "discord")?;
+    self.create_discord_ext_info(id, uid, &channel)?;
+    Ok(DiscordSubscribeResponse::Action(id))
+  }
+}
+
+trait DiscordLinkDm {
+  // binds the direct messages of the user the code is for, returns the user
+  // and the action created if the code is valid
+  fn discord_link(&self, code: &str, now: DateTime<Utc>) -> Result<Option<(i64, i64)>>;
+}
+
+impl DiscordLinkDm for Transaction<'_> {
+  fn discord_link(&self, code: &str, now: DateTime<Utc>) -> Result<Option<(i64, i64)>> {
+    let Some((uid, user_id)) = self.take_discord_link(code, now)? else {
+      return Ok(None);
+    };
+    // direct messages linked again start over
+    if let Some(id) = self.lookup_discord_dm_action(uid, &user_id)? {
+      self.delete_action(uid, id)?;
+    }
+    let id = self.create_action(uid, "discord")?;
+    self.create_discord_ext_info(id, uid, &DiscordChannel::Dm { user_id })?;
+    Ok(Some((uid, id)))
+  }
+}
+
+fn is_snowflake(id: &str) -> bool {
+  !id.is_empty() && id.len() <= 20 && id.chars().all(|c| c.is_ascii_digit())
+}
+
+// `https://discord.com/api/webhooks/<id>/<token>`, which is posted to at the
+// configured api server rather than where the url points
+fn parse_webhook_url(url: &str) -> Option<DiscordChannel> {
+  let url = Url::parse(url).ok()?;
+  let mut segments = url.path_segments()?;
+  segments.find(|e| *e == "webhooks")?;
+  let id = segments.next()?;
+  let token = segments.next()?;
+  let valid_token = !token.is_empty()
+    && token
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+  if !is_snowflake(id) || !valid_token {
+    return None;
+  }
+  Some(DiscordChannel::Webhook {
+    id: id.into(),
+    token: token.into(),
+  })
+}
+
+// a link code yet to be sent to the bot by the user
+struct DiscordPendingLink {
+  code: String,
+  user_id: String,
+}
+
+// the stored channel and user settings a notification is sent with
+struct DiscordTarget {
+  kind: String,
+  user_id: Option<String>,
+  webhook_id: Option<String>,
+  webhook_token: Option<String>,
+  language: String,
+  time_zone: String,
+}
+
+// columns of `discord_ext_info` for a channel
+fn channel_columns(channel: &DiscordChannel) -> (&str, Option<&str>, Option<&str>, Option<&str>) {
+  match channel {
+    DiscordChannel::Dm { user_id } => ("dm", Some(user_id), None, None),
+    DiscordChannel::Webhook { id, token } => ("webhook", None, Some(id), Some(token)),
+  }
+}
+
+// storage of `discord_links` and `discord_ext_info`, for each database backend
+trait DiscordStore {
+  // replaces the pending link of the user, if any
+  fn create_discord_link(
+    &self,
+    code: &str,
+    uid: i64,
+    user_id: &str,
+    expire_time: DateTime<Utc>,
+  ) -> Result<()>;
+  fn list_discord_links(&self, now: DateTime<Utc>) -> Result<Vec<DiscordPendingLink>>;
+  // removes the code along with the expired ones, returns the user of the code
+  // and the account it is for if not expired
+  fn take_discord_link(&self, code: &str, now: DateTime<Utc>) -> Result<Option<(i64, String)>>;
+  fn lookup_discord_dm_action(&self, uid: i64, user_id: &str) -> Result<Option<i64>>;
+  fn lookup_discord_ext_info(&self, id: i64) -> Result<DiscordExtInfo>;
+  fn lookup_discord_target(&self, uid: i64, id: i64) -> Result<Option<DiscordTarget>>;
+  fn create_discord_ext_info(&self, id: i64, uid: i64, channel: &DiscordChannel) -> Result<()>;
+}
+
+impl DiscordStore for DatabaseConnection {
+  fn create_discord_link(
+    &self,
+    code: &str,
+    uid: i64,
+    user_id: &str,
+    expire_time: DateTime<Utc>,
+  ) -> Result<()> {
+    dispatch!(self, conn => conn.create_discord_link(code, uid, user_id, expire_time))
+  }
+
+  fn list_discord_links(&self, now: DateTime<Utc>) -> Result<Vec<DiscordPendingLink>> {
+    dispatch!(self, conn => conn.list_discord_links(now))
+  }
+
+  fn take_discord_link(&self, code: &str, now: DateTime<Utc>) -> Result<Option<(i64, String)>> {
+    dispatch!(self, conn => conn.take_discord_link(code, now))
+  }
+
+  fn lookup_discord_dm_action(&self, uid: i64, user_id: &str) -> Result<Option<i64>> {
+    dispatch!(self, conn => conn.lookup_discord_dm_action(uid, user_id))
+  }
+
+  fn lookup_discord_ext_info(&self, id: i64) -> Result<DiscordExtInfo> {
+    dispatch!(self, conn => conn.lookup_discord_ext_info(id))
+  }
+
+  fn lookup_discord_target(&self, uid: i64, id: i64) -> Result<Option<DiscordTarget>> {
+    dispatch!(self, conn => conn.lookup_discord_target(uid, id))
+  }
+
+  fn create_discord_ext_info(&self, id: i64, uid: i64, channel: &DiscordChannel) -> Result<()> {
+    dispatch!(self, conn => conn.create_discord_ext_info(id, uid, channel))
+  }
+}
+
+impl DiscordStore for Connection {
+  fn create_discord_link(
+    &self,
+    code: &str,
+    uid: i64,
+    user_id: &str,
+    expire_time: DateTime<Utc>,
+  ) -> Result<()> {
+    let mut stmt = self.prepare_cached(
+      "
+      DELETE FROM discord_links
+      WHERE uid = ?1
+      ",
+    )?;
+    stmt.execute((&uid,))?;
+    let mut stmt = self.prepare_cached(
+      "
+      INSERT INTO discord_links ( code, uid, user_id, expire_time )
+      VALUES ( ?1, ?2, ?3, ?4 )
+      ",
+    )?;
+    stmt.execute((&code, &uid, &user_id, &expire_time.timestamp()))?;
+    Ok(())
+  }
+
+  fn list_discord_links(&self, now: DateTime<Utc>) -> Result<Vec<DiscordPendingLink>> {
+    let mut stmt = self.prepare_cached(
+      "
+      SELECT code, user_id
+      FROM discord_links
+      WHERE expire_time > ?1
+      ",
+    )?;
+    let iter = stmt.query_map((&now.timestamp(),), |row| {
+      Ok(DiscordPendingLink {
+        code: row.get(0)?,
+        user_id: row.get(1)?,
+      })
+    })?;
+    let li = itertools::process_results(iter, |iter| iter.collect())?;
+    Ok(li)
+  }
+
+  fn take_discord_link(&self, code: &str, now: DateTime<Utc>) -> Result<Option<(i64, String)>> {
+    let now = now.timestamp();
+    let mut stmt = self.prepare_cached(
+      "
+      SELECT uid, user_id
+      FROM discord_links
+      WHERE code = ?1 AND expire_time > ?2
+      ",
+    )?;
+    let link = stmt
+      .query_row((&code, &now), |row| Ok((row.get(0)?, row.get(1)?)))
+      .optional()?;
+    let mut stmt = self.prepare_cached(
+      "
+      DELETE FROM discord_links
+      WHERE code = ?1 OR expire_time <= ?2
+      ",
+    )?;
+    stmt.execute((&code, &now))?;
+    Ok(link)
+  }
+
+  fn lookup_discord_dm_action(&self, uid: i64, user_id: &str) -> Result<Option<i64>> {
+    let mut stmt = self.prepare_cached(
+      "
+      SELECT id
+      FROM discord_ext_info
+      WHERE uid = ?1 AND kind = 'dm' AND user_id = ?2
+      ",
+    )?;
+    let id = stmt
+      .query_row((&uid, &user_id), |row| row.get(0))
+      .optional()?;
+    Ok(id)
+  }
+
+  fn lookup_discord_ext_info(&self, id: i64) -> Result<DiscordExtInfo> {
+    let mut stmt = self.prepare_cached(
+      "
+      SELECT kind, user_id, webhook_id
+      FROM discord_ext_info
+      WHERE id = ?1
+      ",
+    )?;
+    let info = stmt.query_row((&id,), |row| {
+      Ok(DiscordExtInfo {
+        kind: row.get(0)?,
+        user_id: row.get(1)?,
+        webhook_id: row.get(2)?,
+      })
+    })?;
+    Ok(info)
+  }
+
+  fn lookup_discord_target(&self, uid: i64, id: i64) -> Result<Option<DiscordTarget>> {
+    let mut stmt = self.prepare_cached(
+      "
+      SELECT kind, user_id, webhook_id, webhook_token, language, time_zone
+      FROM discord_ext_info
+        INNER JOIN users ON users.id = uid
+      WHERE uid = ?1 AND discord_ext_info.id = ?2
+      ",
+    )?;
+    let target = stmt
+      .query_row((&uid, &id), |row| {
+        Ok(DiscordTarget {
+          kind: row.get(0)?,
+          user_id: row.get(1)?,
+          webhook_id: row.get(2)?,
+          webhook_token: row.get(3)?,
+          language: row.get(4)?,
+          time_zone: row.get(5)?,
+        })
+      })
+      .optional()?;
+    Ok(target)
+  }
+
+  fn create_discord_ext_info(&self, id: i64, uid: i64, channel: &DiscordChannel) -> Result<()> {
+    let (kind, user_id, webhook_id, webhook_token) = channel_columns(channel);
+    let mut stmt = self.prepare_cached(
+      "
+      INSERT INTO discord_ext_info ( id, uid, kind, user_id, webhook_id, webhook_token )
+      VALUES ( ?1, ?2, ?3, ?4, ?5, ?6 )
+      ",
+    )?;
+    stmt.execute((&id, &uid, &kind, &user_id, &webhook_id, &webhook_token))?;
+    Ok(())
+  }
+}
+
+#[cfg(feature = "postgres")]
+impl DiscordStore for PgConnection {
+  fn create_discord_link(
+    &self,
+    code: &str,
+    uid: i64,
+    user_id: &str,
+    expire_time: DateTime<Utc>,
+  ) -> Result<()> {
+    self.execute(
+      "
+      DELETE FROM discord_links
+      WHERE uid = $1
+      ",
+      &[&uid],
+    )?;
+    self.execute(
+      "
+      INSERT INTO discord_links ( code, uid, user_id, expire_time )
+      VALUES ( $1, $2, $3, $4 )
+      ",
+      &[&code, &uid, &user_id, &expire_time.timestamp()],
+    )?;
+    Ok(())
+  }
+
+  fn list_discord_links(&self, now: DateTime<Utc>) -> Result<Vec<DiscordPendingLink>> {
+    let rows = self.query(
+      "
+      SELECT code, user_id
+      FROM discord_links
+      WHERE expire_time > $1
+      ",
+      &[&now.timestamp()],
+    )?;
+    let mut li = vec![];
+    for row in rows.iter() {
+      li.push(DiscordPendingLink {
+        code: row.try_get(0)?,
+        user_id: row.try_get(1)?,
+      });
+    }
+    Ok(li)
+  }
+
+  fn take_discord_link(&self, code: &str, now: DateTime<Utc>) -> Result<Option<(i64, String)>> {
+    let now = now.timestamp();
+    let row = self.query_opt(
+      "
+      DELETE FROM discord_links
+      WHERE code = $1 AND expire_time > $2
+      RETURNING uid, user_id
+      ",
+      &[&code, &now],
+    )?;
+    self.execute(
+      "
+      DELETE FROM discord_links
+      WHERE expire_time <= $1
+      ",
+      &[&now],
+    )?;
+    match row {
+      Some(row) => Ok(Some((row.try_get(0)?, row.try_get(1)?))),
+      None => Ok(None),
+    }
+  }
+
+  fn lookup_discord_dm_action(&self, uid: i64, user_id: &str) -> Result<Option<i64>> {
+    let row = self.query_opt(
+      "
+      SELECT id
+      FROM discord_ext_info
+      WHERE uid = $1 AND kind = 'dm' AND user_id = $2
+      ",
+      &[&uid, &user_id],
+    )?;
+    match row {
+      Some(row) => Ok(Some(row.try_get(0)?)),
+      None => Ok(None),
+    }
+  }
+
+  fn lookup_discord_ext_info(&self, id: i64) -> Result<DiscordExtInfo> {
+    let row = self.query_row(
+      "
+      SELECT kind, user_id, webhook_id
+      FROM discord_ext_info
+      WHERE id = $1
+      ",
+      &[&id],
+    )?;
+    Ok(DiscordExtInfo {
+      kind: row.try_get(0)?,
+      user_id: row.try_get(1)?,
+      webhook_id: row.try_get(2)?,
+    })
+  }
+
+  fn lookup_discord_target(&self, uid: i64, id: i64) -> Result<Option<DiscordTarget>> {
+    let row = self.query_opt(
+      "
+      SELECT kind, user_id, webhook_id, webhook_token, language, time_zone
+      FROM discord_ext_info
+        INNER JOIN users ON users.id = uid
+      WHERE uid = $1 AND discord_ext_info.id = $2
+      ",
+      &[&uid, &id],
+    )?;
+    let Some(row) = row else {
+      return Ok(None);
+    };
+    Ok(Some(DiscordTarget {
+      kind: row.try_get(0)?,
+      user_id: row.try_get(1)?,
+      webhook_id: row.try_get(2)?,
+      webhook_token: row.try_get(3)?,
+      language: row.try_get(4)?,
+      time_zone: row.try_get(5)?,
+    }))
+  }
+
+  fn create_discord_ext_info(&self, id: i64, uid: i64, channel: &DiscordChannel) -> Result<()> {
+    let (kind, user_id, webhook_id, webhook_token) = channel_columns(channel);
+    self.execute(
+      "
+      INSERT INTO discord_ext_info ( id, uid, kind, user_id, webhook_id, webhook_token )
+      VALUES ( $1, $2, $3, $4, $5, $6 )
+      ",
+      &[&id, &uid, &kind, &user_id, &webhook_id, &webhook_token],
+    )?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::database::user::{CreateUser, CreateUserRequest, LookupUserId, LookupUserIdRequest};
+
+  use super::*;
+
+  #[test]
+  fn test_subscribe() {
+    let db = Database::new_for_test().unwrap();
+    let mut conn = db.get().unwrap();
+
+    let auth_agent = "mock_auth_agent";
+    let auth_uid = "mock_auth_uid";
+    let ok = conn
+      .create_user(CreateUserRequest {
+        auth_agent,
+        auth_uid,
+        email: None,
+        name: None,
+        picture: None,
+        language: None,
+        time_zone: None,
+        availability: None,
+      })
+      .unwrap();
+    assert!(ok);
+    let uid = conn
+      .lookup_user_id(LookupUserIdRequest {
+        auth_agent,
+        auth_uid,
+      })
+      .unwrap();
+
+    let tx = conn.transaction().unwrap();
+    let dm_link = |tx: &Transaction| {
+      let resp = tx
+        .discord_subscribe(
+          uid,
+          DiscordSubscribeRequest::Dm {
+            user_id: "80351110224678912".into(),
+          },
+        )
+        .unwrap();
+      let DiscordSubscribeResponse::Link(link) = resp else {
+        panic!("direct messages bound without a link");
+      };
+      link
+    };
+    let stale = dm_link(&tx);
+    let link = dm_link(&tx);
+    // nothing bound until the code is sent to the bot
+    assert!(tx
+      .lookup_discord_dm_action(uid, "80351110224678912")
+      .unwrap()
+      .is_none());
+    let pending = tx.list_discord_links(Utc::now()).unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].code, link.code);
+    assert!(tx.discord_link(&stale.code, Utc::now()).unwrap().is_none());
+    let (_, dm) = tx.discord_link(&link.code, Utc::now()).unwrap().unwrap();
+    assert!(tx.discord_link(&link.code, Utc::now()).unwrap().is_none());
+    let DiscordSubscribeResponse::Action(webhook) = tx
+      .discord_subscribe(
+        uid,
+        DiscordSubscribeRequest::Webhook {
+          url: "https://discord.com/api/webhooks/1234567890/mock-Token_0".into(),
+        },
+      )
+      .unwrap()
+    else {
+      panic!("webhook bound with a link");
+    };
+    for request in [
+      DiscordSubscribeRequest::Dm {
+        user_id: "@someone".into(),
+      },
+      DiscordSubscribeRequest::Webhook {
+        url: "https://discord.com/api/webhooks/1234567890".into(),
+      },
+      DiscordSubscribeRequest::Webhook {
+        url: "https://discord.com/api/webhooks/mock/token".into(),
+      },
+    ] {
+      assert!(tx.discord_subscribe(uid, request).is_err());
+    }
+    tx.commit().unwrap();
+
+    let info = conn.lookup_discord_ext_info(dm).unwrap();
+    assert_eq!(info.kind, "dm");
+    assert_eq!(info.user_id.as_deref(), Some("80351110224678912"));
+    let target = conn.lookup_discord_target(uid, webhook).unwrap().unwrap();
+    assert_eq!(target.kind, "webhook");
+    assert_eq!(target.webhook_id.as_deref(), Some("1234567890"));
+    assert_eq!(target.webhook_token.as_deref(), Some("mock-Token_0"));
+    assert!(conn
+      .lookup_discord_target(uid + 1, webhook)
+      .unwrap()
+      .is_none());
+  }
+
+  #[tokio::test]
+  async fn test_error_without_token() {
+    // a port nothing listens on
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+      .unwrap()
+      .local_addr()
+      .unwrap();
+    let agent = DiscordActionAgentConfig {
+      bot_token: None,
+      api_url: format!("http://{}", addr),
+    }
+    .collect()
+    .unwrap();
+    let token = "mock-secret-token";
+    let channel = DiscordChannel::Webhook {
+      id: "1234567890".into(),
+      token: token.into(),
+    };
+    let err = agent.post(&channel, json!({})).await.unwrap_err();
+    assert!(matches!(err, Error::NetworkError(_)));
+    assert!(!format!("{:?}", err).contains(token));
+  }
+
+  #[test]
+  fn test_classify() {
+    let err = |code: i64| ApiError {
+      code: Some(code),
+      message: None,
+    };
+    assert!(matches!(
+      classify(403, err(CANNOT_MESSAGE_USER)),
+      Error::ActionExpired(_)
+    ));
+    assert!(matches!(classify(403, err(50013)), Error::ActionExpired(_)));
+    assert!(matches!(classify(401, err(0)), Error::DeliveryRejected(_)));
+    assert!(matches!(
+      classify(429, ApiError::default()),
+      Error::InternalServerError(_)
+    ));
+  }
+}
@@ -0,0 +1,165 @@
+use rust_i18n::t;
+
+#[cfg(feature = "renderer")]
+use crate::renderer::RenderOptions;
+use crate::{
+  database::TimeZone,
+  splatnet::{Message, COOP_WEAPON_GRIZZCO, COOP_WEAPON_RANDOM},
+};
+#[cfg(feature = "renderer")]
+use crate::{Error, Result};
+
+#[cfg(feature = "renderer")]
+use super::ActionContext;
+
+// one-line summary of a message
+pub(super) fn headline(msg: &Message, locale: &str) -> String {
+  match msg {
+    Message::Pvp(item) => format!("{} - {}", item.rule.name(locale), item.mode.name(locale)),
+    Message::Coop(item) => {
+      let stage_b64 = base64::encode(format!("CoopStage-{}", item.stage));
+      let stage = t!(
+        format!("splatnet.stages.{}.name", stage_b64).as_str(),
+        locale = locale
+      );
+      format!("{} - {}", item.mode.title(locale), stage)
+    }
+    Message::Gear(item) => {
+      let name = t!(
+        format!("splatnet.gear.{}.name", item.splatoon3ink_id).as_str(),
+        locale = locale
+      );
+      format!("{} - {}", t!("gears.title", locale = locale), name)
+    }
+    Message::Event(item) => {
+      let name = t!(
+        format!("splatnet.events.{}.name", item.id).as_str(),
+        locale = locale
+      );
+      format!("{} - {}", name, item.rule.name(locale))
+    }
+    Message::Fest(item) => format!("{} - {}", item.state.title(locale), item.title),
+    Message::Digest(items) => format!("{} ({})", t!("digests.title", locale = locale), items.len()),
+  }
+}
+
+pub(super) fn stage_name(id: u32, locale: &str) -> String {
+  let stage_b64 = base64::encode(format!("VsStage-{}", id));
+  t!(
+    format!("splatnet.stages.{}.name", stage_b64).as_str(),
+    locale = locale
+  )
+}
+
+// the lines below the headline
+pub(super) fn body(msg: &Message, locale: &str, time_zone: TimeZone) -> String {
+  match msg {
+    Message::Pvp(item) => {
+      let stages: Vec<_> = item
+        .stages
+        .iter()
+        .map(|id| stage_name(*id, locale))
+        .collect();
+      format!("[{}] & [{}]", stages[0], stages[1])
+    }
+    Message::Coop(item) => {
+      let weapons: Vec<_> = item
+        .weapons
+        .iter()
+        .map(|id| match id.as_str() {
+          COOP_WEAPON_RANDOM | COOP_WEAPON_GRIZZCO => {
+            t!(
+              format!("coop.weapons.{}.name", id).as_str(),
+              locale = locale
+            )
+          }
+          _ => t!(
+            format!("splatnet.weapons.{}.name", id).as_str(),
+            locale = locale
+          ),
+        })
+        .collect();
+      format!(
+        "{}\n{}",
+        weapons.join(" / "),
+        item.king_salmonid.name(locale)
+      )
+    }
+    Message::Gear(item) => {
+      let brand = t!(
+        format!("splatnet.brands.{}.name", item.brand).as_str(),
+        locale = locale
+      );
+      let power = t!(
+        format!("splatnet.powers.{}.name", item.primary_gear_power).as_str(),
+        locale = locale
+      );
+      format!(
+        "[{}] & [{}] +{}\n{}",
+        brand, power, item.additional_gear_powers, item.price
+      )
+    }
+    Message::Event(item) => {
+      let regulation = t!(
+        format!("splatnet.events.{}.regulation", item.id).as_str(),
+        locale = locale
+      );
+      let periods: Vec<_> = item
+        .time_periods
+        .iter()
+        .map(|(start_time, end_time)| {
+          format!(
+            "{} - {}",
+            time_zone.convert(*start_time).format("%m/%d %H:%M"),
+            time_zone.convert(*end_time).format("%H:%M")
+          )
+        })
+        .collect();
+      format!(
+        "{}\n{}",
+        periods.join("\n"),
+        regulation.replace("<br />", "\n")
+      )
+    }
+    Message::Fest(item) => {
+      let fmt = |t| time_zone.convert(t).format("%m/%d %H:%M").to_string();
+      format!("{} - {}", fmt(item.start_time), fmt(item.end_time))
+    }
+    Message::Digest(items) => {
+      let lines: Vec<_> = items
+        .iter()
+        .map(|e| {
+          format!(
+            "{} {}",
+            time_zone.convert(e.timestamp()).format("%m/%d %H:%M"),
+            headline(e, locale)
+          )
+        })
+        .collect();
+      lines.join("\n")
+    }
+  }
+}
+
+// at most the given number of characters, as limited by the target
+#[cfg(any(feature = "telegram", feature = "discord"))]
+pub(super) fn truncate(text: &str, len: usize) -> String {
+  text.chars().take(len).collect()
+}
+
+// the card of a message, a path under the output directory of the renderer,
+// if the kind of message has one
+#[cfg(feature = "renderer")]
+pub(super) fn render_card(
+  ctx: &ActionContext,
+  msg: &Message,
+  opts: &RenderOptions,
+) -> Result<Option<String>> {
+  let path = match msg {
+    Message::Pvp(item) => ctx.renderer.render_pvp(item, opts),
+    Message::Fest(item) => ctx.renderer.render_fest(item, opts),
+    Message::Digest(items) => ctx.renderer.render_digest(items, opts),
+    _ => return Ok(None),
+  };
+  path.map(Some).map_err(Error::InternalServerError)
+}
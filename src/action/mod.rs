@@ -1,38 +1,111 @@
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{
+  collections::HashMap,
+  sync::Arc,
+  time::{Duration, Instant},
+};
 
 use async_trait::async_trait;
-use backoff::ExponentialBackoffBuilder;
-use futures::{future::join_all, Future, FutureExt, TryFutureExt};
-use r2d2_sqlite::rusqlite::Connection;
+use chrono::{DateTime, TimeZone, Utc};
+use futures::FutureExt;
+use itertools::Itertools;
+use tokio::sync::{Notify, Semaphore};
 
 #[cfg(feature = "renderer")]
 use crate::renderer::Renderer;
 use crate::{
   database::{
+    action::{ExpireAction, ExpireActionRequest, LookupActionDigest, UpdateActionRx},
+    coop::{LookupCoop, LookupCoopRequest},
+    delivery::{CreateDelivery, CreateDeliveryRequest, DeliveryStatus, PruneDelivery},
+    event::{LookupEvent, LookupEventRequest},
+    fest::{LookupFest, LookupFestRequest},
+    from_json,
+    gear::{LookupGear, LookupGearRequest},
+    outbox::{
+      ClaimOutbox, ClaimOutboxRequest, ClaimOutboxResponse, CreateOutbox, CreateOutboxRequest,
+      DeleteOutbox, RetryOutbox, RetryOutboxRequest,
+    },
+    pending::{
+      CreatePendingNotification, CreatePendingNotificationRequest, LookupQueuedNotification,
+      LookupQueuedNotificationRequest, TakePendingNotification, TakePendingNotificationRequest,
+    },
     pvp::{LookupPvp, LookupPvpRequest},
-    Database,
+    to_json,
+    user::LookupUserContact,
+    Database, DatabaseConnection,
   },
-  splatnet::Message,
-  Result,
+  splatnet::{CoopMode, Message},
+  BoxError, Error, Result,
 };
 
 pub mod config;
+#[cfg(feature = "discord")]
+pub mod discord;
+#[cfg(any(feature = "webpush", feature = "telegram", feature = "discord"))]
+mod format;
 pub mod infolog;
+#[cfg(feature = "telegram")]
+pub mod telegram;
 #[cfg(feature = "webpush")]
 pub mod webpush;
 
+// how often due reminders are checked
+const PENDING_POLL_SECS: u64 = 30;
+
+// how often the outbox is checked for retries, besides when messages are queued
+const OUTBOX_POLL_SECS: u64 = 5;
+
+// deliveries in flight at a time
+const OUTBOX_WORKERS: usize = 8;
+
+// an attempt taking longer is abandoned and retried later
+const OUTBOX_EMIT_TIMEOUT_SECS: u64 = 2 * 60;
+
+// a claimed delivery is retried after this, in case its worker died, which
+// outlasts any attempt so that no two workers deliver an entry at once
+const OUTBOX_LEASE_SECS: i64 = 5 * 60;
+
+// retried with exponential backoff for about an hour
+const OUTBOX_MAX_ATTEMPTS: u32 = 12;
+const OUTBOX_INITIAL_RETRY_SECS: i64 = 5;
+const OUTBOX_MAX_RETRY_SECS: i64 = 10 * 60;
+
+// delivery attempts are kept for this long
+const HISTORY_RETENTION_DAYS: i64 = 30;
+
+// user_actions columns tracking the latest delivery of each kind
+const RX_COLUMNS: &[&str] = &[
+  "rx_pvp",
+  "rx_event",
+  "rx_coop",
+  "rx_coop_ex",
+  "rx_gear",
+  "rx_gear_brand",
+  "rx_fest",
+];
+
 pub type ActionAgentMap = HashMap<&'static str, Arc<dyn ActionAgent>>;
 
 #[async_trait]
 pub trait ActionAgent: std::fmt::Debug + Send + Sync {
   fn get_ext_info<'a>(
     &self,
-    _conn: &'a Connection,
+    _conn: &'a DatabaseConnection,
     _id: i64,
   ) -> Result<Option<Box<dyn erased_serde::Serialize>>> {
     Ok(None)
   }
 
+  // something the user hands to the agent's side to bind a target, for agents
+  // linked that way
+  fn link(
+    &self,
+    _conn: &DatabaseConnection,
+    _uid: i64,
+  ) -> Result<Box<dyn erased_serde::Serialize>> {
+    Err(Error::InvalidParameter("link", format!("{:?}", self)))
+  }
+
   async fn emit(
     self: Arc<Self>,
     ctx: Arc<ActionContext>,
@@ -44,6 +117,11 @@ pub trait ActionAgent: std::fmt::Debug + Send + Sync {
   async fn test(self: Arc<Self>, _db: Database, _uid: i64, _id: i64) -> Result<()> {
     Ok(())
   }
+
+  // runs for as long as the server, for agents receiving updates of their own
+  async fn watch(self: Arc<Self>, _ctx: Arc<ActionContext>) -> Result<()> {
+    Ok(())
+  }
 }
 
 pub struct ActionContext {
@@ -54,10 +132,31 @@ pub struct ActionContext {
   pub image_url: String,
 }
 
+impl ActionContext {
+  #[cfg(test)]
+  pub(crate) fn new_for_test(database: Database) -> Self {
+    ActionContext {
+      database,
+      #[cfg(feature = "renderer")]
+      renderer: crate::renderer::Renderer::new(crate::renderer::RendererConfig {
+        out_dir: std::env::temp_dir().to_string_lossy().into_owned(),
+        assets_dir: "resources/assets".into(),
+        font_family: String::new(),
+        cache_size: 1,
+      })
+      .unwrap(),
+      #[cfg(feature = "renderer")]
+      image_url: String::new(),
+    }
+  }
+}
+
 #[derive(Clone)]
 pub struct ActionManager {
   ctx: Arc<ActionContext>,
   pub agents: Arc<ActionAgentMap>,
+  // wakes the outbox workers up once messages are queued
+  outbox: Arc<Notify>,
 }
 
 impl ActionManager {
@@ -65,85 +164,781 @@ impl ActionManager {
     ActionManager {
       ctx: Arc::new(ctx),
       agents: Arc::new(agents),
+      outbox: Arc::new(Notify::new()),
     }
   }
 
-  pub fn dispatch(&self, msg: Message) -> Result<impl Future<Output = ()>> {
-    let conn = self.ctx.database.get()?;
-    let (actions, rx, ts) = match &msg {
+  pub fn database(&self) -> &Database {
+    &self.ctx.database
+  }
+
+  // queues the message for the matching actions, now or when due
+  pub fn dispatch(&self, msg: Message) -> Result<()> {
+    let mut conn = self.ctx.database.get()?;
+    let tx = conn.transaction()?;
+    let (actions, rx, ts): (Vec<_>, _, _) = match &msg {
       Message::Pvp(item) => (
-        conn.lookup_pvp(LookupPvpRequest {
+        tx.lookup_pvp(LookupPvpRequest {
           start_time: item.start_time,
           rule: item.rule,
           mode: item.mode,
           stages: &item.stages,
-        })?,
+        })?
+        .iter()
+        .map(|e| (e.id, e.uid, e.agent.clone(), e.remind_mins))
+        .collect(),
         "rx_pvp",
         item.start_time,
       ),
+      Message::Coop(item) => (
+        tx.lookup_coop(LookupCoopRequest {
+          start_time: item.start_time,
+          mode: item.mode,
+          stage: item.stage,
+          weapons: &item.weapons,
+          king_salmonid: item.king_salmonid,
+        })?
+        .iter()
+        .map(|e| (e.id, e.uid, e.agent.clone(), e.remind_mins))
+        .collect(),
+        match item.mode {
+          CoopMode::Regular => "rx_coop",
+          _ => "rx_coop_ex",
+        },
+        item.start_time,
+      ),
+      Message::Gear(item) => (
+        tx.lookup_gear(LookupGearRequest {
+          sale_end_time: item.sale_end_time,
+          gear_type: item.gear_type,
+          brand: &item.brand,
+          primary_gear_power: &item.primary_gear_power,
+          additional_gear_powers: item.additional_gear_powers,
+          price: item.price,
+          pickup: item.pickup,
+        })?
+        .iter()
+        .map(|e| (e.id, e.uid, e.agent.clone(), None))
+        .collect(),
+        if item.pickup {
+          "rx_gear_brand"
+        } else {
+          "rx_gear"
+        },
+        item.sale_end_time,
+      ),
+      Message::Event(item) => {
+        let start_time = item
+          .start_time()
+          .ok_or_else(|| Error::InternalServerError("event without any time period".into()))?;
+        (
+          tx.lookup_event(LookupEventRequest {
+            start_time,
+            event_id: &item.event_id,
+          })?
+          .iter()
+          .map(|e| (e.id, e.uid, e.agent.clone(), e.remind_mins))
+          .collect(),
+          "rx_event",
+          start_time,
+        )
+      }
+      Message::Fest(item) => (
+        tx.lookup_fest(LookupFestRequest {
+          state_time: item.state_time(),
+          state: item.state,
+        })?
+        .iter()
+        .map(|e| (e.id, e.uid, e.agent.clone(), None))
+        .collect(),
+        "rx_fest",
+        item.state_time(),
+      ),
+      Message::Digest(_) => {
+        return Err(Error::InternalServerError(
+          "digests are assembled from pending notifications".into(),
+        ))
+      }
     };
-    let msg = Arc::new(msg);
-    let mut tasks = vec![];
-    let exp = ExponentialBackoffBuilder::new()
-      .with_initial_interval(Duration::from_secs(5))
-      .with_max_interval(Duration::from_secs(60 * 10))
-      .with_max_elapsed_time(Some(Duration::from_secs(60 * 60)))
-      .build();
-    for e in actions.iter() {
-      if let Some(agent) = self.agents.get(e.agent.as_str()) {
-        let task = backoff::future::retry(exp.clone(), {
-          let ctx = self.ctx.clone();
-          let id = e.id;
-          let uid = e.uid;
-          let msg = msg.clone();
-          let agent = agent.clone();
-          let act_agent = e.agent.clone();
-          let mut attempt_idx = 0;
-          move || {
-            attempt_idx += 1;
-            agent
-              .clone()
-              .emit(ctx.clone(), uid, id, msg.clone())
-              .map_err({
-                let agent = act_agent.clone();
-                move |err| {
-                  log::warn!(
-                    "emit {}#{} for uid[{}] (attempt#{}) failed: [{:?}]",
-                    agent,
-                    id,
-                    uid,
-                    attempt_idx,
-                    err
-                  );
-                  backoff::Error::transient(err)
-                }
-              })
+    let now = Utc::now();
+    let encoded = to_json(&msg)?;
+    let mut queued = false;
+    for (id, uid, _, remind_mins) in actions.into_iter() {
+      // the marks only move on delivery, so skip what is still on the way
+      if tx.lookup_queued_notification(LookupQueuedNotificationRequest { id, rx, ts })? {
+        continue;
+      }
+      // reminders are delivered on time, otherwise wait for the next digest
+      let fire_time = match remind_mins {
+        Some(e) => Some(ts - chrono::Duration::minutes(e as i64)),
+        None => {
+          let digest = tx.lookup_action_digest(id)?;
+          digest
+            .mode
+            .next_fire_time(now, digest.hour, digest.time_zone)
+        }
+      };
+      let fire_time = match fire_time.filter(|e| *e > now) {
+        Some(e) => Some(e),
+        // hold back during quiet hours, snooze and vacation
+        None => {
+          let fire_time = tx.lookup_user_contact(uid)?.next_contact_time(now);
+          if is_stale(&msg, fire_time) {
+            drop_stale(&tx, uid, id, msg.kind(), ts, now)?;
+            continue;
           }
+          fire_time
+        }
+      };
+      if let Some(fire_time) = fire_time {
+        tx.create_pending_notification(CreatePendingNotificationRequest {
+          uid,
+          id,
+          rx,
+          ts,
+          fire_time,
+          message: &encoded,
+        })?;
+        continue;
+      }
+      tx.create_outbox(CreateOutboxRequest {
+        uid,
+        id,
+        message: &encoded,
+        marks: &to_json(&[(rx, ts.timestamp())])?,
+      })?;
+      queued = true;
+    }
+    tx.commit()?;
+    if queued {
+      self.outbox.notify_one();
+    }
+    Ok(())
+  }
+
+  // queues the due reminders and digests periodically, delivers the queued
+  // messages, and keeps the agents receiving updates
+  pub async fn watch(self) -> std::result::Result<(), BoxError> {
+    futures::join!(
+      self.clone().watch_pending(),
+      self.clone().watch_outbox(),
+      self.watch_agents()
+    );
+    Ok(())
+  }
+
+  async fn watch_agents(self) {
+    let futs = self.agents.iter().map(|(name, agent)| {
+      agent.clone().watch(self.ctx.clone()).map(move |ret| {
+        if let Err(err) = ret {
+          log::error!("watch action agent [{}] failed: [{:?}]", name, err);
+        }
+      })
+    });
+    futures::future::join_all(futs).await;
+  }
+
+  async fn watch_pending(self) {
+    let mut interval = tokio::time::interval(Duration::from_secs(PENDING_POLL_SECS));
+    loop {
+      interval.tick().await;
+      match self.dispatch_pending() {
+        Ok(true) => self.outbox.notify_one(),
+        Ok(false) => {}
+        Err(err) => log::warn!("dispatch pending notifications failed: [{:?}]", err),
+      }
+      let before = Utc::now() - chrono::Duration::days(HISTORY_RETENTION_DAYS);
+      if let Err(err) = self
+        .ctx
+        .database
+        .get()
+        .and_then(|conn| conn.prune_delivery(before))
+      {
+        log::warn!("prune delivery history failed: [{:?}]", err);
+      }
+    }
+  }
+
+  // moves the due pending notifications to the outbox, returns whether any
+  fn dispatch_pending(&self) -> Result<bool> {
+    let now = Utc::now();
+    let mut conn = self.ctx.database.get()?;
+    let tx = conn.transaction()?;
+    let li = tx.take_pending_notification(TakePendingNotificationRequest { now })?;
+    // notifications of inactive actions are not kept until turned on again
+    let (li, dropped): (Vec<_>, Vec<_>) = li.into_iter().partition(|e| e.active);
+    for e in dropped.iter() {
+      log::info!("drop pending notification for inactive action #{}", e.id);
+      tx.create_delivery(CreateDeliveryRequest {
+        uid: e.uid,
+        id: e.id,
+        kind: from_json::<Message>(&e.message)
+          .map(|msg| msg.kind())
+          .unwrap_or("unknown"),
+        ts: Utc.timestamp_opt(e.ts, 0).unwrap(),
+        status: DeliveryStatus::Dropped,
+        attempt: 0,
+        latency_ms: 0,
+        error: Some("action inactive"),
+        time: now,
+      })?;
+    }
+    // notifications of an action due at the same time make up a digest
+    let mut groups = vec![];
+    for ((id, uid, fire_time), group) in &li.iter().group_by(|e| (e.id, e.uid, e.fire_time)) {
+      let group: Vec<_> = group.collect();
+      // postpone as a whole if the user can't be contacted now
+      if let Some(fire_time) = tx.lookup_user_contact(uid)?.next_contact_time(now) {
+        for e in group.into_iter() {
+          let ts = Utc.timestamp_opt(e.ts, 0).unwrap();
+          if let Ok(msg) = from_json::<Message>(&e.message) {
+            if is_stale(&msg, Some(fire_time)) {
+              drop_stale(&tx, uid, id, msg.kind(), ts, now)?;
+              continue;
+            }
+          }
+          tx.create_pending_notification(CreatePendingNotificationRequest {
+            uid,
+            id,
+            rx: &e.rx,
+            ts,
+            fire_time,
+            message: &e.message,
+          })?;
+        }
+        continue;
+      }
+      groups.push((id, uid, fire_time, group));
+    }
+    let mut queued = false;
+    for (id, uid, fire_time, group) in groups.into_iter() {
+      let mut msgs = vec![];
+      let mut marks = vec![];
+      let mut agent = None;
+      for e in group {
+        let Some(rx) = RX_COLUMNS.iter().find(|rx| **rx == e.rx) else {
+          log::error!("unknown rx column: [{}]", e.rx);
+          continue;
+        };
+        match from_json::<Message>(&e.message) {
+          Ok(msg) => msgs.push(msg),
+          Err(err) => {
+            log::error!("malformed pending notification: [{:?}]", err);
+            continue;
+          }
+        }
+        marks.push((*rx, e.ts));
+        agent = Some(e.agent.as_str());
+      }
+      let Some(agent) = agent else {
+        continue;
+      };
+      log::debug!(
+        "{} notification(s) due at [{}] for {}#{}",
+        msgs.len(),
+        fire_time,
+        agent,
+        id
+      );
+      let msg = if msgs.len() == 1 {
+        msgs.pop().unwrap()
+      } else {
+        Message::Digest(msgs)
+      };
+      // taken and queued at once, so that nothing is lost in between
+      tx.create_outbox(CreateOutboxRequest {
+        uid,
+        id,
+        message: &to_json(&msg)?,
+        marks: &to_json(&marks)?,
+      })?;
+      queued = true;
+    }
+    tx.commit()?;
+    Ok(queued)
+  }
+
+  // drains the outbox with a pool of workers
+  async fn watch_outbox(self) {
+    let workers = Arc::new(Semaphore::new(OUTBOX_WORKERS));
+    let mut interval = tokio::time::interval(Duration::from_secs(OUTBOX_POLL_SECS));
+    loop {
+      tokio::select! {
+        _ = interval.tick() => {}
+        _ = self.outbox.notified() => {}
+      }
+      loop {
+        let now = Utc::now();
+        // no more than the idle workers take, so that leases don't run out waiting
+        let li = self.ctx.database.get().and_then(|conn| {
+          conn.claim_outbox(ClaimOutboxRequest {
+            now,
+            lease_until: now + chrono::Duration::seconds(OUTBOX_LEASE_SECS),
+            limit: workers.available_permits().max(1) as u32,
+          })
         });
-        let task = task.and_then({
-          let uid = e.uid;
-          let id = e.id;
-          let ts = ts.timestamp();
-          let db = self.ctx.database.clone();
-          move |()| async move {
-            let conn = db.get()?;
-            let sql = format!(
-              "
-              UPDATE user_actions
-              SET {rx} = max({rx}, ?3)
-              WHERE uid = ?1 AND id = ?2
-              ",
-              rx = rx
+        let li = match li {
+          Ok(li) if li.is_empty() => break,
+          Ok(li) => li,
+          Err(err) => {
+            log::warn!("claim outbox failed: [{:?}]", err);
+            break;
+          }
+        };
+        for e in li.into_iter() {
+          let permit = workers.clone().acquire_owned().await.unwrap();
+          tokio::spawn(self.clone().deliver(e).map(move |()| drop(permit)));
+        }
+      }
+    }
+  }
+
+  // makes an attempt at a claimed outbox entry, and schedules a retry if failed
+  async fn deliver(self, e: ClaimOutboxResponse) {
+    let ClaimOutboxResponse {
+      oid,
+      id,
+      uid,
+      ref agent,
+      attempts,
+      ..
+    } = e;
+    let msg = match from_json::<Message>(&e.message) {
+      Ok(msg) => Arc::new(msg),
+      Err(err) => {
+        // never going to be delivered
+        log::error!("malformed outbox entry #{}: [{:?}]", oid, err);
+        self.drop_outbox(&e, &err);
+        return;
+      }
+    };
+    let start = Instant::now();
+    let ret = match self.agents.get(agent.as_str()) {
+      Some(act) => {
+        let emit = act.clone().emit(self.ctx.clone(), uid, id, msg.clone());
+        let timeout = Duration::from_secs(OUTBOX_EMIT_TIMEOUT_SECS);
+        tokio::time::timeout(timeout, emit)
+          .await
+          .unwrap_or_else(|_| {
+            Err(Error::InternalServerError(
+              format!("emit timed out after {:?}", timeout).into(),
+            ))
+          })
+      }
+      None => Err(Error::InternalServerError(
+        format!("unknown action agent: [{}]", agent).into(),
+      )),
+    };
+    let latency = start.elapsed();
+    let (status, err) = match ret {
+      Ok(()) => {
+        if let Err(err) = self.finish_outbox(&e) {
+          // delivered again once the lease ends
+          log::warn!("finish outbox #{} failed: [{:?}]", oid, err);
+        }
+        (DeliveryStatus::Delivered, None)
+      }
+      Err(err) => {
+        // what the target refused for good is not retried
+        let next_attempt = match err {
+          Error::ActionExpired(_) | Error::DeliveryRejected(_) => None,
+          _ => retry_time(Utc::now(), attempts),
+        };
+        match (&err, next_attempt) {
+          // logged once expired below
+          (Error::ActionExpired(_), _) => {}
+          (_, Some(_)) => {
+            log::warn!(
+              "emit {}#{} for uid[{}] (attempt#{}) failed: [{:?}]",
+              agent,
+              id,
+              uid,
+              attempts,
+              err
             );
-            conn.execute(&sql, (&uid, &id, &ts))?;
-            Ok(())
           }
-        });
-        tasks.push(task);
-      } else {
-        log::error!("unknown action agent: [{}]", e.agent)
+          (_, None) => {
+            log::error!(
+              "emit {}#{} for uid[{}] given up after {} attempts: [{:?}]",
+              agent,
+              id,
+              uid,
+              attempts,
+              err
+            );
+          }
+        }
+        self.retry_outbox(oid, next_attempt, &err);
+        if let Error::ActionExpired(reason) = &err {
+          self.expire_action(uid, id, reason);
+        }
+        let status = match next_attempt {
+          Some(_) => DeliveryStatus::Failed,
+          None => DeliveryStatus::Dropped,
+        };
+        (status, Some(err.to_string()))
       }
+    };
+    let ret = self.ctx.database.get().and_then(|conn| {
+      conn.create_delivery(CreateDeliveryRequest {
+        uid,
+        id,
+        kind: msg.kind(),
+        ts: msg.timestamp(),
+        status,
+        attempt: attempts,
+        latency_ms: latency.as_millis() as i64,
+        error: err.as_deref(),
+        time: Utc::now(),
+      })
+    });
+    if let Err(err) = ret {
+      log::warn!("record delivery of outbox #{} failed: [{:?}]", oid, err);
+    }
+  }
+
+  // bumps the high-water marks of a delivered entry along with removing it
+  fn finish_outbox(&self, e: &ClaimOutboxResponse) -> Result<()> {
+    let marks: Vec<(String, i64)> = from_json(&e.marks)?;
+    let mut conn = self.ctx.database.get()?;
+    let tx = conn.transaction()?;
+    for (rx, ts) in marks.iter() {
+      let Some(rx) = RX_COLUMNS.iter().find(|e| **e == rx) else {
+        log::error!("unknown rx column: [{}]", rx);
+        continue;
+      };
+      tx.update_action_rx(e.uid, e.id, rx, *ts)?;
     }
-    Ok(join_all(tasks.into_iter()).map(|_| ()))
+    tx.delete_outbox(e.oid)?;
+    tx.commit()?;
+    Ok(())
+  }
+
+  // removes an entry which is never going to be delivered, on the record
+  fn drop_outbox(&self, e: &ClaimOutboxResponse, err: &Error) {
+    let now = Utc::now();
+    let ret = self.ctx.database.get().and_then(|mut conn| {
+      let tx = conn.transaction()?;
+      tx.delete_outbox(e.oid)?;
+      tx.create_delivery(CreateDeliveryRequest {
+        uid: e.uid,
+        id: e.id,
+        kind: "unknown",
+        // nor is the rotation known
+        ts: now,
+        status: DeliveryStatus::Dropped,
+        attempt: e.attempts,
+        latency_ms: 0,
+        error: Some(&err.to_string()),
+        time: now,
+      })?;
+      tx.commit()
+    });
+    if let Err(err) = ret {
+      log::warn!("drop outbox #{} failed: [{:?}]", e.oid, err);
+    }
+  }
+
+  // stops delivering to an action whose target is gone, along with what is
+  // queued for it
+  pub fn expire_action(&self, uid: i64, id: i64, reason: &str) {
+    let ret = self.ctx.database.get().and_then(|mut conn| {
+      let tx = conn.transaction()?;
+      let expired = tx.expire_action(ExpireActionRequest {
+        uid,
+        id,
+        time: Utc::now(),
+        reason,
+      })?;
+      tx.commit()?;
+      Ok(expired)
+    });
+    match ret {
+      Ok(true) => log::info!("action #{} of uid[{}] expired: [{}]", id, uid, reason),
+      Ok(false) => {}
+      Err(err) => log::warn!("expire action #{} failed: [{:?}]", id, err),
+    }
+  }
+
+  fn retry_outbox(&self, oid: i64, next_attempt: Option<DateTime<Utc>>, err: &Error) {
+    let ret = self.ctx.database.get().and_then(|conn| {
+      conn.retry_outbox(RetryOutboxRequest {
+        oid,
+        next_attempt,
+        error: &describe(err),
+      })
+    });
+    if let Err(err) = ret {
+      log::warn!("retry outbox #{} failed: [{:?}]", oid, err);
+    }
+  }
+}
+
+// whether the message would only be delivered once it is of no use
+fn is_stale(msg: &Message, fire_time: Option<DateTime<Utc>>) -> bool {
+  matches!((fire_time, msg.end_time()), (Some(t), Some(end)) if t >= end)
+}
+
+// records a notification held back past the end of what it is about
+fn drop_stale(
+  conn: &DatabaseConnection,
+  uid: i64,
+  id: i64,
+  kind: &str,
+  ts: DateTime<Utc>,
+  now: DateTime<Utc>,
+) -> Result<()> {
+  log::info!("drop stale {} notification for action #{}", kind, id);
+  conn.create_delivery(CreateDeliveryRequest {
+    uid,
+    id,
+    kind,
+    ts,
+    status: DeliveryStatus::Dropped,
+    attempt: 0,
+    latency_ms: 0,
+    error: Some("rotation over"),
+    time: now,
+  })
+}
+
+// what the outbox keeps of a failed attempt for operators, unlike the debug
+// output of the error, which may carry the urls requested along with the tokens
+// in them, while the history users see only has the kind of the error
+fn describe(err: &Error) -> String {
+  match err {
+    Error::InternalServerError(err) => format!("internal error: {}", err),
+    Error::NetworkError(err) => {
+      let reason = match err.status() {
+        Some(status) => format!("status {}", status),
+        None if err.is_timeout() => "timed out".into(),
+        None if err.is_connect() => "connection failed".into(),
+        None if err.is_decode() => "malformed response".into(),
+        None => "request failed".into(),
+      };
+      format!("network error: {}", reason)
+    }
+    Error::ActionExpired(reason) => format!("action expired: {}", reason),
+    Error::DeliveryRejected(reason) => format!("delivery rejected: {}", reason),
+    _ => err.to_string(),
+  }
+}
+
+// reqwest errors of requests to urls with tokens in them, e.g. of bot apis
+#[cfg(any(feature = "telegram", feature = "discord"))]
+fn without_url(err: reqwest::Error) -> Error {
+  Error::NetworkError(err.without_url())
+}
+
+// when to retry after the given number of attempts, none to give up
+fn retry_time(now: DateTime<Utc>, attempts: u32) -> Option<DateTime<Utc>> {
+  if attempts >= OUTBOX_MAX_ATTEMPTS {
+    return None;
+  }
+  let secs = OUTBOX_INITIAL_RETRY_SECS
+    .saturating_mul(1 << (attempts.max(1) - 1).min(30))
+    .min(OUTBOX_MAX_RETRY_SECS);
+  Some(now + chrono::Duration::seconds(secs))
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{
+    database::{
+      action::CreateAction,
+      delivery::{ListDelivery, ListDeliveryRequest},
+      outbox::ListOutbox,
+      query::{CreateQuery, CreateQueryRequest, PvpQueryConfig, QueryConfig},
+      user::{
+        CreateUser, CreateUserRequest, LookupUserId, LookupUserIdRequest, UpdateUserSettings,
+        UserSettings,
+      },
+    },
+    splatnet::{PvpMode, PvpRule, PvpSpiderItem},
+  };
+
+  use super::*;
+
+  // a file shared by every pooled connection, unlike in-memory ones
+  fn temp_db_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!(
+      "splatquery-test-action-{}-{}.db",
+      name,
+      std::process::id()
+    ))
+  }
+
+  fn remove_temp_db(path: &std::path::Path) {
+    for suffix in ["", "-wal", "-shm"] {
+      let _ = std::fs::remove_file(format!("{}{}", path.to_string_lossy(), suffix));
+    }
+  }
+
+  fn create_user(conn: &DatabaseConnection) -> i64 {
+    let auth_agent = "mock_auth_agent";
+    let auth_uid = "mock_auth_uid";
+    conn
+      .create_user(CreateUserRequest {
+        auth_agent,
+        auth_uid,
+        email: None,
+        name: None,
+        picture: None,
+        language: None,
+        time_zone: None,
+        availability: None,
+      })
+      .unwrap();
+    conn
+      .lookup_user_id(LookupUserIdRequest {
+        auth_agent,
+        auth_uid,
+      })
+      .unwrap()
+  }
+
+  #[test]
+  fn test_drop_stale_when_held_back() {
+    let path = temp_db_path("stale");
+    let db = Database::new_from_file(&path).unwrap();
+    let mut conn = db.get().unwrap();
+    let uid = create_user(&conn);
+    let now = Utc::now();
+    // snoozed past the end of the first rotation but not the second
+    conn
+      .update_user_settings(
+        uid,
+        &UserSettings {
+          language: None,
+          time_zone: None,
+          availability: None,
+          remind_mins: None,
+          digest: None,
+          digest_hour: None,
+          quiet_hours: None,
+          snooze_until: Some((now + chrono::Duration::hours(2)).timestamp()),
+          vacation: None,
+        },
+      )
+      .unwrap();
+    let tx = conn.transaction().unwrap();
+    let id = tx.create_action(uid, "mock").unwrap();
+    for hours in [1, 4] {
+      let msg = Message::Pvp(PvpSpiderItem {
+        start_time: now,
+        end_time: now + chrono::Duration::hours(hours),
+        rule: PvpRule::Area,
+        stages: vec![1, 2],
+        mode: PvpMode::X,
+      });
+      tx.create_pending_notification(CreatePendingNotificationRequest {
+        uid,
+        id,
+        rx: "rx_pvp",
+        ts: now + chrono::Duration::hours(hours),
+        fire_time: now,
+        message: &to_json(&msg).unwrap(),
+      })
+      .unwrap();
+    }
+    tx.commit().unwrap();
+
+    let actions = ActionManager::new(
+      ActionContext::new_for_test(db.clone()),
+      ActionAgentMap::new(),
+    );
+    assert!(!actions.dispatch_pending().unwrap());
+    let li = conn
+      .list_delivery(ListDeliveryRequest {
+        uid,
+        id: Some(id),
+        before: None,
+        limit: 10,
+      })
+      .unwrap();
+    assert_eq!(li.len(), 1);
+    assert_eq!(li[0].status, DeliveryStatus::Dropped);
+    assert_eq!(li[0].error.as_deref(), Some("rotation over"));
+    // the other one is held back until the snooze ends
+    let tx = conn.transaction().unwrap();
+    let li = tx
+      .take_pending_notification(TakePendingNotificationRequest {
+        now: now + chrono::Duration::hours(3),
+      })
+      .unwrap();
+    assert_eq!(li.len(), 1);
+    assert_eq!(li[0].ts, (now + chrono::Duration::hours(4)).timestamp());
+
+    drop(tx);
+    drop(conn);
+    drop(actions);
+    drop(db);
+    remove_temp_db(&path);
+  }
+
+  #[test]
+  fn test_redispatch() {
+    let path = temp_db_path("redispatch");
+    let db = Database::new_from_file(&path).unwrap();
+    let mut conn = db.get().unwrap();
+    let uid = create_user(&conn);
+    let tx = conn.transaction().unwrap();
+    tx.create_query(CreateQueryRequest {
+      uid,
+      config: &QueryConfig::Pvp {
+        config: PvpQueryConfig {
+          modes: vec![PvpMode::X],
+          rules: vec![PvpRule::Area],
+          includes: vec![1],
+          excludes: vec![],
+          min_includes: 1,
+          rule_stages: vec![],
+          remind_mins: None,
+          availability: None,
+          enabled: true,
+        },
+      },
+    })
+    .unwrap();
+    let id = tx.create_action(uid, "mock").unwrap();
+    tx.commit().unwrap();
+
+    let now = Utc::now();
+    let item = |stages| PvpSpiderItem {
+      start_time: now + chrono::Duration::hours(1),
+      end_time: now + chrono::Duration::hours(3),
+      rule: PvpRule::Area,
+      stages,
+      mode: PvpMode::X,
+    };
+    let actions = ActionManager::new(
+      ActionContext::new_for_test(db.clone()),
+      ActionAgentMap::new(),
+    );
+    actions.dispatch(Message::Pvp(item(vec![1, 2]))).unwrap();
+    assert_eq!(conn.list_outbox().unwrap().len(), 1);
+    // the same rotation seen again, even if reported differently
+    actions.dispatch(Message::Pvp(item(vec![1, 2]))).unwrap();
+    actions.dispatch(Message::Pvp(item(vec![2, 1]))).unwrap();
+    assert_eq!(conn.list_outbox().unwrap().len(), 1);
+
+    // nor once it is part of a digest
+    let oid = conn.list_outbox().unwrap()[0].oid;
+    conn.delete_outbox(oid).unwrap();
+    conn
+      .create_outbox(CreateOutboxRequest {
+        uid,
+        id,
+        message: &to_json(&Message::Digest(vec![])).unwrap(),
+        marks: &to_json(&[("rx_pvp", (now + chrono::Duration::hours(1)).timestamp())]).unwrap(),
+      })
+      .unwrap();
+    actions.dispatch(Message::Pvp(item(vec![1, 2]))).unwrap();
+    assert_eq!(conn.list_outbox().unwrap().len(), 1);
+
+    drop(conn);
+    drop(actions);
+    drop(db);
+    remove_temp_db(&path);
   }
 }
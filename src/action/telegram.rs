@@ -0,0 +1,845 @@
+#[cfg(feature = "renderer")]
+use std::path::Path;
+use std::{str::FromStr, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use r2d2_sqlite::rusqlite::{Connection, OptionalExtension};
+use rand::{distributions::Alphanumeric, Rng};
+#[cfg(feature = "renderer")]
+use reqwest::multipart::{Form, Part};
+use reqwest::{Client, RequestBuilder};
+use rust_i18n::t;
+use serde::{
+  de::{DeserializeOwned, IgnoredAny},
+  Deserialize, Serialize,
+};
+use serde_json::{json, Value};
+
+#[cfg(feature = "renderer")]
+use crate::renderer::RenderOptions;
+use crate::{
+  database::{
+    action::{CreateAction, DeleteAction},
+    dispatch, Database, DatabaseConnection, Language, TimeZone, Transaction,
+  },
+  splatnet::Message,
+  Error, Result,
+};
+
+#[cfg(feature = "postgres")]
+use crate::database::postgres::PgConnection;
+
+#[cfg(feature = "renderer")]
+use super::format::render_card;
+use super::{
+  format::{body, headline, truncate},
+  without_url, ActionAgent, ActionContext,
+};
+
+// how long a link code may be sent to the bot
+const LINK_EXPIRE_MINS: i64 = 10;
+const LINK_CODE_LEN: usize = 16;
+
+// updates of the bot are long polled for this long at a time
+const POLL_TIMEOUT_SECS: u64 = 30;
+const POLL_RETRY_SECS: u64 = 5;
+
+// in characters, as limited by the bot api
+const MAX_TEXT_LEN: usize = 4096;
+#[cfg(feature = "renderer")]
+const MAX_CAPTION_LEN: usize = 1024;
+
+#[derive(Serialize, Deserialize)]
+pub struct TelegramActionAgentConfig {
+  pub bot_token: String,
+  // the bot api server, e.g. a local stub
+  #[serde(default = "default_api_url")]
+  pub api_url: String,
+  // for links that open the chat with the bot
+  pub bot_username: Option<String>,
+  // whether to long poll the updates of the bot, which links the chats;
+  // telegram serves a bot to a single poller at a time and rejects the others
+  // with 409 Conflict, so only one replica may turn it on, which then binds
+  // the link codes issued by any of them
+  #[serde(default = "default_poll_updates")]
+  pub poll_updates: bool,
+}
+
+fn default_api_url() -> String {
+  "https://api.telegram.org".into()
+}
+
+fn default_poll_updates() -> bool {
+  true
+}
+
+impl TelegramActionAgentConfig {
+  pub fn collect(self) -> Result<TelegramActionAgent> {
+    let client = Client::builder()
+      .timeout(Duration::from_secs(POLL_TIMEOUT_SECS * 2))
+      .build()?;
+    Ok(TelegramActionAgent {
+      token: self.bot_token,
+      api_url: self.api_url.trim_end_matches('/').into(),
+      bot_username: self.bot_username,
+      poll_updates: self.poll_updates,
+      client,
+    })
+  }
+}
+
+#[derive(Serialize)]
+pub struct TelegramExtInfo {
+  pub chat_id: i64,
+  pub chat_type: String,
+  pub title: Option<String>,
+  pub username: Option<String>,
+}
+
+// a code to bind a chat with, by sending `/start <code>` to the bot there
+#[derive(Serialize)]
+pub struct TelegramLink {
+  pub code: String,
+  // opens the chat with the bot, if its username is configured
+  pub url: Option<String>,
+  pub expire_time: i64,
+}
+
+pub struct TelegramActionAgent {
+  token: String,
+  api_url: String,
+  bot_username: Option<String>,
+  poll_updates: bool,
+  client: Client,
+}
+
+impl std::fmt::Debug for TelegramActionAgent {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_tuple("TelegramActionAgent").finish()
+  }
+}
+
+#[async_trait]
+impl ActionAgent for TelegramActionAgent {
+  fn get_ext_info(
+    &self,
+    conn: &DatabaseConnection,
+    id: i64,
+  ) -> Result<Option<Box<dyn erased_serde::Serialize>>> {
+    let info = conn.lookup_telegram_ext_info(id)?;
+    Ok(Some(Box::new(info)))
+  }
+
+  fn link(&self, conn: &DatabaseConnection, uid: i64) -> Result<Box<dyn erased_serde::Serialize>> {
+    let code: String = rand::thread_rng()
+      .sample_iter(&Alphanumeric)
+      .take(LINK_CODE_LEN)
+      .map(char::from)
+      .collect();
+    let expire_time = Utc::now() + chrono::Duration::minutes(LINK_EXPIRE_MINS);
+    conn.create_telegram_link(&code, uid, expire_time)?;
+    let url = self
+      .bot_username
+      .as_ref()
+      .map(|e| format!("https://t.me/{}?start={}", e, code));
+    Ok(Box::new(TelegramLink {
+      code,
+      url,
+      expire_time: expire_time.timestamp(),
+    }))
+  }
+
+  async fn emit(
+    self: Arc<Self>,
+    ctx: Arc<ActionContext>,
+    uid: i64,
+    id: i64,
+    msg: Arc<Message>,
+  ) -> Result<()> {
+    let (chat_id, language, time_zone) = lookup_target(&ctx.database, uid, id)?;
+    let locale = language.locale();
+    let text = format!(
+      "{}\n{}",
+      headline(msg.as_ref(), locale),
+      body(msg.as_ref(), locale, time_zone)
+    );
+    #[cfg(feature = "renderer")]
+    {
+      let img_opts = RenderOptions {
+        platform: "mobile",
+        language,
+        time_zone,
+      };
+      if let Some(img_path) = render_card(&ctx, msg.as_ref(), &img_opts)? {
+        let img_path = Path::new(&ctx.renderer.out_dir()).join(img_path);
+        return self.send_photo(chat_id, &img_path, &text).await;
+      }
+    }
+    self.send_message(chat_id, &text).await
+  }
+
+  async fn test(self: Arc<Self>, db: Database, uid: i64, id: i64) -> Result<()> {
+    let (chat_id, language, _) = lookup_target(&db, uid, id)?;
+    self
+      .send_message(
+        chat_id,
+        &t!("test_notification", locale = language.locale()),
+      )
+      .await
+  }
+
+  // binds the chats that send a link code to the bot
+  async fn watch(self: Arc<Self>, ctx: Arc<ActionContext>) -> Result<()> {
+    if !self.poll_updates {
+      return Ok(());
+    }
+    let mut offset = 0;
+    loop {
+      let params = json!({
+        "offset": offset,
+        "timeout": POLL_TIMEOUT_SECS,
+        "allowed_updates": ["message"],
+      });
+      let updates: Vec<Update> = match self.call("getUpdates", &params).await {
+        Ok(updates) => updates,
+        Err(err) => {
+          log::warn!("get telegram updates failed: [{:?}]", err);
+          tokio::time::sleep(Duration::from_secs(POLL_RETRY_SECS)).await;
+          continue;
+        }
+      };
+      for update in updates.into_iter() {
+        offset = offset.max(update.update_id + 1);
+        let Some(msg) = update.message else {
+          continue;
+        };
+        if let Err(err) = self.handle_message(&ctx.database, msg).await {
+          log::warn!("handle telegram message failed: [{:?}]", err);
+        }
+      }
+    }
+  }
+}
+
+// the chat and user settings a notification is sent with
+fn lookup_target(db: &Database, uid: i64, id: i64) -> Result<(i64, Language, TimeZone)> {
+  let TelegramTarget {
+    chat_id,
+    language,
+    time_zone,
+  } = db
+    .get()?
+    .lookup_telegram_target(uid, id)?
+    .ok_or_else(|| Error::ActionExpired("chat not found".to_string()))?;
+  let language =
+    Language::from_str(&language).map_err(|err| Error::InternalServerError(Box::new(err)))?;
+  let time_zone =
+    TimeZone::from_str(&time_zone).map_err(|err| Error::InternalServerError(Box::new(err)))?;
+  Ok((chat_id, language, time_zone))
+}
+
+impl TelegramActionAgent {
+  async fn handle_message(&self, db: &Database, msg: BotMessage) -> Result<()> {
+    let mut words = msg.text.as_deref().unwrap_or_default().split_whitespace();
+    // `/start@<bot>` in groups
+    match words.next() {
+      Some(cmd) if cmd == "/start" || cmd.starts_with("/start@") => {}
+      _ => return Ok(()),
+    }
+    let chat = msg.chat;
+    let linked = match words.next() {
+      Some(code) => {
+        let mut conn = db.get()?;
+        let tx = conn.transaction()?;
+        let linked = tx.telegram_link(code, &chat, Utc::now())?;
+        tx.commit()?;
+        linked
+      }
+      None => None,
+    };
+    let Some((uid, id)) = linked else {
+      return self
+        .send_message(chat.id, &t!("telegram.invalid_code"))
+        .await;
+    };
+    log::info!("telegram chat [{}] linked to uid[{}]", chat.id, uid);
+    let (_, language, _) = lookup_target(db, uid, id)?;
+    self
+      .send_message(chat.id, &t!("telegram.linked", locale = language.locale()))
+      .await
+  }
+
+  async fn send_message(&self, chat_id: i64, text: &str) -> Result<()> {
+    let params = json!({
+      "chat_id": chat_id,
+      "text": truncate(text, MAX_TEXT_LEN),
+    });
+    let _: IgnoredAny = self.call("sendMessage", &params).await?;
+    Ok(())
+  }
+
+  #[cfg(feature = "renderer")]
+  async fn send_photo(&self, chat_id: i64, img_path: &Path, caption: &str) -> Result<()> {
+    let img = tokio::fs::read(img_path)
+      .await
+      .map_err(|err| Error::InternalServerError(Box::new(err)))?;
+    let form = Form::new()
+      .text("chat_id", chat_id.to_string())
+      .text("caption", truncate(caption, MAX_CAPTION_LEN))
+      .part(
+        "photo",
+        Part::bytes(img)
+          .file_name("card.jpg")
+          .mime_str("image/jpeg")?,
+      );
+    let request = self.client.post(self.url("sendPhoto")).multipart(form);
+    let _: IgnoredAny = self.send(request).await?;
+    Ok(())
+  }
+
+  async fn call<T: DeserializeOwned>(&self, method: &str, params: &Value) -> Result<T> {
+    self
+      .send(self.client.post(self.url(method)).json(params))
+      .await
+  }
+
+  async fn send<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T> {
+    let resp: BotResponse<T> = request
+      .send()
+      .await
+      .map_err(without_url)?
+      .json()
+      .await
+      .map_err(without_url)?;
+    if resp.ok {
+      return resp
+        .result
+        .ok_or_else(|| Error::InternalServerError("telegram response without result".into()));
+    }
+    let description = resp.description.unwrap_or_default();
+    let migrated = resp.parameters.and_then(|e| e.migrate_to_chat_id).is_some();
+    Err(classify(resp.error_code, description, migrated))
+  }
+
+  fn url(&self, method: &str) -> String {
+    format!("{}/bot{}/{}", self.api_url, self.token, method)
+  }
+}
+
+// chats the bot can no longer post to expire the action, messages refused for
+// good are dropped, and the rest, e.g. hitting the flood limits, are retried
+fn classify(code: Option<i64>, description: String, migrated: bool) -> Error {
+  match code {
+    // blocked by the user, or removed from the group
+    Some(403) => Error::ActionExpired(description),
+    // a group upgraded to a supergroup is a chat of its own, to be linked again
+    Some(400) if migrated || description.contains("chat not found") => {
+      Error::ActionExpired(description)
+    }
+    Some(400) => Error::DeliveryRejected(description),
+    _ => {
+      Error::InternalServerError(format!("telegram error [{:?}]: [{}]", code, description).into())
+    }
+  }
+}
+
+#[derive(Deserialize)]
+struct BotResponse<T> {
+  ok: bool,
+  result: Option<T>,
+  error_code: Option<i64>,
+  description: Option<String>,
+  parameters: Option<ResponseParameters>,
+}
+
+#[derive(Deserialize)]
+struct ResponseParameters {
+  migrate_to_chat_id: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct Update {
+  update_id: i64,
+  message: Option<BotMessage>,
+}
+
+#[derive(Deserialize)]
+struct BotMessage {
+  chat: Chat,
+  text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Chat {
+  id: i64,
+  #[serde(rename = "type")]
+  kind: String,
+  // of groups
+  title: Option<String>,
+  // of private chats
+  first_name: Option<String>,
+  username: Option<String>,
+}
+
+trait TelegramLinkChat {
+  // binds the chat to the user who got the code, returns the user and the
+  // action created if the code is valid
+  fn telegram_link(
+    &self,
+    code: &str,
+    chat: &Chat,
+    now: DateTime<Utc>,
+  ) -> Result<Option<(i64, i64)>>;
+}
+
+impl TelegramLinkChat for Transaction<'_> {
+  fn telegram_link(
+    &self,
+    code: &str,
+    chat: &Chat,
+    now: DateTime<Utc>,
+  ) -> Result<Option<(i64, i64)>> {
+    let Some(uid) = self.take_telegram_link(code, now)? else {
+      return Ok(None);
+    };
+    // a chat linked again starts over
+    if let Some(id) = self.lookup_telegram_action(uid, chat.id)? {
+      self.delete_action(uid, id)?;
+    }
+    let id = self.create_action(uid, "telegram")?;
+    self.create_telegram_ext_info(id, uid, chat)?;
+    Ok(Some((uid, id)))
+  }
+}
+
+// the chat and user settings a notification is sent with
+struct TelegramTarget {
+  chat_id: i64,
+  language: String,
+  time_zone: String,
+}
+
+// storage of `telegram_links` and `telegram_ext_info`, for each database backend
+trait TelegramStore {
+  fn create_telegram_link(&self, code: &str, uid: i64, expire_time: DateTime<Utc>) -> Result<()>;
+  // removes the code along with the expired ones, returns the user of the code
+  // if not expired
+  fn take_telegram_link(&self, code: &str, now: DateTime<Utc>) -> Result<Option<i64>>;
+  fn lookup_telegram_action(&self, uid: i64, chat_id: i64) -> Result<Option<i64>>;
+  fn lookup_telegram_ext_info(&self, id: i64) -> Result<TelegramExtInfo>;
+  fn lookup_telegram_target(&self, uid: i64, id: i64) -> Result<Option<TelegramTarget>>;
+  fn create_telegram_ext_info(&self, id: i64, uid: i64, chat: &Chat) -> Result<()>;
+}
+
+impl TelegramStore for DatabaseConnection {
+  fn create_telegram_link(&self, code: &str, uid: i64, expire_time: DateTime<Utc>) -> Result<()> {
+    dispatch!(self, conn => conn.create_telegram_link(code, uid, expire_time))
+  }
+
+  fn take_telegram_link(&self, code: &str, now: DateTime<Utc>) -> Result<Option<i64>> {
+    dispatch!(self, conn => conn.take_telegram_link(code, now))
+  }
+
+  fn lookup_telegram_action(&self, uid: i64, chat_id: i64) -> Result<Option<i64>> {
+    dispatch!(self, conn => conn.lookup_telegram_action(uid, chat_id))
+  }
+
+  fn lookup_telegram_ext_info(&self, id: i64) -> Result<TelegramExtInfo> {
+    dispatch!(self, conn => conn.lookup_telegram_ext_info(id))
+  }
+
+  fn lookup_telegram_target(&self, uid: i64, id: i64) -> Result<Option<TelegramTarget>> {
+    dispatch!(self, conn => conn.lookup_telegram_target(uid, id))
+  }
+
+  fn create_telegram_ext_info(&self, id: i64, uid: i64, chat: &Chat) -> Result<()> {
+    dispatch!(self, conn => conn.create_telegram_ext_info(id, uid, chat))
+  }
+}
+
+impl TelegramStore for Connection {
+  fn create_telegram_link(&self, code: &str, uid: i64, expire_time: DateTime<Utc>) -> Result<()> {
+    let mut stmt = self.prepare_cached(
+      "
+      INSERT INTO telegram_links ( code, uid, expire_time )
+      VALUES ( ?1, ?2, ?3 )
+      ",
+    )?;
+    stmt.execute((&code, &uid, &expire_time.timestamp()))?;
+    Ok(())
+  }
+
+  fn take_telegram_link(&self, code: &str, now: DateTime<Utc>) -> Result<Option<i64>> {
+    let now = now.timestamp();
+    let mut stmt = self.prepare_cached(
+      "
+      SELECT uid
+      FROM telegram_links
+      WHERE code = ?1 AND expire_time > ?2
+      ",
+    )?;
+    let uid = stmt.query_row((&code, &now), |row| row.get(0)).optional()?;
+    let mut stmt = self.prepare_cached(
+      "
+      DELETE FROM telegram_links
+      WHERE code = ?1 OR expire_time <= ?2
+      ",
+    )?;
+    stmt.execute((&code, &now))?;
+    Ok(uid)
+  }
+
+  fn lookup_telegram_action(&self, uid: i64, chat_id: i64) -> Result<Option<i64>> {
+    let mut stmt = self.prepare_cached(
+      "
+      SELECT id
+      FROM telegram_ext_info
+      WHERE uid = ?1 AND chat_id = ?2
+      ",
+    )?;
+    let id = stmt
+      .query_row((&uid, &chat_id), |row| row.get(0))
+      .optional()?;
+    Ok(id)
+  }
+
+  fn lookup_telegram_ext_info(&self, id: i64) -> Result<TelegramExtInfo> {
+    let mut stmt = self.prepare_cached(
+      "
+      SELECT chat_id, chat_type, title, username
+      FROM telegram_ext_info
+      WHERE id = ?1
+      ",
+    )?;
+    let info = stmt.query_row((&id,), |row| {
+      Ok(TelegramExtInfo {
+        chat_id: row.get(0)?,
+        chat_type: row.get(1)?,
+        title: row.get(2)?,
+        username: row.get(3)?,
+      })
+    })?;
+    Ok(info)
+  }
+
+  fn lookup_telegram_target(&self, uid: i64, id: i64) -> Result<Option<TelegramTarget>> {
+    let mut stmt = self.prepare_cached(
+      "
+      SELECT chat_id, language, time_zone
+      FROM telegram_ext_info
+        INNER JOIN users ON users.id = uid
+      WHERE uid = ?1 AND telegram_ext_info.id = ?2
+      ",
+    )?;
+    let target = stmt
+      .query_row((&uid, &id), |row| {
+        Ok(TelegramTarget {
+          chat_id: row.get(0)?,
+          language: row.get(1)?,
+          time_zone: row.get(2)?,
+        })
+      })
+      .optional()?;
+    Ok(target)
+  }
+
+  fn create_telegram_ext_info(&self, id: i64, uid: i64, chat: &Chat) -> Result<()> {
+    let mut stmt = self.prepare_cached(
+      "
+      INSERT INTO telegram_ext_info ( id, uid, chat_id, chat_type, title, username )
+      VALUES ( ?1, ?2, ?3, ?4, ?5, ?6 )
+      ",
+    )?;
+    stmt.execute((
+      &id,
+      &uid,
+      &chat.id,
+      &chat.kind,
+      chat.title.as_ref().or(chat.first_name.as_ref()),
+      &chat.username,
+    ))?;
+    Ok(())
+  }
+}
+
+#[cfg(feature = "postgres")]
+impl TelegramStore for PgConnection {
+  fn create_telegram_link(&self, code: &str, uid: i64, expire_time: DateTime<Utc>) -> Result<()> {
+    self.execute(
+      "
+      INSERT INTO telegram_links ( code, uid, expire_time )
+      VALUES ( $1, $2, $3 )
+      ",
+      &[&code, &uid, &expire_time.timestamp()],
+    )?;
+    Ok(())
+  }
+
+  fn take_telegram_link(&self, code: &str, now: DateTime<Utc>) -> Result<Option<i64>> {
+    let now = now.timestamp();
+    let row = self.query_opt(
+      "
+      DELETE FROM telegram_links
+      WHERE code = $1 AND expire_time > $2
+      RETURNING uid
+      ",
+      &[&code, &now],
+    )?;
+    self.execute(
+      "
+      DELETE FROM telegram_links
+      WHERE expire_time <= $1
+      ",
+      &[&now],
+    )?;
+    match row {
+      Some(row) => Ok(Some(row.try_get(0)?)),
+      None => Ok(None),
+    }
+  }
+
+  fn lookup_telegram_action(&self, uid: i64, chat_id: i64) -> Result<Option<i64>> {
+    let row = self.query_opt(
+      "
+      SELECT id
+      FROM telegram_ext_info
+      WHERE uid = $1 AND chat_id = $2
+      ",
+      &[&uid, &chat_id],
+    )?;
+    match row {
+      Some(row) => Ok(Some(row.try_get(0)?)),
+      None => Ok(None),
+    }
+  }
+
+  fn lookup_telegram_ext_info(&self, id: i64) -> Result<TelegramExtInfo> {
+    let row = self.query_row(
+      "
+      SELECT chat_id, chat_type, title, username
+      FROM telegram_ext_info
+      WHERE id = $1
+      ",
+      &[&id],
+    )?;
+    Ok(TelegramExtInfo {
+      chat_id: row.try_get(0)?,
+      chat_type: row.try_get(1)?,
+      title: row.try_get(2)?,
+      username: row.try_get(3)?,
+    })
+  }
+
+  fn lookup_telegram_target(&self, uid: i64, id: i64) -> Result<Option<TelegramTarget>> {
+    let row = self.query_opt(
+      "
+      SELECT chat_id, language, time_zone
+      FROM telegram_ext_info
+        INNER JOIN users ON users.id = uid
+      WHERE uid = $1 AND telegram_ext_info.id = $2
+      ",
+      &[&uid, &id],
+    )?;
+    let Some(row) = row else {
+      return Ok(None);
+    };
+    Ok(Some(TelegramTarget {
+      chat_id: row.try_get(0)?,
+      language: row.try_get(1)?,
+      time_zone: row.try_get(2)?,
+    }))
+  }
+
+  fn create_telegram_ext_info(&self, id: i64, uid: i64, chat: &Chat) -> Result<()> {
+    self.execute(
+      "
+      INSERT INTO telegram_ext_info ( id, uid, chat_id, chat_type, title, username )
+      VALUES ( $1, $2, $3, $4, $5, $6 )
+      ",
+      &[
+        &id,
+        &uid,
+        &chat.id,
+        &chat.kind,
+        &chat.title.as_ref().or(chat.first_name.as_ref()),
+        &chat.username,
+      ],
+    )?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{
+    action::{ActionAgentMap, ActionManager},
+    database::{
+      action::ListAction,
+      delivery::{ListDelivery, ListDeliveryRequest},
+      outbox::{ClaimOutbox, ClaimOutboxRequest, CreateOutbox, CreateOutboxRequest, ListOutbox},
+      to_json,
+      user::{CreateUser, CreateUserRequest, LookupUserId, LookupUserIdRequest},
+    },
+  };
+
+  use super::*;
+
+  fn create_user(conn: &DatabaseConnection) -> i64 {
+    let auth_agent = "mock_auth_agent";
+    let auth_uid = "mock_auth_uid";
+    let ok = conn
+      .create_user(CreateUserRequest {
+        auth_agent,
+        auth_uid,
+        email: None,
+        name: None,
+        picture: None,
+        language: None,
+        time_zone: None,
+        availability: None,
+      })
+      .unwrap();
+    assert!(ok);
+    conn
+      .lookup_user_id(LookupUserIdRequest {
+        auth_agent,
+        auth_uid,
+      })
+      .unwrap()
+  }
+
+  #[test]
+  fn test_link() {
+    let db = Database::new_for_test().unwrap();
+    let mut conn = db.get().unwrap();
+    let uid = create_user(&conn);
+
+    let now = Utc::now();
+    let expire_time = now + chrono::Duration::minutes(LINK_EXPIRE_MINS);
+    conn.create_telegram_link("code", uid, expire_time).unwrap();
+    conn.create_telegram_link("stale", uid, now).unwrap();
+    let chat = Chat {
+      id: 42,
+      kind: "private".into(),
+      title: None,
+      first_name: Some("mock_name".into()),
+      username: None,
+    };
+    let link = |conn: &mut DatabaseConnection, code| {
+      let tx = conn.transaction().unwrap();
+      let linked = tx.telegram_link(code, &chat, now).unwrap();
+      tx.commit().unwrap();
+      linked
+    };
+    assert_eq!(link(&mut conn, "stale"), None);
+    let (linked_uid, id) = link(&mut conn, "code").unwrap();
+    assert_eq!(linked_uid, uid);
+    // used once
+    assert_eq!(link(&mut conn, "code"), None);
+
+    let info = conn.lookup_telegram_ext_info(id).unwrap();
+    assert_eq!(info.chat_id, 42);
+    assert_eq!(info.title.as_deref(), Some("mock_name"));
+    let target = conn.lookup_telegram_target(uid, id).unwrap().unwrap();
+    assert_eq!(target.chat_id, 42);
+    assert!(conn.lookup_telegram_target(uid + 1, id).unwrap().is_none());
+
+    // linking the chat again replaces the action
+    conn
+      .create_telegram_link("again", uid, expire_time)
+      .unwrap();
+    let (_, id_again) = link(&mut conn, "again").unwrap();
+    assert_ne!(id_again, id);
+    let li = conn.list_action(uid).unwrap();
+    assert_eq!(li.len(), 1);
+    assert_eq!(li[0].id, id_again);
+    assert_eq!(li[0].agent, "telegram");
+  }
+
+  #[tokio::test]
+  async fn test_error_without_token() {
+    // a file shared by every pooled connection, unlike in-memory ones
+    let path = std::env::temp_dir().join(format!(
+      "splatquery-test-telegram-{}.db",
+      std::process::id()
+    ));
+    let db = Database::new_from_file(&path).unwrap();
+    let mut conn = db.get().unwrap();
+    let uid = create_user(&conn);
+    let now = Utc::now();
+    conn
+      .create_telegram_link("code", uid, now + chrono::Duration::minutes(1))
+      .unwrap();
+    let chat = Chat {
+      id: 42,
+      kind: "private".into(),
+      title: None,
+      first_name: None,
+      username: None,
+    };
+    let tx = conn.transaction().unwrap();
+    let (_, id) = tx.telegram_link("code", &chat, now).unwrap().unwrap();
+    tx.commit().unwrap();
+
+    // a port nothing listens on
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+      .unwrap()
+      .local_addr()
+      .unwrap();
+    let token = "mock-secret-token";
+    let agent = TelegramActionAgentConfig {
+      bot_token: token.into(),
+      api_url: format!("http://{}", addr),
+      bot_username: None,
+      poll_updates: false,
+    }
+    .collect()
+    .unwrap();
+    let ctx = ActionContext::new_for_test(db.clone());
+    let mut agents = ActionAgentMap::new();
+    agents.insert("telegram", Arc::new(agent));
+    let actions = ActionManager::new(ctx, agents);
+
+    conn
+      .create_outbox(CreateOutboxRequest {
+        uid,
+        id,
+        message: &to_json(&Message::Digest(vec![])).unwrap(),
+        marks: "[]",
+      })
+      .unwrap();
+    let e = conn
+      .claim_outbox(ClaimOutboxRequest {
+        now,
+        lease_until: now + chrono::Duration::minutes(1),
+        limit: 1,
+      })
+      .unwrap()
+      .pop()
+      .unwrap();
+    actions.clone().deliver(e).await;
+
+    let li = conn.list_outbox().unwrap();
+    let last_error = li[0].last_error.as_deref().unwrap();
+    assert!(last_error.starts_with("network error"));
+    assert!(!last_error.contains(token));
+    let li = conn
+      .list_delivery(ListDeliveryRequest {
+        uid,
+        id: Some(id),
+        before: None,
+        limit: 1,
+      })
+      .unwrap();
+    // only the kind of the error is shown to the user
+    assert_eq!(li[0].error.as_deref(), Some("network error"));
+
+    drop(conn);
+    drop(actions);
+    drop(db);
+    for suffix in ["", "-wal", "-shm"] {
+      let _ = std::fs::remove_file(format!("{}{}", path.to_string_lossy(), suffix));
+    }
+  }
+}
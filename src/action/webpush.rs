@@ -2,23 +2,31 @@ use std::{fs::File, str::FromStr, sync::Arc};
 
 use async_trait::async_trait;
 use chrono::Utc;
-use r2d2_sqlite::rusqlite::{Connection, Transaction};
+use r2d2_sqlite::rusqlite::{Connection, OptionalExtension};
 use rust_i18n::t;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use web_push::{
   ContentEncoding, PartialVapidSignatureBuilder, SubscriptionInfo, SubscriptionKeys,
-  VapidSignatureBuilder, WebPushClient, WebPushMessageBuilder,
+  VapidSignatureBuilder, WebPushClient, WebPushError, WebPushMessageBuilder,
 };
 
 use crate::{
-  database::{action::CreateAction, Database, Language, TimeZone},
+  database::{
+    action::CreateAction, dispatch, Database, DatabaseConnection, Language, TimeZone, Transaction,
+  },
   renderer::RenderOptions,
   splatnet::Message,
   Error, Result,
 };
 
-use super::{ActionAgent, ActionContext};
+#[cfg(feature = "postgres")]
+use crate::database::postgres::PgConnection;
+
+use super::{
+  format::{body, headline, render_card},
+  ActionAgent, ActionContext,
+};
 
 #[derive(Serialize, Deserialize)]
 pub struct WebPushActionAgentConfig {
@@ -66,24 +74,10 @@ impl std::fmt::Debug for WebPushActionAgent {
 impl ActionAgent for WebPushActionAgent {
   fn get_ext_info(
     &self,
-    conn: &Connection,
+    conn: &DatabaseConnection,
     id: i64,
   ) -> Result<Option<Box<dyn erased_serde::Serialize>>> {
-    let mut stmt = conn.prepare_cached(
-      "
-      SELECT endpoint, browser, device, os
-      FROM webpush_ext_info
-      WHERE id = ?1
-      ",
-    )?;
-    let info = stmt.query_row((&id,), |row| {
-      Ok(WebPushExtInfo {
-        endpoint: row.get(0)?,
-        browser: row.get(1)?,
-        device: row.get(2)?,
-        os: row.get(3)?,
-      })
-    })?;
+    let info = conn.lookup_webpush_ext_info(id)?;
     Ok(Some(Box::new(info)))
   }
 
@@ -101,52 +95,58 @@ impl ActionAgent for WebPushActionAgent {
         time_zone,
         ..
       } = ua;
-      match msg.as_ref() {
-        Message::Pvp(item) => {
-          let locale = language.locale();
-          let mode = item.mode.name(locale);
-          let rule = item.rule.name(locale);
-          let stages: Vec<_> = item
-            .stages
-            .iter()
-            .map(|id| {
-              let stage_b64 = base64::encode(format!("VsStage-{}", id));
-              t!(
-                format!("splatnet.stages.{}.name", stage_b64).as_str(),
-                locale = locale
-              )
-            })
-            .collect();
-          let title = format!("{} - {}", rule, mode);
-          let body = format!("[{}] & [{}]", stages[0], stages[1]);
-          let tag = base64::encode(format!("pvp-[{}]-[{}]", item.mode, item.start_time));
-          let platform = match os {
-            Some(os) if os.starts_with("Windows") => "pc",
-            _ => "mobile",
-          };
-          let img_opts = RenderOptions {
-            platform,
-            language,
-            time_zone,
-          };
-          let img_path = ctx
-            .renderer
-            .render_pvp(item, &img_opts)
-            .map_err(|err| Error::InternalServerError(err))?;
-          Ok(json!({
-            "title": title,
-            "options": {
-              "body": body,
-              "image": format!("{}/{}", ctx.image_url, img_path),
-              // FIXME: don't hardcode domain
-              "icon": format!("https://splatquery.koishi.top/{}", item.mode.img_url()),
-              "silent": true,
-              "tag": tag,
-              "timestamp": item.start_time.timestamp_millis(),
-            }
-          }))
-        }
+      let locale = language.locale();
+      let platform = match os {
+        Some(os) if os.starts_with("Windows") => "pc",
+        _ => "mobile",
+      };
+      let img_opts = RenderOptions {
+        platform,
+        language,
+        time_zone,
+      };
+      let img_path = render_card(&ctx, msg.as_ref(), &img_opts)?;
+      // FIXME: don't hardcode domain
+      let (tag, icon) = match msg.as_ref() {
+        Message::Pvp(item) => (
+          format!("pvp-[{}]-[{}]", item.mode, item.start_time),
+          format!("https://splatquery.koishi.top/{}", item.mode.img_url()),
+        ),
+        Message::Coop(item) => (
+          format!("coop-[{}]-[{}]", item.mode, item.start_time),
+          format!("https://splatquery.koishi.top/{}", item.mode.img_url()),
+        ),
+        Message::Gear(item) => (
+          format!("gear-[{}]", item.id),
+          "https://splatquery.koishi.top/logo.svg".into(),
+        ),
+        Message::Event(item) => (
+          format!("event-[{}]", item.id),
+          "https://splatquery.koishi.top/img/mode/event.svg".into(),
+        ),
+        Message::Fest(item) => (
+          format!("fest-[{}]-[{}]", item.id, item.state),
+          "https://splatquery.koishi.top/img/mode/fest.svg".into(),
+        ),
+        Message::Digest(_) => (
+          format!("digest-[{}]", Utc::now()),
+          "https://splatquery.koishi.top/logo.svg".into(),
+        ),
+      };
+      let mut options = json!({
+        "body": body(msg.as_ref(), locale, time_zone),
+        "icon": icon,
+        "silent": true,
+        "tag": base64::encode(tag),
+        "timestamp": msg.timestamp().timestamp_millis(),
+      });
+      if let Some(img_path) = img_path {
+        options["image"] = json!(format!("{}/{}", ctx.image_url, img_path));
       }
+      Ok(json!({
+        "title": headline(msg.as_ref(), locale),
+        "options": options,
+      }))
     };
     self.send(ctx.database.clone(), uid, id, msg).await
   }
@@ -155,7 +155,7 @@ impl ActionAgent for WebPushActionAgent {
     self
       .send(db, uid, id, |ua| {
         Ok(json!({
-          "title": "Test notification",
+          "title": t!("test_notification", locale = ua.language.locale()),
           "options": {
             "body": format!("Browser: [{:?}]\nDevice: [{:?}]\nOS: [{:?}]", ua.browser, ua.device, ua.os),
             "icon": "https://splatquery.koishi.top/logo.svg",
@@ -181,45 +181,17 @@ impl WebPushActionAgent {
   where
     F: FnOnce(UserAgent) -> Result<Value>,
   {
-    let (sub, browser, device, os, language, time_zone): (
-      _,
-      Option<String>,
-      Option<String>,
-      Option<String>,
-      String,
-      String,
-    ) = {
-      let conn = db.get()?;
-      let mut stmt = conn.prepare_cached(
-        "
-        SELECT endpoint, p256dh, auth, browser, device, os, language, time_zone
-        FROM webpush_ext_info
-          INNER JOIN users ON users.id = uid
-        WHERE uid = ?1 AND webpush_ext_info.id = ?2
-        ",
-      )?;
-      stmt
-        .query_row((&uid, &id), |row| {
-          Ok((
-            SubscriptionInfo {
-              endpoint: row.get(0)?,
-              keys: SubscriptionKeys {
-                p256dh: row.get(1)?,
-                auth: row.get(2)?,
-              },
-            },
-            row.get(3)?,
-            row.get(4)?,
-            row.get(5)?,
-            row.get(6)?,
-            row.get(7)?,
-          ))
-        })
-        .map_err(|err| match err {
-          r2d2_sqlite::rusqlite::Error::QueryReturnedNoRows => Error::Unauthorized,
-          _ => Error::SqliteError(err),
-        })?
-    };
+    let WebPushTarget {
+      sub,
+      browser,
+      device,
+      os,
+      language,
+      time_zone,
+    } = db
+      .get()?
+      .lookup_webpush_target(uid, id)?
+      .ok_or_else(|| Error::ActionExpired("subscription not found".to_string()))?;
     let language =
       Language::from_str(&language).map_err(|err| Error::InternalServerError(Box::new(err)))?;
     let time_zone =
@@ -240,26 +212,41 @@ impl WebPushActionAgent {
       .build()
       .map_err(|err| Error::InternalServerError(Box::new(err)))?;
 
-    let mut builder =
-      WebPushMessageBuilder::new(&sub).map_err(|err| Error::InternalServerError(Box::new(err)))?;
+    let mut builder = WebPushMessageBuilder::new(&sub).map_err(classify)?;
     builder.set_payload(ContentEncoding::Aes128Gcm, &payload);
     builder.set_vapid_signature(vapid);
 
-    let message = builder
-      .build()
-      .map_err(|err| Error::InternalServerError(Box::new(err)))?;
+    let message = builder.build().map_err(classify)?;
 
-    self
-      .client
-      .send(message)
-      .await
-      .map_err(|err| Error::InternalServerError(Box::new(err)))?;
+    self.client.send(message).await.map_err(classify)?;
 
     log::debug!("sent [{}] bytes -> [{}]", payload.len(), sub.endpoint);
     Ok(())
   }
 }
 
+// subscriptions the push service no longer accepts expire the action, messages
+// it refuses for good are dropped, and the rest are retried
+fn classify(err: WebPushError) -> Error {
+  match err {
+    // 404 and 410, once the browser unsubscribed or the app was uninstalled
+    WebPushError::EndpointNotFound | WebPushError::EndpointNotValid => {
+      Error::ActionExpired(format!("subscription gone: {}", err))
+    }
+    WebPushError::InvalidUri
+    | WebPushError::MissingCryptoKeys
+    | WebPushError::InvalidCryptoKeys => {
+      Error::ActionExpired(format!("subscription malformed: {}", err))
+    }
+    // refused messages, where 401 and 403 may as well be down to our vapid key,
+    // so the subscription is kept
+    WebPushError::Unauthorized | WebPushError::BadRequest(_) | WebPushError::PayloadTooLarge => {
+      Error::DeliveryRejected(err.to_string())
+    }
+    _ => Error::InternalServerError(Box::new(err)),
+  }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct WebPushSubscribeRequest {
   #[serde(flatten)]
@@ -273,9 +260,111 @@ pub trait WebPushSubscribe {
   fn webpush_subscribe(&self, uid: i64, request: WebPushSubscribeRequest) -> Result<i64>;
 }
 
-impl<'a> WebPushSubscribe for Transaction<'a> {
+impl WebPushSubscribe for Transaction<'_> {
   fn webpush_subscribe(&self, uid: i64, request: WebPushSubscribeRequest) -> Result<i64> {
     let id = self.create_action(uid, "webpush")?;
+    self.create_webpush_ext_info(id, uid, &request)?;
+    Ok(id)
+  }
+}
+
+// the subscription and user settings a notification is sent with
+struct WebPushTarget {
+  sub: SubscriptionInfo,
+  browser: Option<String>,
+  device: Option<String>,
+  os: Option<String>,
+  language: String,
+  time_zone: String,
+}
+
+// storage of `webpush_ext_info`, for each database backend
+trait WebPushStore {
+  fn lookup_webpush_ext_info(&self, id: i64) -> Result<WebPushExtInfo>;
+  fn lookup_webpush_target(&self, uid: i64, id: i64) -> Result<Option<WebPushTarget>>;
+  fn create_webpush_ext_info(
+    &self,
+    id: i64,
+    uid: i64,
+    request: &WebPushSubscribeRequest,
+  ) -> Result<()>;
+}
+
+impl WebPushStore for DatabaseConnection {
+  fn lookup_webpush_ext_info(&self, id: i64) -> Result<WebPushExtInfo> {
+    dispatch!(self, conn => conn.lookup_webpush_ext_info(id))
+  }
+
+  fn lookup_webpush_target(&self, uid: i64, id: i64) -> Result<Option<WebPushTarget>> {
+    dispatch!(self, conn => conn.lookup_webpush_target(uid, id))
+  }
+
+  fn create_webpush_ext_info(
+    &self,
+    id: i64,
+    uid: i64,
+    request: &WebPushSubscribeRequest,
+  ) -> Result<()> {
+    dispatch!(self, conn => conn.create_webpush_ext_info(id, uid, request))
+  }
+}
+
+impl WebPushStore for Connection {
+  fn lookup_webpush_ext_info(&self, id: i64) -> Result<WebPushExtInfo> {
+    let mut stmt = self.prepare_cached(
+      "
+      SELECT endpoint, browser, device, os
+      FROM webpush_ext_info
+      WHERE id = ?1
+      ",
+    )?;
+    let info = stmt.query_row((&id,), |row| {
+      Ok(WebPushExtInfo {
+        endpoint: row.get(0)?,
+        browser: row.get(1)?,
+        device: row.get(2)?,
+        os: row.get(3)?,
+      })
+    })?;
+    Ok(info)
+  }
+
+  fn lookup_webpush_target(&self, uid: i64, id: i64) -> Result<Option<WebPushTarget>> {
+    let mut stmt = self.prepare_cached(
+      "
+      SELECT endpoint, p256dh, auth, browser, device, os, language, time_zone
+      FROM webpush_ext_info
+        INNER JOIN users ON users.id = uid
+      WHERE uid = ?1 AND webpush_ext_info.id = ?2
+      ",
+    )?;
+    let target = stmt
+      .query_row((&uid, &id), |row| {
+        Ok(WebPushTarget {
+          sub: SubscriptionInfo {
+            endpoint: row.get(0)?,
+            keys: SubscriptionKeys {
+              p256dh: row.get(1)?,
+              auth: row.get(2)?,
+            },
+          },
+          browser: row.get(3)?,
+          device: row.get(4)?,
+          os: row.get(5)?,
+          language: row.get(6)?,
+          time_zone: row.get(7)?,
+        })
+      })
+      .optional()?;
+    Ok(target)
+  }
+
+  fn create_webpush_ext_info(
+    &self,
+    id: i64,
+    uid: i64,
+    request: &WebPushSubscribeRequest,
+  ) -> Result<()> {
     let WebPushSubscribeRequest {
       sub,
       browser,
@@ -294,16 +383,94 @@ impl<'a> WebPushSubscribe for Transaction<'a> {
       &sub.endpoint,
       &sub.keys.p256dh,
       &sub.keys.auth,
-      &browser,
-      &device,
-      &os,
+      browser,
+      device,
+      os,
     ))?;
     if n == 0 {
-      Err(Error::SqliteError(
-        r2d2_sqlite::rusqlite::Error::QueryReturnedNoRows,
-      ))
+      Err(Error::NotFound)
     } else {
-      Ok(id)
+      Ok(())
     }
   }
 }
+
+#[cfg(feature = "postgres")]
+impl WebPushStore for PgConnection {
+  fn lookup_webpush_ext_info(&self, id: i64) -> Result<WebPushExtInfo> {
+    let row = self.query_row(
+      "
+      SELECT endpoint, browser, device, os
+      FROM webpush_ext_info
+      WHERE id = $1
+      ",
+      &[&id],
+    )?;
+    Ok(WebPushExtInfo {
+      endpoint: row.try_get(0)?,
+      browser: row.try_get(1)?,
+      device: row.try_get(2)?,
+      os: row.try_get(3)?,
+    })
+  }
+
+  fn lookup_webpush_target(&self, uid: i64, id: i64) -> Result<Option<WebPushTarget>> {
+    let row = self.query_opt(
+      "
+      SELECT endpoint, p256dh, auth, browser, device, os, language, time_zone
+      FROM webpush_ext_info
+        INNER JOIN users ON users.id = uid
+      WHERE uid = $1 AND webpush_ext_info.id = $2
+      ",
+      &[&uid, &id],
+    )?;
+    let Some(row) = row else {
+      return Ok(None);
+    };
+    Ok(Some(WebPushTarget {
+      sub: SubscriptionInfo {
+        endpoint: row.try_get(0)?,
+        keys: SubscriptionKeys {
+          p256dh: row.try_get(1)?,
+          auth: row.try_get(2)?,
+        },
+      },
+      browser: row.try_get(3)?,
+      device: row.try_get(4)?,
+      os: row.try_get(5)?,
+      language: row.try_get(6)?,
+      time_zone: row.try_get(7)?,
+    }))
+  }
+
+  fn create_webpush_ext_info(
+    &self,
+    id: i64,
+    uid: i64,
+    request: &WebPushSubscribeRequest,
+  ) -> Result<()> {
+    let WebPushSubscribeRequest {
+      sub,
+      browser,
+      device,
+      os,
+    } = request;
+    self.execute(
+      "
+      INSERT INTO webpush_ext_info ( id, uid, endpoint, p256dh, auth, browser, device, os )
+      VALUES ( $1, $2, $3, $4, $5, $6, $7, $8 )
+      ",
+      &[
+        &id,
+        &uid,
+        &sub.endpoint,
+        &sub.keys.p256dh,
+        &sub.keys.auth,
+        browser,
+        device,
+        os,
+      ],
+    )?;
+    Ok(())
+  }
+}
@@ -0,0 +1,36 @@
+use axum::{extract::State, response::IntoResponse, Json};
+
+use crate::{
+  action::discord::{DiscordSubscribe, DiscordSubscribeRequest},
+  api::{
+    state::{AppState, InnerAppState},
+    User,
+  },
+  database::user::{LookupUserId, LookupUserIdRequest},
+  Error, Result,
+};
+
+pub async fn subscribe(
+  User(user): User,
+  State(state): State<AppState>,
+  Json(request): Json<DiscordSubscribeRequest>,
+) -> Result<impl IntoResponse> {
+  let InnerAppState { db, .. } = state.0.as_ref();
+
+  let mut conn = db.get()?;
+
+  // find the specified user
+  let uid = conn.lookup_user_id(LookupUserIdRequest {
+    auth_agent: &user.agent,
+    auth_uid: &user.id,
+  })?;
+
+  let tx = conn.transaction()?;
+  let resp = tx.discord_subscribe(uid, request)?;
+  tx.commit()?;
+
+  // the id of the action, or the code to send to the bot for direct messages
+  let resp =
+    serde_json::to_string(&resp).map_err(|err| Error::InternalServerError(Box::new(err)))?;
+  Ok(resp)
+}
@@ -1,7 +1,9 @@
 use crate::{
   database::{
-    action::{DeleteAction, ListAction, ToggleAction},
+    action::{DeleteAction, ListAction, ToggleAction, UpdateActionDigest},
+    delivery::{ListDelivery, ListDeliveryRequest, ListDeliveryResponse},
     user::{LookupUserId, LookupUserIdRequest},
+    DigestMode,
   },
   Error, Result,
 };
@@ -16,6 +18,8 @@ use super::{
   User,
 };
 
+#[cfg(feature = "discord")]
+pub mod discord;
 #[cfg(feature = "webpush")]
 pub mod webpush;
 
@@ -24,6 +28,10 @@ pub struct ListResponse {
   id: i64,
   agent: String,
   active: bool,
+  digest: Option<DigestMode>,
+  last_delivered: Option<i64>,
+  expired_time: Option<i64>,
+  expired_reason: Option<String>,
   ext_info: Option<Box<dyn erased_serde::Serialize>>,
 }
 
@@ -42,6 +50,10 @@ pub async fn list(User(user): User, State(state): State<AppState>) -> Result<imp
           id: e.id,
           agent: e.agent.clone(),
           active: e.active,
+          digest: e.digest,
+          last_delivered: e.last_delivered,
+          expired_time: e.expired_time,
+          expired_reason: e.expired_reason,
           ext_info,
         }),
         Err(err) => log::warn!(
@@ -57,6 +69,57 @@ pub async fn list(User(user): User, State(state): State<AppState>) -> Result<imp
   Ok(resp)
 }
 
+// deliveries on a page of history if not specified, and at most
+const HISTORY_PAGE_SIZE: u32 = 20;
+const HISTORY_MAX_PAGE_SIZE: u32 = 100;
+
+#[derive(Deserialize)]
+pub struct HistoryRequest {
+  // all actions if not specified
+  pub id: Option<i64>,
+  // `next` of the previous page
+  pub before: Option<i64>,
+  pub limit: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct HistoryResponse {
+  items: Vec<ListDeliveryResponse>,
+  // none on the last page
+  next: Option<i64>,
+}
+
+pub async fn history(
+  User(user): User,
+  State(state): State<AppState>,
+  Query(request): Query<HistoryRequest>,
+) -> Result<impl IntoResponse> {
+  let InnerAppState { db, .. } = state.0.as_ref();
+  let HistoryRequest { id, before, limit } = request;
+  let limit = limit.unwrap_or(HISTORY_PAGE_SIZE);
+  if limit == 0 || limit > HISTORY_MAX_PAGE_SIZE {
+    return Err(Error::InvalidParameter("limit", limit.to_string()));
+  }
+  let conn = db.get()?;
+  let uid = conn.lookup_user_id(LookupUserIdRequest {
+    auth_agent: &user.agent,
+    auth_uid: &user.id,
+  })?;
+  let li = conn.list_delivery(ListDeliveryRequest {
+    uid,
+    id,
+    before,
+    limit,
+  })?;
+  let next = match li.last() {
+    Some(e) if li.len() == limit as usize => Some(e.did),
+    _ => None,
+  };
+  let resp = serde_json::to_string(&HistoryResponse { items: li, next })
+    .map_err(|err| Error::InternalServerError(Box::new(err)))?;
+  Ok(resp)
+}
+
 #[derive(Deserialize)]
 pub struct DeleteActionRequest {
   pub id: i64,
@@ -98,6 +161,28 @@ pub async fn toggle(
   Ok(())
 }
 
+#[derive(Deserialize)]
+pub struct DigestActionRequest {
+  // follow user settings if not specified
+  pub mode: Option<DigestMode>,
+}
+
+pub async fn digest(
+  User(user): User,
+  State(state): State<AppState>,
+  Path(agent): Path<String>,
+  Query(request): Query<DigestActionRequest>,
+) -> Result<impl IntoResponse> {
+  let InnerAppState { db, .. } = state.0.as_ref();
+  let conn = db.get()?;
+  let uid = conn.lookup_user_id(LookupUserIdRequest {
+    auth_agent: &user.agent,
+    auth_uid: &user.id,
+  })?;
+  conn.update_action_digest(uid, &agent, request.mode)?;
+  Ok(())
+}
+
 #[derive(Deserialize)]
 pub struct TestActionRequest {
   id: i64,
@@ -119,6 +204,30 @@ pub async fn test(
     auth_agent: &user.agent,
     auth_uid: &user.id,
   })?;
-  agent.clone().test(db.clone(), uid, request.id).await?;
-  Ok(())
+  let ret = agent.clone().test(db.clone(), uid, request.id).await;
+  if let Err(Error::ActionExpired(reason)) = &ret {
+    actions.expire_action(uid, request.id, reason);
+  }
+  ret
+}
+
+pub async fn link(
+  User(user): User,
+  State(state): State<AppState>,
+  Path(agent): Path<String>,
+) -> Result<impl IntoResponse> {
+  let InnerAppState { db, actions, .. } = state.0.as_ref();
+  let conn = db.get()?;
+  let agent = actions
+    .agents
+    .get(agent.as_str())
+    .ok_or_else(|| Error::InvalidParameter("link", agent))?;
+  let uid = conn.lookup_user_id(LookupUserIdRequest {
+    auth_agent: &user.agent,
+    auth_uid: &user.id,
+  })?;
+  let link = agent.link(&conn, uid)?;
+  let resp =
+    serde_json::to_string(&link).map_err(|err| Error::InternalServerError(Box::new(err)))?;
+  Ok(resp)
 }
@@ -6,6 +6,8 @@ use axum::{
   response::{AppendHeaders, IntoResponse},
   Json,
 };
+#[cfg(feature = "api-geoip2")]
+use chrono_tz::{America, Asia, Europe};
 use http::header::AUTHORIZATION;
 #[cfg(feature = "api-geoip2")]
 use maxminddb::geoip2::country::Country;
@@ -84,29 +86,29 @@ pub async fn oauth2(
           is_in_european_union: Some(true /* EU */),
           ..
         } => {
-          log::info!("[{}] -> [cet/enus]", addr.ip());
-          (Some(TimeZone::Cet), Some(Language::EnUs))
+          log::info!("[{}] -> [Europe/Paris/enus]", addr.ip());
+          (Some(TimeZone(Europe::Paris)), Some(Language::EnUs))
         }
         Country {
           geoname_id: Some(1861060 /* JP */),
           ..
         } => {
-          log::info!("[{}] -> [jst/enus]", addr.ip());
-          (Some(TimeZone::Jst), Some(Language::EnUs))
+          log::info!("[{}] -> [Asia/Tokyo/enus]", addr.ip());
+          (Some(TimeZone::JST), Some(Language::EnUs))
         }
         Country {
           geoname_id: Some(1814991 /* CHN */),
           ..
         } => {
-          log::info!("[{}] -> [cst/enus]", addr.ip());
-          (Some(TimeZone::Cst), Some(Language::EnUs))
+          log::info!("[{}] -> [Asia/Shanghai/enus]", addr.ip());
+          (Some(TimeZone(Asia::Shanghai)), Some(Language::EnUs))
         }
         Country {
           geoname_id: Some(6252001 /* US */) | Some(6251999 /* CA */),
           ..
         } => {
-          log::info!("[{}] -> [pdt/enus]", addr.ip());
-          (Some(TimeZone::Pt), Some(Language::EnUs))
+          log::info!("[{}] -> [America/Los_Angeles/enus]", addr.ip());
+          (Some(TimeZone(America::Los_Angeles)), Some(Language::EnUs))
         }
         _ => {
           log::info!(
@@ -133,7 +135,7 @@ pub async fn oauth2(
     picture: auth.picture.as_deref(),
     language,
     time_zone,
-    day_hrs: None,
+    availability: None,
   })?;
 
   if ok {
@@ -72,11 +72,7 @@ pub async fn list(
     auth_uid: &user.id,
   })?;
 
-  let mut li = Vec::new();
-
-  if qtype.is_none() || qtype.unwrap() == QueryType::Pvp {
-    li.append(&mut conn.list_query(ListQueryRequest { uid, qid })?);
-  }
+  let li = conn.list_query(ListQueryRequest { uid, qid, qtype })?;
 
   let resp = serde_json::to_string(&li).map_err(|err| Error::InternalServerError(Box::new(err)))?;
   Ok(resp)
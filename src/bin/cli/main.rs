@@ -1,13 +1,16 @@
 use std::{collections::HashMap, fs::File, io::BufReader};
 
+use chrono::{TimeZone, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use splatquery::{
   action::{config::ActionAgentsConfig, ActionContext, ActionManager},
   database::{
+    outbox::{ListOutbox, ResetOutbox, ResetOutboxRequest},
     query::{CreateQuery, CreateQueryRequest, QueryConfig},
+    spider::{DeleteSpiderCursor, DeleteSpiderCursorRequest, ListSpiderCursor},
     user::{CreateUser, CreateUserRequest, LookupUserId, LookupUserIdRequest},
-    Database,
+    Database, DatabaseConfig,
   },
   splatnet::{SplatNetAgent, SplatNetConfig},
   BoxError, Error,
@@ -24,6 +27,127 @@ pub struct Config {
   pub queries: Vec<QueryConfig>,
 }
 
+// <db> is the server config file, whose `database` section is used, a
+// postgres url, or a sqlite database file; opened without migrating
+fn open_database(db: Option<String>) -> Result<Database, BoxError> {
+  let db = db.ok_or("database required")?;
+  let config: DatabaseConfig = if db.ends_with(".json") {
+    let mut config: Value = serde_json::from_reader(BufReader::new(File::open(db)?))?;
+    serde_json::from_value(config["database"].take())?
+  } else if db.starts_with("postgres://") || db.starts_with("postgresql://") {
+    serde_json::from_value(serde_json::json!({ "backend": "postgres", "url": db }))?
+  } else {
+    DatabaseConfig {
+      path: db,
+      ..Default::default()
+    }
+  };
+  Ok(config.connect()?)
+}
+
+// for commands working on the latest schema, which leave migrations to
+// `cli migrate`
+fn open_migrated_database(db: Option<String>) -> Result<Database, BoxError> {
+  let db = open_database(db)?;
+  let status = db.migration_status()?;
+  if !status.pending.is_empty() {
+    return Err(
+      format!(
+        "database at version {} of {}, run `migrate <db> up` first",
+        status.version, status.latest
+      )
+      .into(),
+    );
+  }
+  Ok(db)
+}
+
+// cli cursor <db> [reset [<name>]]
+fn cursor(mut args: impl Iterator<Item = String>) -> Result<(), BoxError> {
+  let db = open_migrated_database(args.next())?;
+  let conn = db.get()?;
+  match args.next().as_deref() {
+    None | Some("list") => {
+      for e in conn.list_spider_cursor()?.into_iter() {
+        println!("{}\t{}\t{}", e.name, e.value, e.updated_time);
+      }
+    }
+    Some("reset") => {
+      let name = args.next();
+      let n = conn.delete_spider_cursor(DeleteSpiderCursorRequest {
+        name: name.as_deref(),
+      })?;
+      // the running server keeps its cursors in memory
+      println!("{} cursor(s) reset, restart the server to take effect", n);
+    }
+    Some(cmd) => return Err(format!("unknown cursor command: [{}]", cmd).into()),
+  }
+  Ok(())
+}
+
+// cli outbox <db> [list|retry [<id>]]
+fn outbox(mut args: impl Iterator<Item = String>) -> Result<(), BoxError> {
+  let db = open_migrated_database(args.next())?;
+  let conn = db.get()?;
+  match args.next().as_deref() {
+    None | Some("list") => {
+      for e in conn.list_outbox()?.into_iter() {
+        let next_attempt = match e.next_attempt {
+          Some(t) => Utc.timestamp_opt(t, 0).unwrap().to_string(),
+          None => "given up".into(),
+        };
+        println!(
+          "{}\t{}#{}\t{}\t{}\t{}\t{}",
+          e.oid,
+          e.uid,
+          e.id,
+          e.created_time,
+          e.attempts,
+          next_attempt,
+          e.last_error.unwrap_or_default()
+        );
+      }
+    }
+    Some("retry") => {
+      let oid = args.next().map(|e| e.parse()).transpose()?;
+      let n = conn.reset_outbox(ResetOutboxRequest {
+        oid,
+        now: Utc::now(),
+      })?;
+      println!("{} delivery(s) queued again", n);
+    }
+    Some(cmd) => return Err(format!("unknown outbox command: [{}]", cmd).into()),
+  }
+  Ok(())
+}
+
+// cli migrate <db> [status|dry-run|up]
+fn migration(mut args: impl Iterator<Item = String>) -> Result<(), BoxError> {
+  let db = open_database(args.next())?;
+  match args.next().as_deref() {
+    None | Some("status") => {
+      let status = db.migration_status()?;
+      println!("version {} of {}", status.version, status.latest);
+      for (version, name) in status.pending {
+        println!("pending\t{}\t{}", version, name);
+      }
+    }
+    Some("dry-run") => {
+      for (version, name) in db.migrate(true)? {
+        println!("ok\t{}\t{}", version, name);
+      }
+      println!("dry run, nothing applied");
+    }
+    Some("up") => {
+      for (version, name) in db.migrate(false)? {
+        println!("applied\t{}\t{}", version, name);
+      }
+    }
+    Some(cmd) => return Err(format!("unknown migrate command: [{}]", cmd).into()),
+  }
+  Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<(), BoxError> {
   std::env::set_var(
@@ -37,8 +161,19 @@ async fn main() -> Result<(), BoxError> {
   );
   env_logger::init();
 
+  let mut args = std::env::args().skip(1);
+  let path = args.next().unwrap();
+  if path == "cursor" {
+    return cursor(args);
+  }
+  if path == "outbox" {
+    return outbox(args);
+  }
+  if path == "migrate" {
+    return migration(args);
+  }
+
   // read config
-  let path = std::env::args().skip(1).next().unwrap();
   let file = File::open(path)?;
   let reader = BufReader::new(file);
   let config: Config = serde_json::from_reader(reader)?;
@@ -55,7 +190,7 @@ async fn main() -> Result<(), BoxError> {
   );
 
   // prepare splatnet agent
-  let splatnet = SplatNetAgent::new(actions, config.splatnet);
+  let splatnet = SplatNetAgent::new(actions.clone(), config.splatnet)?;
 
   // prepare user
   let mut conn = db.get()?;
@@ -69,7 +204,7 @@ async fn main() -> Result<(), BoxError> {
     picture: None,
     language: None,
     time_zone: None,
-    day_hrs: None,
+    availability: None,
   })?;
   assert!(ok);
   let uid = conn.lookup_user_id(LookupUserIdRequest {
@@ -99,9 +234,7 @@ async fn main() -> Result<(), BoxError> {
     tx.commit()?;
   }
 
-  splatnet
-    .watch()
-    .await
+  futures::try_join!(splatnet.watch(), actions.watch())
     .map_err(|err| Error::InternalServerError(err))?;
 
   Ok(())
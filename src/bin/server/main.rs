@@ -101,7 +101,13 @@ async fn main() -> Result<(), BoxError> {
   };
 
   // prepare splatnet agent
-  let splatnet = SplatNetAgent::new(actions.clone(), config.splatnet)
+  let splatnet = SplatNetAgent::new(actions.clone(), config.splatnet)?
+    .watch()
+    .map_err(|err| Error::InternalServerError(err));
+
+  // deliver queued notifications and deferred reminders, and receive agent updates
+  let reminders = actions
+    .clone()
     .watch()
     .map_err(|err| Error::InternalServerError(err));
 
@@ -128,8 +134,11 @@ async fn main() -> Result<(), BoxError> {
     .route("/query/delete", post(api::query::delete))
     // action apis
     .route("/action/:agent/toggle", post(api::action::toggle))
+    .route("/action/:agent/digest", post(api::action::digest))
     .route("/action/:agent/test", post(api::action::test))
+    .route("/action/:agent/link", post(api::action::link))
     .route("/action/list", get(api::action::list))
+    .route("/action/history", get(api::action::history))
     .route("/action/delete", post(api::action::delete))
     // auth apis
     .route("/auth/:agent", post(api::auth::oauth2));
@@ -140,6 +149,12 @@ async fn main() -> Result<(), BoxError> {
     post(api::action::webpush::subscribe),
   );
 
+  #[cfg(feature = "discord")]
+  let app = app.route(
+    "/action/discord/subscribe",
+    post(api::action::discord::subscribe),
+  );
+
   // add cors layer to the top
   let app = cors(app.with_state(state), &config.http.allow_origins)?;
 
@@ -154,7 +169,7 @@ async fn main() -> Result<(), BoxError> {
     .serve(app.into_make_service_with_connect_info::<SocketAddr>())
     .map_err(|err| Error::InternalServerError(Box::new(err)));
 
-  futures::try_join!(splatnet, server)?;
+  futures::try_join!(splatnet, reminders, server)?;
 
   Ok(())
 }
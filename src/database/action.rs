@@ -1,12 +1,24 @@
-use r2d2_sqlite::rusqlite::{Connection, Transaction};
+use std::str::FromStr;
 
-use crate::Result;
+use chrono::{DateTime, Utc};
+use r2d2_sqlite::rusqlite::Connection;
+
+use crate::{Error, Result};
+
+use super::{DatabaseConnection, DigestMode, TimeZone, Transaction};
 
 pub trait CreateAction {
   fn create_action(&self, uid: i64, agent: &str) -> Result<i64>;
 }
 
-impl<'a> CreateAction for Transaction<'a> {
+// the agent row and the action row are to be created together
+impl CreateAction for Transaction<'_> {
+  fn create_action(&self, uid: i64, agent: &str) -> Result<i64> {
+    dispatch!(&**self, conn => conn.create_action(uid, agent))
+  }
+}
+
+impl CreateAction for Connection {
   fn create_action(&self, uid: i64, agent: &str) -> Result<i64> {
     self
       .prepare_cached(
@@ -41,6 +53,12 @@ pub trait DeleteAction {
   fn delete_action(&self, uid: i64, id: i64) -> Result<()>;
 }
 
+impl DeleteAction for DatabaseConnection {
+  fn delete_action(&self, uid: i64, id: i64) -> Result<()> {
+    dispatch!(self, conn => conn.delete_action(uid, id))
+  }
+}
+
 impl DeleteAction for Connection {
   fn delete_action(&self, uid: i64, id: i64) -> Result<()> {
     self
@@ -55,10 +73,74 @@ impl DeleteAction for Connection {
   }
 }
 
+#[derive(Debug)]
+pub struct ExpireActionRequest<'a> {
+  pub uid: i64,
+  pub id: i64,
+  pub time: DateTime<Utc>,
+  pub reason: &'a str,
+}
+
+pub trait ExpireAction {
+  // stops delivering to an action whose target is gone for good, dropping
+  // what is queued for it; returns whether it was not expired yet
+  fn expire_action(&self, request: ExpireActionRequest) -> Result<bool>;
+}
+
+// the expiry and the queues are to be updated together
+impl ExpireAction for Transaction<'_> {
+  fn expire_action(&self, request: ExpireActionRequest) -> Result<bool> {
+    dispatch!(&**self, conn => conn.expire_action(request))
+  }
+}
+
+impl ExpireAction for Connection {
+  fn expire_action(&self, request: ExpireActionRequest) -> Result<bool> {
+    let ExpireActionRequest {
+      uid,
+      id,
+      time,
+      reason,
+    } = request;
+    let n = self
+      .prepare_cached(
+        "
+        UPDATE user_actions
+        SET expired_time = ?3, expired_reason = ?4
+        WHERE uid = ?1 AND id = ?2 AND expired_time IS NULL
+        ",
+      )?
+      .execute((&uid, &id, &time.timestamp(), &reason))?;
+    self
+      .prepare_cached(
+        "
+        DELETE FROM outbox
+        WHERE aid = ?1
+        ",
+      )?
+      .execute((&id,))?;
+    self
+      .prepare_cached(
+        "
+        DELETE FROM pending_notifications
+        WHERE aid = ?1
+        ",
+      )?
+      .execute((&id,))?;
+    Ok(n > 0)
+  }
+}
+
 pub trait ToggleAction {
   fn toggle_action(&self, uid: i64, agent: &str, active: bool) -> Result<()>;
 }
 
+impl ToggleAction for DatabaseConnection {
+  fn toggle_action(&self, uid: i64, agent: &str, active: bool) -> Result<()> {
+    dispatch!(self, conn => conn.toggle_action(uid, agent, active))
+  }
+}
+
 impl ToggleAction for Connection {
   fn toggle_action(&self, uid: i64, agent: &str, active: bool) -> Result<()> {
     self
@@ -74,21 +156,102 @@ impl ToggleAction for Connection {
   }
 }
 
+pub trait UpdateActionDigest {
+  // follow user settings if not specified
+  fn update_action_digest(&self, uid: i64, agent: &str, digest: Option<DigestMode>) -> Result<()>;
+}
+
+impl UpdateActionDigest for DatabaseConnection {
+  fn update_action_digest(&self, uid: i64, agent: &str, digest: Option<DigestMode>) -> Result<()> {
+    dispatch!(self, conn => conn.update_action_digest(uid, agent, digest))
+  }
+}
+
+impl UpdateActionDigest for Connection {
+  fn update_action_digest(&self, uid: i64, agent: &str, digest: Option<DigestMode>) -> Result<()> {
+    self
+      .prepare_cached(
+        "
+        UPDATE user_action_agents
+        SET digest = ?3
+        WHERE uid = ?1 AND act_agent = ?2
+        ",
+      )?
+      .execute((&uid, &agent, &digest.map(|e| e.to_string())))?;
+    Ok(())
+  }
+}
+
+pub struct LookupActionDigestResponse {
+  pub mode: DigestMode,
+  pub hour: u32,
+  pub time_zone: TimeZone,
+}
+
+pub trait LookupActionDigest {
+  fn lookup_action_digest(&self, id: i64) -> Result<LookupActionDigestResponse>;
+}
+
+impl LookupActionDigest for DatabaseConnection {
+  fn lookup_action_digest(&self, id: i64) -> Result<LookupActionDigestResponse> {
+    dispatch!(self, conn => conn.lookup_action_digest(id))
+  }
+}
+
+impl LookupActionDigest for Connection {
+  fn lookup_action_digest(&self, id: i64) -> Result<LookupActionDigestResponse> {
+    let mut stmt = self.prepare_cached(
+      "
+      SELECT COALESCE(user_action_agents.digest, users.digest), digest_hour, time_zone
+      FROM user_actions
+        INNER JOIN user_action_agents ON user_actions.aid = user_action_agents.id
+        INNER JOIN users ON user_actions.uid = users.id
+      WHERE user_actions.id = ?1
+      ",
+    )?;
+    let (mode, hour, time_zone): (String, u32, String) =
+      stmt.query_row((&id,), |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+    Ok(LookupActionDigestResponse {
+      mode: DigestMode::from_str(&mode).map_err(|err| Error::InternalServerError(Box::new(err)))?,
+      hour,
+      time_zone: TimeZone::from_str(&time_zone)
+        .map_err(|err| Error::InternalServerError(Box::new(err)))?,
+    })
+  }
+}
+
 pub struct ListActionResponse {
   pub id: i64,
   pub agent: String,
   pub active: bool,
+  // none to follow user settings
+  pub digest: Option<DigestMode>,
+  // time of the latest successful delivery
+  pub last_delivered: Option<i64>,
+  // when and why deliveries stopped, see `ExpireAction`
+  pub expired_time: Option<i64>,
+  pub expired_reason: Option<String>,
 }
 
 pub trait ListAction {
   fn list_action(&self, uid: i64) -> Result<Vec<ListActionResponse>>;
 }
 
+impl ListAction for DatabaseConnection {
+  fn list_action(&self, uid: i64) -> Result<Vec<ListActionResponse>> {
+    dispatch!(self, conn => conn.list_action(uid))
+  }
+}
+
 impl ListAction for Connection {
   fn list_action(&self, uid: i64) -> Result<Vec<ListActionResponse>> {
     let mut stmt = self.prepare_cached(
       "
-      SELECT user_actions.id, user_action_agents.act_agent, user_action_agents.act_active
+      SELECT user_actions.id, user_action_agents.act_agent, user_action_agents.act_active,
+        user_action_agents.digest, (
+          SELECT MAX(time) FROM deliveries
+          WHERE aid = user_actions.id AND status = 'delivered'
+        ), user_actions.expired_time, user_actions.expired_reason
       FROM user_action_agents
         INNER JOIN user_actions 
           ON user_action_agents.id = user_actions.aid
@@ -100,9 +263,145 @@ impl ListAction for Connection {
         id: row.get(0)?,
         agent: row.get(1)?,
         active: row.get(2)?,
+        digest: row
+          .get::<_, Option<String>>(3)?
+          .and_then(|e| DigestMode::from_str(&e).ok()),
+        last_delivered: row.get(4)?,
+        expired_time: row.get(5)?,
+        expired_reason: row.get(6)?,
       })
     })?;
     let li = itertools::process_results(iter, |iter| iter.collect())?;
     Ok(li)
   }
 }
+
+pub trait UpdateActionRx {
+  // bumps `rx`, one of the `user_actions` columns tracking the latest delivery
+  // of each kind, to `ts` if behind
+  fn update_action_rx(&self, uid: i64, id: i64, rx: &str, ts: i64) -> Result<()>;
+}
+
+impl UpdateActionRx for DatabaseConnection {
+  fn update_action_rx(&self, uid: i64, id: i64, rx: &str, ts: i64) -> Result<()> {
+    dispatch!(self, conn => conn.update_action_rx(uid, id, rx, ts))
+  }
+}
+
+impl UpdateActionRx for Connection {
+  fn update_action_rx(&self, uid: i64, id: i64, rx: &str, ts: i64) -> Result<()> {
+    let sql = format!(
+      "
+      UPDATE user_actions
+      SET {rx} = max({rx}, ?3)
+      WHERE uid = ?1 AND id = ?2
+      ",
+      rx = rx
+    );
+    self.prepare_cached(&sql)?.execute((&uid, &id, &ts))?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{
+    database::{
+      fest::{LookupFest, LookupFestRequest},
+      outbox::{CreateOutbox, CreateOutboxRequest, ListOutbox},
+      query::{CreateQuery, CreateQueryRequest, FestQueryConfig, QueryConfig},
+      user::{CreateUser, CreateUserRequest, LookupUserId, LookupUserIdRequest},
+      Database,
+    },
+    splatnet::FestState,
+  };
+
+  use super::*;
+
+  #[test]
+  fn test_expire() {
+    let db = Database::new_for_test().unwrap();
+    let mut conn = db.get().unwrap();
+
+    let auth_agent = "mock_auth_agent";
+    let auth_uid = "mock_auth_uid";
+    let ok = conn
+      .create_user(CreateUserRequest {
+        auth_agent,
+        auth_uid,
+        email: None,
+        name: None,
+        picture: None,
+        language: None,
+        time_zone: None,
+        availability: None,
+      })
+      .unwrap();
+    assert!(ok);
+    let uid = conn
+      .lookup_user_id(LookupUserIdRequest {
+        auth_agent,
+        auth_uid,
+      })
+      .unwrap();
+
+    let tx = conn.transaction().unwrap();
+    tx.create_query(CreateQueryRequest {
+      uid,
+      config: &QueryConfig::Fest {
+        config: FestQueryConfig {
+          states: vec![FestState::Scheduled],
+          enabled: true,
+        },
+      },
+    })
+    .unwrap();
+    let act_agent = "mock_act_agent";
+    let id = tx.create_action(uid, act_agent).unwrap();
+    tx.commit().unwrap();
+
+    conn
+      .create_outbox(CreateOutboxRequest {
+        uid,
+        id,
+        message: "{}",
+        marks: "[]",
+      })
+      .unwrap();
+    let lookup = |conn: &DatabaseConnection| {
+      conn
+        .lookup_fest(LookupFestRequest {
+          state_time: Utc::now(),
+          state: FestState::Scheduled,
+        })
+        .unwrap()
+    };
+    assert_eq!(lookup(&conn).len(), 1);
+
+    let now = Utc::now();
+    let expire = |conn: &mut DatabaseConnection| {
+      let tx = conn.transaction().unwrap();
+      let expired = tx
+        .expire_action(ExpireActionRequest {
+          uid,
+          id,
+          time: now,
+          reason: "mock reason",
+        })
+        .unwrap();
+      tx.commit().unwrap();
+      expired
+    };
+    assert!(expire(&mut conn));
+    // expired once
+    assert!(!expire(&mut conn));
+
+    // no longer matched, and nothing left queued
+    assert!(lookup(&conn).is_empty());
+    assert!(conn.list_outbox().unwrap().is_empty());
+    let li = conn.list_action(uid).unwrap();
+    assert_eq!(li.len(), 1);
+    assert_eq!(li[0].expired_time, Some(now.timestamp()));
+    assert_eq!(li[0].expired_reason.as_deref(), Some("mock reason"));
+  }
+}
@@ -0,0 +1,261 @@
+use std::{fmt::Display, str::FromStr};
+
+use chrono::{DateTime, Datelike, Duration, Timelike, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result};
+
+use super::{from_json, TimeZone};
+
+const DAY_MINS: u32 = 24 * 60;
+
+// local minutes of a day [start, end), e.g. `18:00-24:00`
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct TimeRange {
+  pub start: u32,
+  pub end: u32,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("invalid time range: [{0}], expected e.g. [18:00-24:00]")]
+pub struct ParseTimeRangeError(String);
+
+impl TimeRange {
+  pub fn contains(self, mins: u32) -> bool {
+    self.start <= mins && mins < self.end
+  }
+}
+
+impl Display for TimeRange {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "{:02}:{:02}-{:02}:{:02}",
+      self.start / 60,
+      self.start % 60,
+      self.end / 60,
+      self.end % 60
+    )
+  }
+}
+
+impl FromStr for TimeRange {
+  type Err = ParseTimeRangeError;
+
+  fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+    let err = || ParseTimeRangeError(s.into());
+    let parse_mins = |e: &str| -> std::result::Result<u32, ParseTimeRangeError> {
+      let (h, m) = e.trim().split_once(':').ok_or_else(err)?;
+      let h: u32 = h.parse().map_err(|_| err())?;
+      let m: u32 = m.parse().map_err(|_| err())?;
+      if m >= 60 {
+        return Err(err());
+      }
+      Ok(h * 60 + m)
+    };
+    let (start, end) = s.split_once('-').ok_or_else(err)?;
+    let (start, end) = (parse_mins(start)?, parse_mins(end)?);
+    // ranges crossing midnight are to be given as two days
+    if start >= end || end > DAY_MINS {
+      return Err(err());
+    }
+    Ok(TimeRange { start, end })
+  }
+}
+
+impl Serialize for TimeRange {
+  fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+}
+
+impl<'de> Deserialize<'de> for TimeRange {
+  fn deserialize<D: serde::Deserializer<'de>>(
+    deserializer: D,
+  ) -> std::result::Result<Self, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    TimeRange::from_str(&s).map_err(serde::de::Error::custom)
+  }
+}
+
+// local time ranges of each weekday in the user's time zone
+#[derive(Debug, Default, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Availability {
+  #[serde(default)]
+  pub mon: Vec<TimeRange>,
+  #[serde(default)]
+  pub tue: Vec<TimeRange>,
+  #[serde(default)]
+  pub wed: Vec<TimeRange>,
+  #[serde(default)]
+  pub thu: Vec<TimeRange>,
+  #[serde(default)]
+  pub fri: Vec<TimeRange>,
+  #[serde(default)]
+  pub sat: Vec<TimeRange>,
+  #[serde(default)]
+  pub sun: Vec<TimeRange>,
+}
+
+impl Availability {
+  pub fn any() -> Self {
+    let day = vec![TimeRange {
+      start: 0,
+      end: DAY_MINS,
+    }];
+    Availability {
+      mon: day.clone(),
+      tue: day.clone(),
+      wed: day.clone(),
+      thu: day.clone(),
+      fri: day.clone(),
+      sat: day.clone(),
+      sun: day,
+    }
+  }
+
+  pub fn day(&self, weekday: Weekday) -> &Vec<TimeRange> {
+    match weekday {
+      Weekday::Mon => &self.mon,
+      Weekday::Tue => &self.tue,
+      Weekday::Wed => &self.wed,
+      Weekday::Thu => &self.thu,
+      Weekday::Fri => &self.fri,
+      Weekday::Sat => &self.sat,
+      Weekday::Sun => &self.sun,
+    }
+  }
+
+  pub fn day_mut(&mut self, weekday: Weekday) -> &mut Vec<TimeRange> {
+    match weekday {
+      Weekday::Mon => &mut self.mon,
+      Weekday::Tue => &mut self.tue,
+      Weekday::Wed => &mut self.wed,
+      Weekday::Thu => &mut self.thu,
+      Weekday::Fri => &mut self.fri,
+      Weekday::Sat => &mut self.sat,
+      Weekday::Sun => &mut self.sun,
+    }
+  }
+
+  // whether `t` falls into any of the ranges, read in the given time zone
+  pub fn contains(&self, time_zone: TimeZone, t: DateTime<Utc>) -> bool {
+    let t = time_zone.convert(t);
+    let mins = t.hour() * 60 + t.minute();
+    self.day(t.weekday()).iter().any(|e| e.contains(mins))
+  }
+
+  // sorts the ranges of each day and merges the overlapping ones
+  pub fn normalize(&mut self) {
+    let mut weekday = Weekday::Mon;
+    for _ in 0..7 {
+      let day = self.day_mut(weekday);
+      day.sort_by_key(|e| e.start);
+      let mut merged: Vec<TimeRange> = vec![];
+      for e in day.drain(..) {
+        match merged.last_mut() {
+          Some(last) if e.start <= last.end => last.end = last.end.max(e.end),
+          _ => merged.push(e),
+        }
+      }
+      *day = merged;
+      weekday = weekday.succ();
+    }
+  }
+
+  // converts the jst 2-hour slot bitmasks of earlier versions, where weekdays
+  // [0,4) are in the first integer and [4,7) in the second, 12 bits for each,
+  // using the utc offsets of the week containing `now`
+  pub fn from_day_hrs(day_hrs: (i64, i64), time_zone: TimeZone, now: DateTime<Utc>) -> Self {
+    let jst = TimeZone::JST.convert(now);
+    let monday = jst.date_naive() - Duration::days(jst.weekday().num_days_from_monday() as i64);
+    let mut availability = Availability::default();
+    for a in 0..7 {
+      let mask = if a < 4 { day_hrs.0 } else { day_hrs.1 } >> (12 * (a % 4));
+      for b in 0..12 {
+        if mask & (1 << b) == 0 {
+          continue;
+        }
+        let t = (monday + Duration::days(a)).and_hms_opt(2 * b as u32, 0, 0);
+        let Some(t) = t.and_then(|t| t.and_local_timezone(TimeZone::JST.0).single()) else {
+          continue;
+        };
+        // a slot may span local midnight
+        let t = time_zone.convert(t);
+        let start = t.hour() * 60 + t.minute();
+        let end = start + 120;
+        availability.day_mut(t.weekday()).push(TimeRange {
+          start,
+          end: end.min(DAY_MINS),
+        });
+        if end > DAY_MINS {
+          availability.day_mut(t.weekday().succ()).push(TimeRange {
+            start: 0,
+            end: end - DAY_MINS,
+          });
+        }
+      }
+    }
+    availability.normalize();
+    availability
+  }
+}
+
+// whether a user is available at `t`, with the raw `users` columns where a
+// null availability stands for any time
+pub(crate) fn is_available(
+  time_zone: &str,
+  availability: Option<&str>,
+  t: DateTime<Utc>,
+) -> Result<bool> {
+  let Some(availability) = availability else {
+    return Ok(true);
+  };
+  let availability: Availability = from_json(availability)?;
+  let time_zone =
+    TimeZone::from_str(time_zone).map_err(|err| Error::InternalServerError(Box::new(err)))?;
+  Ok(availability.contains(time_zone, t))
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::TimeZone as _;
+
+  use super::*;
+
+  #[test]
+  fn test_simple() {
+    let e: TimeRange = "18:30-24:00".parse().unwrap();
+    assert_eq!(
+      e,
+      TimeRange {
+        start: 1110,
+        end: 1440
+      }
+    );
+    assert_eq!(e.to_string(), "18:30-24:00");
+    assert!("22:00-02:00".parse::<TimeRange>().is_err());
+    assert!("9-12".parse::<TimeRange>().is_err());
+
+    let mut availability: Availability =
+      serde_json::from_str(r#"{"fri":["20:00-22:00","18:00-20:30"]}"#).unwrap();
+    availability.normalize();
+    assert_eq!(availability.fri, vec!["18:00-22:00".parse().unwrap()]);
+    assert!(availability.sat.is_empty());
+
+    // friday 19:00 in new york during dst
+    let tz: TimeZone = "America/New_York".parse().unwrap();
+    let t = Utc.with_ymd_and_hms(2023, 7, 7, 23, 0, 0).unwrap();
+    assert!(availability.contains(tz, t));
+    assert!(!availability.contains(TimeZone::JST, t));
+
+    // friday [0:00, 4:00) jst
+    let day_hrs = (0, 0b11);
+    let availability = Availability::from_day_hrs(day_hrs, TimeZone::JST, t);
+    assert_eq!(availability.fri, vec!["00:00-04:00".parse().unwrap()]);
+    // thursday [11:00, 15:00) in new york
+    let availability = Availability::from_day_hrs(day_hrs, tz, t);
+    assert_eq!(availability.thu, vec!["11:00-15:00".parse().unwrap()]);
+    assert!(availability.fri.is_empty());
+  }
+}
@@ -0,0 +1,507 @@
+use appendlist::AppendList;
+use chrono::{DateTime, Utc};
+use r2d2_sqlite::rusqlite::Connection;
+
+use crate::{
+  splatnet::{CoopMode, KingSalmonid},
+  Error, Result,
+};
+
+use super::{from_json, remind_mins_sql, to_json, DatabaseConnection};
+
+#[derive(Debug)]
+pub struct CoopQueryRecord {
+  pub modes: u8,
+  pub includes: Vec<u32>,
+  pub excludes: Vec<u32>,
+  pub weapons: Vec<String>,
+  pub forbidden_weapons: Vec<String>,
+  pub king_salmonids: u8,
+  // null to follow user settings, negative for at announcement
+  pub remind_mins: Option<i32>,
+  pub enabled: bool,
+}
+
+#[derive(Debug)]
+pub struct CreateCoopQueryRequest<'a> {
+  pub uid: i64,
+  pub record: &'a CoopQueryRecord,
+}
+
+pub trait CreateCoopQuery {
+  fn create_coop_query(&self, request: CreateCoopQueryRequest) -> Result<i64>;
+}
+
+#[derive(Debug)]
+pub struct LookupCoopRequest<'a> {
+  pub start_time: DateTime<Utc>,
+  pub mode: CoopMode,
+  pub stage: u32,
+  pub weapons: &'a [String],
+  pub king_salmonid: KingSalmonid,
+}
+
+pub struct LookupCoopResponse {
+  pub id: i64,
+  pub uid: i64,
+  pub agent: String,
+  // minutes before start, none for at announcement
+  pub remind_mins: Option<i32>,
+}
+
+pub trait LookupCoop {
+  fn lookup_coop(&self, request: LookupCoopRequest) -> Result<AppendList<LookupCoopResponse>>;
+}
+
+#[derive(Debug)]
+pub struct ListCoopQueryRequest {
+  pub uid: i64,
+  pub qid: Option<i64>,
+}
+
+pub struct ListCoopQueryResponse {
+  pub qid: i64,
+  pub record: CoopQueryRecord,
+  pub created_time: String,
+}
+
+pub trait ListCoopQuery {
+  fn list_coop_query(&self, request: ListCoopQueryRequest) -> Result<Vec<ListCoopQueryResponse>>;
+}
+
+#[derive(Debug)]
+pub struct UpdateCoopQueryRequest<'a> {
+  pub uid: i64,
+  pub qid: i64,
+  pub record: &'a CoopQueryRecord,
+}
+
+pub trait UpdateCoopQuery {
+  fn update_coop_query(&self, request: UpdateCoopQueryRequest) -> Result<()>;
+}
+
+#[derive(Debug)]
+pub struct DeleteCoopQueryRequest {
+  pub uid: i64,
+  pub qid: i64,
+}
+
+pub trait DeleteCoopQuery {
+  fn delete_coop_query(&self, request: DeleteCoopQueryRequest) -> Result<()>;
+}
+
+impl CreateCoopQuery for DatabaseConnection {
+  fn create_coop_query(&self, request: CreateCoopQueryRequest) -> Result<i64> {
+    dispatch!(self, conn => conn.create_coop_query(request))
+  }
+}
+
+impl CreateCoopQuery for Connection {
+  fn create_coop_query(&self, request: CreateCoopQueryRequest) -> Result<i64> {
+    let CreateCoopQueryRequest {
+      uid,
+      record:
+        CoopQueryRecord {
+          modes,
+          includes,
+          excludes,
+          weapons,
+          forbidden_weapons,
+          king_salmonids,
+          remind_mins,
+          enabled,
+        },
+    } = request;
+    let mut stmt = self.prepare_cached(
+      "
+      INSERT INTO coop_queries ( uid, modes, includes, excludes, weapons, forbidden_weapons, king_salmonids, remind_mins, enabled )
+      VALUES ( ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9 )
+      ",
+    )?;
+    let n = stmt.execute((
+      &uid,
+      &modes,
+      &to_json(includes)?,
+      &to_json(excludes)?,
+      &to_json(weapons)?,
+      &to_json(forbidden_weapons)?,
+      &king_salmonids,
+      &remind_mins,
+      &enabled,
+    ))?;
+    if n != 1 {
+      Err(Error::NotFound)
+    } else {
+      Ok(self.last_insert_rowid())
+    }
+  }
+}
+
+impl LookupCoop for DatabaseConnection {
+  fn lookup_coop(&self, request: LookupCoopRequest) -> Result<AppendList<LookupCoopResponse>> {
+    dispatch!(self, conn => conn.lookup_coop(request))
+  }
+}
+
+impl LookupCoop for Connection {
+  fn lookup_coop(&self, request: LookupCoopRequest) -> Result<AppendList<LookupCoopResponse>> {
+    let LookupCoopRequest {
+      start_time,
+      mode,
+      stage,
+      weapons,
+      king_salmonid,
+    } = request;
+    // big run and eggstra work overlap with regular schedules
+    let rx = match mode {
+      CoopMode::Regular => "rx_coop",
+      _ => "rx_coop_ex",
+    };
+    let mode = mode as u8;
+    let king_salmonid = king_salmonid as u8;
+    let weapons = to_json(&weapons)?;
+    let ts = start_time.timestamp();
+    let remind_mins = remind_mins_sql("coop_queries");
+    // coop rotations last for days, so availability is not taken into account here
+    let sql = format!(
+      "
+      SELECT user_actions.id, uid_1, act_agent, remind_1
+      FROM (
+        SELECT uid as uid_1, {remind_mins} as remind_1
+        FROM coop_queries
+          INNER JOIN users ON uid = users.id
+        WHERE
+          enabled AND
+          modes & ?5 AND
+          ( king_salmonids = 0 OR king_salmonids & ?2 ) AND
+          ( includes = '[]' OR EXISTS (
+            SELECT 1 FROM json_each(includes) WHERE value = ?1
+          ) ) AND
+          NOT EXISTS (
+            SELECT 1 FROM json_each(excludes) WHERE value = ?1
+          ) AND
+          NOT EXISTS (
+            SELECT 1 FROM json_each(weapons)
+            WHERE value NOT IN ( SELECT value FROM json_each(?3) )
+          ) AND
+          NOT EXISTS (
+            SELECT 1 FROM json_each(forbidden_weapons)
+            WHERE value IN ( SELECT value FROM json_each(?3) )
+          )
+        GROUP BY uid
+      )
+        INNER JOIN user_action_agents ON uid_1 == user_action_agents.uid
+        INNER JOIN user_actions ON aid == user_action_agents.id
+      WHERE act_active AND expired_time IS NULL AND {rx} < ?4
+      "
+    );
+    let mut stmt = self.prepare_cached(&sql)?;
+    let iter = stmt.query_map((&stage, &king_salmonid, &weapons, &ts, &mode), |row| {
+      Ok(LookupCoopResponse {
+        id: row.get(0)?,
+        uid: row.get(1)?,
+        agent: row.get(2)?,
+        remind_mins: row.get::<_, i32>(3).map(|e| (e >= 0).then_some(e))?,
+      })
+    })?;
+    let list = itertools::process_results(iter, |iter| iter.collect())?;
+    Ok(list)
+  }
+}
+
+impl ListCoopQuery for DatabaseConnection {
+  fn list_coop_query(&self, request: ListCoopQueryRequest) -> Result<Vec<ListCoopQueryResponse>> {
+    dispatch!(self, conn => conn.list_coop_query(request))
+  }
+}
+
+impl ListCoopQuery for Connection {
+  fn list_coop_query(&self, request: ListCoopQueryRequest) -> Result<Vec<ListCoopQueryResponse>> {
+    let mut sql: String = "
+      SELECT id, modes, includes, excludes, weapons, forbidden_weapons, king_salmonids, remind_mins, enabled,
+        created_time
+      FROM coop_queries
+      WHERE uid = ?1
+      "
+    .into();
+    if request.qid.is_some() {
+      sql += " AND id = ?2";
+    } else {
+      sql += " AND (1 OR ?2)";
+    }
+    let mut stmt = self.prepare_cached(&sql)?;
+    let iter = stmt.query_map((&request.uid, &request.qid), |row| {
+      Ok((
+        row.get::<_, i64>(0)?,
+        row.get::<_, u8>(1)?,
+        row.get::<_, String>(2)?,
+        row.get::<_, String>(3)?,
+        row.get::<_, String>(4)?,
+        row.get::<_, String>(5)?,
+        row.get::<_, u8>(6)?,
+        row.get::<_, Option<i32>>(7)?,
+        row.get::<_, bool>(8)?,
+        row.get::<_, String>(9)?,
+      ))
+    })?;
+    let mut li = vec![];
+    for e in iter {
+      let (
+        qid,
+        modes,
+        includes,
+        excludes,
+        weapons,
+        forbidden_weapons,
+        king_salmonids,
+        remind_mins,
+        enabled,
+        created_time,
+      ) = e?;
+      li.push(ListCoopQueryResponse {
+        qid,
+        record: CoopQueryRecord {
+          modes,
+          includes: from_json(&includes)?,
+          excludes: from_json(&excludes)?,
+          weapons: from_json(&weapons)?,
+          forbidden_weapons: from_json(&forbidden_weapons)?,
+          king_salmonids,
+          remind_mins,
+          enabled,
+        },
+        created_time,
+      });
+    }
+    Ok(li)
+  }
+}
+
+impl UpdateCoopQuery for DatabaseConnection {
+  fn update_coop_query(&self, request: UpdateCoopQueryRequest) -> Result<()> {
+    dispatch!(self, conn => conn.update_coop_query(request))
+  }
+}
+
+impl UpdateCoopQuery for Connection {
+  fn update_coop_query(&self, request: UpdateCoopQueryRequest) -> Result<()> {
+    let UpdateCoopQueryRequest {
+      uid,
+      qid,
+      record:
+        CoopQueryRecord {
+          modes,
+          includes,
+          excludes,
+          weapons,
+          forbidden_weapons,
+          king_salmonids,
+          remind_mins,
+          enabled,
+        },
+    } = request;
+    let mut stmt = self.prepare_cached(
+      "
+      UPDATE coop_queries
+      SET
+        modes = ?3, includes = ?4, excludes = ?5, weapons = ?6, forbidden_weapons = ?7,
+        king_salmonids = ?8, remind_mins = ?9, enabled = ?10
+      WHERE uid = ?1 AND id = ?2
+      ",
+    )?;
+    let n = stmt.execute((
+      &uid,
+      &qid,
+      &modes,
+      &to_json(includes)?,
+      &to_json(excludes)?,
+      &to_json(weapons)?,
+      &to_json(forbidden_weapons)?,
+      &king_salmonids,
+      &remind_mins,
+      &enabled,
+    ))?;
+    if n != 1 {
+      Err(Error::NotFound)
+    } else {
+      Ok(())
+    }
+  }
+}
+
+impl DeleteCoopQuery for DatabaseConnection {
+  fn delete_coop_query(&self, request: DeleteCoopQueryRequest) -> Result<()> {
+    dispatch!(self, conn => conn.delete_coop_query(request))
+  }
+}
+
+impl DeleteCoopQuery for Connection {
+  fn delete_coop_query(&self, request: DeleteCoopQueryRequest) -> Result<()> {
+    let DeleteCoopQueryRequest { uid, qid } = request;
+    let mut stmt = self.prepare_cached(
+      "
+      DELETE FROM coop_queries
+      WHERE uid = ?1 AND id = ?2",
+    )?;
+    let n = stmt.execute((&uid, &qid))?;
+    if n != 1 {
+      Err(Error::NotFound)
+    } else {
+      Ok(())
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{
+    database::{
+      action::CreateAction,
+      query::{CoopQueryConfig, CreateQuery, CreateQueryRequest, QueryConfig},
+      user::{CreateUser, CreateUserRequest, LookupUserId, LookupUserIdRequest},
+      Database,
+    },
+    splatnet::COOP_WEAPON_GRIZZCO,
+  };
+
+  use super::*;
+
+  #[test]
+  fn test_lookup_simple() {
+    let db = Database::new_for_test().unwrap();
+    let mut conn = db.get().unwrap();
+
+    let auth_agent = "mock_auth_agent";
+    let auth_uid = "mock_auth_uid";
+    let ok = conn
+      .create_user(CreateUserRequest {
+        auth_agent,
+        auth_uid,
+        email: None,
+        name: None,
+        picture: None,
+        language: None,
+        time_zone: None,
+        availability: None,
+      })
+      .unwrap();
+    assert!(ok);
+    let uid = conn
+      .lookup_user_id(LookupUserIdRequest {
+        auth_agent,
+        auth_uid,
+      })
+      .unwrap();
+
+    let tx = conn.transaction().unwrap();
+    tx.create_query(CreateQueryRequest {
+      uid,
+      config: &QueryConfig::Coop {
+        config: CoopQueryConfig {
+          modes: vec![CoopMode::Regular, CoopMode::BigRun],
+          includes: vec![],
+          excludes: vec![6],
+          weapons: vec!["a".into()],
+          forbidden_weapons: vec![COOP_WEAPON_GRIZZCO.into()],
+          king_salmonids: vec![KingSalmonid::Cohozuna],
+          remind_mins: Some(60),
+          enabled: true,
+        },
+      },
+    })
+    .unwrap();
+    let act_agent = "mock_act_agent";
+    let id = tx.create_action(uid, act_agent).unwrap();
+    tx.commit().unwrap();
+
+    let weapons = |li: &[&str]| -> Vec<String> { li.iter().map(|e| e.to_string()).collect() };
+
+    let li = conn
+      .lookup_coop(LookupCoopRequest {
+        start_time: Utc::now(),
+        mode: CoopMode::Regular,
+        stage: 2,
+        weapons: &weapons(&["a", "b", "c", "d"]),
+        king_salmonid: KingSalmonid::Cohozuna,
+      })
+      .unwrap();
+    assert_eq!(li.len(), 1);
+    let e = li.get(0).unwrap();
+    assert_eq!(e.id, id);
+    assert_eq!(e.uid, uid);
+    assert_eq!(e.agent, act_agent);
+    assert_eq!(e.remind_mins, Some(60));
+
+    let li = conn
+      .lookup_coop(LookupCoopRequest {
+        start_time: Utc::now(),
+        mode: CoopMode::Regular,
+        stage: 6,
+        weapons: &weapons(&["a", "b", "c", "d"]),
+        king_salmonid: KingSalmonid::Cohozuna,
+      })
+      .unwrap();
+    // match excl
+    assert_eq!(li.len(), 0);
+
+    let li = conn
+      .lookup_coop(LookupCoopRequest {
+        start_time: Utc::now(),
+        mode: CoopMode::Regular,
+        stage: 2,
+        weapons: &weapons(&["b", "c", "d", "e"]),
+        king_salmonid: KingSalmonid::Cohozuna,
+      })
+      .unwrap();
+    // required weapon missing
+    assert_eq!(li.len(), 0);
+
+    let li = conn
+      .lookup_coop(LookupCoopRequest {
+        start_time: Utc::now(),
+        mode: CoopMode::Regular,
+        stage: 2,
+        weapons: &weapons(&["a", "b", "c", COOP_WEAPON_GRIZZCO]),
+        king_salmonid: KingSalmonid::Cohozuna,
+      })
+      .unwrap();
+    // forbidden weapon
+    assert_eq!(li.len(), 0);
+
+    let li = conn
+      .lookup_coop(LookupCoopRequest {
+        start_time: Utc::now(),
+        mode: CoopMode::Regular,
+        stage: 2,
+        weapons: &weapons(&["a", "b", "c", "d"]),
+        king_salmonid: KingSalmonid::Horrorboros,
+      })
+      .unwrap();
+    // king salmonid mismatch
+    assert_eq!(li.len(), 0);
+
+    let li = conn
+      .lookup_coop(LookupCoopRequest {
+        start_time: Utc::now(),
+        mode: CoopMode::TeamContest,
+        stage: 2,
+        weapons: &weapons(&["a", "b", "c", "d"]),
+        king_salmonid: KingSalmonid::Cohozuna,
+      })
+      .unwrap();
+    // mode mismatch
+    assert_eq!(li.len(), 0);
+
+    let li = conn
+      .lookup_coop(LookupCoopRequest {
+        start_time: Utc::now(),
+        mode: CoopMode::BigRun,
+        stage: 2,
+        weapons: &weapons(&["a", "b", "c", "d"]),
+        king_salmonid: KingSalmonid::Cohozuna,
+      })
+      .unwrap();
+    // big run
+    assert_eq!(li.len(), 1);
+  }
+}
@@ -0,0 +1,302 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use r2d2_sqlite::rusqlite::Connection;
+use serde::Serialize;
+use serde_enum_str::{Deserialize_enum_str, Serialize_enum_str};
+
+use crate::{Error, Result};
+
+use super::DatabaseConnection;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize_enum_str, Deserialize_enum_str)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+  Delivered,
+  // to be retried
+  Failed,
+  // failed and given up
+  Dropped,
+}
+
+#[derive(Debug)]
+pub struct CreateDeliveryRequest<'a> {
+  pub uid: i64,
+  // user action id
+  pub id: i64,
+  // message kind, e.g. `pvp` or `digest`
+  pub kind: &'a str,
+  // start of the rotation notified about
+  pub ts: DateTime<Utc>,
+  pub status: DeliveryStatus,
+  pub attempt: u32,
+  pub latency_ms: i64,
+  // the kind of failure, e.g. `network error`, as users see it in the history
+  pub error: Option<&'a str>,
+  pub time: DateTime<Utc>,
+}
+
+pub trait CreateDelivery {
+  // records an attempt at delivering a message to an action
+  fn create_delivery(&self, request: CreateDeliveryRequest) -> Result<()>;
+}
+
+#[derive(Debug)]
+pub struct ListDeliveryRequest {
+  pub uid: i64,
+  // all actions of the user if none
+  pub id: Option<i64>,
+  // only attempts older than this delivery, for the next page
+  pub before: Option<i64>,
+  pub limit: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ListDeliveryResponse {
+  pub did: i64,
+  pub id: i64,
+  pub agent: String,
+  pub kind: String,
+  pub ts: i64,
+  pub status: DeliveryStatus,
+  pub attempt: u32,
+  pub latency_ms: i64,
+  // the kind of failure, whose details are only logged
+  pub error: Option<String>,
+  pub time: i64,
+}
+
+pub trait ListDelivery {
+  // latest attempts first
+  fn list_delivery(&self, request: ListDeliveryRequest) -> Result<Vec<ListDeliveryResponse>>;
+}
+
+pub trait PruneDelivery {
+  // removes the attempts made before `time`
+  fn prune_delivery(&self, time: DateTime<Utc>) -> Result<usize>;
+}
+
+pub(crate) fn parse_status(status: &str) -> Result<DeliveryStatus> {
+  DeliveryStatus::from_str(status).map_err(|err| Error::InternalServerError(Box::new(err)))
+}
+
+impl CreateDelivery for DatabaseConnection {
+  fn create_delivery(&self, request: CreateDeliveryRequest) -> Result<()> {
+    dispatch!(self, conn => conn.create_delivery(request))
+  }
+}
+
+impl CreateDelivery for Connection {
+  fn create_delivery(&self, request: CreateDeliveryRequest) -> Result<()> {
+    let CreateDeliveryRequest {
+      uid,
+      id,
+      kind,
+      ts,
+      status,
+      attempt,
+      latency_ms,
+      error,
+      time,
+    } = request;
+    let mut stmt = self.prepare_cached(
+      "
+      INSERT INTO deliveries ( uid, aid, kind, ts, status, attempt, latency_ms, error, time )
+      VALUES ( ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9 )
+      ",
+    )?;
+    stmt.execute((
+      &uid,
+      &id,
+      &kind,
+      &ts.timestamp(),
+      &status.to_string(),
+      &attempt,
+      &latency_ms,
+      &error,
+      &time.timestamp(),
+    ))?;
+    Ok(())
+  }
+}
+
+impl ListDelivery for DatabaseConnection {
+  fn list_delivery(&self, request: ListDeliveryRequest) -> Result<Vec<ListDeliveryResponse>> {
+    dispatch!(self, conn => conn.list_delivery(request))
+  }
+}
+
+impl ListDelivery for Connection {
+  fn list_delivery(&self, request: ListDeliveryRequest) -> Result<Vec<ListDeliveryResponse>> {
+    let ListDeliveryRequest {
+      uid,
+      id,
+      before,
+      limit,
+    } = request;
+    let mut stmt = self.prepare_cached(
+      "
+      SELECT deliveries.id, deliveries.aid, act_agent, kind, ts, status, attempt, latency_ms,
+        error, time
+      FROM deliveries
+        INNER JOIN user_actions ON deliveries.aid == user_actions.id
+        INNER JOIN user_action_agents ON user_actions.aid == user_action_agents.id
+      WHERE
+        deliveries.uid = ?1 AND
+        ( ?2 IS NULL OR deliveries.aid = ?2 ) AND
+        ( ?3 IS NULL OR deliveries.id < ?3 )
+      ORDER BY deliveries.id DESC
+      LIMIT ?4
+      ",
+    )?;
+    let iter = stmt.query_map((&uid, &id, &before, &limit), |row| {
+      Ok((
+        row.get::<_, i64>(0)?,
+        row.get::<_, i64>(1)?,
+        row.get::<_, String>(2)?,
+        row.get::<_, String>(3)?,
+        row.get::<_, i64>(4)?,
+        row.get::<_, String>(5)?,
+        row.get::<_, u32>(6)?,
+        row.get::<_, i64>(7)?,
+        row.get::<_, Option<String>>(8)?,
+        row.get::<_, i64>(9)?,
+      ))
+    })?;
+    let mut li = vec![];
+    for e in iter {
+      let (did, id, agent, kind, ts, status, attempt, latency_ms, error, time) = e?;
+      li.push(ListDeliveryResponse {
+        did,
+        id,
+        agent,
+        kind,
+        ts,
+        status: parse_status(&status)?,
+        attempt,
+        latency_ms,
+        error,
+        time,
+      });
+    }
+    Ok(li)
+  }
+}
+
+impl PruneDelivery for DatabaseConnection {
+  fn prune_delivery(&self, time: DateTime<Utc>) -> Result<usize> {
+    dispatch!(self, conn => conn.prune_delivery(time))
+  }
+}
+
+impl PruneDelivery for Connection {
+  fn prune_delivery(&self, time: DateTime<Utc>) -> Result<usize> {
+    let mut stmt = self.prepare_cached(
+      "
+      DELETE FROM deliveries
+      WHERE time < ?1",
+    )?;
+    let n = stmt.execute((&time.timestamp(),))?;
+    Ok(n)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::Duration;
+
+  use crate::database::{
+    action::{CreateAction, ListAction},
+    user::{CreateUser, CreateUserRequest, LookupUserId, LookupUserIdRequest},
+    Database,
+  };
+
+  use super::*;
+
+  #[test]
+  fn test_create_and_list() {
+    let db = Database::new_for_test().unwrap();
+    let mut conn = db.get().unwrap();
+
+    let auth_agent = "mock_auth_agent";
+    let auth_uid = "mock_auth_uid";
+    let ok = conn
+      .create_user(CreateUserRequest {
+        auth_agent,
+        auth_uid,
+        email: None,
+        name: None,
+        picture: None,
+        language: None,
+        time_zone: None,
+        availability: None,
+      })
+      .unwrap();
+    assert!(ok);
+    let uid = conn
+      .lookup_user_id(LookupUserIdRequest {
+        auth_agent,
+        auth_uid,
+      })
+      .unwrap();
+
+    let tx = conn.transaction().unwrap();
+    let act_agent = "mock_act_agent";
+    let id = tx.create_action(uid, act_agent).unwrap();
+    tx.commit().unwrap();
+
+    let now = Utc::now();
+    let request = |status, attempt, time| CreateDeliveryRequest {
+      uid,
+      id,
+      kind: "pvp",
+      ts: now,
+      status,
+      attempt,
+      latency_ms: 100,
+      error: (status != DeliveryStatus::Delivered).then_some("mock error"),
+      time,
+    };
+    let li = conn.list_action(uid).unwrap();
+    assert_eq!(li[0].last_delivered, None);
+
+    conn
+      .create_delivery(request(DeliveryStatus::Failed, 1, now))
+      .unwrap();
+    let li = conn.list_action(uid).unwrap();
+    // failed attempts don't count
+    assert_eq!(li[0].last_delivered, None);
+
+    let later = now + Duration::minutes(1);
+    conn
+      .create_delivery(request(DeliveryStatus::Delivered, 2, later))
+      .unwrap();
+    let li = conn.list_action(uid).unwrap();
+    assert_eq!(li[0].last_delivered, Some(later.timestamp()));
+
+    let list = |before| {
+      conn
+        .list_delivery(ListDeliveryRequest {
+          uid,
+          id: None,
+          before,
+          limit: 1,
+        })
+        .unwrap()
+    };
+    // latest first, a page at a time
+    let li = list(None);
+    assert_eq!(li.len(), 1);
+    assert_eq!(li[0].status, DeliveryStatus::Delivered);
+    assert_eq!(li[0].agent, act_agent);
+    let li = list(Some(li[0].did));
+    assert_eq!(li.len(), 1);
+    assert_eq!(li[0].status, DeliveryStatus::Failed);
+    assert_eq!(li[0].error.as_deref(), Some("mock error"));
+    assert!(list(Some(li[0].did)).is_empty());
+
+    let n = conn.prune_delivery(later).unwrap();
+    assert_eq!(n, 1);
+    assert_eq!(list(None).len(), 1);
+  }
+}
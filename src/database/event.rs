@@ -0,0 +1,345 @@
+use appendlist::AppendList;
+use chrono::{DateTime, Utc};
+use r2d2_sqlite::rusqlite::Connection;
+
+use crate::{Error, Result};
+
+use super::{from_json, remind_mins_sql, to_json, DatabaseConnection};
+
+#[derive(Debug)]
+pub struct EventQueryRecord {
+  pub events: Vec<String>,
+  // null to follow user settings, negative for at announcement
+  pub remind_mins: Option<i32>,
+  pub enabled: bool,
+}
+
+#[derive(Debug)]
+pub struct CreateEventQueryRequest<'a> {
+  pub uid: i64,
+  pub record: &'a EventQueryRecord,
+}
+
+pub trait CreateEventQuery {
+  fn create_event_query(&self, request: CreateEventQueryRequest) -> Result<i64>;
+}
+
+#[derive(Debug)]
+pub struct LookupEventRequest<'a> {
+  pub start_time: DateTime<Utc>,
+  pub event_id: &'a str,
+}
+
+pub struct LookupEventResponse {
+  pub id: i64,
+  pub uid: i64,
+  pub agent: String,
+  // minutes before start, none for at announcement
+  pub remind_mins: Option<i32>,
+}
+
+pub trait LookupEvent {
+  fn lookup_event(&self, request: LookupEventRequest) -> Result<AppendList<LookupEventResponse>>;
+}
+
+#[derive(Debug)]
+pub struct ListEventQueryRequest {
+  pub uid: i64,
+  pub qid: Option<i64>,
+}
+
+pub struct ListEventQueryResponse {
+  pub qid: i64,
+  pub record: EventQueryRecord,
+  pub created_time: String,
+}
+
+pub trait ListEventQuery {
+  fn list_event_query(&self, request: ListEventQueryRequest)
+    -> Result<Vec<ListEventQueryResponse>>;
+}
+
+#[derive(Debug)]
+pub struct UpdateEventQueryRequest<'a> {
+  pub uid: i64,
+  pub qid: i64,
+  pub record: &'a EventQueryRecord,
+}
+
+pub trait UpdateEventQuery {
+  fn update_event_query(&self, request: UpdateEventQueryRequest) -> Result<()>;
+}
+
+#[derive(Debug)]
+pub struct DeleteEventQueryRequest {
+  pub uid: i64,
+  pub qid: i64,
+}
+
+pub trait DeleteEventQuery {
+  fn delete_event_query(&self, request: DeleteEventQueryRequest) -> Result<()>;
+}
+
+impl CreateEventQuery for DatabaseConnection {
+  fn create_event_query(&self, request: CreateEventQueryRequest) -> Result<i64> {
+    dispatch!(self, conn => conn.create_event_query(request))
+  }
+}
+
+impl CreateEventQuery for Connection {
+  fn create_event_query(&self, request: CreateEventQueryRequest) -> Result<i64> {
+    let CreateEventQueryRequest {
+      uid,
+      record: EventQueryRecord {
+        events,
+        remind_mins,
+        enabled,
+      },
+    } = request;
+    let mut stmt = self.prepare_cached(
+      "
+      INSERT INTO event_queries ( uid, events, remind_mins, enabled )
+      VALUES ( ?1, ?2, ?3, ?4 )
+      ",
+    )?;
+    let n = stmt.execute((&uid, &to_json(events)?, &remind_mins, &enabled))?;
+    if n != 1 {
+      Err(Error::NotFound)
+    } else {
+      Ok(self.last_insert_rowid())
+    }
+  }
+}
+
+impl LookupEvent for DatabaseConnection {
+  fn lookup_event(&self, request: LookupEventRequest) -> Result<AppendList<LookupEventResponse>> {
+    dispatch!(self, conn => conn.lookup_event(request))
+  }
+}
+
+impl LookupEvent for Connection {
+  fn lookup_event(&self, request: LookupEventRequest) -> Result<AppendList<LookupEventResponse>> {
+    let LookupEventRequest {
+      start_time,
+      event_id,
+    } = request;
+    let ts = start_time.timestamp();
+    let remind_mins = remind_mins_sql("event_queries");
+    let sql = format!(
+      "
+      SELECT user_actions.id, uid_1, act_agent, remind_1
+      FROM (
+        SELECT uid as uid_1, {remind_mins} as remind_1
+        FROM event_queries
+          INNER JOIN users ON uid = users.id
+        WHERE
+          enabled AND (
+            events = '[]' OR EXISTS (
+              SELECT 1 FROM json_each(events) WHERE value = ?1
+            )
+          )
+        GROUP BY uid
+      )
+        INNER JOIN user_action_agents ON uid_1 == user_action_agents.uid
+        INNER JOIN user_actions ON aid == user_action_agents.id
+      WHERE act_active AND expired_time IS NULL AND rx_event < ?2
+      "
+    );
+    let mut stmt = self.prepare_cached(&sql)?;
+    let iter = stmt.query_map((&event_id, &ts), |row| {
+      Ok(LookupEventResponse {
+        id: row.get(0)?,
+        uid: row.get(1)?,
+        agent: row.get(2)?,
+        remind_mins: row.get::<_, i32>(3).map(|e| (e >= 0).then_some(e))?,
+      })
+    })?;
+    let list = itertools::process_results(iter, |iter| iter.collect())?;
+    Ok(list)
+  }
+}
+
+impl ListEventQuery for DatabaseConnection {
+  fn list_event_query(
+    &self,
+    request: ListEventQueryRequest,
+  ) -> Result<Vec<ListEventQueryResponse>> {
+    dispatch!(self, conn => conn.list_event_query(request))
+  }
+}
+
+impl ListEventQuery for Connection {
+  fn list_event_query(
+    &self,
+    request: ListEventQueryRequest,
+  ) -> Result<Vec<ListEventQueryResponse>> {
+    let mut sql: String = "
+      SELECT id, events, remind_mins, enabled, created_time
+      FROM event_queries
+      WHERE uid = ?1
+      "
+    .into();
+    if request.qid.is_some() {
+      sql += " AND id = ?2";
+    } else {
+      sql += " AND (1 OR ?2)";
+    }
+    let mut stmt = self.prepare_cached(&sql)?;
+    let iter = stmt.query_map((&request.uid, &request.qid), |row| {
+      Ok((
+        row.get::<_, i64>(0)?,
+        row.get::<_, String>(1)?,
+        row.get::<_, Option<i32>>(2)?,
+        row.get::<_, bool>(3)?,
+        row.get::<_, String>(4)?,
+      ))
+    })?;
+    let mut li = vec![];
+    for e in iter {
+      let (qid, events, remind_mins, enabled, created_time) = e?;
+      li.push(ListEventQueryResponse {
+        qid,
+        record: EventQueryRecord {
+          events: from_json(&events)?,
+          remind_mins,
+          enabled,
+        },
+        created_time,
+      });
+    }
+    Ok(li)
+  }
+}
+
+impl UpdateEventQuery for DatabaseConnection {
+  fn update_event_query(&self, request: UpdateEventQueryRequest) -> Result<()> {
+    dispatch!(self, conn => conn.update_event_query(request))
+  }
+}
+
+impl UpdateEventQuery for Connection {
+  fn update_event_query(&self, request: UpdateEventQueryRequest) -> Result<()> {
+    let UpdateEventQueryRequest {
+      uid,
+      qid,
+      record: EventQueryRecord {
+        events,
+        remind_mins,
+        enabled,
+      },
+    } = request;
+    let mut stmt = self.prepare_cached(
+      "
+      UPDATE event_queries
+      SET events = ?3, remind_mins = ?4, enabled = ?5
+      WHERE uid = ?1 AND id = ?2
+      ",
+    )?;
+    let n = stmt.execute((&uid, &qid, &to_json(events)?, &remind_mins, &enabled))?;
+    if n != 1 {
+      Err(Error::NotFound)
+    } else {
+      Ok(())
+    }
+  }
+}
+
+impl DeleteEventQuery for DatabaseConnection {
+  fn delete_event_query(&self, request: DeleteEventQueryRequest) -> Result<()> {
+    dispatch!(self, conn => conn.delete_event_query(request))
+  }
+}
+
+impl DeleteEventQuery for Connection {
+  fn delete_event_query(&self, request: DeleteEventQueryRequest) -> Result<()> {
+    let DeleteEventQueryRequest { uid, qid } = request;
+    let mut stmt = self.prepare_cached(
+      "
+      DELETE FROM event_queries
+      WHERE uid = ?1 AND id = ?2",
+    )?;
+    let n = stmt.execute((&uid, &qid))?;
+    if n != 1 {
+      Err(Error::NotFound)
+    } else {
+      Ok(())
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::database::{
+    action::CreateAction,
+    query::{CreateQuery, CreateQueryRequest, EventQueryConfig, QueryConfig},
+    user::{CreateUser, CreateUserRequest, LookupUserId, LookupUserIdRequest},
+    Database,
+  };
+
+  use super::*;
+
+  #[test]
+  fn test_lookup_simple() {
+    let db = Database::new_for_test().unwrap();
+    let mut conn = db.get().unwrap();
+
+    let auth_agent = "mock_auth_agent";
+    let auth_uid = "mock_auth_uid";
+    let ok = conn
+      .create_user(CreateUserRequest {
+        auth_agent,
+        auth_uid,
+        email: None,
+        name: None,
+        picture: None,
+        language: None,
+        time_zone: None,
+        availability: None,
+      })
+      .unwrap();
+    assert!(ok);
+    let uid = conn
+      .lookup_user_id(LookupUserIdRequest {
+        auth_agent,
+        auth_uid,
+      })
+      .unwrap();
+
+    let tx = conn.transaction().unwrap();
+    tx.create_query(CreateQueryRequest {
+      uid,
+      config: &QueryConfig::Event {
+        config: EventQueryConfig {
+          events: vec!["e0".into(), "e1".into()],
+          remind_mins: None,
+          enabled: true,
+        },
+      },
+    })
+    .unwrap();
+    let act_agent = "mock_act_agent";
+    let id = tx.create_action(uid, act_agent).unwrap();
+    tx.commit().unwrap();
+
+    let li = conn
+      .lookup_event(LookupEventRequest {
+        start_time: Utc::now(),
+        event_id: "e1",
+      })
+      .unwrap();
+    assert_eq!(li.len(), 1);
+    let e = li.get(0).unwrap();
+    assert_eq!(e.id, id);
+    assert_eq!(e.uid, uid);
+    assert_eq!(e.agent, act_agent);
+
+    let li = conn
+      .lookup_event(LookupEventRequest {
+        start_time: Utc::now(),
+        event_id: "e2",
+      })
+      .unwrap();
+    // not subscribed
+    assert_eq!(li.len(), 0);
+  }
+}
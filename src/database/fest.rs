@@ -0,0 +1,308 @@
+use appendlist::AppendList;
+use chrono::{DateTime, Utc};
+use r2d2_sqlite::rusqlite::Connection;
+
+use crate::{splatnet::FestState, Error, Result};
+
+use super::DatabaseConnection;
+
+#[derive(Debug)]
+pub struct FestQueryRecord {
+  pub states: u8,
+  pub enabled: bool,
+}
+
+#[derive(Debug)]
+pub struct CreateFestQueryRequest<'a> {
+  pub uid: i64,
+  pub record: &'a FestQueryRecord,
+}
+
+pub trait CreateFestQuery {
+  fn create_fest_query(&self, request: CreateFestQueryRequest) -> Result<i64>;
+}
+
+#[derive(Debug)]
+pub struct LookupFestRequest {
+  pub state_time: DateTime<Utc>,
+  pub state: FestState,
+}
+
+pub struct LookupFestResponse {
+  pub id: i64,
+  pub uid: i64,
+  pub agent: String,
+}
+
+pub trait LookupFest {
+  fn lookup_fest(&self, request: LookupFestRequest) -> Result<AppendList<LookupFestResponse>>;
+}
+
+#[derive(Debug)]
+pub struct ListFestQueryRequest {
+  pub uid: i64,
+  pub qid: Option<i64>,
+}
+
+pub struct ListFestQueryResponse {
+  pub qid: i64,
+  pub record: FestQueryRecord,
+  pub created_time: String,
+}
+
+pub trait ListFestQuery {
+  fn list_fest_query(&self, request: ListFestQueryRequest) -> Result<Vec<ListFestQueryResponse>>;
+}
+
+#[derive(Debug)]
+pub struct UpdateFestQueryRequest<'a> {
+  pub uid: i64,
+  pub qid: i64,
+  pub record: &'a FestQueryRecord,
+}
+
+pub trait UpdateFestQuery {
+  fn update_fest_query(&self, request: UpdateFestQueryRequest) -> Result<()>;
+}
+
+#[derive(Debug)]
+pub struct DeleteFestQueryRequest {
+  pub uid: i64,
+  pub qid: i64,
+}
+
+pub trait DeleteFestQuery {
+  fn delete_fest_query(&self, request: DeleteFestQueryRequest) -> Result<()>;
+}
+
+impl CreateFestQuery for DatabaseConnection {
+  fn create_fest_query(&self, request: CreateFestQueryRequest) -> Result<i64> {
+    dispatch!(self, conn => conn.create_fest_query(request))
+  }
+}
+
+impl CreateFestQuery for Connection {
+  fn create_fest_query(&self, request: CreateFestQueryRequest) -> Result<i64> {
+    let CreateFestQueryRequest {
+      uid,
+      record: FestQueryRecord { states, enabled },
+    } = request;
+    let mut stmt = self.prepare_cached(
+      "
+      INSERT INTO fest_queries ( uid, states, enabled )
+      VALUES ( ?1, ?2, ?3 )
+      ",
+    )?;
+    let n = stmt.execute((&uid, &states, &enabled))?;
+    if n != 1 {
+      Err(Error::NotFound)
+    } else {
+      Ok(self.last_insert_rowid())
+    }
+  }
+}
+
+impl LookupFest for DatabaseConnection {
+  fn lookup_fest(&self, request: LookupFestRequest) -> Result<AppendList<LookupFestResponse>> {
+    dispatch!(self, conn => conn.lookup_fest(request))
+  }
+}
+
+impl LookupFest for Connection {
+  fn lookup_fest(&self, request: LookupFestRequest) -> Result<AppendList<LookupFestResponse>> {
+    let LookupFestRequest { state_time, state } = request;
+    let state = state as u8;
+    let ts = state_time.timestamp();
+    let mut stmt = self.prepare_cached(
+      "
+      SELECT user_actions.id, uid_1, act_agent
+      FROM (
+        SELECT DISTINCT uid as uid_1
+        FROM fest_queries
+        WHERE enabled AND states & ?1
+      )
+        INNER JOIN user_action_agents ON uid_1 == user_action_agents.uid
+        INNER JOIN user_actions ON aid == user_action_agents.id
+      WHERE act_active AND expired_time IS NULL AND rx_fest < ?2
+      ",
+    )?;
+    let iter = stmt.query_map((&state, &ts), |row| {
+      Ok(LookupFestResponse {
+        id: row.get(0)?,
+        uid: row.get(1)?,
+        agent: row.get(2)?,
+      })
+    })?;
+    let list = itertools::process_results(iter, |iter| iter.collect())?;
+    Ok(list)
+  }
+}
+
+impl ListFestQuery for DatabaseConnection {
+  fn list_fest_query(&self, request: ListFestQueryRequest) -> Result<Vec<ListFestQueryResponse>> {
+    dispatch!(self, conn => conn.list_fest_query(request))
+  }
+}
+
+impl ListFestQuery for Connection {
+  fn list_fest_query(&self, request: ListFestQueryRequest) -> Result<Vec<ListFestQueryResponse>> {
+    let mut sql: String = "
+      SELECT id, states, enabled, created_time
+      FROM fest_queries
+      WHERE uid = ?1
+      "
+    .into();
+    if request.qid.is_some() {
+      sql += " AND id = ?2";
+    } else {
+      sql += " AND (1 OR ?2)";
+    }
+    let mut stmt = self.prepare_cached(&sql)?;
+    let iter = stmt.query_map((&request.uid, &request.qid), |row| {
+      Ok((
+        row.get::<_, i64>(0)?,
+        row.get::<_, u8>(1)?,
+        row.get::<_, bool>(2)?,
+        row.get::<_, String>(3)?,
+      ))
+    })?;
+    let mut li = vec![];
+    for e in iter {
+      let (qid, states, enabled, created_time) = e?;
+      li.push(ListFestQueryResponse {
+        qid,
+        record: FestQueryRecord { states, enabled },
+        created_time,
+      });
+    }
+    Ok(li)
+  }
+}
+
+impl UpdateFestQuery for DatabaseConnection {
+  fn update_fest_query(&self, request: UpdateFestQueryRequest) -> Result<()> {
+    dispatch!(self, conn => conn.update_fest_query(request))
+  }
+}
+
+impl UpdateFestQuery for Connection {
+  fn update_fest_query(&self, request: UpdateFestQueryRequest) -> Result<()> {
+    let UpdateFestQueryRequest {
+      uid,
+      qid,
+      record: FestQueryRecord { states, enabled },
+    } = request;
+    let mut stmt = self.prepare_cached(
+      "
+      UPDATE fest_queries
+      SET states = ?3, enabled = ?4
+      WHERE uid = ?1 AND id = ?2
+      ",
+    )?;
+    let n = stmt.execute((&uid, &qid, &states, &enabled))?;
+    if n != 1 {
+      Err(Error::NotFound)
+    } else {
+      Ok(())
+    }
+  }
+}
+
+impl DeleteFestQuery for DatabaseConnection {
+  fn delete_fest_query(&self, request: DeleteFestQueryRequest) -> Result<()> {
+    dispatch!(self, conn => conn.delete_fest_query(request))
+  }
+}
+
+impl DeleteFestQuery for Connection {
+  fn delete_fest_query(&self, request: DeleteFestQueryRequest) -> Result<()> {
+    let DeleteFestQueryRequest { uid, qid } = request;
+    let mut stmt = self.prepare_cached(
+      "
+      DELETE FROM fest_queries
+      WHERE uid = ?1 AND id = ?2",
+    )?;
+    let n = stmt.execute((&uid, &qid))?;
+    if n != 1 {
+      Err(Error::NotFound)
+    } else {
+      Ok(())
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::database::{
+    action::CreateAction,
+    query::{CreateQuery, CreateQueryRequest, FestQueryConfig, QueryConfig},
+    user::{CreateUser, CreateUserRequest, LookupUserId, LookupUserIdRequest},
+    Database,
+  };
+
+  use super::*;
+
+  #[test]
+  fn test_lookup_simple() {
+    let db = Database::new_for_test().unwrap();
+    let mut conn = db.get().unwrap();
+
+    let auth_agent = "mock_auth_agent";
+    let auth_uid = "mock_auth_uid";
+    let ok = conn
+      .create_user(CreateUserRequest {
+        auth_agent,
+        auth_uid,
+        email: None,
+        name: None,
+        picture: None,
+        language: None,
+        time_zone: None,
+        availability: None,
+      })
+      .unwrap();
+    assert!(ok);
+    let uid = conn
+      .lookup_user_id(LookupUserIdRequest {
+        auth_agent,
+        auth_uid,
+      })
+      .unwrap();
+
+    let tx = conn.transaction().unwrap();
+    tx.create_query(CreateQueryRequest {
+      uid,
+      config: &QueryConfig::Fest {
+        config: FestQueryConfig {
+          states: vec![FestState::Scheduled, FestState::SecondHalf],
+          enabled: true,
+        },
+      },
+    })
+    .unwrap();
+    let act_agent = "mock_act_agent";
+    let id = tx.create_action(uid, act_agent).unwrap();
+    tx.commit().unwrap();
+
+    let li = conn
+      .lookup_fest(LookupFestRequest {
+        state_time: Utc::now(),
+        state: FestState::SecondHalf,
+      })
+      .unwrap();
+    assert_eq!(li.len(), 1);
+    let e = li.get(0).unwrap();
+    assert_eq!(e.id, id);
+    assert_eq!(e.uid, uid);
+    assert_eq!(e.agent, act_agent);
+
+    let li = conn
+      .lookup_fest(LookupFestRequest {
+        state_time: Utc::now(),
+        state: FestState::Closed,
+      })
+      .unwrap();
+    // not subscribed
+    assert_eq!(li.len(), 0);
+  }
+}
@@ -0,0 +1,448 @@
+use appendlist::AppendList;
+use chrono::{DateTime, Utc};
+use r2d2_sqlite::rusqlite::Connection;
+
+use crate::{splatnet::GearType, Error, Result};
+
+use super::{from_json, to_json, DatabaseConnection};
+
+#[derive(Debug)]
+pub struct GearQueryRecord {
+  pub gear_types: u8,
+  pub brands: Vec<String>,
+  pub powers: Vec<String>,
+  pub min_slots: u8,
+  pub max_price: Option<i32>,
+  pub pickup_only: bool,
+  pub enabled: bool,
+}
+
+#[derive(Debug)]
+pub struct CreateGearQueryRequest<'a> {
+  pub uid: i64,
+  pub record: &'a GearQueryRecord,
+}
+
+pub trait CreateGearQuery {
+  fn create_gear_query(&self, request: CreateGearQueryRequest) -> Result<i64>;
+}
+
+#[derive(Debug)]
+pub struct LookupGearRequest<'a> {
+  pub sale_end_time: DateTime<Utc>,
+  pub gear_type: GearType,
+  pub brand: &'a str,
+  pub primary_gear_power: &'a str,
+  pub additional_gear_powers: i32,
+  pub price: i32,
+  pub pickup: bool,
+}
+
+pub struct LookupGearResponse {
+  pub id: i64,
+  pub uid: i64,
+  pub agent: String,
+}
+
+pub trait LookupGear {
+  fn lookup_gear(&self, request: LookupGearRequest) -> Result<AppendList<LookupGearResponse>>;
+}
+
+#[derive(Debug)]
+pub struct ListGearQueryRequest {
+  pub uid: i64,
+  pub qid: Option<i64>,
+}
+
+pub struct ListGearQueryResponse {
+  pub qid: i64,
+  pub record: GearQueryRecord,
+  pub created_time: String,
+}
+
+pub trait ListGearQuery {
+  fn list_gear_query(&self, request: ListGearQueryRequest) -> Result<Vec<ListGearQueryResponse>>;
+}
+
+#[derive(Debug)]
+pub struct UpdateGearQueryRequest<'a> {
+  pub uid: i64,
+  pub qid: i64,
+  pub record: &'a GearQueryRecord,
+}
+
+pub trait UpdateGearQuery {
+  fn update_gear_query(&self, request: UpdateGearQueryRequest) -> Result<()>;
+}
+
+#[derive(Debug)]
+pub struct DeleteGearQueryRequest {
+  pub uid: i64,
+  pub qid: i64,
+}
+
+pub trait DeleteGearQuery {
+  fn delete_gear_query(&self, request: DeleteGearQueryRequest) -> Result<()>;
+}
+
+impl CreateGearQuery for DatabaseConnection {
+  fn create_gear_query(&self, request: CreateGearQueryRequest) -> Result<i64> {
+    dispatch!(self, conn => conn.create_gear_query(request))
+  }
+}
+
+impl CreateGearQuery for Connection {
+  fn create_gear_query(&self, request: CreateGearQueryRequest) -> Result<i64> {
+    let CreateGearQueryRequest {
+      uid,
+      record:
+        GearQueryRecord {
+          gear_types,
+          brands,
+          powers,
+          min_slots,
+          max_price,
+          pickup_only,
+          enabled,
+        },
+    } = request;
+    let mut stmt = self.prepare_cached(
+      "
+      INSERT INTO gear_queries ( uid, gear_types, brands, powers, min_slots, max_price, pickup_only, enabled )
+      VALUES ( ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8 )
+      ",
+    )?;
+    let n = stmt.execute((
+      &uid,
+      &gear_types,
+      &to_json(brands)?,
+      &to_json(powers)?,
+      &min_slots,
+      &max_price,
+      &pickup_only,
+      &enabled,
+    ))?;
+    if n != 1 {
+      Err(Error::NotFound)
+    } else {
+      Ok(self.last_insert_rowid())
+    }
+  }
+}
+
+impl LookupGear for DatabaseConnection {
+  fn lookup_gear(&self, request: LookupGearRequest) -> Result<AppendList<LookupGearResponse>> {
+    dispatch!(self, conn => conn.lookup_gear(request))
+  }
+}
+
+impl LookupGear for Connection {
+  fn lookup_gear(&self, request: LookupGearRequest) -> Result<AppendList<LookupGearResponse>> {
+    let LookupGearRequest {
+      sale_end_time,
+      gear_type,
+      brand,
+      primary_gear_power,
+      additional_gear_powers,
+      price,
+      pickup,
+    } = request;
+    let gear_type = gear_type as u8;
+    let ts = sale_end_time.timestamp();
+    let rx = if pickup { "rx_gear_brand" } else { "rx_gear" };
+    let sql = format!(
+      "
+      SELECT user_actions.id, uid_1, act_agent
+      FROM (
+        SELECT DISTINCT uid as uid_1
+        FROM gear_queries
+        WHERE
+          enabled AND
+          ( gear_types = 0 OR gear_types & ?1 ) AND
+          ( brands = '[]' OR EXISTS (
+            SELECT 1 FROM json_each(brands) WHERE value = ?2
+          ) ) AND
+          ( powers = '[]' OR EXISTS (
+            SELECT 1 FROM json_each(powers) WHERE value = ?3
+          ) ) AND
+          min_slots <= ?4 AND
+          ( max_price IS NULL OR max_price >= ?5 ) AND
+          ( NOT pickup_only OR ?6 )
+      )
+        INNER JOIN user_action_agents ON uid_1 == user_action_agents.uid
+        INNER JOIN user_actions ON aid == user_action_agents.id
+      WHERE act_active AND expired_time IS NULL AND {rx} < ?7
+      "
+    );
+    let mut stmt = self.prepare_cached(&sql)?;
+    let iter = stmt.query_map(
+      (
+        &gear_type,
+        &brand,
+        &primary_gear_power,
+        &additional_gear_powers,
+        &price,
+        &pickup,
+        &ts,
+      ),
+      |row| {
+        Ok(LookupGearResponse {
+          id: row.get(0)?,
+          uid: row.get(1)?,
+          agent: row.get(2)?,
+        })
+      },
+    )?;
+    let list = itertools::process_results(iter, |iter| iter.collect())?;
+    Ok(list)
+  }
+}
+
+impl ListGearQuery for DatabaseConnection {
+  fn list_gear_query(&self, request: ListGearQueryRequest) -> Result<Vec<ListGearQueryResponse>> {
+    dispatch!(self, conn => conn.list_gear_query(request))
+  }
+}
+
+impl ListGearQuery for Connection {
+  fn list_gear_query(&self, request: ListGearQueryRequest) -> Result<Vec<ListGearQueryResponse>> {
+    let mut sql: String = "
+      SELECT id, gear_types, brands, powers, min_slots, max_price, pickup_only, enabled, created_time
+      FROM gear_queries
+      WHERE uid = ?1
+      "
+    .into();
+    if request.qid.is_some() {
+      sql += " AND id = ?2";
+    } else {
+      sql += " AND (1 OR ?2)";
+    }
+    let mut stmt = self.prepare_cached(&sql)?;
+    let iter = stmt.query_map((&request.uid, &request.qid), |row| {
+      Ok((
+        row.get::<_, i64>(0)?,
+        row.get::<_, u8>(1)?,
+        row.get::<_, String>(2)?,
+        row.get::<_, String>(3)?,
+        row.get::<_, u8>(4)?,
+        row.get::<_, Option<i32>>(5)?,
+        row.get::<_, bool>(6)?,
+        row.get::<_, bool>(7)?,
+        row.get::<_, String>(8)?,
+      ))
+    })?;
+    let mut li = vec![];
+    for e in iter {
+      let (
+        qid,
+        gear_types,
+        brands,
+        powers,
+        min_slots,
+        max_price,
+        pickup_only,
+        enabled,
+        created_time,
+      ) = e?;
+      li.push(ListGearQueryResponse {
+        qid,
+        record: GearQueryRecord {
+          gear_types,
+          brands: from_json(&brands)?,
+          powers: from_json(&powers)?,
+          min_slots,
+          max_price,
+          pickup_only,
+          enabled,
+        },
+        created_time,
+      });
+    }
+    Ok(li)
+  }
+}
+
+impl UpdateGearQuery for DatabaseConnection {
+  fn update_gear_query(&self, request: UpdateGearQueryRequest) -> Result<()> {
+    dispatch!(self, conn => conn.update_gear_query(request))
+  }
+}
+
+impl UpdateGearQuery for Connection {
+  fn update_gear_query(&self, request: UpdateGearQueryRequest) -> Result<()> {
+    let UpdateGearQueryRequest {
+      uid,
+      qid,
+      record:
+        GearQueryRecord {
+          gear_types,
+          brands,
+          powers,
+          min_slots,
+          max_price,
+          pickup_only,
+          enabled,
+        },
+    } = request;
+    let mut stmt = self.prepare_cached(
+      "
+      UPDATE gear_queries
+      SET gear_types = ?3, brands = ?4, powers = ?5, min_slots = ?6, max_price = ?7, pickup_only = ?8,
+        enabled = ?9
+      WHERE uid = ?1 AND id = ?2
+      ",
+    )?;
+    let n = stmt.execute((
+      &uid,
+      &qid,
+      &gear_types,
+      &to_json(brands)?,
+      &to_json(powers)?,
+      &min_slots,
+      &max_price,
+      &pickup_only,
+      &enabled,
+    ))?;
+    if n != 1 {
+      Err(Error::NotFound)
+    } else {
+      Ok(())
+    }
+  }
+}
+
+impl DeleteGearQuery for DatabaseConnection {
+  fn delete_gear_query(&self, request: DeleteGearQueryRequest) -> Result<()> {
+    dispatch!(self, conn => conn.delete_gear_query(request))
+  }
+}
+
+impl DeleteGearQuery for Connection {
+  fn delete_gear_query(&self, request: DeleteGearQueryRequest) -> Result<()> {
+    let DeleteGearQueryRequest { uid, qid } = request;
+    let mut stmt = self.prepare_cached(
+      "
+      DELETE FROM gear_queries
+      WHERE uid = ?1 AND id = ?2",
+    )?;
+    let n = stmt.execute((&uid, &qid))?;
+    if n != 1 {
+      Err(Error::NotFound)
+    } else {
+      Ok(())
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::database::{
+    action::CreateAction,
+    query::{CreateQuery, CreateQueryRequest, GearQueryConfig, QueryConfig},
+    user::{CreateUser, CreateUserRequest, LookupUserId, LookupUserIdRequest},
+    Database,
+  };
+
+  use super::*;
+
+  #[test]
+  fn test_lookup_simple() {
+    let db = Database::new_for_test().unwrap();
+    let mut conn = db.get().unwrap();
+
+    let auth_agent = "mock_auth_agent";
+    let auth_uid = "mock_auth_uid";
+    let ok = conn
+      .create_user(CreateUserRequest {
+        auth_agent,
+        auth_uid,
+        email: None,
+        name: None,
+        picture: None,
+        language: None,
+        time_zone: None,
+        availability: None,
+      })
+      .unwrap();
+    assert!(ok);
+    let uid = conn
+      .lookup_user_id(LookupUserIdRequest {
+        auth_agent,
+        auth_uid,
+      })
+      .unwrap();
+
+    let tx = conn.transaction().unwrap();
+    tx.create_query(CreateQueryRequest {
+      uid,
+      config: &QueryConfig::Gears {
+        config: GearQueryConfig {
+          gear_types: vec![GearType::Head, GearType::Shoes],
+          brands: vec![],
+          powers: vec!["p0".into(), "p1".into()],
+          min_slots: 3,
+          max_price: Some(10000),
+          pickup_only: false,
+          enabled: true,
+        },
+      },
+    })
+    .unwrap();
+    let act_agent = "mock_act_agent";
+    let id = tx.create_action(uid, act_agent).unwrap();
+    tx.commit().unwrap();
+
+    let request = || LookupGearRequest {
+      sale_end_time: Utc::now(),
+      gear_type: GearType::Head,
+      brand: "b0",
+      primary_gear_power: "p0",
+      additional_gear_powers: 3,
+      price: 8000,
+      pickup: false,
+    };
+
+    let li = conn.lookup_gear(request()).unwrap();
+    assert_eq!(li.len(), 1);
+    let e = li.get(0).unwrap();
+    assert_eq!(e.id, id);
+    assert_eq!(e.uid, uid);
+    assert_eq!(e.agent, act_agent);
+
+    let li = conn
+      .lookup_gear(LookupGearRequest {
+        gear_type: GearType::Clothing,
+        ..request()
+      })
+      .unwrap();
+    // gear type mismatch
+    assert_eq!(li.len(), 0);
+
+    let li = conn
+      .lookup_gear(LookupGearRequest {
+        primary_gear_power: "p2",
+        ..request()
+      })
+      .unwrap();
+    // primary gear power mismatch
+    assert_eq!(li.len(), 0);
+
+    let li = conn
+      .lookup_gear(LookupGearRequest {
+        additional_gear_powers: 2,
+        ..request()
+      })
+      .unwrap();
+    // not enough slots
+    assert_eq!(li.len(), 0);
+
+    let li = conn
+      .lookup_gear(LookupGearRequest {
+        price: 12000,
+        ..request()
+      })
+      .unwrap();
+    // too expensive
+    assert_eq!(li.len(), 0);
+  }
+}
@@ -0,0 +1,779 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, TimeZone as _, Utc};
+use r2d2_sqlite::rusqlite::{ffi, Connection, Error, Result};
+
+use super::{availability::Availability, TimeZone, DAY_HRS_MAX};
+
+// a schema change applied once in a transaction, which bumps
+// `PRAGMA user_version` to its version; new steps are appended, and applied
+// ones are never edited
+pub struct Migration {
+  pub version: u32,
+  pub name: &'static str,
+  apply: fn(&Connection) -> Result<()>,
+}
+
+// databases of unversioned releases are at version 0 with the tables of the
+// first step, which it leaves as they are
+pub const MIGRATIONS: &[Migration] = &[
+  Migration {
+    version: 1,
+    name: "create tables",
+    apply: create_tables,
+  },
+  Migration {
+    version: 2,
+    name: "coop queries",
+    apply: create_coop_queries,
+  },
+  Migration {
+    version: 3,
+    name: "gear queries",
+    apply: create_gear_queries,
+  },
+  Migration {
+    version: 4,
+    name: "event queries",
+    apply: create_event_queries,
+  },
+  Migration {
+    version: 5,
+    name: "fest queries",
+    apply: create_fest_queries,
+  },
+  Migration {
+    version: 6,
+    name: "spider cursors",
+    apply: create_spider_cursors,
+  },
+  Migration {
+    version: 7,
+    name: "reminders",
+    apply: add_reminders,
+  },
+  Migration {
+    version: 8,
+    name: "digests",
+    apply: add_digests,
+  },
+  Migration {
+    version: 9,
+    name: "quiet hours and snooze",
+    apply: add_quiet_hours,
+  },
+  Migration {
+    version: 10,
+    name: "iana time zones",
+    apply: migrate_time_zones,
+  },
+  Migration {
+    version: 11,
+    name: "local time availability",
+    apply: add_availability,
+  },
+  Migration {
+    version: 12,
+    name: "per query availability and enabled flags",
+    apply: add_query_availability,
+  },
+  Migration {
+    version: 13,
+    name: "pvp stage constraints",
+    apply: add_stage_constraints,
+  },
+  Migration {
+    version: 14,
+    name: "pvp stage id arrays",
+    apply: add_stage_ids,
+  },
+  Migration {
+    version: 15,
+    name: "delivery outbox",
+    apply: create_outbox,
+  },
+  Migration {
+    version: 16,
+    name: "delivery history",
+    apply: create_deliveries,
+  },
+  Migration {
+    version: 17,
+    name: "action expiry",
+    apply: add_action_expiry,
+  },
+  Migration {
+    version: 18,
+    name: "telegram chats",
+    apply: create_telegram_tables,
+  },
+  Migration {
+    version: 19,
+    name: "discord channels",
+    apply: create_discord_tables,
+  },
+  Migration {
+    version: 20,
+    name: "discord links",
+    apply: create_discord_links,
+  },
+  Migration {
+    version: 21,
+    name: "delivery error kinds",
+    apply: migrate_delivery_errors,
+  },
+];
+
+pub struct MigrationStatus {
+  pub version: u32,
+  pub latest: u32,
+  pub pending: Vec<&'static Migration>,
+}
+
+pub fn migration_status(conn: &Connection) -> Result<MigrationStatus> {
+  let version: u32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+  let latest = MIGRATIONS.last().map_or(0, |e| e.version);
+  let pending = MIGRATIONS.iter().filter(|e| e.version > version).collect();
+  Ok(MigrationStatus {
+    version,
+    latest,
+    pending,
+  })
+}
+
+// applies the pending migrations in order, each in a transaction of its own,
+// or all in one transaction rolled back at the end for a dry run
+pub fn migrate(conn: &mut Connection, dry_run: bool) -> Result<Vec<&'static Migration>> {
+  let MigrationStatus {
+    version,
+    latest,
+    pending,
+  } = migration_status(conn)?;
+  if version > latest {
+    return Err(Error::SqliteFailure(
+      ffi::Error::new(ffi::SQLITE_MISMATCH),
+      Some(format!(
+        "database version {} is newer than {}",
+        version, latest
+      )),
+    ));
+  }
+  if dry_run {
+    let tx = conn.transaction()?;
+    for e in pending.iter() {
+      (e.apply)(&tx)?;
+    }
+    tx.rollback()?;
+  } else {
+    for e in pending.iter() {
+      log::info!("applying migration [{}] {}", e.version, e.name);
+      let tx = conn.transaction()?;
+      (e.apply)(&tx)?;
+      tx.pragma_update(None, "user_version", e.version)?;
+      tx.commit()?;
+    }
+  }
+  Ok(pending)
+}
+
+fn create_tables(conn: &Connection) -> Result<()> {
+  conn.execute_batch(
+    "
+    CREATE TABLE IF NOT EXISTS
+    users (
+      id                  INTEGER PRIMARY KEY AUTOINCREMENT,
+      created_time        DATETIME DEFAULT CURRENT_TIMESTAMP,
+      auth_agent          TEXT NOT NULL,
+      auth_uid            TEXT NOT NULL,
+      name                TEXT,
+      email               TEXT,
+      picture             TEXT,
+      language            TEXT NOT NULL,
+      time_zone           TEXT NOT NULL,
+      day_hrs_0           INTEGER NOT NULL,   /* legacy, migrated into availability */
+      day_hrs_1           INTEGER NOT NULL,
+      UNIQUE ( auth_uid, auth_agent )
+    );
+
+    CREATE TABLE IF NOT EXISTS
+    pvp_queries (
+      id                  INTEGER PRIMARY KEY AUTOINCREMENT,
+      uid                 INTEGER NOT NULL,
+      created_time        DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+      modes               TINYINT NOT NULL,
+      rules               TINYINT NOT NULL,
+      includes            INT NOT NULL,       /* legacy, migrated into include_stages */
+      excludes            INT NOT NULL,
+      FOREIGN KEY ( uid ) REFERENCES users ( id ) ON DELETE CASCADE
+    );
+
+    CREATE INDEX IF NOT EXISTS pvp_queries_index
+    ON pvp_queries ( uid );
+
+    CREATE TABLE IF NOT EXISTS
+    user_action_agents (
+      id                  INTEGER PRIMARY KEY AUTOINCREMENT,
+      uid                 INTEGER NOT NULL,
+      act_agent           TEXT NOT NULL,
+      act_active          TINYINT NOT NULL,
+      FOREIGN KEY ( uid ) REFERENCES users ( id ) ON DELETE CASCADE,
+      UNIQUE ( uid, act_agent )
+    );
+
+    CREATE INDEX IF NOT EXISTS user_action_agents_index
+    ON user_action_agents ( uid );
+
+    CREATE TABLE IF NOT EXISTS
+    user_actions (
+      id                  INTEGER PRIMARY KEY AUTOINCREMENT,
+      uid                 INTEGER NOT NULL,
+      aid                 INTEGER NOT NULL,
+      rx_pvp              INTEGER NOT NULL DEFAULT 0,
+      rx_event            INTEGER NOT NULL DEFAULT 0,
+      rx_coop             INTEGER NOT NULL DEFAULT 0,
+      rx_coop_ex          INTEGER NOT NULL DEFAULT 0,
+      rx_gear             INTEGER NOT NULL DEFAULT 0,
+      rx_gear_brand       INTEGER NOT NULL DEFAULT 0,
+      FOREIGN KEY ( aid ) REFERENCES user_action_agents ( id ) ON DELETE CASCADE
+    );
+
+    CREATE TABLE IF NOT EXISTS
+    webpush_ext_info (
+      id                  INTEGER UNIQUE NOT NULL,
+      uid                 INTEGER NOT NULL,
+      endpoint            TEXT NOT NULL,
+      p256dh              TEXT NOT NULL,
+      auth                TEXT NOT NULL,
+      browser             TEXT,
+      device              TEXT,
+      os                  TEXT,
+      FOREIGN KEY ( id ) REFERENCES user_actions ( id ) ON DELETE CASCADE,
+      UNIQUE ( endpoint, uid )
+    );
+    ",
+  )
+}
+
+fn create_coop_queries(conn: &Connection) -> Result<()> {
+  conn.execute_batch(
+    "
+    CREATE TABLE IF NOT EXISTS
+    coop_queries (
+      id                  INTEGER PRIMARY KEY AUTOINCREMENT,
+      uid                 INTEGER NOT NULL,
+      created_time        DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+      modes               TINYINT NOT NULL,
+      includes            TEXT NOT NULL,      /* json array of coop stage ids */
+      excludes            TEXT NOT NULL,
+      weapons             TEXT NOT NULL,      /* json array of weapon ids */
+      forbidden_weapons   TEXT NOT NULL,
+      king_salmonids      TINYINT NOT NULL,   /* 0 for any */
+      FOREIGN KEY ( uid ) REFERENCES users ( id ) ON DELETE CASCADE
+    );
+
+    CREATE INDEX IF NOT EXISTS coop_queries_index
+    ON coop_queries ( uid );
+    ",
+  )
+}
+
+fn create_gear_queries(conn: &Connection) -> Result<()> {
+  conn.execute_batch(
+    "
+    CREATE TABLE IF NOT EXISTS
+    gear_queries (
+      id                  INTEGER PRIMARY KEY AUTOINCREMENT,
+      uid                 INTEGER NOT NULL,
+      created_time        DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+      gear_types          TINYINT NOT NULL,   /* 0 for any */
+      brands              TEXT NOT NULL,      /* json array of brand ids */
+      powers              TEXT NOT NULL,      /* json array of primary gear power ids */
+      min_slots           TINYINT NOT NULL,
+      max_price           INTEGER,            /* null for any */
+      pickup_only         TINYINT NOT NULL,
+      FOREIGN KEY ( uid ) REFERENCES users ( id ) ON DELETE CASCADE
+    );
+
+    CREATE INDEX IF NOT EXISTS gear_queries_index
+    ON gear_queries ( uid );
+    ",
+  )
+}
+
+fn create_event_queries(conn: &Connection) -> Result<()> {
+  conn.execute_batch(
+    "
+    CREATE TABLE IF NOT EXISTS
+    event_queries (
+      id                  INTEGER PRIMARY KEY AUTOINCREMENT,
+      uid                 INTEGER NOT NULL,
+      created_time        DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+      events              TEXT NOT NULL,      /* json array of league match event ids, empty for any */
+      FOREIGN KEY ( uid ) REFERENCES users ( id ) ON DELETE CASCADE
+    );
+
+    CREATE INDEX IF NOT EXISTS event_queries_index
+    ON event_queries ( uid );
+    ",
+  )
+}
+
+fn create_fest_queries(conn: &Connection) -> Result<()> {
+  conn.execute_batch(
+    "
+    CREATE TABLE IF NOT EXISTS
+    fest_queries (
+      id                  INTEGER PRIMARY KEY AUTOINCREMENT,
+      uid                 INTEGER NOT NULL,
+      created_time        DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+      states              TINYINT NOT NULL,
+      FOREIGN KEY ( uid ) REFERENCES users ( id ) ON DELETE CASCADE
+    );
+
+    CREATE INDEX IF NOT EXISTS fest_queries_index
+    ON fest_queries ( uid );
+
+    ALTER TABLE user_actions ADD COLUMN rx_fest INTEGER NOT NULL DEFAULT 0;
+    ",
+  )
+}
+
+fn create_spider_cursors(conn: &Connection) -> Result<()> {
+  conn.execute_batch(
+    "
+    CREATE TABLE IF NOT EXISTS
+    spider_cursors (
+      name                TEXT PRIMARY KEY,
+      value               TEXT NOT NULL,
+      updated_time        DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+    );
+    ",
+  )
+}
+
+// lead times in minutes, negative for at announcement and null on queries to
+// follow user settings
+fn add_reminders(conn: &Connection) -> Result<()> {
+  conn.execute_batch(
+    "
+    ALTER TABLE users ADD COLUMN remind_mins INTEGER NOT NULL DEFAULT -1;
+    ALTER TABLE pvp_queries ADD COLUMN remind_mins INTEGER;
+    ALTER TABLE coop_queries ADD COLUMN remind_mins INTEGER;
+    ALTER TABLE event_queries ADD COLUMN remind_mins INTEGER;
+
+    CREATE TABLE IF NOT EXISTS
+    pending_notifications (
+      id                  INTEGER PRIMARY KEY AUTOINCREMENT,
+      uid                 INTEGER NOT NULL,
+      aid                 INTEGER NOT NULL,   /* user_actions id */
+      rx                  TEXT NOT NULL,      /* user_actions column to bump on delivery */
+      ts                  INTEGER NOT NULL,
+      fire_time           INTEGER NOT NULL,
+      message             TEXT NOT NULL,      /* json encoded message */
+      FOREIGN KEY ( aid ) REFERENCES user_actions ( id ) ON DELETE CASCADE,
+      UNIQUE ( aid, message )
+    );
+
+    CREATE INDEX IF NOT EXISTS pending_notifications_index
+    ON pending_notifications ( fire_time );
+    ",
+  )
+}
+
+// the digest of actions is null to follow user settings, and the hour local
+fn add_digests(conn: &Connection) -> Result<()> {
+  conn.execute_batch(
+    "
+    ALTER TABLE users ADD COLUMN digest TEXT NOT NULL DEFAULT 'immediate';
+    ALTER TABLE users ADD COLUMN digest_hour INTEGER NOT NULL DEFAULT 8;
+    ALTER TABLE user_action_agents ADD COLUMN digest TEXT;
+    ",
+  )
+}
+
+// local hours [start, end), equal for none
+fn add_quiet_hours(conn: &Connection) -> Result<()> {
+  conn.execute_batch(
+    "
+    ALTER TABLE users ADD COLUMN quiet_start INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE users ADD COLUMN quiet_end INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE users ADD COLUMN snooze_until INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE users ADD COLUMN vacation TINYINT NOT NULL DEFAULT 0;
+    ",
+  )
+}
+
+// the availability of queries is null to follow user settings
+fn add_query_availability(conn: &Connection) -> Result<()> {
+  conn.execute_batch(
+    "
+    ALTER TABLE pvp_queries ADD COLUMN availability TEXT;
+    ALTER TABLE pvp_queries ADD COLUMN enabled TINYINT NOT NULL DEFAULT 1;
+    ALTER TABLE coop_queries ADD COLUMN enabled TINYINT NOT NULL DEFAULT 1;
+    ALTER TABLE event_queries ADD COLUMN enabled TINYINT NOT NULL DEFAULT 1;
+    ALTER TABLE fest_queries ADD COLUMN enabled TINYINT NOT NULL DEFAULT 1;
+    ALTER TABLE gear_queries ADD COLUMN enabled TINYINT NOT NULL DEFAULT 1;
+    ",
+  )
+}
+
+// json array of per rule stage constraints
+fn add_stage_constraints(conn: &Connection) -> Result<()> {
+  conn.execute_batch(
+    "
+    ALTER TABLE pvp_queries ADD COLUMN min_includes INTEGER NOT NULL DEFAULT 1;
+    ALTER TABLE pvp_queries ADD COLUMN rule_stages TEXT NOT NULL DEFAULT '[]';
+    ",
+  )
+}
+
+// fixed offset time zones of earlier versions
+fn migrate_time_zones(conn: &Connection) -> Result<()> {
+  conn.execute(
+    "
+    UPDATE users
+    SET time_zone = CASE time_zone
+      WHEN 'jst' THEN 'Asia/Tokyo'
+      WHEN 'pt' THEN 'America/Los_Angeles'
+      WHEN 'cet' THEN 'Europe/Paris'
+      WHEN 'cst' THEN 'Asia/Shanghai'
+    END
+    WHERE time_zone IN ( 'jst', 'pt', 'cet', 'cst' )
+    ",
+    (),
+  )?;
+  Ok(())
+}
+
+// jst slots are converted by the utc offsets of this week, in standard time on
+// the northern hemisphere, so that the result doesn't depend on when it runs
+fn day_hrs_reference_time() -> DateTime<Utc> {
+  Utc.with_ymd_and_hms(2023, 1, 9, 0, 0, 0).unwrap()
+}
+
+// json local time ranges per weekday, null for any
+fn add_availability(conn: &Connection) -> Result<()> {
+  conn.execute_batch(
+    "
+    ALTER TABLE users ADD COLUMN availability TEXT;
+    ",
+  )?;
+  migrate_day_hrs(conn)
+}
+
+fn migrate_day_hrs(conn: &Connection) -> Result<()> {
+  let li = {
+    let mut stmt = conn.prepare(
+      "
+      SELECT id, time_zone, day_hrs_0, day_hrs_1
+      FROM users
+      WHERE day_hrs_0 != ?1 OR day_hrs_1 != ?1
+      ",
+    )?;
+    let iter = stmt.query_map((&DAY_HRS_MAX,), |row| {
+      Ok((
+        row.get::<_, i64>(0)?,
+        row.get::<_, String>(1)?,
+        row.get::<_, i64>(2)?,
+        row.get::<_, i64>(3)?,
+      ))
+    })?;
+    itertools::process_results(iter, |iter| iter.collect::<Vec<_>>())?
+  };
+  let t = day_hrs_reference_time();
+  for (id, time_zone, day_hrs_0, day_hrs_1) in li {
+    let time_zone = TimeZone::from_str(&time_zone).unwrap_or_default();
+    let availability = Availability::from_day_hrs((day_hrs_0, day_hrs_1), time_zone, t);
+    let availability = serde_json::to_string(&availability)
+      .map_err(|err| Error::ToSqlConversionFailure(Box::new(err)))?;
+    conn.execute(
+      "
+      UPDATE users
+      SET availability = ?2, day_hrs_0 = ?3, day_hrs_1 = ?3
+      WHERE id = ?1
+      ",
+      (&id, &availability, &DAY_HRS_MAX),
+    )?;
+  }
+  Ok(())
+}
+
+// json arrays of stage ids
+fn add_stage_ids(conn: &Connection) -> Result<()> {
+  conn.execute_batch(
+    "
+    ALTER TABLE pvp_queries ADD COLUMN include_stages TEXT NOT NULL DEFAULT '[]';
+    ALTER TABLE pvp_queries ADD COLUMN exclude_stages TEXT NOT NULL DEFAULT '[]';
+    ",
+  )?;
+  migrate_stage_masks(conn)
+}
+
+// 32-bit stage masks of earlier versions, where bit `i` is stage `i + 1`
+fn migrate_stage_masks(conn: &Connection) -> Result<()> {
+  let li = {
+    let mut stmt = conn.prepare(
+      "
+      SELECT id, includes, excludes
+      FROM pvp_queries
+      WHERE includes != 0 OR excludes != 0
+      ",
+    )?;
+    let iter = stmt.query_map((), |row| {
+      Ok((
+        row.get::<_, i64>(0)?,
+        row.get::<_, u32>(1)?,
+        row.get::<_, u32>(2)?,
+      ))
+    })?;
+    itertools::process_results(iter, |iter| iter.collect::<Vec<_>>())?
+  };
+  let unfold = |mask: u32| {
+    let li: Vec<_> = (0..32)
+      .filter(|i| mask & (1 << i) != 0)
+      .map(|i| i + 1)
+      .collect();
+    serde_json::to_string(&li).map_err(|err| Error::ToSqlConversionFailure(Box::new(err)))
+  };
+  for (id, includes, excludes) in li {
+    conn.execute(
+      "
+      UPDATE pvp_queries
+      SET include_stages = ?2, exclude_stages = ?3, includes = 0, excludes = 0
+      WHERE id = ?1
+      ",
+      (&id, &unfold(includes)?, &unfold(excludes)?),
+    )?;
+  }
+  Ok(())
+}
+
+fn create_outbox(conn: &Connection) -> Result<()> {
+  conn.execute_batch(
+    "
+    CREATE TABLE IF NOT EXISTS
+    outbox (
+      id                  INTEGER PRIMARY KEY AUTOINCREMENT,
+      uid                 INTEGER NOT NULL,
+      aid                 INTEGER NOT NULL,   /* user_actions id */
+      message             TEXT NOT NULL,      /* json encoded message */
+      marks               TEXT NOT NULL,      /* json encoded user_actions columns to bump on delivery */
+      attempts            INTEGER NOT NULL DEFAULT 0,
+      next_attempt        INTEGER,            /* null if given up */
+      last_error          TEXT,
+      created_time        DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+      FOREIGN KEY ( aid ) REFERENCES user_actions ( id ) ON DELETE CASCADE,
+      UNIQUE ( aid, message )
+    );
+
+    CREATE INDEX IF NOT EXISTS outbox_index
+    ON outbox ( next_attempt );
+    ",
+  )
+}
+
+fn create_deliveries(conn: &Connection) -> Result<()> {
+  conn.execute_batch(
+    "
+    CREATE TABLE IF NOT EXISTS
+    deliveries (
+      id                  INTEGER PRIMARY KEY AUTOINCREMENT,
+      uid                 INTEGER NOT NULL,
+      aid                 INTEGER NOT NULL,   /* user_actions id */
+      kind                TEXT NOT NULL,
+      ts                  INTEGER NOT NULL,   /* start of the rotation */
+      status              TEXT NOT NULL,
+      attempt             INTEGER NOT NULL,
+      latency_ms          INTEGER NOT NULL,
+      error               TEXT,
+      time                INTEGER NOT NULL,
+      FOREIGN KEY ( aid ) REFERENCES user_actions ( id ) ON DELETE CASCADE
+    );
+
+    CREATE INDEX IF NOT EXISTS deliveries_uid_index
+    ON deliveries ( uid, id );
+
+    CREATE INDEX IF NOT EXISTS deliveries_aid_index
+    ON deliveries ( aid, status, time );
+
+    CREATE INDEX IF NOT EXISTS deliveries_time_index
+    ON deliveries ( time );
+    ",
+  )
+}
+
+// null while deliverable
+fn add_action_expiry(conn: &Connection) -> Result<()> {
+  conn.execute_batch(
+    "
+    ALTER TABLE user_actions ADD COLUMN expired_time INTEGER;
+    ALTER TABLE user_actions ADD COLUMN expired_reason TEXT;
+    ",
+  )
+}
+
+fn create_telegram_tables(conn: &Connection) -> Result<()> {
+  conn.execute_batch(
+    "
+    CREATE TABLE IF NOT EXISTS
+    telegram_links (
+      code                TEXT PRIMARY KEY,   /* sent to the bot as `/start <code>` */
+      uid                 INTEGER NOT NULL,
+      expire_time         INTEGER NOT NULL,
+      FOREIGN KEY ( uid ) REFERENCES users ( id ) ON DELETE CASCADE
+    );
+
+    CREATE TABLE IF NOT EXISTS
+    telegram_ext_info (
+      id                  INTEGER UNIQUE NOT NULL,
+      uid                 INTEGER NOT NULL,
+      chat_id             INTEGER NOT NULL,
+      chat_type           TEXT NOT NULL,      /* private, group, supergroup or channel */
+      title               TEXT,               /* name of the user or the group */
+      username            TEXT,
+      FOREIGN KEY ( id ) REFERENCES user_actions ( id ) ON DELETE CASCADE,
+      UNIQUE ( chat_id, uid )
+    );
+    ",
+  )
+}
+
+fn create_discord_tables(conn: &Connection) -> Result<()> {
+  conn.execute_batch(
+    "
+    CREATE TABLE IF NOT EXISTS
+    discord_ext_info (
+      id                  INTEGER UNIQUE NOT NULL,
+      uid                 INTEGER NOT NULL,
+      kind                TEXT NOT NULL,      /* dm or webhook */
+      user_id             TEXT,               /* of dm */
+      webhook_id          TEXT,               /* of webhook */
+      webhook_token       TEXT,
+      FOREIGN KEY ( id ) REFERENCES user_actions ( id ) ON DELETE CASCADE
+    );
+    ",
+  )
+}
+
+fn create_discord_links(conn: &Connection) -> Result<()> {
+  conn.execute_batch(
+    "
+    CREATE TABLE IF NOT EXISTS
+    discord_links (
+      code                TEXT PRIMARY KEY,   /* sent to the bot in a direct message */
+      uid                 INTEGER NOT NULL,
+      user_id             TEXT NOT NULL,      /* who is expected to send it */
+      expire_time         INTEGER NOT NULL,
+      FOREIGN KEY ( uid ) REFERENCES users ( id ) ON DELETE CASCADE
+    );
+    ",
+  )
+}
+
+// the debug output of errors recorded before, which may carry urls with tokens
+fn migrate_delivery_errors(conn: &Connection) -> Result<()> {
+  conn.execute_batch(
+    "
+    UPDATE deliveries
+    SET error = CASE
+      WHEN error LIKE 'ActionExpired%' OR error LIKE 'action expired%' THEN 'action expired'
+      WHEN error LIKE 'DeliveryRejected%' OR error LIKE 'delivery rejected%' THEN 'delivery rejected'
+      WHEN error LIKE 'NetworkError%' OR error LIKE 'network error%' THEN 'network error'
+      WHEN error = 'action inactive' THEN error
+      ELSE 'internal error'
+    END
+    WHERE error IS NOT NULL;
+    ",
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_migrate() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let status = migration_status(&conn).unwrap();
+    assert_eq!(status.version, 0);
+    assert_eq!(status.pending.len(), MIGRATIONS.len());
+
+    let li = migrate(&mut conn, true).unwrap();
+    assert_eq!(li.len(), MIGRATIONS.len());
+    // nothing applied in a dry run
+    let status = migration_status(&conn).unwrap();
+    assert_eq!(status.version, 0);
+    let n: i64 = conn
+      .query_row("SELECT COUNT(*) FROM sqlite_master", (), |row| row.get(0))
+      .unwrap();
+    assert_eq!(n, 0);
+
+    migrate(&mut conn, false).unwrap();
+    let status = migration_status(&conn).unwrap();
+    assert_eq!(status.version, status.latest);
+    assert!(status.pending.is_empty());
+    // applied once
+    assert!(migrate(&mut conn, false).unwrap().is_empty());
+  }
+
+  #[test]
+  fn test_migrate_unversioned() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    // as created by releases before versioned migrations
+    create_tables(&conn).unwrap();
+    conn
+      .execute_batch(
+        "
+        INSERT INTO users ( auth_agent, auth_uid, language, time_zone, day_hrs_0, day_hrs_1 )
+        VALUES ( 'mock_auth_agent', 'mock_auth_uid', 'en-US', 'jst', 0, 3 );
+        INSERT INTO pvp_queries ( uid, modes, rules, includes, excludes )
+        VALUES ( 1, 1, 1, 5, 0 );
+        ",
+      )
+      .unwrap();
+    migrate(&mut conn, false).unwrap();
+    let (time_zone, availability): (String, Option<String>) = conn
+      .query_row("SELECT time_zone, availability FROM users", (), |row| {
+        Ok((row.get(0)?, row.get(1)?))
+      })
+      .unwrap();
+    assert_eq!(time_zone, "Asia/Tokyo");
+    assert!(availability.is_some());
+    let (include_stages, enabled): (String, bool) = conn
+      .query_row(
+        "SELECT include_stages, enabled FROM pvp_queries",
+        (),
+        |row| Ok((row.get(0)?, row.get(1)?)),
+      )
+      .unwrap();
+    assert_eq!(include_stages, "[1,3]");
+    assert!(enabled);
+  }
+
+  #[test]
+  fn test_migrate_day_hrs() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    migrate(&mut conn, false).unwrap();
+    // friday [0:00, 4:00) jst
+    conn
+      .execute(
+        "
+        INSERT INTO users ( auth_agent, auth_uid, language, time_zone, day_hrs_0, day_hrs_1 )
+        VALUES ( 'mock_auth_agent', 'mock_auth_uid', 'en-US', 'America/New_York', 0, 3 )
+        ",
+        (),
+      )
+      .unwrap();
+    migrate_day_hrs(&conn).unwrap();
+    let (availability, day_hrs_1): (String, i64) = conn
+      .query_row("SELECT availability, day_hrs_1 FROM users", (), |row| {
+        Ok((row.get(0)?, row.get(1)?))
+      })
+      .unwrap();
+    // thursday [10:00, 14:00) in new york, by standard time all the year
+    let availability: Availability = serde_json::from_str(&availability).unwrap();
+    assert_eq!(availability.thu, vec!["10:00-14:00".parse().unwrap()]);
+    assert!(availability.fri.is_empty());
+    assert_eq!(day_hrs_1, DAY_HRS_MAX);
+  }
+}
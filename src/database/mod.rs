@@ -1,17 +1,50 @@
-use std::{ops::Deref, path::Path};
+use std::{fmt::Display, ops::Deref, path::Path, str::FromStr};
 
-use chrono::{DateTime, FixedOffset};
-use r2d2::Pool;
+use chrono::{DateTime, Duration, DurationRound, TimeZone as _, Utc};
+use chrono_tz::Tz;
+use derivative::Derivative;
+use r2d2::{Pool, PooledConnection};
 use r2d2_sqlite::{rusqlite::Connection, SqliteConnectionManager};
-use serde::Deserialize;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_enum_str::{Deserialize_enum_str, Serialize_enum_str};
 use strum_macros::EnumIter;
 
-use crate::Result;
+use crate::{Error, Result};
+
+use self::migration::{migrate, migration_status};
+#[cfg(feature = "postgres")]
+use self::postgres::{PgConnection, PgPool};
+
+// forwards a call to the backend of a `DatabaseConnection`, binding the
+// backend connection to `$conn`
+macro_rules! dispatch {
+  ($self:expr, $conn:ident => $call:expr) => {
+    match $self {
+      $crate::database::DatabaseConnection::Sqlite($conn) => $call,
+      #[cfg(feature = "postgres")]
+      $crate::database::DatabaseConnection::Postgres($conn) => $call,
+    }
+  };
+}
+// for action agents with tables of their own, this module sees it already
+#[allow(unused_imports)]
+pub(crate) use dispatch;
 
 pub mod action;
+pub mod availability;
+pub mod coop;
+pub mod delivery;
+pub mod event;
+pub mod fest;
+pub mod gear;
+pub mod migration;
+pub mod outbox;
+pub mod pending;
+#[cfg(feature = "postgres")]
+pub mod postgres;
 pub mod pvp;
 pub mod query;
+pub mod spider;
 pub mod user;
 
 #[derive(
@@ -34,138 +67,398 @@ impl Language {
   }
 }
 
+// an iana time zone, e.g. `Asia/Tokyo`
+#[derive(Debug, Hash, PartialEq, Eq, Clone, Copy)]
+pub struct TimeZone(pub Tz);
+
+#[derive(thiserror::Error, Debug)]
+#[error("unknown time zone: [{0}]")]
+pub struct ParseTimeZoneError(String);
+
+impl TimeZone {
+  // splatnet rotations are aligned to jst
+  pub const JST: TimeZone = TimeZone(chrono_tz::Asia::Tokyo);
+
+  pub fn convert<T>(self, t: DateTime<T>) -> DateTime<Tz>
+  where
+    T: chrono::TimeZone,
+  {
+    t.with_timezone(&self.0)
+  }
+
+  // earliest time after `now` when the local clock reads `hour`:00, or the
+  // first valid time after it if skipped by a dst transition
+  pub fn next_local_hour(self, now: DateTime<Utc>, hour: u32) -> Option<DateTime<Utc>> {
+    let local = self.convert(now);
+    let mut date = local.date_naive();
+    for _ in 0..3 {
+      let t = date.and_hms_opt(hour, 0, 0)?;
+      let t = self.0.from_local_datetime(&t).earliest().or_else(|| {
+        self
+          .0
+          .from_local_datetime(&(t + Duration::hours(1)))
+          .earliest()
+      });
+      if let Some(t) = t.filter(|t| *t > local) {
+        return Some(t.with_timezone(&Utc));
+      }
+      date = date.succ_opt()?;
+    }
+    None
+  }
+}
+
+impl Default for TimeZone {
+  fn default() -> Self {
+    Self::JST
+  }
+}
+
+impl Display for TimeZone {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_str(self.0.name())
+  }
+}
+
+impl FromStr for TimeZone {
+  type Err = ParseTimeZoneError;
+
+  fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+    // fixed offset zones of earlier versions
+    let tz = match s {
+      "jst" => chrono_tz::Asia::Tokyo,
+      "pt" => chrono_tz::America::Los_Angeles,
+      "cet" => chrono_tz::Europe::Paris,
+      "cst" => chrono_tz::Asia::Shanghai,
+      _ => Tz::from_str(s).map_err(|_| ParseTimeZoneError(s.into()))?,
+    };
+    Ok(TimeZone(tz))
+  }
+}
+
+impl Serialize for TimeZone {
+  fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(self.0.name())
+  }
+}
+
+impl<'de> Deserialize<'de> for TimeZone {
+  fn deserialize<D: serde::Deserializer<'de>>(
+    deserializer: D,
+  ) -> std::result::Result<Self, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    TimeZone::from_str(&s).map_err(serde::de::Error::custom)
+  }
+}
+
 #[derive(
   Debug, Hash, PartialEq, Eq, Clone, Copy, Serialize_enum_str, Deserialize_enum_str, EnumIter,
 )]
 #[serde(rename_all = "lowercase")]
-pub enum TimeZone {
-  Jst = 9,
-  Pt = -7,
-  Cet = 2,
-  Cst = 8,
+pub enum DigestMode {
+  Immediate,
+  Hourly,
+  Daily,
 }
 
-impl TimeZone {
-  pub fn convert<T>(self, t: DateTime<T>) -> DateTime<FixedOffset>
-  where
-    T: chrono::TimeZone,
-  {
-    t.with_timezone(&FixedOffset::east_opt(self as i32 * 3600).unwrap())
+impl DigestMode {
+  // next delivery slot strictly after `now`, none for immediate delivery,
+  // where daily digests are sent at `hour` in the given time zone
+  pub fn next_fire_time(
+    self,
+    now: DateTime<Utc>,
+    hour: u32,
+    time_zone: TimeZone,
+  ) -> Option<DateTime<Utc>> {
+    match self {
+      Self::Immediate => None,
+      Self::Hourly => Some(now.duration_trunc(Duration::hours(1)).ok()? + Duration::hours(1)),
+      Self::Daily => time_zone.next_local_hour(now, hour),
+    }
   }
 }
 
-#[derive(Deserialize)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize_enum_str, Deserialize_enum_str)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalMode {
+  Delete,
+  Truncate,
+  Persist,
+  Memory,
+  Wal,
+  Off,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize_enum_str, Deserialize_enum_str)]
+#[serde(rename_all = "lowercase")]
+pub enum Synchronous {
+  Off,
+  Normal,
+  Full,
+  Extra,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DatabaseBackend {
+  Sqlite,
+  #[cfg(feature = "postgres")]
+  Postgres,
+}
+
+#[derive(Deserialize, Derivative)]
+#[derivative(Default)]
 pub struct DatabaseConfig {
+  #[serde(default = "default_backend")]
+  #[derivative(Default(value = "default_backend()"))]
+  pub backend: DatabaseBackend,
+  // database file of the sqlite backend
+  #[serde(default)]
   pub path: String,
+  // connection string of the postgres backend, e.g. `postgres://user@host/db`
+  #[serde(default)]
+  pub url: String,
+  #[serde(default = "default_pool_size")]
+  #[derivative(Default(value = "default_pool_size()"))]
+  pub pool_size: u32,
+  #[serde(default = "default_connection_timeout_secs")]
+  #[derivative(Default(value = "default_connection_timeout_secs()"))]
+  pub connection_timeout_secs: u64,
+  // wal lets the spider write while api requests keep reading, sqlite only
+  #[serde(default = "default_journal_mode")]
+  #[derivative(Default(value = "default_journal_mode()"))]
+  pub journal_mode: JournalMode,
+  // normal is durable enough under wal and saves an fsync per commit, sqlite only
+  #[serde(default = "default_synchronous")]
+  #[derivative(Default(value = "default_synchronous()"))]
+  pub synchronous: Synchronous,
+  // how long a connection waits on a locked database before SQLITE_BUSY, sqlite only
+  #[serde(default = "default_busy_timeout_ms")]
+  #[derivative(Default(value = "default_busy_timeout_ms()"))]
+  pub busy_timeout_ms: u64,
+}
+
+fn default_backend() -> DatabaseBackend {
+  DatabaseBackend::Sqlite
+}
+
+fn default_pool_size() -> u32 {
+  10
+}
+
+fn default_connection_timeout_secs() -> u64 {
+  30
+}
+
+fn default_journal_mode() -> JournalMode {
+  JournalMode::Wal
+}
+
+fn default_synchronous() -> Synchronous {
+  Synchronous::Normal
+}
+
+fn default_busy_timeout_ms() -> u64 {
+  5000
 }
 
 impl DatabaseConfig {
   pub fn collect(self) -> Result<Database> {
-    Database::new_from_file(self.path)
+    let db = self.connect()?;
+    db.migrate(false)?;
+    Ok(db)
+  }
+
+  // opens the database as is, see `Database::migrate`
+  pub fn connect(self) -> Result<Database> {
+    let connection_timeout = std::time::Duration::from_secs(self.connection_timeout_secs);
+    match self.backend {
+      DatabaseBackend::Sqlite => {
+        let Self {
+          journal_mode,
+          synchronous,
+          busy_timeout_ms,
+          ..
+        } = self;
+        let manager = SqliteConnectionManager::file(self.path).with_init(move |conn| {
+          do_init(conn)?;
+          conn.busy_timeout(std::time::Duration::from_millis(busy_timeout_ms))?;
+          // journal_mode reports the resulting mode as a row
+          conn
+            .pragma_update_and_check(None, "journal_mode", journal_mode.to_string(), |_| Ok(()))?;
+          conn.pragma_update(None, "synchronous", synchronous.to_string())
+        });
+        let pool = Pool::builder()
+          .max_size(self.pool_size)
+          .connection_timeout(connection_timeout)
+          .build(manager)?;
+        Ok(Database::Sqlite(pool))
+      }
+      #[cfg(feature = "postgres")]
+      DatabaseBackend::Postgres => {
+        let pool = postgres::connect(self.url.parse()?, self.pool_size, connection_timeout)?;
+        Ok(Database::Postgres(pool))
+      }
+    }
   }
 }
 
+// schema version of either backend
+pub struct SchemaStatus {
+  pub version: u32,
+  pub latest: u32,
+  // versions and names of the migrations to apply
+  pub pending: Vec<(u32, &'static str)>,
+}
+
 #[derive(Clone)]
-pub struct Database(Pool<SqliteConnectionManager>);
+pub enum Database {
+  Sqlite(Pool<SqliteConnectionManager>),
+  #[cfg(feature = "postgres")]
+  Postgres(PgPool),
+}
 
 impl Database {
   pub fn new_in_memory() -> Result<Database> {
-    // TODO: maybe any flags later
-    let manager = SqliteConnectionManager::memory().with_init(do_init);
-    Ok(Database(Pool::new(manager)?))
+    // every in-memory connection is a database of its own
+    let manager = SqliteConnectionManager::memory().with_init(|conn| {
+      do_init(conn)?;
+      migrate(conn, false).map(|_| ())
+    });
+    Ok(Database::Sqlite(Pool::new(manager)?))
   }
 
   pub fn new_from_file<P: AsRef<Path>>(path: P) -> Result<Database> {
-    // TODO: maybe any flags later
-    let manager = SqliteConnectionManager::file(path).with_init(do_init);
-    Ok(Database(Pool::new(manager)?))
+    DatabaseConfig {
+      path: path.as_ref().to_string_lossy().into_owned(),
+      ..Default::default()
+    }
+    .collect()
+  }
+
+  // an empty database for unit tests, on the local postgres server at
+  // `SPLATQUERY_TEST_POSTGRES` if set, or in memory otherwise
+  #[cfg(test)]
+  pub(crate) fn new_for_test() -> Result<Database> {
+    #[cfg(feature = "postgres")]
+    {
+      if let Ok(url) = std::env::var("SPLATQUERY_TEST_POSTGRES") {
+        return postgres::new_for_test(url.parse()?);
+      }
+    }
+    Database::new_in_memory()
+  }
+
+  pub fn migration_status(&self) -> Result<SchemaStatus> {
+    match self {
+      Self::Sqlite(pool) => {
+        let status = migration_status(&*pool.get()?)?;
+        Ok(SchemaStatus {
+          version: status.version,
+          latest: status.latest,
+          pending: status.pending.iter().map(|e| (e.version, e.name)).collect(),
+        })
+      }
+      #[cfg(feature = "postgres")]
+      Self::Postgres(pool) => postgres::migration_status(&PgConnection::get(pool)?),
+    }
+  }
+
+  // applies the pending migrations, or rolls them back for a dry run, and
+  // returns their versions and names
+  pub fn migrate(&self, dry_run: bool) -> Result<Vec<(u32, &'static str)>> {
+    match self {
+      Self::Sqlite(pool) => {
+        let li = migrate(&mut *pool.get()?, dry_run)?;
+        Ok(li.iter().map(|e| (e.version, e.name)).collect())
+      }
+      #[cfg(feature = "postgres")]
+      Self::Postgres(pool) => postgres::migrate(&PgConnection::get(pool)?, dry_run),
+    }
+  }
+
+  pub fn get(&self) -> Result<DatabaseConnection> {
+    match self {
+      Self::Sqlite(pool) => Ok(DatabaseConnection::Sqlite(pool.get()?)),
+      #[cfg(feature = "postgres")]
+      Self::Postgres(pool) => Ok(DatabaseConnection::Postgres(PgConnection::get(pool)?)),
+    }
+  }
+}
+
+// a pooled connection of either backend, which implements all the query
+// traits by forwarding to the backend, see `dispatch`
+pub enum DatabaseConnection {
+  Sqlite(PooledConnection<SqliteConnectionManager>),
+  #[cfg(feature = "postgres")]
+  Postgres(PgConnection),
+}
+
+impl DatabaseConnection {
+  pub fn transaction(&mut self) -> Result<Transaction<'_>> {
+    self.execute_batch("BEGIN")?;
+    Ok(Transaction {
+      conn: self,
+      finished: false,
+    })
+  }
+
+  fn execute_batch(&self, sql: &str) -> Result<()> {
+    dispatch!(self, conn => Ok(conn.execute_batch(sql)?))
+  }
+}
+
+// rolled back on drop unless committed
+pub struct Transaction<'a> {
+  conn: &'a mut DatabaseConnection,
+  finished: bool,
+}
+
+impl Transaction<'_> {
+  pub fn commit(mut self) -> Result<()> {
+    self.conn.execute_batch("COMMIT")?;
+    self.finished = true;
+    Ok(())
   }
 }
 
-impl Deref for Database {
-  type Target = Pool<SqliteConnectionManager>;
+impl Deref for Transaction<'_> {
+  type Target = DatabaseConnection;
   fn deref(&self) -> &Self::Target {
-    &self.0
+    self.conn
   }
 }
 
+impl Drop for Transaction<'_> {
+  fn drop(&mut self) {
+    if !self.finished {
+      if let Err(err) = self.conn.execute_batch("ROLLBACK") {
+        log::warn!("rollback failed: [{:?}]", err);
+      }
+    }
+  }
+}
+
+pub(crate) fn to_json<T: Serialize>(value: &T) -> Result<String> {
+  serde_json::to_string(value).map_err(|err| Error::InternalServerError(Box::new(err)))
+}
+
+pub(crate) fn from_json<T: DeserializeOwned>(s: &str) -> Result<T> {
+  serde_json::from_str(s).map_err(|err| Error::InternalServerError(Box::new(err)))
+}
+
+// reminder lead of a user over all the matched queries, where a query
+// follows user settings if not specified: at announcement if any query asks
+// for it, otherwise the longest lead
+fn remind_mins_sql(queries: &str) -> String {
+  let e = format!("COALESCE({queries}.remind_mins, users.remind_mins)");
+  format!("CASE WHEN MIN({e}) < 0 THEN -1 ELSE MAX({e}) END")
+}
+
+// jst 2-hour slot bitmasks of earlier versions, all set for any time
+pub(crate) const DAY_HRS_MAX: i64 = (1i64 << 48) - 1;
+
 fn do_init(conn: &mut Connection) -> Result<(), r2d2_sqlite::rusqlite::Error> {
   conn.execute("PRAGMA foreign_keys = ON", ())?;
-  conn.execute_batch(
-    "BEGIN;
-  
-    CREATE TABLE IF NOT EXISTS
-    users (
-      id                  INTEGER PRIMARY KEY AUTOINCREMENT,
-      created_time        DATETIME DEFAULT CURRENT_TIMESTAMP,
-      auth_agent          TEXT NOT NULL,
-      auth_uid            TEXT NOT NULL,
-      name                TEXT,
-      email               TEXT,
-      picture             TEXT,
-      language            TEXT NOT NULL,
-      time_zone           TEXT NOT NULL,
-      day_hrs_0           INTEGER NOT NULL,   /* jst wd [0,4), 12 bits for each day  */
-      day_hrs_1           INTEGER NOT NULL,   /* [4,7) */
-      UNIQUE ( auth_uid, auth_agent )
-    );
-
-    CREATE TABLE IF NOT EXISTS
-    pvp_queries (
-      id                  INTEGER PRIMARY KEY AUTOINCREMENT,
-      uid                 INTEGER NOT NULL,
-      created_time        DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
-      modes               TINYINT NOT NULL,
-      rules               TINYINT NOT NULL,
-      includes            INT NOT NULL,
-      excludes            INT NOT NULL,
-      FOREIGN KEY ( uid ) REFERENCES users ( id ) ON DELETE CASCADE
-    );
-
-    CREATE INDEX IF NOT EXISTS pvp_queries_index
-    ON pvp_queries ( uid );
-
-    CREATE TABLE IF NOT EXISTS
-    user_action_agents (
-      id                  INTEGER PRIMARY KEY AUTOINCREMENT,
-      uid                 INTEGER NOT NULL,
-      act_agent           TEXT NOT NULL,
-      act_active          TINYINT NOT NULL,
-      FOREIGN KEY ( uid ) REFERENCES users ( id ) ON DELETE CASCADE,
-      UNIQUE ( uid, act_agent )
-    );
-
-    CREATE INDEX IF NOT EXISTS user_action_agents_index
-    ON user_action_agents ( uid );
-
-    CREATE TABLE IF NOT EXISTS
-    user_actions (
-      id                  INTEGER PRIMARY KEY AUTOINCREMENT,
-      uid                 INTEGER NOT NULL,
-      aid                 INTEGER NOT NULL,
-      rx_pvp              INTEGER NOT NULL DEFAULT 0,
-      rx_event            INTEGER NOT NULL DEFAULT 0,
-      rx_coop             INTEGER NOT NULL DEFAULT 0,
-      rx_coop_ex          INTEGER NOT NULL DEFAULT 0,
-      rx_gear             INTEGER NOT NULL DEFAULT 0,
-      rx_gear_brand       INTEGER NOT NULL DEFAULT 0,
-      FOREIGN KEY ( aid ) REFERENCES user_action_agents ( id ) ON DELETE CASCADE
-    );
-
-    CREATE TABLE IF NOT EXISTS
-    webpush_ext_info (
-      id                  INTEGER UNIQUE NOT NULL,
-      uid                 INTEGER NOT NULL,
-      endpoint            TEXT NOT NULL,
-      p256dh              TEXT NOT NULL,
-      auth                TEXT NOT NULL,
-      browser             TEXT,
-      device              TEXT,
-      os                  TEXT,
-      FOREIGN KEY ( id ) REFERENCES user_actions ( id ) ON DELETE CASCADE,
-      UNIQUE ( endpoint, uid )
-    );
-
-    COMMIT;",
-  )
+  Ok(())
 }
@@ -0,0 +1,381 @@
+use chrono::{DateTime, Utc};
+use r2d2_sqlite::rusqlite::Connection;
+
+use crate::{Error, Result};
+
+use super::DatabaseConnection;
+
+#[derive(Debug)]
+pub struct CreateOutboxRequest<'a> {
+  pub uid: i64,
+  // user action id
+  pub id: i64,
+  // json encoded message
+  pub message: &'a str,
+  // json encoded user_actions columns and timestamps to bump on delivery
+  pub marks: &'a str,
+}
+
+pub trait CreateOutbox {
+  // queues a message for delivery right away, once per action
+  fn create_outbox(&self, request: CreateOutboxRequest) -> Result<bool>;
+}
+
+#[derive(Debug)]
+pub struct ClaimOutboxRequest {
+  pub now: DateTime<Utc>,
+  // claimed entries are retried after this if not finished by then
+  pub lease_until: DateTime<Utc>,
+  pub limit: u32,
+}
+
+#[derive(Debug)]
+pub struct ClaimOutboxResponse {
+  pub oid: i64,
+  pub id: i64,
+  pub uid: i64,
+  pub agent: String,
+  pub message: String,
+  pub marks: String,
+  // including this one
+  pub attempts: u32,
+}
+
+pub trait ClaimOutbox {
+  // takes up to `limit` entries due by `now` for an attempt, which no other
+  // worker claims again until the lease ends
+  fn claim_outbox(&self, request: ClaimOutboxRequest) -> Result<Vec<ClaimOutboxResponse>>;
+}
+
+#[derive(Debug)]
+pub struct RetryOutboxRequest<'a> {
+  pub oid: i64,
+  // none to give up
+  pub next_attempt: Option<DateTime<Utc>>,
+  pub error: &'a str,
+}
+
+pub trait RetryOutbox {
+  fn retry_outbox(&self, request: RetryOutboxRequest) -> Result<()>;
+}
+
+pub trait DeleteOutbox {
+  // removes a delivered or dropped entry
+  fn delete_outbox(&self, oid: i64) -> Result<()>;
+}
+
+#[derive(Debug)]
+pub struct ListOutboxResponse {
+  pub oid: i64,
+  pub id: i64,
+  pub uid: i64,
+  pub attempts: u32,
+  // none if given up
+  pub next_attempt: Option<i64>,
+  pub last_error: Option<String>,
+  pub created_time: String,
+}
+
+pub trait ListOutbox {
+  fn list_outbox(&self) -> Result<Vec<ListOutboxResponse>>;
+}
+
+#[derive(Debug)]
+pub struct ResetOutboxRequest {
+  // all given up entries if none
+  pub oid: Option<i64>,
+  pub now: DateTime<Utc>,
+}
+
+pub trait ResetOutbox {
+  // queues given up entries again with a fresh attempt count
+  fn reset_outbox(&self, request: ResetOutboxRequest) -> Result<usize>;
+}
+
+impl CreateOutbox for DatabaseConnection {
+  fn create_outbox(&self, request: CreateOutboxRequest) -> Result<bool> {
+    dispatch!(self, conn => conn.create_outbox(request))
+  }
+}
+
+impl CreateOutbox for Connection {
+  fn create_outbox(&self, request: CreateOutboxRequest) -> Result<bool> {
+    let CreateOutboxRequest {
+      uid,
+      id,
+      message,
+      marks,
+    } = request;
+    let mut stmt = self.prepare_cached(
+      "
+      INSERT OR IGNORE
+      INTO outbox ( uid, aid, message, marks, next_attempt )
+      VALUES ( ?1, ?2, ?3, ?4, ?5 )
+      ",
+    )?;
+    let n = stmt.execute((&uid, &id, &message, &marks, &Utc::now().timestamp()))?;
+    Ok(n > 0)
+  }
+}
+
+impl ClaimOutbox for DatabaseConnection {
+  fn claim_outbox(&self, request: ClaimOutboxRequest) -> Result<Vec<ClaimOutboxResponse>> {
+    dispatch!(self, conn => conn.claim_outbox(request))
+  }
+}
+
+impl ClaimOutbox for Connection {
+  fn claim_outbox(&self, request: ClaimOutboxRequest) -> Result<Vec<ClaimOutboxResponse>> {
+    let ClaimOutboxRequest {
+      now,
+      lease_until,
+      limit,
+    } = request;
+    let now = now.timestamp();
+    let mut stmt = self.prepare_cached(
+      "
+      SELECT outbox.id, outbox.aid, outbox.uid, act_agent, message, marks, attempts
+      FROM outbox
+        INNER JOIN user_actions ON outbox.aid == user_actions.id
+        INNER JOIN user_action_agents ON user_actions.aid == user_action_agents.id
+      WHERE next_attempt <= ?1
+      ORDER BY next_attempt, outbox.id
+      LIMIT ?2
+      ",
+    )?;
+    let iter = stmt.query_map((&now, &limit), |row| {
+      Ok(ClaimOutboxResponse {
+        oid: row.get(0)?,
+        id: row.get(1)?,
+        uid: row.get(2)?,
+        agent: row.get(3)?,
+        message: row.get(4)?,
+        marks: row.get(5)?,
+        attempts: row.get(6)?,
+      })
+    })?;
+    let li: Vec<_> = itertools::process_results(iter, |iter| iter.collect())?;
+    // only the worker whose update is not preempted gets the entry
+    let mut stmt = self.prepare_cached(
+      "
+      UPDATE outbox
+      SET attempts = attempts + 1, next_attempt = ?3
+      WHERE id = ?1 AND next_attempt <= ?2
+      ",
+    )?;
+    let mut claimed = vec![];
+    for mut e in li.into_iter() {
+      if stmt.execute((&e.oid, &now, &lease_until.timestamp()))? == 1 {
+        e.attempts += 1;
+        claimed.push(e);
+      }
+    }
+    Ok(claimed)
+  }
+}
+
+impl RetryOutbox for DatabaseConnection {
+  fn retry_outbox(&self, request: RetryOutboxRequest) -> Result<()> {
+    dispatch!(self, conn => conn.retry_outbox(request))
+  }
+}
+
+impl RetryOutbox for Connection {
+  fn retry_outbox(&self, request: RetryOutboxRequest) -> Result<()> {
+    let RetryOutboxRequest {
+      oid,
+      next_attempt,
+      error,
+    } = request;
+    let mut stmt = self.prepare_cached(
+      "
+      UPDATE outbox
+      SET next_attempt = ?2, last_error = ?3
+      WHERE id = ?1
+      ",
+    )?;
+    let n = stmt.execute((&oid, &next_attempt.map(|e| e.timestamp()), &error))?;
+    if n != 1 {
+      Err(Error::NotFound)
+    } else {
+      Ok(())
+    }
+  }
+}
+
+impl DeleteOutbox for DatabaseConnection {
+  fn delete_outbox(&self, oid: i64) -> Result<()> {
+    dispatch!(self, conn => conn.delete_outbox(oid))
+  }
+}
+
+impl DeleteOutbox for Connection {
+  fn delete_outbox(&self, oid: i64) -> Result<()> {
+    let mut stmt = self.prepare_cached(
+      "
+      DELETE FROM outbox
+      WHERE id = ?1",
+    )?;
+    let n = stmt.execute((&oid,))?;
+    if n != 1 {
+      Err(Error::NotFound)
+    } else {
+      Ok(())
+    }
+  }
+}
+
+impl ListOutbox for DatabaseConnection {
+  fn list_outbox(&self) -> Result<Vec<ListOutboxResponse>> {
+    dispatch!(self, conn => conn.list_outbox())
+  }
+}
+
+impl ListOutbox for Connection {
+  fn list_outbox(&self) -> Result<Vec<ListOutboxResponse>> {
+    let mut stmt = self.prepare_cached(
+      "
+      SELECT id, aid, uid, attempts, next_attempt, last_error, created_time
+      FROM outbox
+      ORDER BY id
+      ",
+    )?;
+    let iter = stmt.query_map((), |row| {
+      Ok(ListOutboxResponse {
+        oid: row.get(0)?,
+        id: row.get(1)?,
+        uid: row.get(2)?,
+        attempts: row.get(3)?,
+        next_attempt: row.get(4)?,
+        last_error: row.get(5)?,
+        created_time: row.get(6)?,
+      })
+    })?;
+    let li = itertools::process_results(iter, |iter| iter.collect())?;
+    Ok(li)
+  }
+}
+
+impl ResetOutbox for DatabaseConnection {
+  fn reset_outbox(&self, request: ResetOutboxRequest) -> Result<usize> {
+    dispatch!(self, conn => conn.reset_outbox(request))
+  }
+}
+
+impl ResetOutbox for Connection {
+  fn reset_outbox(&self, request: ResetOutboxRequest) -> Result<usize> {
+    let mut stmt = self.prepare_cached(
+      "
+      UPDATE outbox
+      SET attempts = 0, next_attempt = ?2
+      WHERE next_attempt IS NULL AND ( ?1 IS NULL OR id = ?1 )
+      ",
+    )?;
+    let n = stmt.execute((&request.oid, &request.now.timestamp()))?;
+    Ok(n)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::Duration;
+
+  use crate::database::{
+    action::CreateAction,
+    user::{CreateUser, CreateUserRequest, LookupUserId, LookupUserIdRequest},
+    Database,
+  };
+
+  use super::*;
+
+  #[test]
+  fn test_claim_and_retry() {
+    let db = Database::new_for_test().unwrap();
+    let mut conn = db.get().unwrap();
+
+    let auth_agent = "mock_auth_agent";
+    let auth_uid = "mock_auth_uid";
+    let ok = conn
+      .create_user(CreateUserRequest {
+        auth_agent,
+        auth_uid,
+        email: None,
+        name: None,
+        picture: None,
+        language: None,
+        time_zone: None,
+        availability: None,
+      })
+      .unwrap();
+    assert!(ok);
+    let uid = conn
+      .lookup_user_id(LookupUserIdRequest {
+        auth_agent,
+        auth_uid,
+      })
+      .unwrap();
+
+    let tx = conn.transaction().unwrap();
+    let act_agent = "mock_act_agent";
+    let id = tx.create_action(uid, act_agent).unwrap();
+    tx.commit().unwrap();
+
+    let request = || CreateOutboxRequest {
+      uid,
+      id,
+      message: "{}",
+      marks: "[[\"rx_pvp\",0]]",
+    };
+    assert!(conn.create_outbox(request()).unwrap());
+    // the same message is only queued once
+    assert!(!conn.create_outbox(request()).unwrap());
+
+    let now = Utc::now();
+    let claim = |now: DateTime<Utc>| {
+      conn
+        .claim_outbox(ClaimOutboxRequest {
+          now,
+          lease_until: now + Duration::minutes(5),
+          limit: 8,
+        })
+        .unwrap()
+    };
+    let li = claim(now);
+    assert_eq!(li.len(), 1);
+    assert_eq!(li[0].id, id);
+    assert_eq!(li[0].agent, act_agent);
+    assert_eq!(li[0].attempts, 1);
+    let oid = li[0].oid;
+    // leased to the first worker
+    assert_eq!(claim(now).len(), 0);
+    // retried once the lease ends
+    let li = claim(now + Duration::minutes(5));
+    assert_eq!(li.len(), 1);
+    assert_eq!(li[0].attempts, 2);
+
+    conn
+      .retry_outbox(RetryOutboxRequest {
+        oid,
+        next_attempt: None,
+        error: "mock error",
+      })
+      .unwrap();
+    // given up
+    assert_eq!(claim(now + Duration::days(1)).len(), 0);
+    let li = conn.list_outbox().unwrap();
+    assert_eq!(li.len(), 1);
+    assert_eq!(li[0].next_attempt, None);
+    assert_eq!(li[0].last_error.as_deref(), Some("mock error"));
+
+    let n = conn
+      .reset_outbox(ResetOutboxRequest { oid: None, now })
+      .unwrap();
+    assert_eq!(n, 1);
+    let li = claim(now);
+    assert_eq!(li.len(), 1);
+    assert_eq!(li[0].attempts, 1);
+
+    conn.delete_outbox(oid).unwrap();
+    assert!(conn.list_outbox().unwrap().is_empty());
+  }
+}
@@ -0,0 +1,295 @@
+use chrono::{DateTime, Utc};
+use r2d2_sqlite::rusqlite::Connection;
+
+use crate::Result;
+
+use super::{from_json, DatabaseConnection};
+
+#[derive(Debug)]
+pub struct CreatePendingNotificationRequest<'a> {
+  pub uid: i64,
+  // user action id
+  pub id: i64,
+  pub rx: &'a str,
+  pub ts: DateTime<Utc>,
+  pub fire_time: DateTime<Utc>,
+  // json encoded message
+  pub message: &'a str,
+}
+
+pub trait CreatePendingNotification {
+  fn create_pending_notification(&self, request: CreatePendingNotificationRequest) -> Result<bool>;
+}
+
+#[derive(Debug)]
+pub struct TakePendingNotificationRequest {
+  pub now: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub struct TakePendingNotificationResponse {
+  pub id: i64,
+  pub uid: i64,
+  pub agent: String,
+  pub rx: String,
+  pub ts: i64,
+  pub fire_time: i64,
+  pub message: String,
+  // notifications of inactive actions are taken as well, to be dropped
+  pub active: bool,
+}
+
+pub trait TakePendingNotification {
+  // removes all the notifications due by `now` and returns them, ordered by
+  // action and fire time, so that a digest comes out consecutively
+  fn take_pending_notification(
+    &self,
+    request: TakePendingNotificationRequest,
+  ) -> Result<Vec<TakePendingNotificationResponse>>;
+}
+
+#[derive(Debug)]
+pub struct LookupQueuedNotificationRequest<'a> {
+  // user action id
+  pub id: i64,
+  pub rx: &'a str,
+  pub ts: DateTime<Utc>,
+}
+
+pub trait LookupQueuedNotification {
+  // whether the action already holds the item, either pending or in the
+  // outbox, so that dispatching it again queues nothing
+  fn lookup_queued_notification(&self, request: LookupQueuedNotificationRequest) -> Result<bool>;
+}
+
+// whether any of the json encoded outbox marks covers the item
+pub(crate) fn is_marked(marks: &[String], rx: &str, ts: i64) -> Result<bool> {
+  for e in marks.iter() {
+    let marks: Vec<(String, i64)> = from_json(e)?;
+    if marks.iter().any(|(k, v)| k == rx && *v == ts) {
+      return Ok(true);
+    }
+  }
+  Ok(false)
+}
+
+impl CreatePendingNotification for DatabaseConnection {
+  fn create_pending_notification(&self, request: CreatePendingNotificationRequest) -> Result<bool> {
+    dispatch!(self, conn => conn.create_pending_notification(request))
+  }
+}
+
+impl CreatePendingNotification for Connection {
+  fn create_pending_notification(&self, request: CreatePendingNotificationRequest) -> Result<bool> {
+    let CreatePendingNotificationRequest {
+      uid,
+      id,
+      rx,
+      ts,
+      fire_time,
+      message,
+    } = request;
+    let mut stmt = self.prepare_cached(
+      "
+      INSERT OR IGNORE
+      INTO pending_notifications ( uid, aid, rx, ts, fire_time, message )
+      VALUES ( ?1, ?2, ?3, ?4, ?5, ?6 )
+      ",
+    )?;
+    let n = stmt.execute((
+      &uid,
+      &id,
+      &rx,
+      &ts.timestamp(),
+      &fire_time.timestamp(),
+      &message,
+    ))?;
+    Ok(n > 0)
+  }
+}
+
+impl TakePendingNotification for DatabaseConnection {
+  fn take_pending_notification(
+    &self,
+    request: TakePendingNotificationRequest,
+  ) -> Result<Vec<TakePendingNotificationResponse>> {
+    dispatch!(self, conn => conn.take_pending_notification(request))
+  }
+}
+
+impl TakePendingNotification for Connection {
+  fn take_pending_notification(
+    &self,
+    request: TakePendingNotificationRequest,
+  ) -> Result<Vec<TakePendingNotificationResponse>> {
+    let now = request.now.timestamp();
+    let mut stmt = self.prepare_cached(
+      "
+      SELECT pending_notifications.aid, pending_notifications.uid, act_agent, rx, ts, fire_time,
+        message, act_active
+      FROM pending_notifications
+        INNER JOIN user_actions ON pending_notifications.aid == user_actions.id
+        INNER JOIN user_action_agents ON user_actions.aid == user_action_agents.id
+      WHERE fire_time <= ?1
+      ORDER BY pending_notifications.aid, fire_time, pending_notifications.id
+      ",
+    )?;
+    let iter = stmt.query_map((&now,), |row| {
+      Ok(TakePendingNotificationResponse {
+        id: row.get(0)?,
+        uid: row.get(1)?,
+        agent: row.get(2)?,
+        rx: row.get(3)?,
+        ts: row.get(4)?,
+        fire_time: row.get(5)?,
+        message: row.get(6)?,
+        active: row.get(7)?,
+      })
+    })?;
+    let li = itertools::process_results(iter, |iter| iter.collect())?;
+    let mut stmt = self.prepare_cached(
+      "
+      DELETE FROM pending_notifications
+      WHERE fire_time <= ?1",
+    )?;
+    stmt.execute((&now,))?;
+    Ok(li)
+  }
+}
+
+impl LookupQueuedNotification for DatabaseConnection {
+  fn lookup_queued_notification(&self, request: LookupQueuedNotificationRequest) -> Result<bool> {
+    dispatch!(self, conn => conn.lookup_queued_notification(request))
+  }
+}
+
+impl LookupQueuedNotification for Connection {
+  fn lookup_queued_notification(&self, request: LookupQueuedNotificationRequest) -> Result<bool> {
+    let LookupQueuedNotificationRequest { id, rx, ts } = request;
+    let ts = ts.timestamp();
+    let mut stmt = self.prepare_cached(
+      "
+      SELECT EXISTS (
+        SELECT 1 FROM pending_notifications
+        WHERE aid = ?1 AND rx = ?2 AND ts = ?3
+      )
+      ",
+    )?;
+    if stmt.query_row((&id, &rx, &ts), |row| row.get(0))? {
+      return Ok(true);
+    }
+    let mut stmt = self.prepare_cached(
+      "
+      SELECT marks FROM outbox
+      WHERE aid = ?1
+      ",
+    )?;
+    let iter = stmt.query_map((&id,), |row| row.get(0))?;
+    let marks: Vec<String> = itertools::process_results(iter, |iter| iter.collect())?;
+    is_marked(&marks, rx, ts)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::Duration;
+
+  use crate::database::{
+    action::{CreateAction, ToggleAction},
+    user::{CreateUser, CreateUserRequest, LookupUserId, LookupUserIdRequest},
+    Database,
+  };
+
+  use super::*;
+
+  #[test]
+  fn test_create_and_take() {
+    let db = Database::new_for_test().unwrap();
+    let mut conn = db.get().unwrap();
+
+    let auth_agent = "mock_auth_agent";
+    let auth_uid = "mock_auth_uid";
+    let ok = conn
+      .create_user(CreateUserRequest {
+        auth_agent,
+        auth_uid,
+        email: None,
+        name: None,
+        picture: None,
+        language: None,
+        time_zone: None,
+        availability: None,
+      })
+      .unwrap();
+    assert!(ok);
+    let uid = conn
+      .lookup_user_id(LookupUserIdRequest {
+        auth_agent,
+        auth_uid,
+      })
+      .unwrap();
+
+    let tx = conn.transaction().unwrap();
+    let act_agent = "mock_act_agent";
+    let id = tx.create_action(uid, act_agent).unwrap();
+    tx.commit().unwrap();
+
+    let now = Utc::now();
+    let request = || CreatePendingNotificationRequest {
+      uid,
+      id,
+      rx: "rx_pvp",
+      ts: now + Duration::hours(1),
+      fire_time: now + Duration::minutes(30),
+      message: "{}",
+    };
+    assert!(conn.create_pending_notification(request()).unwrap());
+    // the same message is only scheduled once
+    assert!(!conn.create_pending_notification(request()).unwrap());
+    assert!(conn
+      .create_pending_notification(CreatePendingNotificationRequest {
+        rx: "rx_coop",
+        message: "[]",
+        ..request()
+      })
+      .unwrap());
+
+    let li = conn
+      .take_pending_notification(TakePendingNotificationRequest { now })
+      .unwrap();
+    // not due yet
+    assert_eq!(li.len(), 0);
+
+    let later = now + Duration::minutes(30);
+    let li = conn
+      .take_pending_notification(TakePendingNotificationRequest { now: later })
+      .unwrap();
+    // both due at the same time for a digest
+    assert_eq!(li.len(), 2);
+    assert_eq!(li[0].id, id);
+    assert_eq!(li[0].agent, act_agent);
+    assert_eq!(li[0].rx, "rx_pvp");
+    assert_eq!(li[1].rx, "rx_coop");
+    assert_eq!(li[0].fire_time, li[1].fire_time);
+    assert!(li[0].active);
+
+    let li = conn
+      .take_pending_notification(TakePendingNotificationRequest { now: later })
+      .unwrap();
+    // already taken
+    assert_eq!(li.len(), 0);
+
+    // taken for the record even if the action is turned off
+    conn.toggle_action(uid, act_agent, false).unwrap();
+    assert!(conn.create_pending_notification(request()).unwrap());
+    let li = conn
+      .take_pending_notification(TakePendingNotificationRequest { now: later })
+      .unwrap();
+    assert_eq!(li.len(), 1);
+    assert!(!li[0].active);
+    let li = conn
+      .take_pending_notification(TakePendingNotificationRequest { now: later })
+      .unwrap();
+    assert_eq!(li.len(), 0);
+  }
+}
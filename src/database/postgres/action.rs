@@ -0,0 +1,198 @@
+use std::str::FromStr;
+
+use crate::{
+  database::{
+    action::{
+      CreateAction, DeleteAction, ExpireAction, ExpireActionRequest, ListAction,
+      ListActionResponse, LookupActionDigest, LookupActionDigestResponse, ToggleAction,
+      UpdateActionDigest, UpdateActionRx,
+    },
+    DigestMode, TimeZone,
+  },
+  Error, Result,
+};
+
+use super::PgConnection;
+
+impl CreateAction for PgConnection {
+  fn create_action(&self, uid: i64, agent: &str) -> Result<i64> {
+    self.execute(
+      "
+      INSERT INTO user_action_agents ( uid, act_agent, act_active )
+      VALUES ( $1, $2, TRUE )
+      ON CONFLICT DO NOTHING
+      ",
+      &[&uid, &agent],
+    )?;
+    let aid: i64 = self
+      .query_row(
+        "
+        SELECT id
+        FROM user_action_agents
+        WHERE uid = $1 AND act_agent = $2
+        ",
+        &[&uid, &agent],
+      )?
+      .try_get(0)?;
+    let id = self
+      .query_row(
+        "
+        INSERT INTO user_actions ( aid, uid )
+        VALUES ( $1, $2 )
+        RETURNING id
+        ",
+        &[&aid, &uid],
+      )?
+      .try_get(0)?;
+    Ok(id)
+  }
+}
+
+impl DeleteAction for PgConnection {
+  fn delete_action(&self, uid: i64, id: i64) -> Result<()> {
+    self.execute(
+      "
+      DELETE FROM user_actions
+      WHERE uid = $1 AND id = $2
+      ",
+      &[&uid, &id],
+    )?;
+    Ok(())
+  }
+}
+
+impl ExpireAction for PgConnection {
+  fn expire_action(&self, request: ExpireActionRequest) -> Result<bool> {
+    let ExpireActionRequest {
+      uid,
+      id,
+      time,
+      reason,
+    } = request;
+    let n = self.execute(
+      "
+      UPDATE user_actions
+      SET expired_time = $3, expired_reason = $4
+      WHERE uid = $1 AND id = $2 AND expired_time IS NULL
+      ",
+      &[&uid, &id, &time.timestamp(), &reason],
+    )?;
+    self.execute(
+      "
+      DELETE FROM outbox
+      WHERE aid = $1
+      ",
+      &[&id],
+    )?;
+    self.execute(
+      "
+      DELETE FROM pending_notifications
+      WHERE aid = $1
+      ",
+      &[&id],
+    )?;
+    Ok(n > 0)
+  }
+}
+
+impl ToggleAction for PgConnection {
+  fn toggle_action(&self, uid: i64, agent: &str, active: bool) -> Result<()> {
+    self.execute(
+      "
+      UPDATE user_action_agents
+      SET act_active = $3
+      WHERE uid = $1 AND act_agent = $2
+      ",
+      &[&uid, &agent, &active],
+    )?;
+    Ok(())
+  }
+}
+
+impl UpdateActionDigest for PgConnection {
+  fn update_action_digest(&self, uid: i64, agent: &str, digest: Option<DigestMode>) -> Result<()> {
+    self.execute(
+      "
+      UPDATE user_action_agents
+      SET digest = $3
+      WHERE uid = $1 AND act_agent = $2
+      ",
+      &[&uid, &agent, &digest.map(|e| e.to_string())],
+    )?;
+    Ok(())
+  }
+}
+
+impl LookupActionDigest for PgConnection {
+  fn lookup_action_digest(&self, id: i64) -> Result<LookupActionDigestResponse> {
+    let row = self.query_row(
+      "
+      SELECT COALESCE(user_action_agents.digest, users.digest), digest_hour, time_zone
+      FROM user_actions
+        INNER JOIN user_action_agents ON user_actions.aid = user_action_agents.id
+        INNER JOIN users ON user_actions.uid = users.id
+      WHERE user_actions.id = $1
+      ",
+      &[&id],
+    )?;
+    let mode: String = row.try_get(0)?;
+    let hour: i32 = row.try_get(1)?;
+    let time_zone: String = row.try_get(2)?;
+    Ok(LookupActionDigestResponse {
+      mode: DigestMode::from_str(&mode).map_err(|err| Error::InternalServerError(Box::new(err)))?,
+      hour: hour as u32,
+      time_zone: TimeZone::from_str(&time_zone)
+        .map_err(|err| Error::InternalServerError(Box::new(err)))?,
+    })
+  }
+}
+
+impl ListAction for PgConnection {
+  fn list_action(&self, uid: i64) -> Result<Vec<ListActionResponse>> {
+    let rows = self.query(
+      "
+      SELECT user_actions.id, user_action_agents.act_agent, user_action_agents.act_active,
+        user_action_agents.digest, (
+          SELECT MAX(time) FROM deliveries
+          WHERE aid = user_actions.id AND status = 'delivered'
+        ), user_actions.expired_time, user_actions.expired_reason
+      FROM user_action_agents
+        INNER JOIN user_actions
+          ON user_action_agents.id = user_actions.aid
+      WHERE user_action_agents.uid = $1
+      ORDER BY user_actions.id
+      ",
+      &[&uid],
+    )?;
+    let mut li = vec![];
+    for row in rows.iter() {
+      li.push(ListActionResponse {
+        id: row.try_get(0)?,
+        agent: row.try_get(1)?,
+        active: row.try_get(2)?,
+        digest: row
+          .try_get::<_, Option<String>>(3)?
+          .and_then(|e| DigestMode::from_str(&e).ok()),
+        last_delivered: row.try_get(4)?,
+        expired_time: row.try_get(5)?,
+        expired_reason: row.try_get(6)?,
+      });
+    }
+    Ok(li)
+  }
+}
+
+impl UpdateActionRx for PgConnection {
+  fn update_action_rx(&self, uid: i64, id: i64, rx: &str, ts: i64) -> Result<()> {
+    let sql = format!(
+      "
+      UPDATE user_actions
+      SET {rx} = GREATEST({rx}, $3)
+      WHERE uid = $1 AND id = $2
+      ",
+      rx = rx
+    );
+    self.execute(&sql, &[&uid, &id, &ts])?;
+    Ok(())
+  }
+}
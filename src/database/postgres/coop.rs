@@ -0,0 +1,213 @@
+use appendlist::AppendList;
+
+use crate::{
+  database::{
+    coop::{
+      CoopQueryRecord, CreateCoopQuery, CreateCoopQueryRequest, DeleteCoopQuery,
+      DeleteCoopQueryRequest, ListCoopQuery, ListCoopQueryRequest, ListCoopQueryResponse,
+      LookupCoop, LookupCoopRequest, LookupCoopResponse, UpdateCoopQuery, UpdateCoopQueryRequest,
+    },
+    from_json, remind_mins_sql, to_json,
+  },
+  splatnet::CoopMode,
+  Error, Result,
+};
+
+use super::PgConnection;
+
+impl CreateCoopQuery for PgConnection {
+  fn create_coop_query(&self, request: CreateCoopQueryRequest) -> Result<i64> {
+    let CreateCoopQueryRequest {
+      uid,
+      record:
+        CoopQueryRecord {
+          modes,
+          includes,
+          excludes,
+          weapons,
+          forbidden_weapons,
+          king_salmonids,
+          remind_mins,
+          enabled,
+        },
+    } = request;
+    let id = self
+      .query_row(
+        "
+        INSERT INTO coop_queries ( uid, modes, includes, excludes, weapons, forbidden_weapons, king_salmonids, remind_mins, enabled )
+        VALUES ( $1, $2, $3, $4, $5, $6, $7, $8, $9 )
+        RETURNING id
+        ",
+        &[
+          &uid,
+          &(*modes as i32),
+          &to_json(includes)?,
+          &to_json(excludes)?,
+          &to_json(weapons)?,
+          &to_json(forbidden_weapons)?,
+          &(*king_salmonids as i32),
+          remind_mins,
+          enabled,
+        ],
+      )?
+      .try_get(0)?;
+    Ok(id)
+  }
+}
+
+impl LookupCoop for PgConnection {
+  fn lookup_coop(&self, request: LookupCoopRequest) -> Result<AppendList<LookupCoopResponse>> {
+    let LookupCoopRequest {
+      start_time,
+      mode,
+      stage,
+      weapons,
+      king_salmonid,
+    } = request;
+    // big run and eggstra work overlap with regular schedules
+    let rx = match mode {
+      CoopMode::Regular => "rx_coop",
+      _ => "rx_coop_ex",
+    };
+    let mode = mode as u8 as i32;
+    let king_salmonid = king_salmonid as u8 as i32;
+    let stage = stage as i32;
+    let weapons = to_json(&weapons)?;
+    let ts = start_time.timestamp();
+    let remind_mins = remind_mins_sql("coop_queries");
+    // json arrays are kept as text, see the sqlite backend for the conditions
+    let sql = format!(
+      "
+      SELECT user_actions.id, uid_1, act_agent, remind_1
+      FROM (
+        SELECT uid as uid_1, {remind_mins} as remind_1
+        FROM coop_queries
+          INNER JOIN users ON uid = users.id
+        WHERE
+          enabled AND
+          modes & $5 != 0 AND
+          ( king_salmonids = 0 OR king_salmonids & $2 != 0 ) AND
+          ( includes = '[]' OR includes::jsonb @> jsonb_build_array($1::INTEGER) ) AND
+          NOT excludes::jsonb @> jsonb_build_array($1::INTEGER) AND
+          $3::TEXT::jsonb @> weapons::jsonb AND
+          NOT EXISTS (
+            SELECT 1 FROM jsonb_array_elements(forbidden_weapons::jsonb)
+            WHERE $3::TEXT::jsonb @> jsonb_build_array(value)
+          )
+        GROUP BY uid
+      ) AS matched
+        INNER JOIN user_action_agents ON uid_1 = user_action_agents.uid
+        INNER JOIN user_actions ON aid = user_action_agents.id
+      WHERE act_active AND expired_time IS NULL AND {rx} < $4
+      "
+    );
+    let rows = self.query(&sql, &[&stage, &king_salmonid, &weapons, &ts, &mode])?;
+    let list = AppendList::new();
+    for row in rows.iter() {
+      let remind_mins: i32 = row.try_get(3)?;
+      list.push(LookupCoopResponse {
+        id: row.try_get(0)?,
+        uid: row.try_get(1)?,
+        agent: row.try_get(2)?,
+        remind_mins: (remind_mins >= 0).then_some(remind_mins),
+      });
+    }
+    Ok(list)
+  }
+}
+
+impl ListCoopQuery for PgConnection {
+  fn list_coop_query(&self, request: ListCoopQueryRequest) -> Result<Vec<ListCoopQueryResponse>> {
+    let rows = self.query(
+      "
+      SELECT id, modes, includes, excludes, weapons, forbidden_weapons, king_salmonids, remind_mins, enabled,
+        to_char(created_time, 'YYYY-MM-DD HH24:MI:SS')
+      FROM coop_queries
+      WHERE uid = $1 AND ( $2::BIGINT IS NULL OR id = $2 )
+      ORDER BY id
+      ",
+      &[&request.uid, &request.qid],
+    )?;
+    let mut li = vec![];
+    for row in rows.iter() {
+      li.push(ListCoopQueryResponse {
+        qid: row.try_get(0)?,
+        record: CoopQueryRecord {
+          modes: row.try_get::<_, i32>(1)? as u8,
+          includes: from_json(row.try_get(2)?)?,
+          excludes: from_json(row.try_get(3)?)?,
+          weapons: from_json(row.try_get(4)?)?,
+          forbidden_weapons: from_json(row.try_get(5)?)?,
+          king_salmonids: row.try_get::<_, i32>(6)? as u8,
+          remind_mins: row.try_get(7)?,
+          enabled: row.try_get(8)?,
+        },
+        created_time: row.try_get(9)?,
+      });
+    }
+    Ok(li)
+  }
+}
+
+impl UpdateCoopQuery for PgConnection {
+  fn update_coop_query(&self, request: UpdateCoopQueryRequest) -> Result<()> {
+    let UpdateCoopQueryRequest {
+      uid,
+      qid,
+      record:
+        CoopQueryRecord {
+          modes,
+          includes,
+          excludes,
+          weapons,
+          forbidden_weapons,
+          king_salmonids,
+          remind_mins,
+          enabled,
+        },
+    } = request;
+    let n = self.execute(
+      "
+      UPDATE coop_queries
+      SET
+        modes = $3, includes = $4, excludes = $5, weapons = $6, forbidden_weapons = $7,
+        king_salmonids = $8, remind_mins = $9, enabled = $10
+      WHERE uid = $1 AND id = $2
+      ",
+      &[
+        &uid,
+        &qid,
+        &(*modes as i32),
+        &to_json(includes)?,
+        &to_json(excludes)?,
+        &to_json(weapons)?,
+        &to_json(forbidden_weapons)?,
+        &(*king_salmonids as i32),
+        remind_mins,
+        enabled,
+      ],
+    )?;
+    if n != 1 {
+      Err(Error::NotFound)
+    } else {
+      Ok(())
+    }
+  }
+}
+
+impl DeleteCoopQuery for PgConnection {
+  fn delete_coop_query(&self, request: DeleteCoopQueryRequest) -> Result<()> {
+    let DeleteCoopQueryRequest { uid, qid } = request;
+    let n = self.execute(
+      "
+      DELETE FROM coop_queries
+      WHERE uid = $1 AND id = $2",
+      &[&uid, &qid],
+    )?;
+    if n != 1 {
+      Err(Error::NotFound)
+    } else {
+      Ok(())
+    }
+  }
+}
@@ -0,0 +1,101 @@
+use chrono::{DateTime, Utc};
+
+use crate::{
+  database::delivery::{
+    parse_status, CreateDelivery, CreateDeliveryRequest, ListDelivery, ListDeliveryRequest,
+    ListDeliveryResponse, PruneDelivery,
+  },
+  Result,
+};
+
+use super::PgConnection;
+
+impl CreateDelivery for PgConnection {
+  fn create_delivery(&self, request: CreateDeliveryRequest) -> Result<()> {
+    let CreateDeliveryRequest {
+      uid,
+      id,
+      kind,
+      ts,
+      status,
+      attempt,
+      latency_ms,
+      error,
+      time,
+    } = request;
+    self.execute(
+      "
+      INSERT INTO deliveries ( uid, aid, kind, ts, status, attempt, latency_ms, error, time )
+      VALUES ( $1, $2, $3, $4, $5, $6, $7, $8, $9 )
+      ",
+      &[
+        &uid,
+        &id,
+        &kind,
+        &ts.timestamp(),
+        &status.to_string(),
+        &(attempt as i32),
+        &latency_ms,
+        &error,
+        &time.timestamp(),
+      ],
+    )?;
+    Ok(())
+  }
+}
+
+impl ListDelivery for PgConnection {
+  fn list_delivery(&self, request: ListDeliveryRequest) -> Result<Vec<ListDeliveryResponse>> {
+    let ListDeliveryRequest {
+      uid,
+      id,
+      before,
+      limit,
+    } = request;
+    let rows = self.query(
+      "
+      SELECT deliveries.id, deliveries.aid, act_agent, kind, ts, status, attempt, latency_ms,
+        error, time
+      FROM deliveries
+        INNER JOIN user_actions ON deliveries.aid = user_actions.id
+        INNER JOIN user_action_agents ON user_actions.aid = user_action_agents.id
+      WHERE
+        deliveries.uid = $1 AND
+        ( $2::BIGINT IS NULL OR deliveries.aid = $2 ) AND
+        ( $3::BIGINT IS NULL OR deliveries.id < $3 )
+      ORDER BY deliveries.id DESC
+      LIMIT $4
+      ",
+      &[&uid, &id, &before, &(limit as i64)],
+    )?;
+    let mut li = vec![];
+    for row in rows.iter() {
+      let status: String = row.try_get(5)?;
+      li.push(ListDeliveryResponse {
+        did: row.try_get(0)?,
+        id: row.try_get(1)?,
+        agent: row.try_get(2)?,
+        kind: row.try_get(3)?,
+        ts: row.try_get(4)?,
+        status: parse_status(&status)?,
+        attempt: row.try_get::<_, i32>(6)? as u32,
+        latency_ms: row.try_get(7)?,
+        error: row.try_get(8)?,
+        time: row.try_get(9)?,
+      });
+    }
+    Ok(li)
+  }
+}
+
+impl PruneDelivery for PgConnection {
+  fn prune_delivery(&self, time: DateTime<Utc>) -> Result<usize> {
+    let n = self.execute(
+      "
+      DELETE FROM deliveries
+      WHERE time < $1",
+      &[&time.timestamp()],
+    )?;
+    Ok(n as usize)
+  }
+}
@@ -0,0 +1,154 @@
+use appendlist::AppendList;
+
+use crate::{
+  database::{
+    event::{
+      CreateEventQuery, CreateEventQueryRequest, DeleteEventQuery, DeleteEventQueryRequest,
+      EventQueryRecord, ListEventQuery, ListEventQueryRequest, ListEventQueryResponse, LookupEvent,
+      LookupEventRequest, LookupEventResponse, UpdateEventQuery, UpdateEventQueryRequest,
+    },
+    from_json, remind_mins_sql, to_json,
+  },
+  Error, Result,
+};
+
+use super::PgConnection;
+
+impl CreateEventQuery for PgConnection {
+  fn create_event_query(&self, request: CreateEventQueryRequest) -> Result<i64> {
+    let CreateEventQueryRequest {
+      uid,
+      record: EventQueryRecord {
+        events,
+        remind_mins,
+        enabled,
+      },
+    } = request;
+    let id = self
+      .query_row(
+        "
+        INSERT INTO event_queries ( uid, events, remind_mins, enabled )
+        VALUES ( $1, $2, $3, $4 )
+        RETURNING id
+        ",
+        &[&uid, &to_json(events)?, remind_mins, enabled],
+      )?
+      .try_get(0)?;
+    Ok(id)
+  }
+}
+
+impl LookupEvent for PgConnection {
+  fn lookup_event(&self, request: LookupEventRequest) -> Result<AppendList<LookupEventResponse>> {
+    let LookupEventRequest {
+      start_time,
+      event_id,
+    } = request;
+    let ts = start_time.timestamp();
+    let remind_mins = remind_mins_sql("event_queries");
+    let sql = format!(
+      "
+      SELECT user_actions.id, uid_1, act_agent, remind_1
+      FROM (
+        SELECT uid as uid_1, {remind_mins} as remind_1
+        FROM event_queries
+          INNER JOIN users ON uid = users.id
+        WHERE
+          enabled AND (
+            events = '[]' OR events::jsonb @> jsonb_build_array($1::TEXT)
+          )
+        GROUP BY uid
+      ) AS matched
+        INNER JOIN user_action_agents ON uid_1 = user_action_agents.uid
+        INNER JOIN user_actions ON aid = user_action_agents.id
+      WHERE act_active AND expired_time IS NULL AND rx_event < $2
+      "
+    );
+    let rows = self.query(&sql, &[&event_id, &ts])?;
+    let list = AppendList::new();
+    for row in rows.iter() {
+      let remind_mins: i32 = row.try_get(3)?;
+      list.push(LookupEventResponse {
+        id: row.try_get(0)?,
+        uid: row.try_get(1)?,
+        agent: row.try_get(2)?,
+        remind_mins: (remind_mins >= 0).then_some(remind_mins),
+      });
+    }
+    Ok(list)
+  }
+}
+
+impl ListEventQuery for PgConnection {
+  fn list_event_query(
+    &self,
+    request: ListEventQueryRequest,
+  ) -> Result<Vec<ListEventQueryResponse>> {
+    let rows = self.query(
+      "
+      SELECT id, events, remind_mins, enabled, to_char(created_time, 'YYYY-MM-DD HH24:MI:SS')
+      FROM event_queries
+      WHERE uid = $1 AND ( $2::BIGINT IS NULL OR id = $2 )
+      ORDER BY id
+      ",
+      &[&request.uid, &request.qid],
+    )?;
+    let mut li = vec![];
+    for row in rows.iter() {
+      li.push(ListEventQueryResponse {
+        qid: row.try_get(0)?,
+        record: EventQueryRecord {
+          events: from_json(row.try_get(1)?)?,
+          remind_mins: row.try_get(2)?,
+          enabled: row.try_get(3)?,
+        },
+        created_time: row.try_get(4)?,
+      });
+    }
+    Ok(li)
+  }
+}
+
+impl UpdateEventQuery for PgConnection {
+  fn update_event_query(&self, request: UpdateEventQueryRequest) -> Result<()> {
+    let UpdateEventQueryRequest {
+      uid,
+      qid,
+      record: EventQueryRecord {
+        events,
+        remind_mins,
+        enabled,
+      },
+    } = request;
+    let n = self.execute(
+      "
+      UPDATE event_queries
+      SET events = $3, remind_mins = $4, enabled = $5
+      WHERE uid = $1 AND id = $2
+      ",
+      &[&uid, &qid, &to_json(events)?, remind_mins, enabled],
+    )?;
+    if n != 1 {
+      Err(Error::NotFound)
+    } else {
+      Ok(())
+    }
+  }
+}
+
+impl DeleteEventQuery for PgConnection {
+  fn delete_event_query(&self, request: DeleteEventQueryRequest) -> Result<()> {
+    let DeleteEventQueryRequest { uid, qid } = request;
+    let n = self.execute(
+      "
+      DELETE FROM event_queries
+      WHERE uid = $1 AND id = $2",
+      &[&uid, &qid],
+    )?;
+    if n != 1 {
+      Err(Error::NotFound)
+    } else {
+      Ok(())
+    }
+  }
+}
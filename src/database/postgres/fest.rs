@@ -0,0 +1,129 @@
+use appendlist::AppendList;
+
+use crate::{
+  database::fest::{
+    CreateFestQuery, CreateFestQueryRequest, DeleteFestQuery, DeleteFestQueryRequest,
+    FestQueryRecord, ListFestQuery, ListFestQueryRequest, ListFestQueryResponse, LookupFest,
+    LookupFestRequest, LookupFestResponse, UpdateFestQuery, UpdateFestQueryRequest,
+  },
+  Error, Result,
+};
+
+use super::PgConnection;
+
+impl CreateFestQuery for PgConnection {
+  fn create_fest_query(&self, request: CreateFestQueryRequest) -> Result<i64> {
+    let CreateFestQueryRequest {
+      uid,
+      record: FestQueryRecord { states, enabled },
+    } = request;
+    let id = self
+      .query_row(
+        "
+        INSERT INTO fest_queries ( uid, states, enabled )
+        VALUES ( $1, $2, $3 )
+        RETURNING id
+        ",
+        &[&uid, &(*states as i32), enabled],
+      )?
+      .try_get(0)?;
+    Ok(id)
+  }
+}
+
+impl LookupFest for PgConnection {
+  fn lookup_fest(&self, request: LookupFestRequest) -> Result<AppendList<LookupFestResponse>> {
+    let LookupFestRequest { state_time, state } = request;
+    let state = state as u8 as i32;
+    let ts = state_time.timestamp();
+    let rows = self.query(
+      "
+      SELECT user_actions.id, uid_1, act_agent
+      FROM (
+        SELECT DISTINCT uid as uid_1
+        FROM fest_queries
+        WHERE enabled AND states & $1 != 0
+      ) AS matched
+        INNER JOIN user_action_agents ON uid_1 = user_action_agents.uid
+        INNER JOIN user_actions ON aid = user_action_agents.id
+      WHERE act_active AND expired_time IS NULL AND rx_fest < $2
+      ",
+      &[&state, &ts],
+    )?;
+    let list = AppendList::new();
+    for row in rows.iter() {
+      list.push(LookupFestResponse {
+        id: row.try_get(0)?,
+        uid: row.try_get(1)?,
+        agent: row.try_get(2)?,
+      });
+    }
+    Ok(list)
+  }
+}
+
+impl ListFestQuery for PgConnection {
+  fn list_fest_query(&self, request: ListFestQueryRequest) -> Result<Vec<ListFestQueryResponse>> {
+    let rows = self.query(
+      "
+      SELECT id, states, enabled, to_char(created_time, 'YYYY-MM-DD HH24:MI:SS')
+      FROM fest_queries
+      WHERE uid = $1 AND ( $2::BIGINT IS NULL OR id = $2 )
+      ORDER BY id
+      ",
+      &[&request.uid, &request.qid],
+    )?;
+    let mut li = vec![];
+    for row in rows.iter() {
+      li.push(ListFestQueryResponse {
+        qid: row.try_get(0)?,
+        record: FestQueryRecord {
+          states: row.try_get::<_, i32>(1)? as u8,
+          enabled: row.try_get(2)?,
+        },
+        created_time: row.try_get(3)?,
+      });
+    }
+    Ok(li)
+  }
+}
+
+impl UpdateFestQuery for PgConnection {
+  fn update_fest_query(&self, request: UpdateFestQueryRequest) -> Result<()> {
+    let UpdateFestQueryRequest {
+      uid,
+      qid,
+      record: FestQueryRecord { states, enabled },
+    } = request;
+    let n = self.execute(
+      "
+      UPDATE fest_queries
+      SET states = $3, enabled = $4
+      WHERE uid = $1 AND id = $2
+      ",
+      &[&uid, &qid, &(*states as i32), enabled],
+    )?;
+    if n != 1 {
+      Err(Error::NotFound)
+    } else {
+      Ok(())
+    }
+  }
+}
+
+impl DeleteFestQuery for PgConnection {
+  fn delete_fest_query(&self, request: DeleteFestQueryRequest) -> Result<()> {
+    let DeleteFestQueryRequest { uid, qid } = request;
+    let n = self.execute(
+      "
+      DELETE FROM fest_queries
+      WHERE uid = $1 AND id = $2",
+      &[&uid, &qid],
+    )?;
+    if n != 1 {
+      Err(Error::NotFound)
+    } else {
+      Ok(())
+    }
+  }
+}
@@ -0,0 +1,205 @@
+use appendlist::AppendList;
+
+use crate::{
+  database::{
+    from_json,
+    gear::{
+      CreateGearQuery, CreateGearQueryRequest, DeleteGearQuery, DeleteGearQueryRequest,
+      GearQueryRecord, ListGearQuery, ListGearQueryRequest, ListGearQueryResponse, LookupGear,
+      LookupGearRequest, LookupGearResponse, UpdateGearQuery, UpdateGearQueryRequest,
+    },
+    to_json,
+  },
+  Error, Result,
+};
+
+use super::PgConnection;
+
+impl CreateGearQuery for PgConnection {
+  fn create_gear_query(&self, request: CreateGearQueryRequest) -> Result<i64> {
+    let CreateGearQueryRequest {
+      uid,
+      record:
+        GearQueryRecord {
+          gear_types,
+          brands,
+          powers,
+          min_slots,
+          max_price,
+          pickup_only,
+          enabled,
+        },
+    } = request;
+    let id = self
+      .query_row(
+        "
+        INSERT INTO gear_queries ( uid, gear_types, brands, powers, min_slots, max_price, pickup_only, enabled )
+        VALUES ( $1, $2, $3, $4, $5, $6, $7, $8 )
+        RETURNING id
+        ",
+        &[
+          &uid,
+          &(*gear_types as i32),
+          &to_json(brands)?,
+          &to_json(powers)?,
+          &(*min_slots as i32),
+          max_price,
+          pickup_only,
+          enabled,
+        ],
+      )?
+      .try_get(0)?;
+    Ok(id)
+  }
+}
+
+impl LookupGear for PgConnection {
+  fn lookup_gear(&self, request: LookupGearRequest) -> Result<AppendList<LookupGearResponse>> {
+    let LookupGearRequest {
+      sale_end_time,
+      gear_type,
+      brand,
+      primary_gear_power,
+      additional_gear_powers,
+      price,
+      pickup,
+    } = request;
+    let gear_type = gear_type as u8 as i32;
+    let ts = sale_end_time.timestamp();
+    let rx = if pickup { "rx_gear_brand" } else { "rx_gear" };
+    let sql = format!(
+      "
+      SELECT user_actions.id, uid_1, act_agent
+      FROM (
+        SELECT DISTINCT uid as uid_1
+        FROM gear_queries
+        WHERE
+          enabled AND
+          ( gear_types = 0 OR gear_types & $1 != 0 ) AND
+          ( brands = '[]' OR brands::jsonb @> jsonb_build_array($2::TEXT) ) AND
+          ( powers = '[]' OR powers::jsonb @> jsonb_build_array($3::TEXT) ) AND
+          min_slots <= $4 AND
+          ( max_price IS NULL OR max_price >= $5 ) AND
+          ( NOT pickup_only OR $6::BOOLEAN )
+      ) AS matched
+        INNER JOIN user_action_agents ON uid_1 = user_action_agents.uid
+        INNER JOIN user_actions ON aid = user_action_agents.id
+      WHERE act_active AND expired_time IS NULL AND {rx} < $7
+      "
+    );
+    let rows = self.query(
+      &sql,
+      &[
+        &gear_type,
+        &brand,
+        &primary_gear_power,
+        &additional_gear_powers,
+        &price,
+        &pickup,
+        &ts,
+      ],
+    )?;
+    let list = AppendList::new();
+    for row in rows.iter() {
+      list.push(LookupGearResponse {
+        id: row.try_get(0)?,
+        uid: row.try_get(1)?,
+        agent: row.try_get(2)?,
+      });
+    }
+    Ok(list)
+  }
+}
+
+impl ListGearQuery for PgConnection {
+  fn list_gear_query(&self, request: ListGearQueryRequest) -> Result<Vec<ListGearQueryResponse>> {
+    let rows = self.query(
+      "
+      SELECT id, gear_types, brands, powers, min_slots, max_price, pickup_only, enabled,
+        to_char(created_time, 'YYYY-MM-DD HH24:MI:SS')
+      FROM gear_queries
+      WHERE uid = $1 AND ( $2::BIGINT IS NULL OR id = $2 )
+      ORDER BY id
+      ",
+      &[&request.uid, &request.qid],
+    )?;
+    let mut li = vec![];
+    for row in rows.iter() {
+      li.push(ListGearQueryResponse {
+        qid: row.try_get(0)?,
+        record: GearQueryRecord {
+          gear_types: row.try_get::<_, i32>(1)? as u8,
+          brands: from_json(row.try_get(2)?)?,
+          powers: from_json(row.try_get(3)?)?,
+          min_slots: row.try_get::<_, i32>(4)? as u8,
+          max_price: row.try_get(5)?,
+          pickup_only: row.try_get(6)?,
+          enabled: row.try_get(7)?,
+        },
+        created_time: row.try_get(8)?,
+      });
+    }
+    Ok(li)
+  }
+}
+
+impl UpdateGearQuery for PgConnection {
+  fn update_gear_query(&self, request: UpdateGearQueryRequest) -> Result<()> {
+    let UpdateGearQueryRequest {
+      uid,
+      qid,
+      record:
+        GearQueryRecord {
+          gear_types,
+          brands,
+          powers,
+          min_slots,
+          max_price,
+          pickup_only,
+          enabled,
+        },
+    } = request;
+    let n = self.execute(
+      "
+      UPDATE gear_queries
+      SET
+        gear_types = $3, brands = $4, powers = $5, min_slots = $6, max_price = $7,
+        pickup_only = $8, enabled = $9
+      WHERE uid = $1 AND id = $2
+      ",
+      &[
+        &uid,
+        &qid,
+        &(*gear_types as i32),
+        &to_json(brands)?,
+        &to_json(powers)?,
+        &(*min_slots as i32),
+        max_price,
+        pickup_only,
+        enabled,
+      ],
+    )?;
+    if n != 1 {
+      Err(Error::NotFound)
+    } else {
+      Ok(())
+    }
+  }
+}
+
+impl DeleteGearQuery for PgConnection {
+  fn delete_gear_query(&self, request: DeleteGearQueryRequest) -> Result<()> {
+    let DeleteGearQueryRequest { uid, qid } = request;
+    let n = self.execute(
+      "
+      DELETE FROM gear_queries
+      WHERE uid = $1 AND id = $2",
+      &[&uid, &qid],
+    )?;
+    if n != 1 {
+      Err(Error::NotFound)
+    } else {
+      Ok(())
+    }
+  }
+}
@@ -0,0 +1,405 @@
+use crate::{database::SchemaStatus, Error, Result};
+
+use super::PgConnection;
+
+// a schema change of the postgres backend, recorded in `schema_migrations`;
+// new steps are appended, and applied ones are never edited
+struct Migration {
+  version: i32,
+  name: &'static str,
+  sql: &'static str,
+}
+
+// the postgres schema starts from the latest sqlite one, without the legacy
+// columns kept there for databases of earlier versions
+const MIGRATIONS: &[Migration] = &[
+  Migration {
+    version: 1,
+    name: "create tables",
+    sql: CREATE_TABLES,
+  },
+  Migration {
+    version: 2,
+    name: "delivery outbox",
+    sql: CREATE_OUTBOX,
+  },
+  Migration {
+    version: 3,
+    name: "delivery history",
+    sql: CREATE_DELIVERIES,
+  },
+  Migration {
+    version: 4,
+    name: "action expiry",
+    sql: ADD_ACTION_EXPIRY,
+  },
+  Migration {
+    version: 5,
+    name: "telegram chats",
+    sql: CREATE_TELEGRAM_TABLES,
+  },
+  Migration {
+    version: 6,
+    name: "discord channels",
+    sql: CREATE_DISCORD_TABLES,
+  },
+  Migration {
+    version: 7,
+    name: "discord links",
+    sql: CREATE_DISCORD_LINKS,
+  },
+  Migration {
+    version: 8,
+    name: "delivery error kinds",
+    sql: MIGRATE_DELIVERY_ERRORS,
+  },
+];
+
+// serializes replicas starting up at the same time
+const MIGRATION_LOCK: i64 = 0x5350_4c51;
+
+pub fn migration_status(conn: &PgConnection) -> Result<SchemaStatus> {
+  // no migration applied yet without the table
+  let exists: bool = conn
+    .query_row("SELECT to_regclass('schema_migrations') IS NOT NULL", &[])?
+    .try_get(0)?;
+  let version = if exists { applied_version(conn)? } else { 0 };
+  Ok(SchemaStatus {
+    version: version as u32,
+    latest: MIGRATIONS.last().map_or(0, |e| e.version) as u32,
+    pending: pending(version),
+  })
+}
+
+// applies the pending migrations in one transaction, rolled back at the end
+// for a dry run
+pub fn migrate(conn: &PgConnection, dry_run: bool) -> Result<Vec<(u32, &'static str)>> {
+  conn.execute_batch("BEGIN")?;
+  let result = migrate_locked(conn);
+  let commit = result.is_ok() && !dry_run;
+  conn.execute_batch(if commit { "COMMIT" } else { "ROLLBACK" })?;
+  result
+}
+
+fn applied_version(conn: &PgConnection) -> Result<i32> {
+  let version = conn
+    .query_row(
+      "SELECT COALESCE(MAX(version), 0) FROM schema_migrations",
+      &[],
+    )?
+    .try_get(0)?;
+  Ok(version)
+}
+
+fn pending(version: i32) -> Vec<(u32, &'static str)> {
+  MIGRATIONS
+    .iter()
+    .filter(|e| e.version > version)
+    .map(|e| (e.version as u32, e.name))
+    .collect()
+}
+
+fn migrate_locked(conn: &PgConnection) -> Result<Vec<(u32, &'static str)>> {
+  conn.query("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK])?;
+  conn.execute_batch(
+    "
+    CREATE TABLE IF NOT EXISTS
+    schema_migrations (
+      version             INTEGER PRIMARY KEY,
+      name                TEXT NOT NULL,
+      applied_time        TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc')
+    )
+    ",
+  )?;
+  let version = applied_version(conn)?;
+  let latest = MIGRATIONS.last().map_or(0, |e| e.version);
+  if version > latest {
+    return Err(Error::InternalServerError(
+      format!("database version {} is newer than {}", version, latest).into(),
+    ));
+  }
+  for e in MIGRATIONS.iter().filter(|e| e.version > version) {
+    log::info!("applying migration [{}] {}", e.version, e.name);
+    conn.execute_batch(e.sql)?;
+    conn.execute(
+      "INSERT INTO schema_migrations ( version, name ) VALUES ( $1, $2 )",
+      &[&e.version, &e.name],
+    )?;
+  }
+  Ok(pending(version))
+}
+
+const CREATE_TABLES: &str = "
+  CREATE TABLE
+  users (
+    id                  BIGSERIAL PRIMARY KEY,
+    created_time        TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+    auth_agent          TEXT NOT NULL,
+    auth_uid            TEXT NOT NULL,
+    name                TEXT,
+    email               TEXT,
+    picture             TEXT,
+    language            TEXT NOT NULL,
+    time_zone           TEXT NOT NULL,
+    availability        TEXT,               /* json local time ranges per weekday, null for any */
+    remind_mins         INTEGER NOT NULL DEFAULT -1,  /* negative for at announcement */
+    digest              TEXT NOT NULL DEFAULT 'immediate',
+    digest_hour         INTEGER NOT NULL DEFAULT 8,   /* local hour for daily digests */
+    quiet_start         INTEGER NOT NULL DEFAULT 0,   /* local hours [start, end), equal for none */
+    quiet_end           INTEGER NOT NULL DEFAULT 0,
+    snooze_until        BIGINT NOT NULL DEFAULT 0,
+    vacation            BOOLEAN NOT NULL DEFAULT FALSE,
+    UNIQUE ( auth_uid, auth_agent )
+  );
+
+  CREATE TABLE
+  pvp_queries (
+    id                  BIGSERIAL PRIMARY KEY,
+    uid                 BIGINT NOT NULL REFERENCES users ( id ) ON DELETE CASCADE,
+    created_time        TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+    modes               INTEGER NOT NULL,
+    rules               INTEGER NOT NULL,
+    include_stages      TEXT NOT NULL DEFAULT '[]',  /* json array of stage ids */
+    exclude_stages      TEXT NOT NULL DEFAULT '[]',
+    min_includes        INTEGER NOT NULL DEFAULT 1,
+    rule_stages         TEXT NOT NULL DEFAULT '[]',  /* json array of per rule stage constraints */
+    remind_mins         INTEGER,            /* null to follow user settings */
+    availability        TEXT,               /* null to follow user settings */
+    enabled             BOOLEAN NOT NULL DEFAULT TRUE
+  );
+
+  CREATE INDEX pvp_queries_index
+  ON pvp_queries ( uid );
+
+  CREATE TABLE
+  coop_queries (
+    id                  BIGSERIAL PRIMARY KEY,
+    uid                 BIGINT NOT NULL REFERENCES users ( id ) ON DELETE CASCADE,
+    created_time        TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+    modes               INTEGER NOT NULL,
+    includes            TEXT NOT NULL,      /* json array of coop stage ids */
+    excludes            TEXT NOT NULL,
+    weapons             TEXT NOT NULL,      /* json array of weapon ids */
+    forbidden_weapons   TEXT NOT NULL,
+    king_salmonids      INTEGER NOT NULL,   /* 0 for any */
+    remind_mins         INTEGER,
+    enabled             BOOLEAN NOT NULL DEFAULT TRUE
+  );
+
+  CREATE INDEX coop_queries_index
+  ON coop_queries ( uid );
+
+  CREATE TABLE
+  event_queries (
+    id                  BIGSERIAL PRIMARY KEY,
+    uid                 BIGINT NOT NULL REFERENCES users ( id ) ON DELETE CASCADE,
+    created_time        TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+    events              TEXT NOT NULL,      /* json array of league match event ids, empty for any */
+    remind_mins         INTEGER,
+    enabled             BOOLEAN NOT NULL DEFAULT TRUE
+  );
+
+  CREATE INDEX event_queries_index
+  ON event_queries ( uid );
+
+  CREATE TABLE
+  fest_queries (
+    id                  BIGSERIAL PRIMARY KEY,
+    uid                 BIGINT NOT NULL REFERENCES users ( id ) ON DELETE CASCADE,
+    created_time        TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+    states              INTEGER NOT NULL,
+    enabled             BOOLEAN NOT NULL DEFAULT TRUE
+  );
+
+  CREATE INDEX fest_queries_index
+  ON fest_queries ( uid );
+
+  CREATE TABLE
+  gear_queries (
+    id                  BIGSERIAL PRIMARY KEY,
+    uid                 BIGINT NOT NULL REFERENCES users ( id ) ON DELETE CASCADE,
+    created_time        TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+    gear_types          INTEGER NOT NULL,   /* 0 for any */
+    brands              TEXT NOT NULL,      /* json array of brand ids */
+    powers              TEXT NOT NULL,      /* json array of primary gear power ids */
+    min_slots           INTEGER NOT NULL,
+    max_price           INTEGER,            /* null for any */
+    pickup_only         BOOLEAN NOT NULL,
+    enabled             BOOLEAN NOT NULL DEFAULT TRUE
+  );
+
+  CREATE INDEX gear_queries_index
+  ON gear_queries ( uid );
+
+  CREATE TABLE
+  spider_cursors (
+    name                TEXT PRIMARY KEY,
+    value               TEXT NOT NULL,
+    updated_time        TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc')
+  );
+
+  CREATE TABLE
+  user_action_agents (
+    id                  BIGSERIAL PRIMARY KEY,
+    uid                 BIGINT NOT NULL REFERENCES users ( id ) ON DELETE CASCADE,
+    act_agent           TEXT NOT NULL,
+    act_active          BOOLEAN NOT NULL,
+    digest              TEXT,               /* null to follow user settings */
+    UNIQUE ( uid, act_agent )
+  );
+
+  CREATE INDEX user_action_agents_index
+  ON user_action_agents ( uid );
+
+  CREATE TABLE
+  user_actions (
+    id                  BIGSERIAL PRIMARY KEY,
+    uid                 BIGINT NOT NULL,
+    aid                 BIGINT NOT NULL REFERENCES user_action_agents ( id ) ON DELETE CASCADE,
+    rx_pvp              BIGINT NOT NULL DEFAULT 0,
+    rx_event            BIGINT NOT NULL DEFAULT 0,
+    rx_coop             BIGINT NOT NULL DEFAULT 0,
+    rx_coop_ex          BIGINT NOT NULL DEFAULT 0,
+    rx_gear             BIGINT NOT NULL DEFAULT 0,
+    rx_gear_brand       BIGINT NOT NULL DEFAULT 0,
+    rx_fest             BIGINT NOT NULL DEFAULT 0
+  );
+
+  CREATE TABLE
+  pending_notifications (
+    id                  BIGSERIAL PRIMARY KEY,
+    uid                 BIGINT NOT NULL,
+    aid                 BIGINT NOT NULL REFERENCES user_actions ( id ) ON DELETE CASCADE,
+    rx                  TEXT NOT NULL,      /* user_actions column to bump on delivery */
+    ts                  BIGINT NOT NULL,
+    fire_time           BIGINT NOT NULL,
+    message             TEXT NOT NULL       /* json encoded message */
+  );
+
+  CREATE INDEX pending_notifications_index
+  ON pending_notifications ( fire_time );
+
+  /* messages may exceed the size limit of a btree entry */
+  CREATE UNIQUE INDEX pending_notifications_message_index
+  ON pending_notifications ( aid, md5(message) );
+
+  CREATE TABLE
+  webpush_ext_info (
+    id                  BIGINT UNIQUE NOT NULL REFERENCES user_actions ( id ) ON DELETE CASCADE,
+    uid                 BIGINT NOT NULL,
+    endpoint            TEXT NOT NULL,
+    p256dh              TEXT NOT NULL,
+    auth                TEXT NOT NULL,
+    browser             TEXT,
+    device              TEXT,
+    os                  TEXT,
+    UNIQUE ( endpoint, uid )
+  );
+";
+
+const CREATE_OUTBOX: &str = "
+  CREATE TABLE
+  outbox (
+    id                  BIGSERIAL PRIMARY KEY,
+    uid                 BIGINT NOT NULL,
+    aid                 BIGINT NOT NULL REFERENCES user_actions ( id ) ON DELETE CASCADE,
+    message             TEXT NOT NULL,      /* json encoded message */
+    marks               TEXT NOT NULL,      /* json encoded user_actions columns to bump on delivery */
+    attempts            INTEGER NOT NULL DEFAULT 0,
+    next_attempt        BIGINT,             /* null if given up */
+    last_error          TEXT,
+    created_time        TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc')
+  );
+
+  CREATE INDEX outbox_index
+  ON outbox ( next_attempt );
+
+  CREATE UNIQUE INDEX outbox_message_index
+  ON outbox ( aid, md5(message) );
+";
+
+const CREATE_DELIVERIES: &str = "
+  CREATE TABLE
+  deliveries (
+    id                  BIGSERIAL PRIMARY KEY,
+    uid                 BIGINT NOT NULL,
+    aid                 BIGINT NOT NULL REFERENCES user_actions ( id ) ON DELETE CASCADE,
+    kind                TEXT NOT NULL,
+    ts                  BIGINT NOT NULL,    /* start of the rotation */
+    status              TEXT NOT NULL,
+    attempt             INTEGER NOT NULL,
+    latency_ms          BIGINT NOT NULL,
+    error               TEXT,
+    time                BIGINT NOT NULL
+  );
+
+  CREATE INDEX deliveries_uid_index
+  ON deliveries ( uid, id );
+
+  CREATE INDEX deliveries_aid_index
+  ON deliveries ( aid, status, time );
+
+  CREATE INDEX deliveries_time_index
+  ON deliveries ( time );
+";
+
+const ADD_ACTION_EXPIRY: &str = "
+  ALTER TABLE user_actions
+  ADD COLUMN expired_time BIGINT,           /* null while deliverable */
+  ADD COLUMN expired_reason TEXT;
+";
+
+const CREATE_TELEGRAM_TABLES: &str = "
+  CREATE TABLE
+  telegram_links (
+    code                TEXT PRIMARY KEY,   /* sent to the bot as `/start <code>` */
+    uid                 BIGINT NOT NULL REFERENCES users ( id ) ON DELETE CASCADE,
+    expire_time         BIGINT NOT NULL
+  );
+
+  CREATE TABLE
+  telegram_ext_info (
+    id                  BIGINT UNIQUE NOT NULL REFERENCES user_actions ( id ) ON DELETE CASCADE,
+    uid                 BIGINT NOT NULL,
+    chat_id             BIGINT NOT NULL,
+    chat_type           TEXT NOT NULL,      /* private, group, supergroup or channel */
+    title               TEXT,               /* name of the user or the group */
+    username            TEXT,
+    UNIQUE ( chat_id, uid )
+  );
+";
+
+const CREATE_DISCORD_TABLES: &str = "
+  CREATE TABLE
+  discord_ext_info (
+    id                  BIGINT UNIQUE NOT NULL REFERENCES user_actions ( id ) ON DELETE CASCADE,
+    uid                 BIGINT NOT NULL,
+    kind                TEXT NOT NULL,      /* dm or webhook */
+    user_id             TEXT,               /* of dm */
+    webhook_id          TEXT,               /* of webhook */
+    webhook_token       TEXT
+  );
+";
+
+const CREATE_DISCORD_LINKS: &str = "
+  CREATE TABLE
+  discord_links (
+    code                TEXT PRIMARY KEY,   /* sent to the bot in a direct message */
+    uid                 BIGINT NOT NULL REFERENCES users ( id ) ON DELETE CASCADE,
+    user_id             TEXT NOT NULL,      /* who is expected to send it */
+    expire_time         BIGINT NOT NULL
+  );
+";
+
+// the debug output of errors recorded before, which may carry urls with tokens
+const MIGRATE_DELIVERY_ERRORS: &str = "
+  UPDATE deliveries
+  SET error = CASE
+    WHEN error LIKE 'ActionExpired%' OR error LIKE 'action expired%' THEN 'action expired'
+    WHEN error LIKE 'DeliveryRejected%' OR error LIKE 'delivery rejected%' THEN 'delivery rejected'
+    WHEN error LIKE 'NetworkError%' OR error LIKE 'network error%' THEN 'network error'
+    WHEN error = 'action inactive' THEN error
+    ELSE 'internal error'
+  END
+  WHERE error IS NOT NULL;
+";
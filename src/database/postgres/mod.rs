@@ -0,0 +1,133 @@
+use std::{cell::RefCell, time::Duration};
+
+use r2d2::{Pool, PooledConnection};
+use r2d2_postgres::{
+  postgres::{types::ToSql, Config, NoTls, Row},
+  PostgresConnectionManager,
+};
+use tokio::runtime::{Handle, RuntimeFlavor};
+
+use crate::{Error, Result};
+
+#[cfg(test)]
+use super::Database;
+
+pub use self::migration::{migrate, migration_status};
+
+mod action;
+mod coop;
+mod delivery;
+mod event;
+mod fest;
+mod gear;
+mod migration;
+mod outbox;
+mod pending;
+mod pvp;
+mod spider;
+mod user;
+
+pub type PgPool = Pool<PostgresConnectionManager<NoTls>>;
+
+type Params<'a> = &'a [&'a (dyn ToSql + Sync)];
+
+// the sync client blocks on a runtime of its own, which tokio forbids on the
+// threads of a runtime, so calls made from async code are moved off them
+fn blocking<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+  match Handle::try_current() {
+    Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+      tokio::task::block_in_place(f)
+    }
+    Ok(_) => std::thread::scope(|s| {
+      s.spawn(f)
+        .join()
+        .unwrap_or_else(|err| std::panic::resume_unwind(err))
+    }),
+    Err(_) => f(),
+  }
+}
+
+pub(crate) fn connect(
+  config: Config,
+  pool_size: u32,
+  connection_timeout: Duration,
+) -> Result<PgPool> {
+  let manager = PostgresConnectionManager::new(config, NoTls);
+  let pool = blocking(|| {
+    Pool::builder()
+      .max_size(pool_size)
+      .connection_timeout(connection_timeout)
+      .build(manager)
+  })?;
+  Ok(pool)
+}
+
+// a schema of its own for each test database, on a throwaway local server
+#[cfg(test)]
+pub(crate) fn new_for_test(mut config: Config) -> Result<Database> {
+  use std::sync::atomic::{AtomicUsize, Ordering};
+
+  static SEQ: AtomicUsize = AtomicUsize::new(0);
+  let schema = format!(
+    "test_{}_{}",
+    std::process::id(),
+    SEQ.fetch_add(1, Ordering::Relaxed)
+  );
+  blocking(|| {
+    let sql = format!("DROP SCHEMA IF EXISTS {schema} CASCADE; CREATE SCHEMA {schema}");
+    config.connect(NoTls)?.batch_execute(&sql)
+  })?;
+  config.options(&format!("-c search_path={schema}"));
+  let pool = connect(config, 2, Duration::from_secs(10))?;
+  migrate(&PgConnection::get(&pool)?, false)?;
+  Ok(Database::Postgres(pool))
+}
+
+// a pooled client behind the `&self` interface of the query traits, which is
+// none only while being dropped
+pub struct PgConnection(RefCell<Option<PooledConnection<PostgresConnectionManager<NoTls>>>>);
+
+impl PgConnection {
+  pub(crate) fn get(pool: &PgPool) -> Result<PgConnection> {
+    let conn = blocking(|| pool.get())?;
+    Ok(PgConnection(RefCell::new(Some(conn))))
+  }
+
+  pub(crate) fn execute(&self, sql: &str, params: Params) -> Result<u64> {
+    let mut conn = self.0.borrow_mut();
+    let client = conn.as_mut().expect("connection dropped");
+    Ok(blocking(|| client.execute(sql, params))?)
+  }
+
+  pub(crate) fn query(&self, sql: &str, params: Params) -> Result<Vec<Row>> {
+    let mut conn = self.0.borrow_mut();
+    let client = conn.as_mut().expect("connection dropped");
+    Ok(blocking(|| client.query(sql, params))?)
+  }
+
+  pub(crate) fn query_opt(&self, sql: &str, params: Params) -> Result<Option<Row>> {
+    let mut conn = self.0.borrow_mut();
+    let client = conn.as_mut().expect("connection dropped");
+    Ok(blocking(|| client.query_opt(sql, params))?)
+  }
+
+  // the single row of a query, or `Error::NotFound` if none
+  pub(crate) fn query_row(&self, sql: &str, params: Params) -> Result<Row> {
+    self.query_opt(sql, params)?.ok_or(Error::NotFound)
+  }
+
+  pub(crate) fn execute_batch(&self, sql: &str) -> Result<()> {
+    let mut conn = self.0.borrow_mut();
+    let client = conn.as_mut().expect("connection dropped");
+    Ok(blocking(|| client.batch_execute(sql))?)
+  }
+}
+
+impl Drop for PgConnection {
+  fn drop(&mut self) {
+    // a broken client is closed rather than returned to the pool
+    if let Some(conn) = self.0.get_mut().take() {
+      blocking(move || drop(conn));
+    }
+  }
+}
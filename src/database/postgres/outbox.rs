@@ -0,0 +1,158 @@
+use chrono::Utc;
+
+use crate::{
+  database::outbox::{
+    ClaimOutbox, ClaimOutboxRequest, ClaimOutboxResponse, CreateOutbox, CreateOutboxRequest,
+    DeleteOutbox, ListOutbox, ListOutboxResponse, ResetOutbox, ResetOutboxRequest, RetryOutbox,
+    RetryOutboxRequest,
+  },
+  Error, Result,
+};
+
+use super::PgConnection;
+
+impl CreateOutbox for PgConnection {
+  fn create_outbox(&self, request: CreateOutboxRequest) -> Result<bool> {
+    let CreateOutboxRequest {
+      uid,
+      id,
+      message,
+      marks,
+    } = request;
+    let n = self.execute(
+      "
+      INSERT INTO outbox ( uid, aid, message, marks, next_attempt )
+      VALUES ( $1, $2, $3, $4, $5 )
+      ON CONFLICT DO NOTHING
+      ",
+      &[&uid, &id, &message, &marks, &Utc::now().timestamp()],
+    )?;
+    Ok(n > 0)
+  }
+}
+
+impl ClaimOutbox for PgConnection {
+  fn claim_outbox(&self, request: ClaimOutboxRequest) -> Result<Vec<ClaimOutboxResponse>> {
+    let ClaimOutboxRequest {
+      now,
+      lease_until,
+      limit,
+    } = request;
+    // rows claimed by other replicas in the meantime are skipped rather than waited for
+    let rows = self.query(
+      "
+      WITH claimed AS (
+        UPDATE outbox
+        SET attempts = attempts + 1, next_attempt = $2
+        WHERE id IN (
+          SELECT id FROM outbox
+          WHERE next_attempt <= $1
+          ORDER BY next_attempt, id
+          LIMIT $3
+          FOR UPDATE SKIP LOCKED
+        )
+        RETURNING id, aid, uid, message, marks, attempts
+      )
+      SELECT claimed.id, claimed.aid, claimed.uid, act_agent, message, marks, attempts
+      FROM claimed
+        INNER JOIN user_actions ON claimed.aid = user_actions.id
+        INNER JOIN user_action_agents ON user_actions.aid = user_action_agents.id
+      ORDER BY claimed.id
+      ",
+      &[&now.timestamp(), &lease_until.timestamp(), &(limit as i64)],
+    )?;
+    let mut li = vec![];
+    for row in rows.iter() {
+      li.push(ClaimOutboxResponse {
+        oid: row.try_get(0)?,
+        id: row.try_get(1)?,
+        uid: row.try_get(2)?,
+        agent: row.try_get(3)?,
+        message: row.try_get(4)?,
+        marks: row.try_get(5)?,
+        attempts: row.try_get::<_, i32>(6)? as u32,
+      });
+    }
+    Ok(li)
+  }
+}
+
+impl RetryOutbox for PgConnection {
+  fn retry_outbox(&self, request: RetryOutboxRequest) -> Result<()> {
+    let RetryOutboxRequest {
+      oid,
+      next_attempt,
+      error,
+    } = request;
+    let n = self.execute(
+      "
+      UPDATE outbox
+      SET next_attempt = $2, last_error = $3
+      WHERE id = $1
+      ",
+      &[&oid, &next_attempt.map(|e| e.timestamp()), &error],
+    )?;
+    if n != 1 {
+      Err(Error::NotFound)
+    } else {
+      Ok(())
+    }
+  }
+}
+
+impl DeleteOutbox for PgConnection {
+  fn delete_outbox(&self, oid: i64) -> Result<()> {
+    let n = self.execute(
+      "
+      DELETE FROM outbox
+      WHERE id = $1",
+      &[&oid],
+    )?;
+    if n != 1 {
+      Err(Error::NotFound)
+    } else {
+      Ok(())
+    }
+  }
+}
+
+impl ListOutbox for PgConnection {
+  fn list_outbox(&self) -> Result<Vec<ListOutboxResponse>> {
+    let rows = self.query(
+      "
+      SELECT id, aid, uid, attempts, next_attempt, last_error,
+        to_char(created_time, 'YYYY-MM-DD HH24:MI:SS')
+      FROM outbox
+      ORDER BY id
+      ",
+      &[],
+    )?;
+    let mut li = vec![];
+    for row in rows.iter() {
+      li.push(ListOutboxResponse {
+        oid: row.try_get(0)?,
+        id: row.try_get(1)?,
+        uid: row.try_get(2)?,
+        attempts: row.try_get::<_, i32>(3)? as u32,
+        next_attempt: row.try_get(4)?,
+        last_error: row.try_get(5)?,
+        created_time: row.try_get(6)?,
+      });
+    }
+    Ok(li)
+  }
+}
+
+impl ResetOutbox for PgConnection {
+  fn reset_outbox(&self, request: ResetOutboxRequest) -> Result<usize> {
+    let n = self.execute(
+      "
+      UPDATE outbox
+      SET attempts = 0, next_attempt = $2
+      WHERE next_attempt IS NULL AND ( $1::BIGINT IS NULL OR id = $1 )
+      ",
+      &[&request.oid, &request.now.timestamp()],
+    )?;
+    Ok(n as usize)
+  }
+}
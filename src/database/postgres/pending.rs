@@ -0,0 +1,110 @@
+use crate::{
+  database::pending::{
+    is_marked, CreatePendingNotification, CreatePendingNotificationRequest,
+    LookupQueuedNotification, LookupQueuedNotificationRequest, TakePendingNotification,
+    TakePendingNotificationRequest, TakePendingNotificationResponse,
+  },
+  Result,
+};
+
+use super::PgConnection;
+
+impl CreatePendingNotification for PgConnection {
+  fn create_pending_notification(&self, request: CreatePendingNotificationRequest) -> Result<bool> {
+    let CreatePendingNotificationRequest {
+      uid,
+      id,
+      rx,
+      ts,
+      fire_time,
+      message,
+    } = request;
+    let n = self.execute(
+      "
+      INSERT INTO pending_notifications ( uid, aid, rx, ts, fire_time, message )
+      VALUES ( $1, $2, $3, $4, $5, $6 )
+      ON CONFLICT DO NOTHING
+      ",
+      &[
+        &uid,
+        &id,
+        &rx,
+        &ts.timestamp(),
+        &fire_time.timestamp(),
+        &message,
+      ],
+    )?;
+    Ok(n > 0)
+  }
+}
+
+impl TakePendingNotification for PgConnection {
+  fn take_pending_notification(
+    &self,
+    request: TakePendingNotificationRequest,
+  ) -> Result<Vec<TakePendingNotificationResponse>> {
+    let now = request.now.timestamp();
+    // deleted and returned at once, so that replicas polling at the same time
+    // never take the same notification
+    let rows = self.query(
+      "
+      WITH taken AS (
+        DELETE FROM pending_notifications
+        WHERE fire_time <= $1
+        RETURNING id, aid, uid, rx, ts, fire_time, message
+      )
+      SELECT taken.aid, taken.uid, act_agent, rx, ts, fire_time, message, act_active
+      FROM taken
+        INNER JOIN user_actions ON taken.aid = user_actions.id
+        INNER JOIN user_action_agents ON user_actions.aid = user_action_agents.id
+      ORDER BY taken.aid, fire_time, taken.id
+      ",
+      &[&now],
+    )?;
+    let mut li = vec![];
+    for row in rows.iter() {
+      li.push(TakePendingNotificationResponse {
+        id: row.try_get(0)?,
+        uid: row.try_get(1)?,
+        agent: row.try_get(2)?,
+        rx: row.try_get(3)?,
+        ts: row.try_get(4)?,
+        fire_time: row.try_get(5)?,
+        message: row.try_get(6)?,
+        active: row.try_get(7)?,
+      });
+    }
+    Ok(li)
+  }
+}
+
+impl LookupQueuedNotification for PgConnection {
+  fn lookup_queued_notification(&self, request: LookupQueuedNotificationRequest) -> Result<bool> {
+    let LookupQueuedNotificationRequest { id, rx, ts } = request;
+    let ts = ts.timestamp();
+    let row = self.query_row(
+      "
+      SELECT EXISTS (
+        SELECT 1 FROM pending_notifications
+        WHERE aid = $1 AND rx = $2 AND ts = $3
+      )
+      ",
+      &[&id, &rx, &ts],
+    )?;
+    if row.try_get(0)? {
+      return Ok(true);
+    }
+    let rows = self.query(
+      "
+      SELECT marks FROM outbox
+      WHERE aid = $1
+      ",
+      &[&id],
+    )?;
+    let mut marks = vec![];
+    for row in rows.iter() {
+      marks.push(row.try_get(0)?);
+    }
+    is_marked(&marks, rx, ts)
+  }
+}
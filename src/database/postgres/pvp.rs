@@ -0,0 +1,216 @@
+use appendlist::AppendList;
+
+use crate::{
+  database::{
+    from_json,
+    pvp::{
+      match_pvp_lookup, CreatePvpQuery, CreatePvpQueryRequest, DeletePvpQuery,
+      DeletePvpQueryRequest, ListPvpQuery, ListPvpQueryRequest, ListPvpQueryResponse, LookupPvp,
+      LookupPvpRequest, LookupPvpResponse, PvpLookupRow, PvpQueryRecord, UpdatePvpQuery,
+      UpdatePvpQueryRequest,
+    },
+    to_json,
+  },
+  Error, Result,
+};
+
+use super::PgConnection;
+
+impl CreatePvpQuery for PgConnection {
+  fn create_pvp_query(&self, request: CreatePvpQueryRequest) -> Result<i64> {
+    let CreatePvpQueryRequest {
+      uid,
+      record:
+        PvpQueryRecord {
+          modes,
+          rules,
+          includes,
+          excludes,
+          min_includes,
+          rule_stages,
+          remind_mins,
+          availability,
+          enabled,
+        },
+    } = request;
+    let includes = to_json(includes)?;
+    let excludes = to_json(excludes)?;
+    let rule_stages = to_json(rule_stages)?;
+    let availability = availability.as_ref().map(to_json).transpose()?;
+    let id = self
+      .query_row(
+        "
+        INSERT INTO pvp_queries ( uid, modes, rules, include_stages, exclude_stages, min_includes, rule_stages, remind_mins, availability, enabled )
+        VALUES ( $1, $2, $3, $4, $5, $6, $7, $8, $9, $10 )
+        RETURNING id
+        ",
+        &[
+          &uid,
+          &(*modes as i32),
+          &(*rules as i32),
+          &includes,
+          &excludes,
+          &(*min_includes as i32),
+          &rule_stages,
+          remind_mins,
+          &availability,
+          enabled,
+        ],
+      )?
+      .try_get(0)?;
+    Ok(id)
+  }
+}
+
+impl LookupPvp for PgConnection {
+  fn lookup_pvp(&self, request: LookupPvpRequest) -> Result<AppendList<LookupPvpResponse>> {
+    let LookupPvpRequest {
+      start_time,
+      rule,
+      mode,
+      stages,
+    } = request;
+    let ts = start_time.timestamp();
+    // see the sqlite backend for why stages and availability are matched later
+    let rows = self.query(
+      "
+      SELECT user_actions.id, pvp_queries.uid, act_agent,
+        COALESCE(pvp_queries.remind_mins, users.remind_mins), users.time_zone,
+        COALESCE(pvp_queries.availability, users.availability),
+        include_stages, exclude_stages, min_includes, rule_stages
+      FROM pvp_queries
+        INNER JOIN users ON pvp_queries.uid = users.id
+        INNER JOIN user_action_agents ON pvp_queries.uid = user_action_agents.uid
+        INNER JOIN user_actions ON aid = user_action_agents.id
+      WHERE
+        enabled AND
+        modes & $1 != 0 AND
+        rules & $2 != 0 AND
+        act_active AND expired_time IS NULL AND rx_pvp < $3
+      ORDER BY user_actions.id
+      ",
+      &[&(mode as u8 as i32), &(rule as u8 as i32), &ts],
+    )?;
+    let mut li = vec![];
+    for row in rows.iter() {
+      li.push(PvpLookupRow {
+        response: LookupPvpResponse {
+          id: row.try_get(0)?,
+          uid: row.try_get(1)?,
+          agent: row.try_get(2)?,
+          remind_mins: Some(row.try_get(3)?),
+        },
+        time_zone: row.try_get(4)?,
+        availability: row.try_get(5)?,
+        includes: row.try_get(6)?,
+        excludes: row.try_get(7)?,
+        min_includes: row.try_get::<_, i32>(8)? as u32,
+        rule_stages: row.try_get(9)?,
+      });
+    }
+    match_pvp_lookup(li, rule, stages, start_time)
+  }
+}
+
+impl ListPvpQuery for PgConnection {
+  fn list_pvp_query(&self, request: ListPvpQueryRequest) -> Result<Vec<ListPvpQueryResponse>> {
+    let rows = self.query(
+      "
+      SELECT id, modes, rules, include_stages, exclude_stages, min_includes, rule_stages,
+        remind_mins, availability, enabled, to_char(created_time, 'YYYY-MM-DD HH24:MI:SS')
+      FROM pvp_queries
+      WHERE uid = $1 AND ( $2::BIGINT IS NULL OR id = $2 )
+      ORDER BY id
+      ",
+      &[&request.uid, &request.qid],
+    )?;
+    let mut li = vec![];
+    for row in rows.iter() {
+      let availability: Option<String> = row.try_get(8)?;
+      li.push(ListPvpQueryResponse {
+        qid: row.try_get(0)?,
+        record: PvpQueryRecord {
+          modes: row.try_get::<_, i32>(1)? as u8,
+          rules: row.try_get::<_, i32>(2)? as u8,
+          includes: from_json(row.try_get(3)?)?,
+          excludes: from_json(row.try_get(4)?)?,
+          min_includes: row.try_get::<_, i32>(5)? as u32,
+          rule_stages: from_json(row.try_get(6)?)?,
+          remind_mins: row.try_get(7)?,
+          availability: availability.as_deref().map(from_json).transpose()?,
+          enabled: row.try_get(9)?,
+        },
+        created_time: row.try_get(10)?,
+      });
+    }
+    Ok(li)
+  }
+}
+
+impl UpdatePvpQuery for PgConnection {
+  fn update_pvp_query(&self, request: UpdatePvpQueryRequest) -> Result<()> {
+    let UpdatePvpQueryRequest {
+      uid,
+      qid,
+      record:
+        PvpQueryRecord {
+          modes,
+          rules,
+          includes,
+          excludes,
+          min_includes,
+          rule_stages,
+          remind_mins,
+          availability,
+          enabled,
+        },
+    } = request;
+    let includes = to_json(includes)?;
+    let excludes = to_json(excludes)?;
+    let rule_stages = to_json(rule_stages)?;
+    let availability = availability.as_ref().map(to_json).transpose()?;
+    let n = self.execute(
+      "
+      UPDATE pvp_queries
+      SET modes = $3, rules = $4, include_stages = $5, exclude_stages = $6, min_includes = $7,
+        rule_stages = $8, remind_mins = $9, availability = $10, enabled = $11
+      WHERE uid = $1 AND id = $2
+      ",
+      &[
+        &uid,
+        &qid,
+        &(*modes as i32),
+        &(*rules as i32),
+        &includes,
+        &excludes,
+        &(*min_includes as i32),
+        &rule_stages,
+        remind_mins,
+        &availability,
+        enabled,
+      ],
+    )?;
+    if n != 1 {
+      Err(Error::NotFound)
+    } else {
+      Ok(())
+    }
+  }
+}
+
+impl DeletePvpQuery for PgConnection {
+  fn delete_pvp_query(&self, request: DeletePvpQueryRequest) -> Result<()> {
+    let DeletePvpQueryRequest { uid, qid } = request;
+    let n = self.execute(
+      "
+      DELETE FROM pvp_queries
+      WHERE uid = $1 AND id = $2",
+      &[&uid, &qid],
+    )?;
+    if n != 1 {
+      Err(Error::NotFound)
+    } else {
+      Ok(())
+    }
+  }
+}
@@ -0,0 +1,61 @@
+use crate::{
+  database::spider::{
+    DeleteSpiderCursor, DeleteSpiderCursorRequest, ListSpiderCursor, ListSpiderCursorResponse,
+    UpdateSpiderCursor, UpdateSpiderCursorRequest,
+  },
+  Result,
+};
+
+use super::PgConnection;
+
+impl ListSpiderCursor for PgConnection {
+  fn list_spider_cursor(&self) -> Result<Vec<ListSpiderCursorResponse>> {
+    let rows = self.query(
+      "
+      SELECT name, value, to_char(updated_time, 'YYYY-MM-DD HH24:MI:SS')
+      FROM spider_cursors
+      ORDER BY name
+      ",
+      &[],
+    )?;
+    let mut li = vec![];
+    for row in rows.iter() {
+      li.push(ListSpiderCursorResponse {
+        name: row.try_get(0)?,
+        value: row.try_get(1)?,
+        updated_time: row.try_get(2)?,
+      });
+    }
+    Ok(li)
+  }
+}
+
+impl UpdateSpiderCursor for PgConnection {
+  fn update_spider_cursor(&self, request: UpdateSpiderCursorRequest) -> Result<()> {
+    for (name, value) in request.cursors.iter() {
+      self.execute(
+        "
+        INSERT INTO spider_cursors ( name, value )
+        VALUES ( $1, $2 )
+        ON CONFLICT ( name ) DO UPDATE
+        SET value = excluded.value, updated_time = now() AT TIME ZONE 'utc'
+        WHERE spider_cursors.value != excluded.value
+        ",
+        &[name, value],
+      )?;
+    }
+    Ok(())
+  }
+}
+
+impl DeleteSpiderCursor for PgConnection {
+  fn delete_spider_cursor(&self, request: DeleteSpiderCursorRequest) -> Result<usize> {
+    let n = self.execute(
+      "
+      DELETE FROM spider_cursors
+      WHERE $1::TEXT IS NULL OR name = $1",
+      &[&request.name],
+    )?;
+    Ok(n as usize)
+  }
+}
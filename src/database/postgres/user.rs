@@ -0,0 +1,189 @@
+use std::str::FromStr;
+
+use crate::{
+  database::{
+    availability::Availability,
+    from_json, to_json,
+    user::{
+      check_user_settings, CreateUser, CreateUserRequest, ListUserSettings, LookupUserContact,
+      LookupUserContactResponse, LookupUserId, LookupUserIdRequest, UpdateUserSettings,
+      UserSettings,
+    },
+    DigestMode, Language, TimeZone,
+  },
+  Error, Result,
+};
+
+use super::PgConnection;
+
+impl CreateUser for PgConnection {
+  fn create_user(&self, request: CreateUserRequest) -> Result<bool> {
+    let CreateUserRequest {
+      auth_agent,
+      auth_uid,
+      name,
+      email,
+      picture,
+      language,
+      time_zone,
+      availability,
+    } = request;
+    let language = language.unwrap_or(Language::EnUs).to_string();
+    let time_zone = time_zone.unwrap_or_default().to_string();
+    let availability = availability.map(to_json).transpose()?;
+    let n = self.execute(
+      "
+      INSERT INTO users ( auth_agent, auth_uid, name, email, picture, language, time_zone, availability )
+      VALUES ( $1, $2, $3, $4, $5, $6, $7, $8 )
+      ON CONFLICT DO NOTHING
+      ",
+      &[
+        &auth_agent,
+        &auth_uid,
+        &name,
+        &email,
+        &picture,
+        &language,
+        &time_zone,
+        &availability,
+      ],
+    )?;
+    Ok(n > 0)
+  }
+}
+
+impl LookupUserId for PgConnection {
+  fn lookup_user_id(&self, request: LookupUserIdRequest) -> Result<i64> {
+    let row = self
+      .query_opt(
+        "
+        SELECT id
+        FROM users
+        WHERE auth_uid = $1 AND auth_agent = $2
+        ",
+        &[&request.auth_uid, &request.auth_agent],
+      )?
+      .ok_or(Error::Unauthorized)?;
+    Ok(row.try_get(0)?)
+  }
+}
+
+impl ListUserSettings for PgConnection {
+  fn list_user_settings(&self, uid: i64) -> Result<UserSettings> {
+    let row = self.query_row(
+      "
+      SELECT language, time_zone, availability, remind_mins, digest, digest_hour,
+        quiet_start, quiet_end, snooze_until, vacation
+      FROM users
+      WHERE id = $1
+      ",
+      &[&uid],
+    )?;
+    let language: String = row.try_get(0)?;
+    let time_zone: String = row.try_get(1)?;
+    let availability: Option<String> = row.try_get(2)?;
+    let digest: String = row.try_get(4)?;
+    let digest_hour: i32 = row.try_get(5)?;
+    let quiet_start: i32 = row.try_get(6)?;
+    let quiet_end: i32 = row.try_get(7)?;
+    let availability = match availability {
+      Some(e) => from_json(&e)?,
+      None => Availability::any(),
+    };
+    Ok(UserSettings {
+      language: Some(
+        Language::from_str(&language).map_err(|err| Error::InternalServerError(Box::new(err)))?,
+      ),
+      time_zone: Some(
+        TimeZone::from_str(&time_zone).map_err(|err| Error::InternalServerError(Box::new(err)))?,
+      ),
+      availability: Some(availability),
+      remind_mins: Some(row.try_get(3)?),
+      digest: Some(
+        DigestMode::from_str(&digest).map_err(|err| Error::InternalServerError(Box::new(err)))?,
+      ),
+      digest_hour: Some(digest_hour as u32),
+      quiet_hours: Some((quiet_start as u32, quiet_end as u32)),
+      snooze_until: Some(row.try_get(8)?),
+      vacation: Some(row.try_get(9)?),
+    })
+  }
+}
+
+impl UpdateUserSettings for PgConnection {
+  fn update_user_settings(&self, uid: i64, settings: &UserSettings) -> Result<()> {
+    let UserSettings {
+      language,
+      time_zone,
+      remind_mins,
+      digest,
+      digest_hour,
+      quiet_hours,
+      snooze_until,
+      vacation,
+      ..
+    } = settings;
+    let availability = check_user_settings(settings)?;
+    let language = language.map(|e| e.to_string());
+    let time_zone = time_zone.map(|e| e.to_string());
+    let digest = digest.map(|e| e.to_string());
+    let n = self.execute(
+      "
+      UPDATE users
+      SET
+        language = coalesce($2, language),
+        time_zone = coalesce($3, time_zone),
+        availability = coalesce($4, availability),
+        remind_mins = coalesce($5, remind_mins),
+        digest = coalesce($6, digest),
+        digest_hour = coalesce($7, digest_hour),
+        quiet_start = coalesce($8, quiet_start),
+        quiet_end = coalesce($9, quiet_end),
+        snooze_until = coalesce($10, snooze_until),
+        vacation = coalesce($11, vacation)
+      WHERE id = $1
+      ",
+      &[
+        &uid,
+        &language,
+        &time_zone,
+        &availability,
+        &remind_mins,
+        &digest,
+        &digest_hour.map(|e| e as i32),
+        &quiet_hours.map(|e| e.0 as i32),
+        &quiet_hours.map(|e| e.1 as i32),
+        &snooze_until,
+        &vacation,
+      ],
+    )?;
+    if n == 0 {
+      Err(Error::Unauthorized)
+    } else {
+      Ok(())
+    }
+  }
+}
+
+impl LookupUserContact for PgConnection {
+  fn lookup_user_contact(&self, uid: i64) -> Result<LookupUserContactResponse> {
+    let row = self.query_row(
+      "
+      SELECT time_zone, quiet_start, quiet_end, snooze_until, vacation
+      FROM users
+      WHERE id = $1
+      ",
+      &[&uid],
+    )?;
+    let time_zone: String = row.try_get(0)?;
+    let quiet_start: i32 = row.try_get(1)?;
+    let quiet_end: i32 = row.try_get(2)?;
+    Ok(LookupUserContactResponse {
+      time_zone: TimeZone::from_str(&time_zone)
+        .map_err(|err| Error::InternalServerError(Box::new(err)))?,
+      quiet_hours: (quiet_start as u32, quiet_end as u32),
+      snooze_until: row.try_get(3)?,
+      vacation: row.try_get(4)?,
+    })
+  }
+}
@@ -1,24 +1,63 @@
 use appendlist::AppendList;
-use chrono::{DateTime, Datelike, Timelike, Utc};
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
 use r2d2_sqlite::rusqlite::Connection;
+use serde::{Deserialize, Serialize};
 
 use crate::{
   splatnet::{PvpMode, PvpRule},
   Error, Result,
 };
 
-use super::TimeZone;
+use super::{
+  availability::{is_available, Availability},
+  from_json, to_json, DatabaseConnection,
+};
+
+// whether the stages of a rotation satisfy the constraints, where no
+// included stages stand for any stage
+fn match_stages(includes: &[u32], excludes: &[u32], min_includes: u32, stages: &[u32]) -> bool {
+  if stages.iter().any(|e| excludes.contains(e)) {
+    return false;
+  }
+  let n = stages.iter().filter(|e| includes.contains(e)).count();
+  includes.is_empty() || n >= min_includes as usize
+}
+
+// stage constraints of a single rule, in place of the query-wide ones
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct PvpRuleStages {
+  pub rule: PvpRule,
+  // any stage if empty
+  #[serde(default)]
+  pub includes: Vec<u32>,
+  #[serde(default)]
+  pub excludes: Vec<u32>,
+  #[serde(default = "default_min_includes")]
+  pub min_includes: u32,
+}
 
-fn fold_stage_mask(stages: &[u32]) -> u32 {
-  stages.iter().fold(0u32, |a, b| a | (1 << (b - 1)))
+pub(crate) fn default_min_includes() -> u32 {
+  1
 }
 
+// stages of a rotation
+pub(crate) const PVP_ROTATION_STAGES: u32 = 2;
+
 #[derive(Debug)]
 pub struct PvpQueryRecord {
   pub modes: u8,
   pub rules: u8,
-  pub includes: u32,
-  pub excludes: u32,
+  pub includes: Vec<u32>,
+  pub excludes: Vec<u32>,
+  // included stages required in a rotation, 2 for both
+  pub min_includes: u32,
+  pub rule_stages: Vec<PvpRuleStages>,
+  // null to follow user settings, negative for at announcement
+  pub remind_mins: Option<i32>,
+  // null to follow user settings
+  pub availability: Option<Availability>,
+  pub enabled: bool,
 }
 
 #[derive(Debug)]
@@ -43,6 +82,8 @@ pub struct LookupPvpResponse {
   pub id: i64,
   pub uid: i64,
   pub agent: String,
+  // minutes before start, none for at announcement
+  pub remind_mins: Option<i32>,
 }
 
 pub trait LookupPvp {
@@ -86,6 +127,12 @@ pub trait DeletePvpQuery {
   fn delete_pvp_query(&self, request: DeletePvpQueryRequest) -> Result<()>;
 }
 
+impl CreatePvpQuery for DatabaseConnection {
+  fn create_pvp_query(&self, request: CreatePvpQueryRequest) -> Result<i64> {
+    dispatch!(self, conn => conn.create_pvp_query(request))
+  }
+}
+
 impl CreatePvpQuery for Connection {
   fn create_pvp_query(&self, request: CreatePvpQueryRequest) -> Result<i64> {
     let CreatePvpQueryRequest {
@@ -96,25 +143,49 @@ impl CreatePvpQuery for Connection {
           rules,
           includes,
           excludes,
+          min_includes,
+          rule_stages,
+          remind_mins,
+          availability,
+          enabled,
         },
     } = request;
+    let includes = to_json(includes)?;
+    let excludes = to_json(excludes)?;
+    let rule_stages = to_json(rule_stages)?;
+    let availability = availability.as_ref().map(to_json).transpose()?;
     let mut stmt = self.prepare_cached(
       "
-      INSERT INTO pvp_queries ( uid, modes, rules, includes, excludes )
-      VALUES ( ?1, ?2, ?3, ?4, ?5 )
+      INSERT INTO pvp_queries ( uid, modes, rules, includes, excludes, include_stages, exclude_stages, min_includes, rule_stages, remind_mins, availability, enabled )
+      VALUES ( ?1, ?2, ?3, 0, 0, ?4, ?5, ?6, ?7, ?8, ?9, ?10 )
       ",
     )?;
-    let n = stmt.execute((&uid, &modes, &rules, &includes, &excludes))?;
+    let n = stmt.execute((
+      &uid,
+      &modes,
+      &rules,
+      &includes,
+      &excludes,
+      &min_includes,
+      &rule_stages,
+      &remind_mins,
+      &availability,
+      &enabled,
+    ))?;
     if n != 1 {
-      Err(Error::SqliteError(
-        r2d2_sqlite::rusqlite::Error::QueryReturnedNoRows,
-      ))
+      Err(Error::NotFound)
     } else {
       Ok(self.last_insert_rowid())
     }
   }
 }
 
+impl LookupPvp for DatabaseConnection {
+  fn lookup_pvp(&self, request: LookupPvpRequest) -> Result<AppendList<LookupPvpResponse>> {
+    dispatch!(self, conn => conn.lookup_pvp(request))
+  }
+}
+
 impl LookupPvp for Connection {
   fn lookup_pvp(&self, request: LookupPvpRequest) -> Result<AppendList<LookupPvpResponse>> {
     let LookupPvpRequest {
@@ -123,52 +194,111 @@ impl LookupPvp for Connection {
       mode,
       stages,
     } = request;
-    let mode = mode as u8;
-    let rule = rule as u8;
-    let stages = fold_stage_mask(stages);
     let ts = start_time.timestamp();
-    let t = TimeZone::Jst.convert(start_time);
-    let a = t.weekday() as u32;
-    let b = t.hour() / 2;
-    let day_hrs = if a < 4 { "day_hrs_0" } else { "day_hrs_1" };
-    let day_hrs_v = (1i64 << b) << 12 * (a % 4);
-    // FIXME: add tests
-    let sql = format!(
+    // one row per matched query and action, as stages may be constrained per
+    // rule, and availability is in local time and may be set per query, so
+    // they are matched here rather than in sql
+    let mut stmt = self.prepare_cached(
       "
-      SELECT user_actions.id, uid_1, act_agent
-      FROM (
-        SELECT uid as uid_1
-        FROM pvp_queries 
-          INNER JOIN users ON uid = users.id
-        WHERE 
-          {day_hrs} & ?5 AND
-          modes & ?1 AND 
-          rules & ?2 AND 
-          includes & ?3 AND 
-          NOT (excludes & ?3)
-      ) 
-        INNER JOIN user_action_agents ON uid_1 == user_action_agents.uid
+      SELECT user_actions.id, pvp_queries.uid, act_agent,
+        COALESCE(pvp_queries.remind_mins, users.remind_mins), users.time_zone,
+        COALESCE(pvp_queries.availability, users.availability),
+        include_stages, exclude_stages, min_includes, rule_stages
+      FROM pvp_queries 
+        INNER JOIN users ON pvp_queries.uid = users.id
+        INNER JOIN user_action_agents ON pvp_queries.uid == user_action_agents.uid
         INNER JOIN user_actions ON aid == user_action_agents.id
-      WHERE act_active AND rx_pvp < ?4
-      "
-    );
-    let mut stmt = self.prepare_cached(&sql)?;
-    let iter = stmt.query_map((&mode, &rule, &stages, &ts, &day_hrs_v), |row| {
-      Ok(LookupPvpResponse {
-        id: row.get(0)?,
-        uid: row.get(1)?,
-        agent: row.get(2)?,
+      WHERE 
+        enabled AND
+        modes & ?1 AND 
+        rules & ?2 AND 
+        act_active AND expired_time IS NULL AND rx_pvp < ?3
+      ORDER BY user_actions.id
+      ",
+    )?;
+    let iter = stmt.query_map((&(mode as u8), &(rule as u8), &ts), |row| {
+      Ok(PvpLookupRow {
+        response: LookupPvpResponse {
+          id: row.get(0)?,
+          uid: row.get(1)?,
+          agent: row.get(2)?,
+          remind_mins: Some(row.get(3)?),
+        },
+        time_zone: row.get(4)?,
+        availability: row.get(5)?,
+        includes: row.get(6)?,
+        excludes: row.get(7)?,
+        min_includes: row.get(8)?,
+        rule_stages: row.get(9)?,
       })
     })?;
-    let list = itertools::process_results(iter, |iter| iter.collect())?;
-    Ok(list)
+    let li: Vec<_> = itertools::process_results(iter, |iter| iter.collect())?;
+    match_pvp_lookup(li, rule, stages, start_time)
+  }
+}
+
+// a matched query of `lookup_pvp` and its action, before stages and
+// availability are checked
+pub(crate) struct PvpLookupRow {
+  pub response: LookupPvpResponse,
+  pub time_zone: String,
+  pub availability: Option<String>,
+  pub includes: String,
+  pub excludes: String,
+  pub min_includes: u32,
+  pub rule_stages: String,
+}
+
+// the rows are ordered by action
+pub(crate) fn match_pvp_lookup(
+  rows: Vec<PvpLookupRow>,
+  rule: PvpRule,
+  stages: &[u32],
+  start_time: DateTime<Utc>,
+) -> Result<AppendList<LookupPvpResponse>> {
+  let mut li = vec![];
+  for e in rows {
+    let rule_stages: Vec<PvpRuleStages> = from_json(&e.rule_stages)?;
+    let ok = match rule_stages.iter().find(|e| e.rule == rule) {
+      Some(e) => match_stages(&e.includes, &e.excludes, e.min_includes, stages),
+      None => {
+        let includes: Vec<u32> = from_json(&e.includes)?;
+        let excludes: Vec<u32> = from_json(&e.excludes)?;
+        // no stage at all if none included, as in earlier versions
+        !includes.is_empty() && match_stages(&includes, &excludes, e.min_includes, stages)
+      }
+    };
+    if ok && is_available(&e.time_zone, e.availability.as_deref(), start_time)? {
+      li.push(e.response);
+    }
+  }
+  // reminder lead over all the matched queries, see `remind_mins_sql`
+  let list = AppendList::new();
+  for (_, group) in &li.into_iter().group_by(|e| e.id) {
+    let group: Vec<_> = group.collect();
+    let remind_mins = if group.iter().any(|e| e.remind_mins.unwrap_or(-1) < 0) {
+      None
+    } else {
+      group.iter().filter_map(|e| e.remind_mins).max()
+    };
+    if let Some(e) = group.into_iter().next() {
+      list.push(LookupPvpResponse { remind_mins, ..e });
+    }
+  }
+  Ok(list)
+}
+
+impl ListPvpQuery for DatabaseConnection {
+  fn list_pvp_query(&self, request: ListPvpQueryRequest) -> Result<Vec<ListPvpQueryResponse>> {
+    dispatch!(self, conn => conn.list_pvp_query(request))
   }
 }
 
 impl ListPvpQuery for Connection {
   fn list_pvp_query(&self, request: ListPvpQueryRequest) -> Result<Vec<ListPvpQueryResponse>> {
     let mut sql: String = "
-      SELECT id, modes, rules, includes, excludes, created_time
+      SELECT id, modes, rules, include_stages, exclude_stages, min_includes, rule_stages,
+        remind_mins, availability, enabled, created_time
       FROM pvp_queries
       WHERE uid = ?1
       "
@@ -180,22 +310,47 @@ impl ListPvpQuery for Connection {
     }
     let mut stmt = self.prepare_cached(&sql)?;
     let iter = stmt.query_map((&request.uid, &request.qid), |row| {
-      Ok(ListPvpQueryResponse {
-        qid: row.get(0)?,
-        record: PvpQueryRecord {
-          modes: row.get(1)?,
-          rules: row.get(2)?,
-          includes: row.get(3)?,
-          excludes: row.get(4)?,
+      Ok((
+        ListPvpQueryResponse {
+          qid: row.get(0)?,
+          record: PvpQueryRecord {
+            modes: row.get(1)?,
+            rules: row.get(2)?,
+            includes: vec![],
+            excludes: vec![],
+            min_includes: row.get(5)?,
+            rule_stages: vec![],
+            remind_mins: row.get(7)?,
+            availability: None,
+            enabled: row.get(9)?,
+          },
+          created_time: row.get(10)?,
         },
-        created_time: row.get(5)?,
-      })
+        row.get::<_, String>(3)?,
+        row.get::<_, String>(4)?,
+        row.get::<_, String>(6)?,
+        row.get::<_, Option<String>>(8)?,
+      ))
     })?;
-    let li = itertools::process_results(iter, |iter| iter.collect())?;
+    let mut li = vec![];
+    for e in iter {
+      let (mut e, includes, excludes, rule_stages, availability) = e?;
+      e.record.includes = from_json(&includes)?;
+      e.record.excludes = from_json(&excludes)?;
+      e.record.rule_stages = from_json(&rule_stages)?;
+      e.record.availability = availability.as_deref().map(from_json).transpose()?;
+      li.push(e);
+    }
     Ok(li)
   }
 }
 
+impl UpdatePvpQuery for DatabaseConnection {
+  fn update_pvp_query(&self, request: UpdatePvpQueryRequest) -> Result<()> {
+    dispatch!(self, conn => conn.update_pvp_query(request))
+  }
+}
+
 impl UpdatePvpQuery for Connection {
   fn update_pvp_query(&self, request: UpdatePvpQueryRequest) -> Result<()> {
     let UpdatePvpQueryRequest {
@@ -207,26 +362,52 @@ impl UpdatePvpQuery for Connection {
           rules,
           includes,
           excludes,
+          min_includes,
+          rule_stages,
+          remind_mins,
+          availability,
+          enabled,
         },
     } = request;
+    let includes = to_json(includes)?;
+    let excludes = to_json(excludes)?;
+    let rule_stages = to_json(rule_stages)?;
+    let availability = availability.as_ref().map(to_json).transpose()?;
     let mut stmt = self.prepare_cached(
       "
       UPDATE pvp_queries
-      SET modes = ?3, rules = ?4, includes = ?5, excludes = ?6
+      SET modes = ?3, rules = ?4, include_stages = ?5, exclude_stages = ?6, min_includes = ?7,
+        rule_stages = ?8, remind_mins = ?9, availability = ?10, enabled = ?11
       WHERE uid = ?1 AND id = ?2
       ",
     )?;
-    let n = stmt.execute((&uid, &qid, &modes, &rules, &includes, &excludes))?;
+    let n = stmt.execute((
+      &uid,
+      &qid,
+      &modes,
+      &rules,
+      &includes,
+      &excludes,
+      &min_includes,
+      &rule_stages,
+      &remind_mins,
+      &availability,
+      &enabled,
+    ))?;
     if n != 1 {
-      Err(Error::SqliteError(
-        r2d2_sqlite::rusqlite::Error::QueryReturnedNoRows,
-      ))
+      Err(Error::NotFound)
     } else {
       Ok(())
     }
   }
 }
 
+impl DeletePvpQuery for DatabaseConnection {
+  fn delete_pvp_query(&self, request: DeletePvpQueryRequest) -> Result<()> {
+    dispatch!(self, conn => conn.delete_pvp_query(request))
+  }
+}
+
 impl DeletePvpQuery for Connection {
   fn delete_pvp_query(&self, request: DeletePvpQueryRequest) -> Result<()> {
     let DeletePvpQueryRequest { uid, qid } = request;
@@ -237,9 +418,7 @@ impl DeletePvpQuery for Connection {
     )?;
     let n = stmt.execute((&uid, &qid))?;
     if n != 1 {
-      Err(Error::SqliteError(
-        r2d2_sqlite::rusqlite::Error::QueryReturnedNoRows,
-      ))
+      Err(Error::NotFound)
     } else {
       Ok(())
     }
@@ -248,14 +427,19 @@ impl DeletePvpQuery for Connection {
 
 #[cfg(test)]
 mod tests {
+  use chrono::TimeZone as _;
+
   use crate::{
     database::{
       action::CreateAction,
       query::{
-        CreateQuery, CreateQueryRequest, PvpQueryConfig, QueryConfig, UpdateQuery,
-        UpdateQueryRequest,
+        CreateQuery, CreateQueryRequest, DeleteQuery, DeleteQueryRequest, PvpQueryConfig,
+        QueryConfig, QueryType, UpdateQuery, UpdateQueryRequest,
+      },
+      user::{
+        CreateUser, CreateUserRequest, LookupUserId, LookupUserIdRequest, UpdateUserSettings,
+        UserSettings,
       },
-      user::{CreateUser, CreateUserRequest, LookupUserId, LookupUserIdRequest},
       Database,
     },
     splatnet::PvpMode,
@@ -265,7 +449,7 @@ mod tests {
 
   #[test]
   fn test_lookup_simple() {
-    let db = Database::new_in_memory().unwrap();
+    let db = Database::new_for_test().unwrap();
     let mut conn = db.get().unwrap();
 
     let auth_agent = "mock_auth_agent";
@@ -279,7 +463,7 @@ mod tests {
         picture: None,
         language: None,
         time_zone: None,
-        day_hrs: None,
+        availability: None,
       })
       .unwrap();
     assert!(ok);
@@ -300,6 +484,11 @@ mod tests {
             rules: vec![PvpRule::Asari],
             includes: vec![1, 2],
             excludes: vec![4, 5],
+            min_includes: 1,
+            rule_stages: vec![],
+            remind_mins: None,
+            availability: None,
+            enabled: true,
           },
         },
       })
@@ -401,6 +590,11 @@ mod tests {
           rules: vec![PvpRule::Asari],
           includes: vec![10],
           excludes: vec![1, 2],
+          min_includes: 1,
+          rule_stages: vec![],
+          remind_mins: None,
+          availability: None,
+          enabled: true,
         },
       },
     })
@@ -417,5 +611,234 @@ mod tests {
       .unwrap();
     // updated
     assert_eq!(li.len(), 0);
+
+    conn
+      .update_user_settings(
+        uid,
+        &UserSettings {
+          language: None,
+          time_zone: Some("Europe/Paris".parse().unwrap()),
+          availability: Some(serde_json::from_str(r#"{"fri":["18:00-24:00"]}"#).unwrap()),
+          remind_mins: None,
+          digest: None,
+          digest_hour: None,
+          quiet_hours: None,
+          snooze_until: None,
+          vacation: None,
+        },
+      )
+      .unwrap();
+    let lookup = |start_time| {
+      conn
+        .lookup_pvp(LookupPvpRequest {
+          start_time,
+          rule: PvpRule::Asari,
+          mode: PvpMode::X,
+          stages: &[10],
+        })
+        .unwrap()
+        .len()
+    };
+    // friday 19:00 cest
+    assert_eq!(
+      lookup(Utc.with_ymd_and_hms(2023, 7, 7, 17, 0, 0).unwrap()),
+      1
+    );
+    // friday 17:00 cest
+    assert_eq!(
+      lookup(Utc.with_ymd_and_hms(2023, 7, 7, 15, 0, 0).unwrap()),
+      0
+    );
+    // friday 19:00 cet
+    assert_eq!(
+      lookup(Utc.with_ymd_and_hms(2023, 12, 1, 18, 0, 0).unwrap()),
+      1
+    );
+
+    let config = |enabled| PvpQueryConfig {
+      modes: vec![PvpMode::X],
+      rules: vec![PvpRule::Asari],
+      includes: vec![10],
+      excludes: vec![],
+      min_includes: 1,
+      rule_stages: vec![],
+      remind_mins: Some(30),
+      availability: Some(serde_json::from_str(r#"{"sat":["10:00-12:00"]}"#).unwrap()),
+      enabled,
+    };
+    let tx = conn.transaction().unwrap();
+    let qid = tx
+      .create_query(CreateQueryRequest {
+        uid,
+        config: &QueryConfig::Pvp {
+          config: config(true),
+        },
+      })
+      .unwrap();
+    tx.commit().unwrap();
+    let lookup = |conn: &DatabaseConnection, start_time| {
+      let li = conn
+        .lookup_pvp(LookupPvpRequest {
+          start_time,
+          rule: PvpRule::Asari,
+          mode: PvpMode::X,
+          stages: &[10],
+        })
+        .unwrap();
+      li.iter().map(|e| e.remind_mins).collect::<Vec<_>>()
+    };
+    // saturday 11:00 cest, by the query's own window
+    let sat = Utc.with_ymd_and_hms(2023, 7, 8, 9, 0, 0).unwrap();
+    assert_eq!(lookup(&conn, sat), vec![Some(30)]);
+    // friday 19:00 cest, by the user's window only
+    let fri = Utc.with_ymd_and_hms(2023, 7, 7, 17, 0, 0).unwrap();
+    assert_eq!(lookup(&conn, fri), vec![None]);
+
+    let tx = conn.transaction().unwrap();
+    tx.update_query(UpdateQueryRequest {
+      uid,
+      qid,
+      config: &QueryConfig::Pvp {
+        config: config(false),
+      },
+    })
+    .unwrap();
+    tx.commit().unwrap();
+    // disabled
+    assert_eq!(lookup(&conn, sat), vec![]);
+
+    let tx = conn.transaction().unwrap();
+    for config in [
+      PvpQueryConfig {
+        modes: vec![PvpMode::X],
+        rules: vec![PvpRule::Hoko, PvpRule::Asari],
+        includes: vec![],
+        excludes: vec![],
+        min_includes: 1,
+        rule_stages: vec![
+          PvpRuleStages {
+            rule: PvpRule::Hoko,
+            includes: vec![7, 40],
+            excludes: vec![],
+            min_includes: 1,
+          },
+          PvpRuleStages {
+            rule: PvpRule::Asari,
+            includes: vec![],
+            excludes: vec![8],
+            min_includes: 1,
+          },
+        ],
+        remind_mins: None,
+        availability: None,
+        enabled: true,
+      },
+      PvpQueryConfig {
+        modes: vec![PvpMode::X],
+        rules: vec![PvpRule::Yagura],
+        includes: vec![],
+        excludes: vec![],
+        min_includes: 1,
+        rule_stages: vec![],
+        remind_mins: None,
+        availability: None,
+        enabled: true,
+      },
+      PvpQueryConfig {
+        modes: vec![PvpMode::X],
+        rules: vec![PvpRule::Area],
+        includes: vec![11, 12],
+        excludes: vec![],
+        min_includes: 2,
+        rule_stages: vec![],
+        remind_mins: None,
+        availability: None,
+        enabled: true,
+      },
+    ] {
+      tx.create_query(CreateQueryRequest {
+        uid,
+        config: &QueryConfig::Pvp { config },
+      })
+      .unwrap();
+    }
+    tx.commit().unwrap();
+    let lookup = |rule, stages: &[u32]| {
+      conn
+        .lookup_pvp(LookupPvpRequest {
+          start_time: fri,
+          rule,
+          mode: PvpMode::X,
+          stages,
+        })
+        .unwrap()
+        .len()
+    };
+    // rainmaker on stage 7 or 40 only
+    assert_eq!(lookup(PvpRule::Hoko, &[7, 9]), 1);
+    assert_eq!(lookup(PvpRule::Hoko, &[9, 11]), 0);
+    // stage ids beyond 32
+    assert_eq!(lookup(PvpRule::Hoko, &[40, 9]), 1);
+    // clam blitz on anything but stage 8
+    assert_eq!(lookup(PvpRule::Asari, &[9, 11]), 1);
+    assert_eq!(lookup(PvpRule::Asari, &[8, 9]), 0);
+    // more included stages than a rotation has
+    for min_includes in [0, 3] {
+      let ret = conn.create_query(CreateQueryRequest {
+        uid,
+        config: &QueryConfig::Pvp {
+          config: PvpQueryConfig {
+            modes: vec![PvpMode::X],
+            rules: vec![PvpRule::Area],
+            includes: vec![11, 12],
+            excludes: vec![],
+            min_includes,
+            rule_stages: vec![],
+            remind_mins: None,
+            availability: None,
+            enabled: true,
+          },
+        },
+      });
+      assert!(matches!(
+        ret,
+        Err(Error::InvalidParameter("min_includes", _))
+      ));
+    }
+    // stage ids start from 1, per rule as well
+    let ret = conn.create_query(CreateQueryRequest {
+      uid,
+      config: &QueryConfig::Pvp {
+        config: PvpQueryConfig {
+          modes: vec![PvpMode::X],
+          rules: vec![PvpRule::Hoko],
+          includes: vec![],
+          excludes: vec![],
+          min_includes: 1,
+          rule_stages: vec![PvpRuleStages {
+            rule: PvpRule::Hoko,
+            includes: vec![],
+            excludes: vec![0],
+            min_includes: 1,
+          }],
+          remind_mins: None,
+          availability: None,
+          enabled: true,
+        },
+      },
+    });
+    assert!(matches!(ret, Err(Error::InvalidParameter("stageid", _))));
+    // missing queries are reported alike by both backends
+    let ret = conn.delete_query(DeleteQueryRequest {
+      uid,
+      qid: -1,
+      qtype: QueryType::Pvp,
+    });
+    assert!(matches!(ret, Err(Error::NotFound)));
+    // tower control on no stage
+    assert_eq!(lookup(PvpRule::Yagura, &[9, 11]), 0);
+    // splat zones on both stages
+    assert_eq!(lookup(PvpRule::Area, &[11, 12]), 1);
+    assert_eq!(lookup(PvpRule::Area, &[11, 9]), 0);
   }
 }
@@ -1,17 +1,39 @@
-use r2d2_sqlite::rusqlite::Connection;
 use serde::{Deserialize, Serialize};
 use serde_enum_str::{Deserialize_enum_str, Serialize_enum_str};
 use strum::IntoEnumIterator;
 
 use crate::{
   database::pvp::CreatePvpQueryRequest,
-  splatnet::{PvpMode, PvpRule},
+  splatnet::{CoopMode, FestState, GearType, KingSalmonid, PvpMode, PvpRule},
   Error, Result,
 };
 
-use super::pvp::{
-  CreatePvpQuery, DeletePvpQuery, DeletePvpQueryRequest, ListPvpQuery, ListPvpQueryRequest,
-  PvpQueryRecord, UpdatePvpQuery, UpdatePvpQueryRequest,
+use super::{
+  availability::Availability,
+  coop::{
+    CoopQueryRecord, CreateCoopQuery, CreateCoopQueryRequest, DeleteCoopQuery,
+    DeleteCoopQueryRequest, ListCoopQuery, ListCoopQueryRequest, UpdateCoopQuery,
+    UpdateCoopQueryRequest,
+  },
+  event::{
+    CreateEventQuery, CreateEventQueryRequest, DeleteEventQuery, DeleteEventQueryRequest,
+    EventQueryRecord, ListEventQuery, ListEventQueryRequest, UpdateEventQuery,
+    UpdateEventQueryRequest,
+  },
+  fest::{
+    CreateFestQuery, CreateFestQueryRequest, DeleteFestQuery, DeleteFestQueryRequest,
+    FestQueryRecord, ListFestQuery, ListFestQueryRequest, UpdateFestQuery, UpdateFestQueryRequest,
+  },
+  gear::{
+    CreateGearQuery, CreateGearQueryRequest, DeleteGearQuery, DeleteGearQueryRequest,
+    GearQueryRecord, ListGearQuery, ListGearQueryRequest, UpdateGearQuery, UpdateGearQueryRequest,
+  },
+  pvp::{
+    default_min_includes, CreatePvpQuery, DeletePvpQuery, DeletePvpQueryRequest, ListPvpQuery,
+    ListPvpQueryRequest, PvpQueryRecord, PvpRuleStages, UpdatePvpQuery, UpdatePvpQueryRequest,
+    PVP_ROTATION_STAGES,
+  },
+  DatabaseConnection,
 };
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize_enum_str, Deserialize_enum_str)]
@@ -20,6 +42,8 @@ pub enum QueryType {
   Pvp,
   Coop,
   Gears,
+  Event,
+  Fest,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -28,22 +52,29 @@ pub struct PvpQueryConfig {
   pub modes: Vec<PvpMode>,
   #[serde(default = "default_query_pvp_product_rules")]
   pub rules: Vec<PvpRule>,
+  // no stage if empty, unlike the ones of `rule_stages`
   pub includes: Vec<u32>,
   #[serde(default)]
   pub excludes: Vec<u32>,
+  // included stages required in a rotation, e.g. 2 for both stages
+  #[serde(default = "default_min_includes")]
+  pub min_includes: u32,
+  // stage constraints of specific rules in place of the ones above
+  #[serde(default)]
+  pub rule_stages: Vec<PvpRuleStages>,
+  // minutes before start, follows user settings if not specified
+  #[serde(default)]
+  pub remind_mins: Option<i32>,
+  // local time ranges in the user's time zone, follows user settings if not specified
+  #[serde(default)]
+  pub availability: Option<Availability>,
+  // disabled queries are kept but never matched
+  #[serde(default = "default_query_enabled")]
+  pub enabled: bool,
 }
 
 impl From<&PvpQueryRecord> for PvpQueryConfig {
   fn from(value: &PvpQueryRecord) -> Self {
-    let parse_stage_list = |stages: u32| {
-      let mut stages_ = vec![];
-      for i in 0..32 {
-        if ((1u32 << i) & stages) != 0 {
-          stages_.push(i + 1);
-        }
-      }
-      stages_
-    };
     let parse_modes_list = |modes: u8| {
       let mut modes_ = vec![];
       for mode in PvpMode::iter() {
@@ -64,13 +95,16 @@ impl From<&PvpQueryRecord> for PvpQueryConfig {
     };
     let modes = parse_modes_list(value.modes);
     let rules = parse_rules_list(value.rules);
-    let includes = parse_stage_list(value.includes);
-    let excludes = parse_stage_list(value.excludes);
     PvpQueryConfig {
       modes,
       rules,
-      includes,
-      excludes,
+      includes: value.includes.clone(),
+      excludes: value.excludes.clone(),
+      min_includes: value.min_includes,
+      rule_stages: value.rule_stages.clone(),
+      remind_mins: value.remind_mins,
+      availability: value.availability.clone(),
+      enabled: value.enabled,
     }
   }
 }
@@ -79,25 +113,255 @@ impl TryInto<PvpQueryRecord> for &PvpQueryConfig {
   type Error = Error;
 
   fn try_into(self) -> std::result::Result<PvpQueryRecord, Self::Error> {
-    let parse_stage_list = |stages: &[u32]| -> Result<_> {
-      let mut ret = 0u32;
-      for id in stages {
-        ret |= id
-          .checked_sub(1)
-          .and_then(|e| 1u32.checked_shl(e))
-          .ok_or_else(|| Error::InvalidParameter("stageid", id.to_string()))?;
-      }
-      Ok(ret)
+    let check_stage_list = |stages: &[u32]| -> Result<()> {
+      match stages.iter().find(|id| **id == 0) {
+        Some(id) => Err(Error::InvalidParameter("stageid", id.to_string())),
+        None => Ok(()),
+      }
+    };
+    let check_min_includes = |n: u32| -> Result<()> {
+      if n == 0 || n > PVP_ROTATION_STAGES {
+        Err(Error::InvalidParameter("min_includes", n.to_string()))
+      } else {
+        Ok(())
+      }
     };
     let modes = self.modes.iter().fold(0u8, |a, b| a | *b as u8);
     let rules = self.rules.iter().fold(0u8, |a, b| a | *b as u8);
-    let includes = parse_stage_list(&self.includes)?;
-    let excludes = parse_stage_list(&self.excludes)?;
+    check_stage_list(&self.includes)?;
+    check_stage_list(&self.excludes)?;
+    check_min_includes(self.min_includes)?;
+    for (i, e) in self.rule_stages.iter().enumerate() {
+      // the rule itself is still to be chosen in `rules`, and listed once
+      if (e.rule as u8) & rules == 0 || self.rule_stages[..i].iter().any(|f| f.rule == e.rule) {
+        return Err(Error::InvalidParameter("rule_stages", e.rule.to_string()));
+      }
+      check_stage_list(&e.includes)?;
+      check_stage_list(&e.excludes)?;
+      check_min_includes(e.min_includes)?;
+    }
     Ok(PvpQueryRecord {
       modes,
       rules,
-      includes,
-      excludes,
+      includes: self.includes.clone(),
+      excludes: self.excludes.clone(),
+      min_includes: self.min_includes,
+      rule_stages: self.rule_stages.clone(),
+      remind_mins: self.remind_mins,
+      availability: self.availability.clone().map(|mut e| {
+        e.normalize();
+        e
+      }),
+      enabled: self.enabled,
+    })
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CoopQueryConfig {
+  #[serde(default = "default_query_coop_modes")]
+  pub modes: Vec<CoopMode>,
+  #[serde(default)]
+  pub includes: Vec<u32>,
+  #[serde(default)]
+  pub excludes: Vec<u32>,
+  #[serde(default)]
+  pub weapons: Vec<String>,
+  #[serde(default)]
+  pub forbidden_weapons: Vec<String>,
+  #[serde(default)]
+  pub king_salmonids: Vec<KingSalmonid>,
+  #[serde(default)]
+  pub remind_mins: Option<i32>,
+  #[serde(default = "default_query_enabled")]
+  pub enabled: bool,
+}
+
+impl From<&CoopQueryRecord> for CoopQueryConfig {
+  fn from(value: &CoopQueryRecord) -> Self {
+    let mut modes = vec![];
+    for mode in CoopMode::iter() {
+      if ((mode as u8) & value.modes) != 0 {
+        modes.push(mode);
+      }
+    }
+    let mut king_salmonids = vec![];
+    for king in KingSalmonid::iter() {
+      if ((king as u8) & value.king_salmonids) != 0 {
+        king_salmonids.push(king);
+      }
+    }
+    CoopQueryConfig {
+      modes,
+      includes: value.includes.clone(),
+      excludes: value.excludes.clone(),
+      weapons: value.weapons.clone(),
+      forbidden_weapons: value.forbidden_weapons.clone(),
+      king_salmonids,
+      remind_mins: value.remind_mins,
+      enabled: value.enabled,
+    }
+  }
+}
+
+impl TryInto<CoopQueryRecord> for &CoopQueryConfig {
+  type Error = Error;
+
+  fn try_into(self) -> std::result::Result<CoopQueryRecord, Self::Error> {
+    for id in self.includes.iter().chain(self.excludes.iter()) {
+      if *id == 0 {
+        return Err(Error::InvalidParameter("stageid", id.to_string()));
+      }
+    }
+    for id in self.weapons.iter().chain(self.forbidden_weapons.iter()) {
+      if id.is_empty() {
+        return Err(Error::InvalidParameter("weaponid", id.clone()));
+      }
+    }
+    let modes = self.modes.iter().fold(0u8, |a, b| a | *b as u8);
+    let king_salmonids = self.king_salmonids.iter().fold(0u8, |a, b| a | *b as u8);
+    Ok(CoopQueryRecord {
+      modes,
+      includes: self.includes.clone(),
+      excludes: self.excludes.clone(),
+      weapons: self.weapons.clone(),
+      forbidden_weapons: self.forbidden_weapons.clone(),
+      king_salmonids,
+      remind_mins: self.remind_mins,
+      enabled: self.enabled,
+    })
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GearQueryConfig {
+  #[serde(default)]
+  pub gear_types: Vec<GearType>,
+  #[serde(default)]
+  pub brands: Vec<String>,
+  #[serde(default)]
+  pub powers: Vec<String>,
+  #[serde(default)]
+  pub min_slots: u8,
+  #[serde(default)]
+  pub max_price: Option<i32>,
+  #[serde(default)]
+  pub pickup_only: bool,
+  #[serde(default = "default_query_enabled")]
+  pub enabled: bool,
+}
+
+impl From<&GearQueryRecord> for GearQueryConfig {
+  fn from(value: &GearQueryRecord) -> Self {
+    let mut gear_types = vec![];
+    for gear_type in GearType::iter() {
+      if ((gear_type as u8) & value.gear_types) != 0 {
+        gear_types.push(gear_type);
+      }
+    }
+    GearQueryConfig {
+      gear_types,
+      brands: value.brands.clone(),
+      powers: value.powers.clone(),
+      min_slots: value.min_slots,
+      max_price: value.max_price,
+      pickup_only: value.pickup_only,
+      enabled: value.enabled,
+    }
+  }
+}
+
+impl TryInto<GearQueryRecord> for &GearQueryConfig {
+  type Error = Error;
+
+  fn try_into(self) -> std::result::Result<GearQueryRecord, Self::Error> {
+    if self.min_slots > 3 {
+      return Err(Error::InvalidParameter(
+        "min_slots",
+        self.min_slots.to_string(),
+      ));
+    }
+    let gear_types = self.gear_types.iter().fold(0u8, |a, b| a | *b as u8);
+    Ok(GearQueryRecord {
+      gear_types,
+      brands: self.brands.clone(),
+      powers: self.powers.clone(),
+      min_slots: self.min_slots,
+      max_price: self.max_price,
+      pickup_only: self.pickup_only,
+      enabled: self.enabled,
+    })
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EventQueryConfig {
+  #[serde(default)]
+  pub events: Vec<String>,
+  #[serde(default)]
+  pub remind_mins: Option<i32>,
+  #[serde(default = "default_query_enabled")]
+  pub enabled: bool,
+}
+
+impl From<&EventQueryRecord> for EventQueryConfig {
+  fn from(value: &EventQueryRecord) -> Self {
+    EventQueryConfig {
+      events: value.events.clone(),
+      remind_mins: value.remind_mins,
+      enabled: value.enabled,
+    }
+  }
+}
+
+impl TryInto<EventQueryRecord> for &EventQueryConfig {
+  type Error = Error;
+
+  fn try_into(self) -> std::result::Result<EventQueryRecord, Self::Error> {
+    for event in self.events.iter() {
+      if event.is_empty() {
+        return Err(Error::InvalidParameter("events", event.clone()));
+      }
+    }
+    Ok(EventQueryRecord {
+      events: self.events.clone(),
+      remind_mins: self.remind_mins,
+      enabled: self.enabled,
+    })
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FestQueryConfig {
+  #[serde(default = "default_query_fest_states")]
+  pub states: Vec<FestState>,
+  #[serde(default = "default_query_enabled")]
+  pub enabled: bool,
+}
+
+impl From<&FestQueryRecord> for FestQueryConfig {
+  fn from(value: &FestQueryRecord) -> Self {
+    let mut states = vec![];
+    for state in FestState::iter() {
+      if ((state as u8) & value.states) != 0 {
+        states.push(state);
+      }
+    }
+    FestQueryConfig {
+      states,
+      enabled: value.enabled,
+    }
+  }
+}
+
+impl TryInto<FestQueryRecord> for &FestQueryConfig {
+  type Error = Error;
+
+  fn try_into(self) -> std::result::Result<FestQueryRecord, Self::Error> {
+    let states = self.states.iter().fold(0u8, |a, b| a | *b as u8);
+    Ok(FestQueryRecord {
+      states,
+      enabled: self.enabled,
     })
   }
 }
@@ -110,6 +374,22 @@ pub enum QueryConfig {
     #[serde(flatten)]
     config: PvpQueryConfig,
   },
+  Coop {
+    #[serde(flatten)]
+    config: CoopQueryConfig,
+  },
+  Gears {
+    #[serde(flatten)]
+    config: GearQueryConfig,
+  },
+  Event {
+    #[serde(flatten)]
+    config: EventQueryConfig,
+  },
+  Fest {
+    #[serde(flatten)]
+    config: FestQueryConfig,
+  },
 }
 
 fn default_query_pvp_product_rules() -> Vec<PvpRule> {
@@ -130,6 +410,23 @@ fn default_query_pvp_modes() -> Vec<PvpMode> {
   ]
 }
 
+fn default_query_fest_states() -> Vec<FestState> {
+  vec![
+    FestState::Scheduled,
+    FestState::FirstHalf,
+    FestState::SecondHalf,
+    FestState::Closed,
+  ]
+}
+
+fn default_query_enabled() -> bool {
+  true
+}
+
+fn default_query_coop_modes() -> Vec<CoopMode> {
+  vec![CoopMode::Regular, CoopMode::BigRun, CoopMode::TeamContest]
+}
+
 pub struct CreateQueryRequest<'a> {
   pub uid: i64,
   pub config: &'a QueryConfig,
@@ -142,6 +439,7 @@ pub trait CreateQuery {
 pub struct ListQueryRequest {
   pub uid: i64,
   pub qid: Option<i64>,
+  pub qtype: Option<QueryType>,
 }
 
 #[derive(Serialize)]
@@ -175,7 +473,7 @@ pub trait DeleteQuery {
   fn delete_query(&self, request: DeleteQueryRequest) -> Result<()>;
 }
 
-impl CreateQuery for Connection {
+impl CreateQuery for DatabaseConnection {
   fn create_query(&self, request: CreateQueryRequest) -> Result<i64> {
     let CreateQueryRequest { uid, config } = request;
     match config {
@@ -186,27 +484,108 @@ impl CreateQuery for Connection {
         // emit id
         Ok(id)
       }
+      QueryConfig::Coop { config } => {
+        let record = &config.try_into()?;
+        let id = self.create_coop_query(CreateCoopQueryRequest { uid, record })?;
+        Ok(id)
+      }
+      QueryConfig::Gears { config } => {
+        let record = &config.try_into()?;
+        let id = self.create_gear_query(CreateGearQueryRequest { uid, record })?;
+        Ok(id)
+      }
+      QueryConfig::Event { config } => {
+        let record = &config.try_into()?;
+        let id = self.create_event_query(CreateEventQueryRequest { uid, record })?;
+        Ok(id)
+      }
+      QueryConfig::Fest { config } => {
+        let record = &config.try_into()?;
+        let id = self.create_fest_query(CreateFestQueryRequest { uid, record })?;
+        Ok(id)
+      }
     }
   }
 }
 
-impl ListQuery for Connection {
+impl ListQuery for DatabaseConnection {
   fn list_query(&self, request: ListQueryRequest) -> Result<Vec<ListQueryResponse>> {
-    let ListQueryRequest { uid, qid } = request;
-    let li = self.list_pvp_query(ListPvpQueryRequest { uid, qid })?;
-    let iter = li.into_iter().map(|e| ListQueryResponse {
-      qid: e.qid,
-      config: QueryConfig::Pvp {
-        config: (&e.record).into(),
-      },
-      created_time: e.created_time,
-    });
-    let li = iter.collect();
+    let ListQueryRequest { uid, qid, qtype } = request;
+    // ids are only unique within a query type
+    if let (Some(qid), None) = (qid, qtype) {
+      return Err(Error::InvalidParameter("qid", qid.to_string()));
+    }
+    let mut li = vec![];
+    if qtype.is_none() || qtype == Some(QueryType::Pvp) {
+      let iter = self
+        .list_pvp_query(ListPvpQueryRequest { uid, qid })?
+        .into_iter()
+        .map(|e| ListQueryResponse {
+          qid: e.qid,
+          config: QueryConfig::Pvp {
+            config: (&e.record).into(),
+          },
+          created_time: e.created_time,
+        });
+      li.extend(iter);
+    }
+    if qtype.is_none() || qtype == Some(QueryType::Coop) {
+      let iter = self
+        .list_coop_query(ListCoopQueryRequest { uid, qid })?
+        .into_iter()
+        .map(|e| ListQueryResponse {
+          qid: e.qid,
+          config: QueryConfig::Coop {
+            config: (&e.record).into(),
+          },
+          created_time: e.created_time,
+        });
+      li.extend(iter);
+    }
+    if qtype.is_none() || qtype == Some(QueryType::Gears) {
+      let iter = self
+        .list_gear_query(ListGearQueryRequest { uid, qid })?
+        .into_iter()
+        .map(|e| ListQueryResponse {
+          qid: e.qid,
+          config: QueryConfig::Gears {
+            config: (&e.record).into(),
+          },
+          created_time: e.created_time,
+        });
+      li.extend(iter);
+    }
+    if qtype.is_none() || qtype == Some(QueryType::Event) {
+      let iter = self
+        .list_event_query(ListEventQueryRequest { uid, qid })?
+        .into_iter()
+        .map(|e| ListQueryResponse {
+          qid: e.qid,
+          config: QueryConfig::Event {
+            config: (&e.record).into(),
+          },
+          created_time: e.created_time,
+        });
+      li.extend(iter);
+    }
+    if qtype.is_none() || qtype == Some(QueryType::Fest) {
+      let iter = self
+        .list_fest_query(ListFestQueryRequest { uid, qid })?
+        .into_iter()
+        .map(|e| ListQueryResponse {
+          qid: e.qid,
+          config: QueryConfig::Fest {
+            config: (&e.record).into(),
+          },
+          created_time: e.created_time,
+        });
+      li.extend(iter);
+    }
     Ok(li)
   }
 }
 
-impl UpdateQuery for Connection {
+impl UpdateQuery for DatabaseConnection {
   fn update_query(&self, request: UpdateQueryRequest) -> Result<()> {
     let UpdateQueryRequest { uid, qid, config } = request;
     match config {
@@ -215,11 +594,31 @@ impl UpdateQuery for Connection {
         self.update_pvp_query(UpdatePvpQueryRequest { uid, qid, record })?;
         Ok(())
       }
+      QueryConfig::Coop { config } => {
+        let record = &config.try_into()?;
+        self.update_coop_query(UpdateCoopQueryRequest { uid, qid, record })?;
+        Ok(())
+      }
+      QueryConfig::Gears { config } => {
+        let record = &config.try_into()?;
+        self.update_gear_query(UpdateGearQueryRequest { uid, qid, record })?;
+        Ok(())
+      }
+      QueryConfig::Event { config } => {
+        let record = &config.try_into()?;
+        self.update_event_query(UpdateEventQueryRequest { uid, qid, record })?;
+        Ok(())
+      }
+      QueryConfig::Fest { config } => {
+        let record = &config.try_into()?;
+        self.update_fest_query(UpdateFestQueryRequest { uid, qid, record })?;
+        Ok(())
+      }
     }
   }
 }
 
-impl DeleteQuery for Connection {
+impl DeleteQuery for DatabaseConnection {
   fn delete_query(&self, request: DeleteQueryRequest) -> Result<()> {
     let DeleteQueryRequest { uid, qid, qtype } = request;
     match qtype {
@@ -227,7 +626,107 @@ impl DeleteQuery for Connection {
         self.delete_pvp_query(DeletePvpQueryRequest { uid, qid })?;
         Ok(())
       }
-      _ => Err(Error::InvalidParameter("qtype", qtype.to_string())),
+      QueryType::Coop => {
+        self.delete_coop_query(DeleteCoopQueryRequest { uid, qid })?;
+        Ok(())
+      }
+      QueryType::Gears => {
+        self.delete_gear_query(DeleteGearQueryRequest { uid, qid })?;
+        Ok(())
+      }
+      QueryType::Event => {
+        self.delete_event_query(DeleteEventQueryRequest { uid, qid })?;
+        Ok(())
+      }
+      QueryType::Fest => {
+        self.delete_fest_query(DeleteFestQueryRequest { uid, qid })?;
+        Ok(())
+      }
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use crate::database::{
+    user::{CreateUser, CreateUserRequest, LookupUserId, LookupUserIdRequest},
+    Database,
+  };
+
+  use super::*;
+
+  #[test]
+  fn test_list_by_qid() {
+    let db = Database::new_for_test().unwrap();
+    let mut conn = db.get().unwrap();
+
+    let auth_agent = "mock_auth_agent";
+    let auth_uid = "mock_auth_uid";
+    conn
+      .create_user(CreateUserRequest {
+        auth_agent,
+        auth_uid,
+        email: None,
+        name: None,
+        picture: None,
+        language: None,
+        time_zone: None,
+        availability: None,
+      })
+      .unwrap();
+    let uid = conn
+      .lookup_user_id(LookupUserIdRequest {
+        auth_agent,
+        auth_uid,
+      })
+      .unwrap();
+
+    let tx = conn.transaction().unwrap();
+    let qid = tx
+      .create_query(CreateQueryRequest {
+        uid,
+        config: &QueryConfig::Pvp {
+          config: PvpQueryConfig {
+            modes: vec![PvpMode::X],
+            rules: vec![PvpRule::Asari],
+            includes: vec![1],
+            excludes: vec![],
+            min_includes: 1,
+            rule_stages: vec![],
+            remind_mins: None,
+            availability: None,
+            enabled: true,
+          },
+        },
+      })
+      .unwrap();
+    tx.commit().unwrap();
+
+    let li = conn
+      .list_query(ListQueryRequest {
+        uid,
+        qid: Some(qid),
+        qtype: Some(QueryType::Pvp),
+      })
+      .unwrap();
+    assert_eq!(li.len(), 1);
+    assert_eq!(li[0].qid, qid);
+
+    // the same id may belong to a query of another type
+    let ret = conn.list_query(ListQueryRequest {
+      uid,
+      qid: Some(qid),
+      qtype: None,
+    });
+    assert!(matches!(ret, Err(Error::InvalidParameter("qid", _))));
+
+    let li = conn
+      .list_query(ListQueryRequest {
+        uid,
+        qid: None,
+        qtype: None,
+      })
+      .unwrap();
+    assert_eq!(li.len(), 1);
+  }
+}
@@ -0,0 +1,144 @@
+use r2d2_sqlite::rusqlite::Connection;
+
+use crate::Result;
+
+use super::DatabaseConnection;
+
+#[derive(Debug)]
+pub struct ListSpiderCursorResponse {
+  pub name: String,
+  pub value: String,
+  pub updated_time: String,
+}
+
+pub trait ListSpiderCursor {
+  fn list_spider_cursor(&self) -> Result<Vec<ListSpiderCursorResponse>>;
+}
+
+#[derive(Debug)]
+pub struct UpdateSpiderCursorRequest<'a> {
+  pub cursors: &'a [(&'a str, String)],
+}
+
+pub trait UpdateSpiderCursor {
+  fn update_spider_cursor(&self, request: UpdateSpiderCursorRequest) -> Result<()>;
+}
+
+#[derive(Debug)]
+pub struct DeleteSpiderCursorRequest<'a> {
+  // delete all cursors if not specified
+  pub name: Option<&'a str>,
+}
+
+pub trait DeleteSpiderCursor {
+  fn delete_spider_cursor(&self, request: DeleteSpiderCursorRequest) -> Result<usize>;
+}
+
+impl ListSpiderCursor for DatabaseConnection {
+  fn list_spider_cursor(&self) -> Result<Vec<ListSpiderCursorResponse>> {
+    dispatch!(self, conn => conn.list_spider_cursor())
+  }
+}
+
+impl ListSpiderCursor for Connection {
+  fn list_spider_cursor(&self) -> Result<Vec<ListSpiderCursorResponse>> {
+    let mut stmt = self.prepare_cached(
+      "
+      SELECT name, value, updated_time
+      FROM spider_cursors
+      ORDER BY name
+      ",
+    )?;
+    let iter = stmt.query_map((), |row| {
+      Ok(ListSpiderCursorResponse {
+        name: row.get(0)?,
+        value: row.get(1)?,
+        updated_time: row.get(2)?,
+      })
+    })?;
+    let li = itertools::process_results(iter, |iter| iter.collect())?;
+    Ok(li)
+  }
+}
+
+impl UpdateSpiderCursor for DatabaseConnection {
+  fn update_spider_cursor(&self, request: UpdateSpiderCursorRequest) -> Result<()> {
+    dispatch!(self, conn => conn.update_spider_cursor(request))
+  }
+}
+
+impl UpdateSpiderCursor for Connection {
+  fn update_spider_cursor(&self, request: UpdateSpiderCursorRequest) -> Result<()> {
+    let mut stmt = self.prepare_cached(
+      "
+      INSERT INTO spider_cursors ( name, value )
+      VALUES ( ?1, ?2 )
+      ON CONFLICT ( name ) DO UPDATE
+      SET value = excluded.value, updated_time = CURRENT_TIMESTAMP
+      WHERE value != excluded.value
+      ",
+    )?;
+    for (name, value) in request.cursors.iter() {
+      stmt.execute((name, value))?;
+    }
+    Ok(())
+  }
+}
+
+impl DeleteSpiderCursor for DatabaseConnection {
+  fn delete_spider_cursor(&self, request: DeleteSpiderCursorRequest) -> Result<usize> {
+    dispatch!(self, conn => conn.delete_spider_cursor(request))
+  }
+}
+
+impl DeleteSpiderCursor for Connection {
+  fn delete_spider_cursor(&self, request: DeleteSpiderCursorRequest) -> Result<usize> {
+    let mut stmt = self.prepare_cached(
+      "
+      DELETE FROM spider_cursors
+      WHERE ?1 IS NULL OR name = ?1",
+    )?;
+    let n = stmt.execute((&request.name,))?;
+    Ok(n)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::database::Database;
+
+  use super::*;
+
+  #[test]
+  fn test_update_and_reset() {
+    let db = Database::new_for_test().unwrap();
+    let conn = db.get().unwrap();
+
+    conn
+      .update_spider_cursor(UpdateSpiderCursorRequest {
+        cursors: &[("pvp_regular", "t0".into()), ("coop_normal", "t1".into())],
+      })
+      .unwrap();
+    conn
+      .update_spider_cursor(UpdateSpiderCursorRequest {
+        cursors: &[("pvp_regular", "t2".into())],
+      })
+      .unwrap();
+    let li = conn.list_spider_cursor().unwrap();
+    assert_eq!(li.len(), 2);
+    assert_eq!(li[0].name, "coop_normal");
+    assert_eq!(li[1].value, "t2");
+
+    let n = conn
+      .delete_spider_cursor(DeleteSpiderCursorRequest {
+        name: Some("pvp_regular"),
+      })
+      .unwrap();
+    assert_eq!(n, 1);
+    let n = conn
+      .delete_spider_cursor(DeleteSpiderCursorRequest { name: None })
+      .unwrap();
+    assert_eq!(n, 1);
+    assert!(conn.list_spider_cursor().unwrap().is_empty());
+  }
+}
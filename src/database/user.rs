@@ -1,13 +1,15 @@
 use std::str::FromStr;
 
+use chrono::{DateTime, Duration, TimeZone as _, Timelike, Utc};
 use r2d2_sqlite::rusqlite::Connection;
 use serde::{Deserialize, Serialize};
 
 use crate::{Error, Result};
 
-use super::{Language, TimeZone};
-
-const DAY_HRS_MAX: i64 = (1i64 << 48) - 1;
+use super::{
+  availability::Availability, from_json, to_json, DatabaseConnection, DigestMode, Language,
+  TimeZone, DAY_HRS_MAX,
+};
 
 #[derive(Debug)]
 pub struct CreateUserRequest<'a> {
@@ -18,9 +20,8 @@ pub struct CreateUserRequest<'a> {
   pub picture: Option<&'a str>,
   pub language: Option<Language>,
   pub time_zone: Option<TimeZone>,
-  // js can't represent integer > 2^53
-  // https://stackoverflow.com/a/2983294/9602625
-  pub day_hrs: Option<(i64, i64)>,
+  // none for any time
+  pub availability: Option<&'a Availability>,
 }
 
 pub trait CreateUser {
@@ -41,17 +42,95 @@ pub trait LookupUserId {
 pub struct UserSettings {
   pub language: Option<Language>,
   pub time_zone: Option<TimeZone>,
-  pub day_hrs: Option<(i64, i64)>,
+  // local time ranges in `time_zone` to be notified of pvp rotations
+  pub availability: Option<Availability>,
+  // default reminder lead for queries, negative for at announcement
+  pub remind_mins: Option<i32>,
+  pub digest: Option<DigestMode>,
+  // local hour in [0, 24) for daily digests
+  pub digest_hour: Option<u32>,
+  // local hours [start, end) not to be contacted, equal for none
+  pub quiet_hours: Option<(u32, u32)>,
+  // timestamp until which notifications are held back, 0 for none
+  pub snooze_until: Option<i64>,
+  pub vacation: Option<bool>,
 }
 
 pub trait ListUserSettings {
   fn list_user_settings(&self, uid: i64) -> Result<UserSettings>;
 }
 
+// validates the settings to update, and returns the availability to store if
+// specified
+pub(crate) fn check_user_settings(settings: &UserSettings) -> Result<Option<String>> {
+  if let Some(hour) = settings.digest_hour.filter(|e| *e >= 24) {
+    return Err(Error::InvalidParameter("digest_hour", hour.to_string()));
+  }
+  if let Some((start, end)) = settings.quiet_hours.filter(|(a, b)| *a >= 24 || *b >= 24) {
+    return Err(Error::InvalidParameter(
+      "quiet_hours",
+      format!("{}-{}", start, end),
+    ));
+  }
+  settings
+    .availability
+    .as_ref()
+    .map(|e| {
+      let mut e = e.clone();
+      e.normalize();
+      to_json(&e)
+    })
+    .transpose()
+}
+
 pub trait UpdateUserSettings {
   fn update_user_settings(&self, uid: i64, settings: &UserSettings) -> Result<()>;
 }
 
+// when notifications are held back during vacation, check again after this long
+const VACATION_RECHECK_MINS: i64 = 10;
+
+pub struct LookupUserContactResponse {
+  pub time_zone: TimeZone,
+  pub quiet_hours: (u32, u32),
+  pub snooze_until: i64,
+  pub vacation: bool,
+}
+
+impl LookupUserContactResponse {
+  // earliest time after `now` the user may be contacted, none if right now
+  pub fn next_contact_time(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    if self.vacation {
+      return Some(now + Duration::minutes(VACATION_RECHECK_MINS));
+    }
+    let mut t = now;
+    if self.snooze_until > t.timestamp() {
+      t = Utc.timestamp_opt(self.snooze_until, 0).single()?;
+    }
+    let (start, end) = self.quiet_hours;
+    let hour = self.time_zone.convert(t).hour();
+    let quiet = if start <= end {
+      start <= hour && hour < end
+    } else {
+      hour >= start || hour < end
+    };
+    if quiet {
+      t = self.time_zone.next_local_hour(t, end)?;
+    }
+    (t > now).then_some(t)
+  }
+}
+
+pub trait LookupUserContact {
+  fn lookup_user_contact(&self, uid: i64) -> Result<LookupUserContactResponse>;
+}
+
+impl CreateUser for DatabaseConnection {
+  fn create_user(&self, request: CreateUserRequest) -> Result<bool> {
+    dispatch!(self, conn => conn.create_user(request))
+  }
+}
+
 impl CreateUser for Connection {
   fn create_user(&self, request: CreateUserRequest) -> Result<bool> {
     let CreateUserRequest {
@@ -62,17 +141,17 @@ impl CreateUser for Connection {
       picture,
       language,
       time_zone,
-      day_hrs,
+      availability,
     } = request;
     let language = language.unwrap_or(Language::EnUs).to_string();
-    let time_zone = time_zone.unwrap_or(TimeZone::Jst).to_string();
-    let (day_hrs_0, day_hrs_1) = day_hrs.unwrap_or((DAY_HRS_MAX, DAY_HRS_MAX));
+    let time_zone = time_zone.unwrap_or_default().to_string();
+    let availability = availability.map(to_json).transpose()?;
     let n = self
       .prepare_cached(
         "
         INSERT OR IGNORE
-        INTO users ( auth_agent, auth_uid, name, email, picture, language, time_zone, day_hrs_0, day_hrs_1 )
-        VALUES ( ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9 )
+        INTO users ( auth_agent, auth_uid, name, email, picture, language, time_zone, day_hrs_0, day_hrs_1, availability )
+        VALUES ( ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?8, ?9 )
         ",
       )?
       .execute((
@@ -83,13 +162,19 @@ impl CreateUser for Connection {
         &picture,
         &language,
         &time_zone,
-        &day_hrs_0,
-        &day_hrs_1,
+        &DAY_HRS_MAX,
+        &availability,
       ))?;
     Ok(n > 0)
   }
 }
 
+impl LookupUserId for DatabaseConnection {
+  fn lookup_user_id(&self, request: LookupUserIdRequest) -> Result<i64> {
+    dispatch!(self, conn => conn.lookup_user_id(request))
+  }
+}
+
 impl LookupUserId for Connection {
   fn lookup_user_id(&self, request: LookupUserIdRequest) -> Result<i64> {
     self
@@ -108,47 +193,98 @@ impl LookupUserId for Connection {
   }
 }
 
+impl ListUserSettings for DatabaseConnection {
+  fn list_user_settings(&self, uid: i64) -> Result<UserSettings> {
+    dispatch!(self, conn => conn.list_user_settings(uid))
+  }
+}
+
 impl ListUserSettings for Connection {
   fn list_user_settings(&self, uid: i64) -> Result<UserSettings> {
     let mut stmt = self.prepare_cached(
       "
-      SELECT language, time_zone, day_hrs_0, day_hrs_1
+      SELECT language, time_zone, availability, remind_mins, digest, digest_hour,
+        quiet_start, quiet_end, snooze_until, vacation
       FROM users
       WHERE id = ?1
       ",
     )?;
-    let s: (String, String, _, _) = stmt.query_row((&uid,), |row| {
-      Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
-    })?;
+    let s: (String, String, Option<String>, _, String, _, _, _, _, _) =
+      stmt.query_row((&uid,), |row| {
+        Ok((
+          row.get(0)?,
+          row.get(1)?,
+          row.get(2)?,
+          row.get(3)?,
+          row.get(4)?,
+          row.get(5)?,
+          row.get(6)?,
+          row.get(7)?,
+          row.get(8)?,
+          row.get(9)?,
+        ))
+      })?;
     let language =
       Some(Language::from_str(&s.0).map_err(|err| Error::InternalServerError(Box::new(err)))?);
     let time_zone =
       Some(TimeZone::from_str(&s.1).map_err(|err| Error::InternalServerError(Box::new(err)))?);
+    let availability = match s.2 {
+      Some(e) => from_json(&e)?,
+      None => Availability::any(),
+    };
+    let digest =
+      Some(DigestMode::from_str(&s.4).map_err(|err| Error::InternalServerError(Box::new(err)))?);
     Ok(UserSettings {
       language,
       time_zone,
-      day_hrs: Some((s.2, s.3)),
+      availability: Some(availability),
+      remind_mins: Some(s.3),
+      digest,
+      digest_hour: Some(s.5),
+      quiet_hours: Some((s.6, s.7)),
+      snooze_until: Some(s.8),
+      vacation: Some(s.9),
     })
   }
 }
 
+impl UpdateUserSettings for DatabaseConnection {
+  fn update_user_settings(&self, uid: i64, settings: &UserSettings) -> Result<()> {
+    dispatch!(self, conn => conn.update_user_settings(uid, settings))
+  }
+}
+
 impl UpdateUserSettings for Connection {
   fn update_user_settings(&self, uid: i64, settings: &UserSettings) -> Result<()> {
     let UserSettings {
       language,
       time_zone,
-      day_hrs,
+      remind_mins,
+      digest,
+      digest_hour,
+      quiet_hours,
+      snooze_until,
+      vacation,
+      ..
     } = settings;
+    let availability = check_user_settings(settings)?;
     let language = language.map(|e| e.to_string());
     let time_zone = time_zone.map(|e| e.to_string());
+    let digest = digest.map(|e| e.to_string());
     let mut stmt = self.prepare_cached(
       "
       UPDATE users
       SET 
         language = coalesce(?2, language), 
         time_zone = coalesce(?3, time_zone), 
-        day_hrs_0 = coalesce(?4, day_hrs_0), 
-        day_hrs_1 = coalesce(?5, day_hrs_1)
+        availability = coalesce(?4, availability), 
+        remind_mins = coalesce(?5, remind_mins),
+        digest = coalesce(?6, digest),
+        digest_hour = coalesce(?7, digest_hour),
+        quiet_start = coalesce(?8, quiet_start),
+        quiet_end = coalesce(?9, quiet_end),
+        snooze_until = coalesce(?10, snooze_until),
+        vacation = coalesce(?11, vacation)
       WHERE id = ?1
       ",
     )?;
@@ -156,8 +292,14 @@ impl UpdateUserSettings for Connection {
       &uid,
       &language,
       &time_zone,
-      &day_hrs.map(|e| e.0),
-      &day_hrs.map(|e| e.1),
+      &availability,
+      &remind_mins,
+      &digest,
+      &digest_hour,
+      &quiet_hours.map(|e| e.0),
+      &quiet_hours.map(|e| e.1),
+      &snooze_until,
+      &vacation,
     ))?;
     if n == 0 {
       Err(Error::Unauthorized)
@@ -167,6 +309,41 @@ impl UpdateUserSettings for Connection {
   }
 }
 
+impl LookupUserContact for DatabaseConnection {
+  fn lookup_user_contact(&self, uid: i64) -> Result<LookupUserContactResponse> {
+    dispatch!(self, conn => conn.lookup_user_contact(uid))
+  }
+}
+
+impl LookupUserContact for Connection {
+  fn lookup_user_contact(&self, uid: i64) -> Result<LookupUserContactResponse> {
+    let mut stmt = self.prepare_cached(
+      "
+      SELECT time_zone, quiet_start, quiet_end, snooze_until, vacation
+      FROM users
+      WHERE id = ?1
+      ",
+    )?;
+    let (time_zone, quiet_start, quiet_end, snooze_until, vacation): (String, _, _, _, _) = stmt
+      .query_row((&uid,), |row| {
+        Ok((
+          row.get(0)?,
+          row.get(1)?,
+          row.get(2)?,
+          row.get(3)?,
+          row.get(4)?,
+        ))
+      })?;
+    Ok(LookupUserContactResponse {
+      time_zone: TimeZone::from_str(&time_zone)
+        .map_err(|err| Error::InternalServerError(Box::new(err)))?,
+      quiet_hours: (quiet_start, quiet_end),
+      snooze_until,
+      vacation,
+    })
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use crate::database::Database;
@@ -175,7 +352,7 @@ mod tests {
 
   #[tokio::test]
   async fn test_simple() {
-    let db = Database::new_in_memory().unwrap();
+    let db = Database::new_for_test().unwrap();
 
     let conn = db.get().unwrap();
     let auth_agent = "mock_auth_agent";
@@ -188,7 +365,7 @@ mod tests {
         picture: None,
         language: None,
         time_zone: None,
-        day_hrs: None,
+        availability: None,
       })
       .unwrap();
     assert!(u1);
@@ -201,7 +378,7 @@ mod tests {
         picture: None,
         language: None,
         time_zone: None,
-        day_hrs: None,
+        availability: None,
       })
       .unwrap();
     assert!(!u2);
@@ -214,7 +391,7 @@ mod tests {
         picture: None,
         language: None,
         time_zone: None,
-        day_hrs: None,
+        availability: None,
       })
       .unwrap();
     assert!(u3);
@@ -225,5 +402,52 @@ mod tests {
         auth_uid: "u1",
       })
       .unwrap();
+
+    conn
+      .update_user_settings(
+        uid,
+        &UserSettings {
+          language: None,
+          time_zone: Some(TimeZone::JST),
+          availability: None,
+          remind_mins: None,
+          digest: None,
+          digest_hour: None,
+          quiet_hours: Some((23, 7)),
+          snooze_until: None,
+          vacation: None,
+        },
+      )
+      .unwrap();
+    let contact = conn.lookup_user_contact(uid).unwrap();
+    // 01:00 jst is quiet until 07:00 jst
+    let now = Utc.with_ymd_and_hms(2023, 7, 1, 16, 0, 0).unwrap();
+    let until = Utc.with_ymd_and_hms(2023, 7, 1, 22, 0, 0).unwrap();
+    assert_eq!(contact.next_contact_time(now), Some(until));
+    // 12:00 jst is fine
+    let now = Utc.with_ymd_and_hms(2023, 7, 1, 3, 0, 0).unwrap();
+    assert_eq!(contact.next_contact_time(now), None);
+
+    conn
+      .update_user_settings(
+        uid,
+        &UserSettings {
+          language: None,
+          time_zone: Some("America/New_York".parse().unwrap()),
+          availability: None,
+          remind_mins: None,
+          digest: None,
+          digest_hour: None,
+          quiet_hours: None,
+          snooze_until: None,
+          vacation: None,
+        },
+      )
+      .unwrap();
+    let contact = conn.lookup_user_contact(uid).unwrap();
+    // 00:00 edt is quiet until 07:00 edt
+    let now = Utc.with_ymd_and_hms(2023, 7, 1, 4, 0, 0).unwrap();
+    let until = Utc.with_ymd_and_hms(2023, 7, 1, 11, 0, 0).unwrap();
+    assert_eq!(contact.next_contact_time(now), Some(until));
   }
 }
@@ -25,8 +25,13 @@ pub enum Error {
   #[error("r2d2 error")]
   R2D2Error(#[from] r2d2::Error),
 
+  // missing rows are reported as `NotFound` instead
   #[error("sqlite error")]
-  SqliteError(#[from] r2d2_sqlite::rusqlite::Error),
+  SqliteError(r2d2_sqlite::rusqlite::Error),
+
+  #[cfg(feature = "postgres")]
+  #[error("postgres error")]
+  PostgresError(#[from] r2d2_postgres::postgres::Error),
 
   #[cfg(feature = "api")]
   #[error("jwt error")]
@@ -37,6 +42,27 @@ pub enum Error {
 
   #[error("unauthorized")]
   Unauthorized,
+
+  // the entity looked up or changed doesn't exist, on either backend
+  #[error("not found")]
+  NotFound,
+
+  // the target of an action is gone for good, e.g. an unsubscribed browser
+  #[error("action expired")]
+  ActionExpired(String),
+
+  // a message the target refused, which is not to be retried
+  #[error("delivery rejected")]
+  DeliveryRejected(String),
+}
+
+impl From<r2d2_sqlite::rusqlite::Error> for Error {
+  fn from(err: r2d2_sqlite::rusqlite::Error) -> Self {
+    match err {
+      r2d2_sqlite::rusqlite::Error::QueryReturnedNoRows => Self::NotFound,
+      _ => Self::SqliteError(err),
+    }
+  }
 }
 
 #[cfg(feature = "api")]
@@ -57,17 +83,13 @@ impl IntoResponse for Error {
         StatusCode::REQUEST_TIMEOUT
       }
       Self::SqliteError(err) => {
-        use r2d2_sqlite::rusqlite::Error;
-        match err {
-          Error::QueryReturnedNoRows => {
-            log::debug!("entity not exist");
-            StatusCode::BAD_REQUEST
-          }
-          _ => {
-            log::error!("sqlite error treated as internal error: [{:?}]", err);
-            StatusCode::INTERNAL_SERVER_ERROR
-          }
-        }
+        log::error!("sqlite error treated as internal error: [{:?}]", err);
+        StatusCode::INTERNAL_SERVER_ERROR
+      }
+      #[cfg(feature = "postgres")]
+      Self::PostgresError(err) => {
+        log::error!("postgres error treated as internal error: [{:?}]", err);
+        StatusCode::INTERNAL_SERVER_ERROR
       }
       Self::JwtError(err) => {
         use jsonwebtoken::errors::ErrorKind;
@@ -90,6 +112,18 @@ impl IntoResponse for Error {
         log::debug!("unauthorized");
         StatusCode::UNAUTHORIZED
       }
+      Self::NotFound => {
+        log::debug!("entity not exist");
+        StatusCode::BAD_REQUEST
+      }
+      Self::ActionExpired(reason) => {
+        log::debug!("action expired: [{}]", reason);
+        StatusCode::GONE
+      }
+      Self::DeliveryRejected(reason) => {
+        log::warn!("delivery rejected: [{}]", reason);
+        StatusCode::BAD_GATEWAY
+      }
     };
     code.into_response()
   }
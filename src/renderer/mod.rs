@@ -13,13 +13,15 @@ use resvg::{
   tiny_skia::Pixmap,
   usvg::{fontdb, Options, Transform, Tree, TreeParsing, TreeTextToPath},
 };
+use rust_i18n::t;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use ttl_cache::TtlCache;
 use walkdir::WalkDir;
 
 use crate::{
   database::{Language, TimeZone},
-  splatnet::PvpSpiderItem,
+  splatnet::{FestSpiderItem, Message, PvpSpiderItem},
   BoxError,
 };
 
@@ -33,6 +35,9 @@ pub struct RendererConfig {
   pub cache_size: usize,
 }
 
+// rows of a digest image, the rest is left to the notification body
+const DIGEST_MAX_ROWS: usize = 8;
+
 fn default_cache_size() -> usize {
   1024
 }
@@ -117,6 +122,92 @@ impl Renderer {
     )
   }
 
+  pub fn render_fest(
+    &self,
+    item: &FestSpiderItem,
+    opts: &RenderOptions,
+  ) -> Result<String, BoxError> {
+    let locale = opts.language.locale();
+    self.render(
+      &format!("fest.{}", opts.platform),
+      || {
+        let colors: Vec<_> = item.teams.iter().map(|e| e.hex_color()).collect();
+        let tricolor_stage = item
+          .tricolor_stage
+          .map(|s| base64::encode(format!("VsStage-{}", s)));
+        let fmt = |t| opts.time_zone.convert(t).format("%m/%d %H:%M").to_string();
+        context!(
+          state => item.state.title(locale),
+          colors => colors,
+          tricolor_stage => tricolor_stage,
+          time_range => format!("{} - {}", fmt(item.start_time), fmt(item.end_time)),
+        )
+      },
+      &[
+        &opts.language.to_string(),
+        &opts.time_zone.to_string(),
+        &item.id,
+        &item.state.to_string(),
+      ],
+    )
+  }
+
+  pub fn render_digest(&self, items: &[Message], opts: &RenderOptions) -> Result<String, BoxError> {
+    let locale = opts.language.locale();
+    let hash = hex(&Sha256::digest(serde_json::to_vec(items)?));
+    self.render(
+      &format!("digest.{}", opts.platform),
+      || {
+        let fmt = |t| opts.time_zone.convert(t).format("%m/%d %H:%M").to_string();
+        let rows: Vec<_> = items
+          .iter()
+          .take(DIGEST_MAX_ROWS)
+          .map(|msg| {
+            let (icon, label, stages) = match msg {
+              Message::Pvp(item) => (
+                Some(item.rule.img_url()),
+                format!("{} - {}", item.rule.name(locale), item.mode.name(locale)),
+                item
+                  .stages
+                  .iter()
+                  .map(|s| base64::encode(format!("VsStage-{}", s)))
+                  .collect::<Vec<_>>(),
+              ),
+              Message::Coop(item) => (Some(item.mode.img_url()), item.mode.title(locale), vec![]),
+              Message::Gear(_) => (None, t!("gears.title", locale = locale), vec![]),
+              Message::Event(item) => (
+                Some("img/mode/event.svg".into()),
+                t!(
+                  format!("splatnet.events.{}.name", item.id).as_str(),
+                  locale = locale
+                ),
+                vec![],
+              ),
+              Message::Fest(item) => (
+                Some("img/mode/fest.svg".into()),
+                item.state.title(locale),
+                vec![],
+              ),
+              Message::Digest(_) => (None, String::new(), vec![]),
+            };
+            context!(
+              icon => icon,
+              label => label,
+              time => fmt(msg.timestamp()),
+              stages => stages,
+            )
+          })
+          .collect();
+        context!(rows => rows)
+      },
+      &[
+        &opts.language.to_string(),
+        &opts.time_zone.to_string(),
+        &hash,
+      ],
+    )
+  }
+
   fn render<S, Ctx>(&self, tmpl: &str, ctx: S, keys: &[&str]) -> Result<String, BoxError>
   where
     S: FnOnce() -> Ctx,
@@ -162,6 +253,10 @@ impl Renderer {
   // pub async fn get(&self, id: &str) -> Option<Arc<Vec<u8>>> {}
 }
 
+fn hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
 fn fmt_time_range_2h<T>(st: DateTime<T>, tz: TimeZone) -> String
 where
   T: chrono::TimeZone,
@@ -169,27 +264,20 @@ where
   let st = tz.convert(st);
   let mo = st.month();
   let md = st.day();
-  match tz {
-    TimeZone::Pt | TimeZone::Cet => {
-      // let et = st.add(chrono::Duration::hours(2));
-      let (st0, st1) = st.hour12();
-      let st0 = ["AM", "PM"][st0 as usize];
+  // 24-hour clock in asia, 12-hour clock with the zone abbreviation elsewhere
+  if tz.0.name().starts_with("Asia/") {
+    let st1 = st.hour();
+    if tz == TimeZone::JST {
       format!(
-        "{mo}/{md}. {st1} {st0} - {tz}",
-        tz = tz.to_string().to_uppercase(),
+        "{mo}/{md}({wd}) {st1}:00 - ",
+        wd = ["月", "水", "火", "木", "金", "土", "日"][st.weekday() as usize]
       )
+    } else {
+      format!("{mo}/{md} {st1}:00 - ")
     }
-    TimeZone::Jst | TimeZone::Cst => {
-      let st1 = st.hour();
-      let et1 = st1 + 2;
-      if tz == TimeZone::Jst {
-        format!(
-          "{mo}/{md}({wd}) {st1}:00 - ",
-          wd = ["月", "水", "火", "木", "金", "土", "日"][st.weekday() as usize]
-        )
-      } else {
-        format!("{mo}/{md} {st1}:00 - ")
-      }
-    }
+  } else {
+    let (st0, st1) = st.hour12();
+    let st0 = ["AM", "PM"][st0 as usize];
+    format!("{mo}/{md}. {st1} {st0} - {tz}", tz = st.format("%Z"))
   }
 }
@@ -1,7 +1,8 @@
 use backoff::ExponentialBackoffBuilder;
-use chrono::{Duration, DurationRound, Local, Utc};
+use chrono::{DateTime, Duration, DurationRound, Local, Utc};
 use derivative::Derivative;
-use futures::{future::join_all, Future, FutureExt};
+use futures::{future::join_all, Future};
+use rand::Rng;
 use rust_i18n::t;
 use serde::{Deserialize, Serialize};
 use serde_enum_str::{Deserialize_enum_str, Serialize_enum_str};
@@ -12,14 +13,23 @@ use tokio::{
   time::{sleep_until, Instant},
 };
 
-use crate::{action::ActionManager, BoxError};
+use crate::{
+  action::ActionManager,
+  database::spider::{ListSpiderCursor, UpdateSpiderCursor, UpdateSpiderCursorRequest},
+  BoxError,
+};
 
+pub use self::source::{DataKind, DataSource, DataSourceConfig, HttpClientConfig};
 use self::spider::Spider;
-pub use self::spider::{CoopSpiderItem, GearSpiderItem, PvpSpiderItem};
+pub use self::spider::{
+  CoopSpiderItem, EventSpiderItem, FestSpiderItem, FestTeam, GearSpiderItem, PvpSpiderItem,
+  COOP_WEAPON_GRIZZCO, COOP_WEAPON_RANDOM,
+};
 
 mod gear;
 mod iso8601;
 mod schedules;
+mod source;
 mod spider;
 
 #[derive(
@@ -80,7 +90,56 @@ impl PvpRule {
   }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(
+  Debug, Hash, PartialEq, Eq, Clone, Copy, Serialize_enum_str, Deserialize_enum_str, EnumIter,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum CoopMode {
+  Unknown = 0,
+  Regular = 1,
+  BigRun = 2,
+  TeamContest = 4,
+}
+
+impl CoopMode {
+  pub fn title(self, locale: &str) -> String {
+    t!(
+      format!("coop.modes.{}.title", self).as_str(),
+      locale = locale
+    )
+  }
+
+  pub fn img_url(self) -> String {
+    match self {
+      Self::BigRun => "img/mode/coop.bigrun.svg".into(),
+      Self::TeamContest => "img/mode/coop.eggstra.svg".into(),
+      _ => "img/mode/coop.svg".into(),
+    }
+  }
+}
+
+#[derive(
+  Debug, Hash, PartialEq, Eq, Clone, Copy, Serialize_enum_str, Deserialize_enum_str, EnumIter,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum KingSalmonid {
+  Unknown = 0,
+  Cohozuna = 1,
+  Horrorboros = 2,
+  Megalodontia = 4,
+  Triumvirate = 8,
+}
+
+impl KingSalmonid {
+  pub fn name(self, locale: &str) -> String {
+    t!(format!("kings.{}.name", self).as_str(), locale = locale)
+  }
+}
+
+#[derive(
+  Debug, Hash, PartialEq, Eq, Clone, Copy, Serialize_enum_str, Deserialize_enum_str, EnumIter,
+)]
+#[serde(rename_all = "lowercase")]
 pub enum GearType {
   Unknown = 0,
   Head = 1,
@@ -88,6 +147,27 @@ pub enum GearType {
   Shoes = 4,
 }
 
+#[derive(
+  Debug, Hash, PartialEq, Eq, Clone, Copy, Serialize_enum_str, Deserialize_enum_str, EnumIter,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum FestState {
+  Unknown = 0,
+  Scheduled = 1,
+  FirstHalf = 2,
+  SecondHalf = 4,
+  Closed = 8,
+}
+
+impl FestState {
+  pub fn title(self, locale: &str) -> String {
+    t!(
+      format!("fests.states.{}.title", self).as_str(),
+      locale = locale
+    )
+  }
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
   #[error("message")]
@@ -96,112 +176,335 @@ pub enum Error {
 
 #[derive(Serialize, Deserialize, Default)]
 pub struct SplatNetConfig {
+  // rotation of each source, polls are aligned to multiples of it
   #[serde(default)]
   pub update_interval_mins: SplatNetUpdateIntervalConfig,
+  // delay after the rotation boundary before polling
+  #[serde(default)]
+  pub update_offset_secs: SplatNetUpdateOffsetConfig,
+  // random delay in [0, jitter) added to each scheduled poll
+  #[serde(default)]
+  pub update_jitter_secs: i64,
+  #[serde(default)]
+  pub retry: SplatNetRetryConfig,
+  #[serde(default)]
+  pub source: DataSourceConfig,
+  #[serde(default)]
+  pub http: HttpClientConfig,
+}
+
+impl SplatNetConfig {
+  // polls are aligned to the rotations and retried with the backoff, which
+  // both take positive durations only
+  fn validate(&self) -> Result<(), BoxError> {
+    let positive = [
+      (
+        "update_interval_mins.gears",
+        self.update_interval_mins.gears,
+      ),
+      (
+        "update_interval_mins.schedules",
+        self.update_interval_mins.schedules,
+      ),
+    ];
+    for (name, value) in positive.into_iter() {
+      if value <= 0 {
+        return Err(format!("splatnet.{} must be positive: [{}]", name, value).into());
+      }
+    }
+    let non_negative = [
+      ("update_offset_secs.gears", self.update_offset_secs.gears),
+      (
+        "update_offset_secs.schedules",
+        self.update_offset_secs.schedules,
+      ),
+      ("update_jitter_secs", self.update_jitter_secs),
+      (
+        "retry.initial_interval_secs",
+        self.retry.initial_interval_secs,
+      ),
+      ("retry.max_interval_mins", self.retry.max_interval_mins),
+      ("retry.max_elapsed_mins", self.retry.max_elapsed_mins),
+    ];
+    for (name, value) in non_negative.into_iter() {
+      if value < 0 {
+        return Err(format!("splatnet.{} must not be negative: [{}]", name, value).into());
+      }
+    }
+    Ok(())
+  }
 }
 
 #[derive(Serialize, Deserialize, Derivative)]
 #[derivative(Default)]
 pub struct SplatNetUpdateIntervalConfig {
-  #[serde(default)]
-  #[derivative(Default(value = "120"))]
+  #[serde(default = "default_gears_update_interval_mins")]
+  #[derivative(Default(value = "default_gears_update_interval_mins()"))]
   pub gears: i64,
-  #[serde(default)]
-  #[derivative(Default(value = "30"))]
+  #[serde(default = "default_schedules_update_interval_mins")]
+  #[derivative(Default(value = "default_schedules_update_interval_mins()"))]
+  pub schedules: i64,
+}
+
+fn default_gears_update_interval_mins() -> i64 {
+  240
+}
+
+fn default_schedules_update_interval_mins() -> i64 {
+  120
+}
+
+#[derive(Serialize, Deserialize, Derivative)]
+#[derivative(Default)]
+pub struct SplatNetUpdateOffsetConfig {
+  #[serde(default = "default_update_offset_secs")]
+  #[derivative(Default(value = "default_update_offset_secs()"))]
+  pub gears: i64,
+  #[serde(default = "default_update_offset_secs")]
+  #[derivative(Default(value = "default_update_offset_secs()"))]
   pub schedules: i64,
 }
 
-#[derive(Debug)]
+fn default_update_offset_secs() -> i64 {
+  5
+}
+
+#[derive(Serialize, Deserialize, Derivative)]
+#[derivative(Default)]
+pub struct SplatNetRetryConfig {
+  #[serde(default = "default_retry_initial_interval_secs")]
+  #[derivative(Default(value = "default_retry_initial_interval_secs()"))]
+  pub initial_interval_secs: i64,
+  #[serde(default = "default_retry_max_interval_mins")]
+  #[derivative(Default(value = "default_retry_max_interval_mins()"))]
+  pub max_interval_mins: i64,
+  // give up retrying until the next rotation
+  #[serde(default = "default_retry_max_elapsed_mins")]
+  #[derivative(Default(value = "default_retry_max_elapsed_mins()"))]
+  pub max_elapsed_mins: i64,
+}
+
+fn default_retry_initial_interval_secs() -> i64 {
+  5
+}
+
+fn default_retry_max_interval_mins() -> i64 {
+  30
+}
+
+fn default_retry_max_elapsed_mins() -> i64 {
+  15
+}
+
+#[derive(Clone, Copy)]
+struct PollConfig {
+  rotation: Duration,
+  offset: Duration,
+  jitter: Duration,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub enum Message {
   Pvp(PvpSpiderItem),
+  Coop(CoopSpiderItem),
+  Gear(GearSpiderItem),
+  Event(EventSpiderItem),
+  Fest(FestSpiderItem),
+  // several messages delivered at once
+  Digest(Vec<Message>),
+}
+
+impl Message {
+  pub fn kind(&self) -> &'static str {
+    match self {
+      Self::Pvp(_) => "pvp",
+      Self::Coop(_) => "coop",
+      Self::Gear(_) => "gear",
+      Self::Event(_) => "event",
+      Self::Fest(_) => "fest",
+      Self::Digest(_) => "digest",
+    }
+  }
+
+  pub fn timestamp(&self) -> DateTime<Utc> {
+    match self {
+      Self::Pvp(item) => item.start_time,
+      Self::Coop(item) => item.start_time,
+      Self::Gear(item) => item.sale_end_time,
+      Self::Event(item) => item.start_time().unwrap_or(DateTime::<Utc>::MIN_UTC),
+      Self::Fest(item) => item.state_time(),
+      Self::Digest(items) => items
+        .iter()
+        .map(|e| e.timestamp())
+        .min()
+        .unwrap_or(DateTime::<Utc>::MIN_UTC),
+    }
+  }
+
+  // after which the message is of no use, none if it stays news
+  pub fn end_time(&self) -> Option<DateTime<Utc>> {
+    match self {
+      Self::Pvp(item) => Some(item.end_time),
+      Self::Coop(item) => Some(item.end_time),
+      Self::Gear(item) => Some(item.sale_end_time),
+      Self::Event(item) => Some(
+        item
+          .time_periods
+          .last()
+          .map_or(DateTime::<Utc>::MIN_UTC, |e| e.1),
+      ),
+      // the results of a fest come out once it ends
+      Self::Fest(item) => (!matches!(item.state, FestState::Closed)).then_some(item.end_time),
+      Self::Digest(items) => items
+        .iter()
+        .map(|e| e.end_time())
+        .collect::<Option<Vec<_>>>()?
+        .into_iter()
+        .max(),
+    }
+  }
 }
 
 pub struct SplatNetAgent {
   actions: ActionManager,
-  gear_update_interval: Duration,
-  schedules_update_interval: Duration,
+  gears_poll: PollConfig,
+  schedules_poll: PollConfig,
+  retry: SplatNetRetryConfig,
   state: RwLock<Spider>,
 }
 
 impl SplatNetAgent {
-  pub fn new(actions: ActionManager, config: SplatNetConfig) -> Arc<Self> {
-    Arc::new(SplatNetAgent {
+  pub fn new(actions: ActionManager, config: SplatNetConfig) -> Result<Arc<Self>, BoxError> {
+    config.validate()?;
+    let client = config.http.collect()?;
+    Ok(Arc::new(SplatNetAgent {
       actions,
-      gear_update_interval: Duration::minutes(config.update_interval_mins.gears),
-      schedules_update_interval: Duration::minutes(config.update_interval_mins.schedules),
-      state: RwLock::new(Spider::new()),
-    })
+      gears_poll: PollConfig {
+        rotation: Duration::minutes(config.update_interval_mins.gears),
+        offset: Duration::seconds(config.update_offset_secs.gears),
+        jitter: Duration::seconds(config.update_jitter_secs),
+      },
+      schedules_poll: PollConfig {
+        rotation: Duration::minutes(config.update_interval_mins.schedules),
+        offset: Duration::seconds(config.update_offset_secs.schedules),
+        jitter: Duration::seconds(config.update_jitter_secs),
+      },
+      retry: config.retry,
+      state: RwLock::new(Spider::new(config.source.collect(&client))),
+    }))
   }
 
   pub async fn watch(self: Arc<Self>) -> Result<(), BoxError> {
-    let update_gears = self.clone().poll(Duration::hours(4), |this| {
-      Box::pin(async move {
-        match this.state.write().await.update_gear().await {
-          Ok(gears) => {
-            if gears.is_empty() {
-              false
-            } else {
-              log::info!("gears += {}", gears.len());
-              this
-                .handle_gear_update(gears)
-                .await
-                .unwrap_or_else(|err| this.handle_error(err));
-              true
-            }
-          }
-          Err(err) => {
-            log::warn!("update gears failed: [{:?}]", err);
-            false
-          }
-        }
-      })
+    self.restore_cursors().await?;
+    let update_gears = self.clone().poll(self.gears_poll, |this| {
+      Box::pin(async move { this.update_gears().await })
     });
-    let update_schedules = self.clone().poll(Duration::hours(2), |this| {
-      Box::pin(async move {
-        match this.state.write().await.update_schedules().await {
-          Ok((pvp, coop)) => {
-            if pvp.is_empty() {
-              false
-            } else {
-              log::info!("pvp += {}, coop += {}", pvp.len(), coop.len());
-              let tasks: [Pin<Box<dyn Future<Output = Result<(), BoxError>> + Send>>; 2] = [
-                Box::pin(this.handle_pvp_update(pvp)),
-                Box::pin(this.handle_coop_update(coop)),
-              ];
-              join_all(tasks)
-                .map(|rets| {
-                  for ret in rets.into_iter() {
-                    ret.unwrap_or_else(|err| this.handle_error(err));
-                  }
-                })
-                .await;
-              true
-            }
-          }
-          Err(err) => {
-            log::warn!("update schedules failed: [{:?}]", err);
-            false
-          }
-        }
-      })
+    let update_schedules = self.clone().poll(self.schedules_poll, |this| {
+      Box::pin(async move { this.update_schedules().await })
     });
     futures::join!(update_gears, update_schedules);
     Ok(())
   }
 
+  async fn update_gears(&self) -> bool {
+    let mut state = self.state.write().await;
+    let prev = state.cursors();
+    let gears = match state.update_gear().await {
+      Ok(gears) => gears,
+      Err(err) => {
+        log::warn!("update gears failed: [{:?}]", err);
+        return false;
+      }
+    };
+    let updated = !gears.is_empty();
+    let mut failed = vec![];
+    if updated {
+      log::info!("gears += {}", gears.len());
+      if let Err(err) = self.handle_gear_update(gears).await {
+        self.handle_error(err);
+        failed.push("gear");
+      }
+    }
+    self.commit_cursors(&mut state, &prev, &failed);
+    if failed.is_empty() {
+      state.commit(DataKind::Gear);
+    }
+    updated && failed.is_empty()
+  }
+
+  async fn update_schedules(&self) -> bool {
+    let mut state = self.state.write().await;
+    let prev = state.cursors();
+    let (pvp, coop, event, fest) = match state.update_schedules().await {
+      Ok(items) => items,
+      Err(err) => {
+        log::warn!("update schedules failed: [{:?}]", err);
+        return false;
+      }
+    };
+    // fest announcements are not aligned to the schedule rotation
+    let updated = !pvp.is_empty();
+    let mut failed = vec![];
+    if updated || !coop.is_empty() || !event.is_empty() || !fest.is_empty() {
+      log::info!(
+        "pvp += {}, coop += {}, event += {}, fest += {}",
+        pvp.len(),
+        coop.len(),
+        event.len(),
+        fest.len()
+      );
+      let tasks: [Pin<Box<dyn Future<Output = Result<(), BoxError>> + Send>>; 4] = [
+        Box::pin(self.handle_pvp_update(pvp)),
+        Box::pin(self.handle_coop_update(coop)),
+        Box::pin(self.handle_event_update(event)),
+        Box::pin(self.handle_fest_update(fest)),
+      ];
+      let rets = join_all(tasks).await;
+      for (kind, ret) in ["pvp", "coop", "event", "fest"].into_iter().zip(rets) {
+        if let Err(err) = ret {
+          self.handle_error(err);
+          failed.push(kind);
+        }
+      }
+    }
+    self.commit_cursors(&mut state, &prev, &failed);
+    if failed.is_empty() {
+      state.commit(DataKind::Schedules);
+    }
+    updated && failed.is_empty()
+  }
+
   async fn poll(
     self: Arc<Self>,
-    rotation: Duration,
+    config: PollConfig,
     update: impl Fn(Arc<Self>) -> Pin<Box<dyn Future<Output = bool>>>,
   ) {
+    let PollConfig {
+      rotation,
+      offset,
+      jitter,
+    } = config;
     let mut tick = Instant::now();
     loop {
       sleep_until(tick).await;
       // content not updated
       if !update(self.clone()).await {
         let exp = ExponentialBackoffBuilder::new()
-          .with_initial_interval(Duration::seconds(5).to_std().unwrap())
-          .with_max_interval(Duration::minutes(30).to_std().unwrap())
+          .with_initial_interval(
+            Duration::seconds(self.retry.initial_interval_secs)
+              .to_std()
+              .unwrap(),
+          )
+          .with_max_interval(
+            Duration::minutes(self.retry.max_interval_mins)
+              .to_std()
+              .unwrap(),
+          )
+          .with_max_elapsed_time(Some(
+            Duration::minutes(self.retry.max_elapsed_mins)
+              .to_std()
+              .unwrap(),
+          ))
           .build();
         tick = backoff::future::retry(exp, || async {
           log::info!("retrying update...");
@@ -222,8 +525,13 @@ impl SplatNetAgent {
       // measure tasks elapsed time
       let elapsed = Duration::from_std(Instant::now() - tick).unwrap();
       let fire = Utc::now() - elapsed;
-      let eps = Duration::seconds(5);
-      let next_fire = fire.duration_trunc(rotation).unwrap() + rotation + eps;
+      let jitter = if jitter > Duration::zero() {
+        Duration::milliseconds(rand::thread_rng().gen_range(0..jitter.num_milliseconds()))
+      } else {
+        Duration::zero()
+      };
+      let next_fire =
+        (fire - offset).duration_trunc(rotation).unwrap() + rotation + offset + jitter;
       tick += (next_fire - fire).to_std().unwrap();
       log::info!(
         "scheduled next tick at [{}]",
@@ -232,24 +540,178 @@ impl SplatNetAgent {
     }
   }
 
+  async fn restore_cursors(&self) -> Result<(), BoxError> {
+    let conn = self.actions.database().get()?;
+    let mut state = self.state.write().await;
+    for e in conn.list_spider_cursor()?.into_iter() {
+      match state.restore(&e.name, &e.value) {
+        Ok(()) => log::info!("restored cursor.{} [{}]", e.name, e.value),
+        Err(err) => log::warn!("restore cursor.{} failed: [{:?}]", e.name, err),
+      }
+    }
+    Ok(())
+  }
+
+  // persists the cursors moved since `prev`, once the items of their kinds
+  // are dispatched, or rewinds them for the next poll otherwise
+  fn commit_cursors(&self, state: &mut Spider, prev: &[(&'static str, String)], failed: &[&str]) {
+    if !failed.is_empty() {
+      if let Err(err) = state.rewind(prev, failed) {
+        self.handle_error(err);
+      }
+    }
+    // cursors reset by the cli are left untouched until they move again
+    let cursors: Vec<_> = state
+      .cursors()
+      .into_iter()
+      .filter(|e| !prev.contains(e))
+      .collect();
+    if cursors.is_empty() {
+      return;
+    }
+    let ret =
+      self.actions.database().get().and_then(|conn| {
+        conn.update_spider_cursor(UpdateSpiderCursorRequest { cursors: &cursors })
+      });
+    if let Err(err) = ret {
+      self.handle_error(err.into());
+    }
+  }
+
   fn handle_error(&self, err: BoxError) {
     log::warn!("{:?}", err);
   }
 
   async fn handle_gear_update(&self, items: Vec<GearSpiderItem>) -> Result<(), BoxError> {
+    for item in items.into_iter() {
+      self.actions.dispatch(Message::Gear(item))?;
+    }
     Ok(())
   }
 
   async fn handle_pvp_update(&self, items: Vec<PvpSpiderItem>) -> Result<(), BoxError> {
-    let mut tasks = vec![];
     for item in items.into_iter() {
-      tasks.push(self.actions.dispatch(Message::Pvp(item))?);
+      self.actions.dispatch(Message::Pvp(item))?;
+    }
+    Ok(())
+  }
+
+  async fn handle_event_update(&self, items: Vec<EventSpiderItem>) -> Result<(), BoxError> {
+    for item in items.into_iter() {
+      self.actions.dispatch(Message::Event(item))?;
+    }
+    Ok(())
+  }
+
+  async fn handle_fest_update(&self, items: Vec<FestSpiderItem>) -> Result<(), BoxError> {
+    for item in items.into_iter() {
+      self.actions.dispatch(Message::Fest(item))?;
     }
-    join_all(tasks).await;
     Ok(())
   }
 
   async fn handle_coop_update(&self, items: Vec<CoopSpiderItem>) -> Result<(), BoxError> {
+    for item in items.into_iter() {
+      self.actions.dispatch(Message::Coop(item))?;
+    }
     Ok(())
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use std::{collections::HashMap, path::Path};
+
+  use crate::{
+    action::ActionContext,
+    database::{Database, DatabaseConnection},
+  };
+
+  use super::*;
+
+  fn new_agent(database: Database) -> Arc<SplatNetAgent> {
+    let ctx = ActionContext::new_for_test(database);
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("resources/test");
+    SplatNetAgent::new(
+      ActionManager::new(ctx, HashMap::new()),
+      SplatNetConfig {
+        source: DataSourceConfig::Replay {
+          dir: dir.to_string_lossy().into_owned(),
+        },
+        ..Default::default()
+      },
+    )
+    .unwrap()
+  }
+
+  #[test]
+  fn test_validate_config() {
+    assert!(SplatNetConfig::default().validate().is_ok());
+
+    let mut config = SplatNetConfig::default();
+    config.update_interval_mins.schedules = 0;
+    assert!(config.validate().is_err());
+
+    let mut config = SplatNetConfig::default();
+    config.retry.max_elapsed_mins = -1;
+    assert!(config.validate().is_err());
+  }
+
+  #[test]
+  fn test_event_without_periods() {
+    let item = EventSpiderItem {
+      id: String::new(),
+      event_id: String::new(),
+      name: String::new(),
+      desc: String::new(),
+      regulation: String::new(),
+      rule: PvpRule::Area,
+      stages: vec![],
+      time_periods: vec![],
+    };
+    assert!(item.start_time().is_none());
+    let msg = Message::Digest(vec![Message::Event(item)]);
+    assert_eq!(msg.timestamp(), DateTime::<Utc>::MIN_UTC);
+  }
+
+  fn saved_cursor(db: &Database, name: &str) -> Option<String> {
+    let conn = db.get().unwrap();
+    let li = conn.list_spider_cursor().unwrap();
+    li.into_iter().find(|e| e.name == name).map(|e| e.value)
+  }
+
+  #[tokio::test]
+  async fn test_cursor_kept_on_dispatch_failure() {
+    // a file shared by every pooled connection, unlike in-memory ones
+    let path = std::env::temp_dir().join(format!("splatquery-test-{}.db", std::process::id()));
+    let db = Database::new_from_file(&path).unwrap();
+    let toggle = |from: &str, to: &str| {
+      let sql = format!("ALTER TABLE {} RENAME TO {}", from, to);
+      match db.get().unwrap() {
+        DatabaseConnection::Sqlite(conn) => conn.execute_batch(&sql).unwrap(),
+        #[cfg(feature = "postgres")]
+        _ => unreachable!(),
+      }
+    };
+
+    // gear lookups fail without the queries table
+    toggle("gear_queries", "gear_queries_off");
+    let agent = new_agent(db.clone());
+    assert!(!agent.update_gears().await);
+    assert_eq!(saved_cursor(&db, "gear_limited"), None);
+    assert_eq!(saved_cursor(&db, "gear_hash"), None);
+
+    // the same payload is dispatched again once lookups succeed
+    toggle("gear_queries_off", "gear_queries");
+    assert!(agent.update_gears().await);
+    assert!(saved_cursor(&db, "gear_limited").is_some());
+    assert!(saved_cursor(&db, "gear_hash").is_some());
+    assert!(!agent.update_gears().await);
+
+    drop(agent);
+    drop(db);
+    for suffix in ["", "-wal", "-shm"] {
+      let _ = std::fs::remove_file(format!("{}{}", path.to_string_lossy(), suffix));
+    }
+  }
+}
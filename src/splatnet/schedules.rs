@@ -1,12 +1,12 @@
 use chrono::{DateTime, Utc};
 use serde::Deserialize;
 
-#[derive(Deserialize, PartialEq, Eq, Debug)]
+#[derive(Deserialize, PartialEq, Debug)]
 pub struct RawSchedulesResponse {
   pub data: RawSchedulesData,
 }
 
-#[derive(Deserialize, PartialEq, Eq, Debug)]
+#[derive(Deserialize, PartialEq, Debug)]
 pub struct RawSchedulesData {
   #[serde(rename = "regularSchedules")]
   pub regular_schedules: RawScheduleList<RawPvpSchedule<RawRegularMatchSetting>>,
@@ -25,6 +25,10 @@ pub struct RawSchedulesData {
 
   #[serde(rename = "coopGroupingSchedule")]
   pub coop_grouping_schedule: RawCoopGroupingSchedule,
+
+  #[serde(rename = "currentFest")]
+  #[serde(default)]
+  pub current_fest: Option<RawCurrentFest>,
   // #[serde(rename = "vsStages")]
   // pub vs_stages: VSStages,
 }
@@ -146,10 +150,67 @@ pub struct RawPvpStage {
   pub name: String,
 }
 
+#[derive(Deserialize, PartialEq, Debug)]
+pub struct RawCurrentFest {
+  pub id: String,
+
+  pub title: String,
+
+  #[serde(rename = "startTime")]
+  #[serde(deserialize_with = "super::iso8601::parse")]
+  pub start_time: DateTime<Utc>,
+
+  #[serde(rename = "midtermTime")]
+  #[serde(deserialize_with = "super::iso8601::parse")]
+  pub midterm_time: DateTime<Utc>,
+
+  #[serde(rename = "endTime")]
+  #[serde(deserialize_with = "super::iso8601::parse")]
+  pub end_time: DateTime<Utc>,
+
+  pub state: String,
+
+  pub teams: Vec<RawFestTeam>,
+
+  #[serde(rename = "tricolorStage")]
+  pub tricolor_stage: Option<RawFestStage>,
+}
+
+#[derive(Deserialize, PartialEq, Debug)]
+pub struct RawFestTeam {
+  pub id: String,
+
+  pub color: RawColor,
+}
+
+#[derive(Deserialize, PartialEq, Clone, Copy, Debug)]
+pub struct RawColor {
+  pub r: f32,
+
+  pub g: f32,
+
+  pub b: f32,
+
+  pub a: f32,
+}
+
+#[derive(Deserialize, PartialEq, Eq, Debug)]
+pub struct RawFestStage {
+  pub id: String,
+
+  pub name: String,
+}
+
 #[derive(Deserialize, PartialEq, Eq, Debug)]
 pub struct RawCoopGroupingSchedule {
   #[serde(rename = "regularSchedules")]
   pub regular_schedules: RawScheduleList<RawCoopNormalSchedule>,
+
+  #[serde(rename = "bigRunSchedules")]
+  pub big_run_schedules: RawScheduleList<RawCoopNormalSchedule>,
+
+  #[serde(rename = "teamContestSchedules")]
+  pub team_contest_schedules: RawScheduleList<RawCoopNormalSchedule>,
 }
 
 #[derive(Deserialize, PartialEq, Eq, Debug)]
@@ -159,6 +220,8 @@ pub struct RawCoopNormalSchedule {
 
   pub setting: RawCoopNormalSetting,
 
+  // not guessed for eggstra work
+  #[serde(default)]
   #[serde(rename = "__splatoon3ink_king_salmonid_guess")]
   pub splatoon3ink_king_salmonid_guess: String,
 }
@@ -184,12 +247,21 @@ pub struct RawCoopWeapon {
   pub splatoon3ink_id: String,
 
   pub name: String,
+
+  pub image: RawImage,
+}
+
+#[derive(Deserialize, PartialEq, Eq, Debug)]
+pub struct RawImage {
+  pub url: String,
 }
 
 #[cfg(test)]
 mod test {
   use std::{fs::File, io::BufReader, path::Path};
 
+  use itertools::Itertools;
+
   use super::*;
 
   #[test]
@@ -266,19 +338,31 @@ mod test {
           weapons: vec![
             RawCoopWeapon {
               splatoon3ink_id: String::from("49171e6de78e50c7"),
-              name: String::from("Splattershot Jr.")
+              name: String::from("Splattershot Jr."),
+              image: RawImage {
+                url: String::from("https://splatoon3.ink/assets/splatnet/v1/weapon_illust/8e134a80cd54f4235329493afd43ff754b367a65e460facfcca862b174754b0e_0.png")
+              }
             },
             RawCoopWeapon {
               splatoon3ink_id: String::from("09465cbd66e15c68"),
-              name: String::from("Splat Dualies")
+              name: String::from("Splat Dualies"),
+              image: RawImage {
+                url: String::from("https://splatoon3.ink/assets/splatnet/v1/weapon_illust/b43978029ea582de3aca34549cafd810df20082b94104634093392e11e30d9bd_0.png")
+              }
             },
             RawCoopWeapon {
               splatoon3ink_id: String::from("b0343d4f4b600e95"),
-              name: String::from("Jet Squelcher")
+              name: String::from("Jet Squelcher"),
+              image: RawImage {
+                url: String::from("https://splatoon3.ink/assets/splatnet/v1/weapon_illust/035920eb9428955c25aecb8a56c2b1b58f3e322af3657d921db1778de4b80c59_0.png")
+              }
             },
             RawCoopWeapon {
               splatoon3ink_id: String::from("aae42b6ef1b5090d"),
-              name: String::from("Hydra Splatling")
+              name: String::from("Hydra Splatling"),
+              image: RawImage {
+                url: String::from("https://splatoon3.ink/assets/splatnet/v1/weapon_illust/34fe0401b6f6a0b09839696fc820ece9570a9d56e3a746b65f0604dec91a9920_0.png")
+              }
             },
           ]
         },
@@ -286,4 +370,26 @@ mod test {
       }
     );
   }
+  #[test]
+  fn test_parse_fest_schedules_response() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+      .join("resources/test/splat3ink_schedules_fest_response.json");
+    // strip the leading `//` comment lines
+    let json: String = std::fs::read_to_string(path)
+      .unwrap()
+      .lines()
+      .filter(|line| !line.starts_with("//"))
+      .join("\n");
+    let schedules: RawSchedulesResponse = serde_json::from_str(&json).unwrap();
+    let fest = schedules.data.current_fest.unwrap();
+    assert_eq!(fest.id, "RmVzdC1VUzpKVUVBLTAwMDA3");
+    assert_eq!(fest.state, "SCHEDULED");
+    assert_eq!(
+      fest.midterm_time,
+      DateTime::parse_from_rfc3339("2023-07-16T00:00:00Z").unwrap()
+    );
+    assert_eq!(fest.teams.len(), 3);
+    assert_eq!(fest.teams[2].id, "RmVzdFRlYW0tVVM6SlVFQS0wMDAwNzpDaGFybGll");
+    assert_eq!(fest.tricolor_stage.unwrap().id, "VnNTdGFnZS04");
+  }
 }
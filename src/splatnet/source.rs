@@ -0,0 +1,264 @@
+use std::{collections::HashMap, path::PathBuf, sync::Mutex, time::Duration};
+
+use async_trait::async_trait;
+use derivative::Derivative;
+use reqwest::{
+  header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+  Client, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use serde_enum_str::{Deserialize_enum_str, Serialize_enum_str};
+
+use crate::BoxError;
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, Copy, Serialize_enum_str, Deserialize_enum_str)]
+#[serde(rename_all = "lowercase")]
+pub enum DataKind {
+  Gear,
+  Schedules,
+}
+
+#[async_trait]
+pub trait DataSource: Send + Sync {
+  // returns `None` if the payload is not modified since last commit
+  async fn fetch(&self, kind: DataKind) -> Result<Option<Vec<u8>>, BoxError>;
+
+  // marks the payload of the last fetch as handled, until then it is fetched
+  // again in full
+  fn commit(&self, _kind: DataKind) {}
+}
+
+#[derive(Serialize, Deserialize, Derivative)]
+#[derivative(Default)]
+pub struct HttpClientConfig {
+  // splatoon3.ink asks for a user agent with contact info
+  #[serde(default = "default_user_agent")]
+  #[derivative(Default(value = "default_user_agent()"))]
+  pub user_agent: String,
+  #[serde(default = "default_timeout_secs")]
+  #[derivative(Default(value = "default_timeout_secs()"))]
+  pub timeout_secs: u64,
+  #[serde(default = "default_connect_timeout_secs")]
+  #[derivative(Default(value = "default_connect_timeout_secs()"))]
+  pub connect_timeout_secs: u64,
+}
+
+fn default_user_agent() -> String {
+  format!(
+    "splatquery/{} (+https://github.com/xlnx/splatquery)",
+    env!("CARGO_PKG_VERSION")
+  )
+}
+
+fn default_timeout_secs() -> u64 {
+  30
+}
+
+fn default_connect_timeout_secs() -> u64 {
+  10
+}
+
+impl HttpClientConfig {
+  pub fn collect(&self) -> Result<Client, BoxError> {
+    let client = Client::builder()
+      .user_agent(&self.user_agent)
+      .timeout(Duration::from_secs(self.timeout_secs))
+      .connect_timeout(Duration::from_secs(self.connect_timeout_secs))
+      .build()?;
+    Ok(client)
+  }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(tag = "type")]
+#[serde(rename_all = "lowercase")]
+pub enum DataSourceConfig {
+  #[default]
+  Splatoon3Ink,
+  // a mirror serving `{base_url}/gear.json` and `{base_url}/schedules.json`
+  Http {
+    base_url: String,
+  },
+  // recorded payloads, see `ReplayDataSource`
+  Replay {
+    dir: String,
+  },
+}
+
+impl DataSourceConfig {
+  pub fn collect(self, client: &Client) -> Box<dyn DataSource> {
+    match self {
+      Self::Splatoon3Ink => Box::new(HttpDataSource::new(
+        client.clone(),
+        "https://splatoon3.ink/data",
+      )),
+      Self::Http { base_url } => Box::new(HttpDataSource::new(
+        client.clone(),
+        base_url.trim_end_matches('/'),
+      )),
+      Self::Replay { dir } => Box::new(ReplayDataSource::new(dir)),
+    }
+  }
+}
+
+#[derive(Default)]
+struct Validators {
+  etag: Option<String>,
+  last_modified: Option<String>,
+}
+
+pub struct HttpDataSource {
+  client: Client,
+  base_url: String,
+  validators: Mutex<HashMap<DataKind, Validators>>,
+  // validators of fetched payloads not committed yet
+  pending: Mutex<HashMap<DataKind, Validators>>,
+}
+
+impl HttpDataSource {
+  pub fn new<S: Into<String>>(client: Client, base_url: S) -> Self {
+    HttpDataSource {
+      client,
+      base_url: base_url.into(),
+      validators: Mutex::new(HashMap::new()),
+      pending: Mutex::new(HashMap::new()),
+    }
+  }
+}
+
+#[async_trait]
+impl DataSource for HttpDataSource {
+  async fn fetch(&self, kind: DataKind) -> Result<Option<Vec<u8>>, BoxError> {
+    let url = format!("{}/{}.json", self.base_url, kind);
+    let mut request = self.client.get(&url);
+    if let Some(e) = self.validators.lock().unwrap().get(&kind) {
+      if let Some(etag) = &e.etag {
+        request = request.header(IF_NONE_MATCH, etag);
+      }
+      if let Some(last_modified) = &e.last_modified {
+        request = request.header(IF_MODIFIED_SINCE, last_modified);
+      }
+    }
+    let response = request.send().await?;
+    log::debug!("GET [{}] -> {}", url, response.status().as_u16());
+    if response.status() == StatusCode::NOT_MODIFIED {
+      return Ok(None);
+    }
+    let response = response.error_for_status()?;
+    let header = |name| {
+      response
+        .headers()
+        .get(name)
+        .and_then(|e| e.to_str().ok())
+        .map(String::from)
+    };
+    let validators = Validators {
+      etag: header(ETAG),
+      last_modified: header(LAST_MODIFIED),
+    };
+    let body = response.bytes().await?.to_vec();
+    self.pending.lock().unwrap().insert(kind, validators);
+    Ok(Some(body))
+  }
+
+  fn commit(&self, kind: DataKind) {
+    if let Some(validators) = self.pending.lock().unwrap().remove(&kind) {
+      self.validators.lock().unwrap().insert(kind, validators);
+    }
+  }
+}
+
+// replays `*.json` files whose names contain the data kind (`gear` or `schedules`)
+// in lexicographic order, and sticks to the last one when exhausted
+pub struct ReplayDataSource {
+  dir: PathBuf,
+  cursors: Mutex<HashMap<DataKind, usize>>,
+}
+
+impl ReplayDataSource {
+  pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+    ReplayDataSource {
+      dir: dir.into(),
+      cursors: Mutex::new(HashMap::new()),
+    }
+  }
+}
+
+#[async_trait]
+impl DataSource for ReplayDataSource {
+  async fn fetch(&self, kind: DataKind) -> Result<Option<Vec<u8>>, BoxError> {
+    let mut files = vec![];
+    for entry in std::fs::read_dir(&self.dir)? {
+      let path = entry?.path();
+      let name = path.file_name().and_then(|e| e.to_str()).unwrap_or("");
+      if name.ends_with(".json") && name.contains(&kind.to_string()) {
+        files.push(path);
+      }
+    }
+    files.sort();
+    let path = {
+      let mut cursors = self.cursors.lock().unwrap();
+      let idx = cursors.entry(kind).or_insert(0);
+      let path = files
+        .get(*idx)
+        .or(files.last())
+        .ok_or_else(|| format!("no {} payload in [{:?}]", kind, self.dir))?
+        .clone();
+      *idx += 1;
+      path
+    };
+    log::debug!("REPLAY [{:?}]", path);
+    // recorded payloads may start with `//` comment lines
+    let body = std::fs::read_to_string(path)?;
+    let body: Vec<_> = body
+      .lines()
+      .filter(|line| !line.starts_with("//"))
+      .collect();
+    Ok(Some(body.join("\n").into_bytes()))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+  };
+
+  use super::*;
+
+  // answers 304 to conditional requests, and 200 with an etag otherwise
+  async fn serve(listener: TcpListener) {
+    loop {
+      let Ok((mut stream, _)) = listener.accept().await else {
+        return;
+      };
+      let mut buf = vec![0; 4096];
+      let n = stream.read(&mut buf).await.unwrap();
+      let request = String::from_utf8_lossy(&buf[..n]).to_lowercase();
+      let response = if request.contains("if-none-match: \"v1\"") {
+        "HTTP/1.1 304 Not Modified\r\nconnection: close\r\n\r\n"
+      } else {
+        "HTTP/1.1 200 OK\r\netag: \"v1\"\r\ncontent-length: 2\r\nconnection: close\r\n\r\n{}"
+      };
+      stream.write_all(response.as_bytes()).await.unwrap();
+    }
+  }
+
+  #[tokio::test]
+  async fn test_validators_committed_after_update() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(serve(listener));
+    let source = HttpDataSource::new(Client::new(), base_url);
+
+    // the update of the payload failed, so it is fetched again
+    assert!(source.fetch(DataKind::Gear).await.unwrap().is_some());
+    assert!(source.fetch(DataKind::Gear).await.unwrap().is_some());
+
+    source.commit(DataKind::Gear);
+    assert!(source.fetch(DataKind::Gear).await.unwrap().is_none());
+    // validators are kept per kind
+    assert!(source.fetch(DataKind::Schedules).await.unwrap().is_some());
+  }
+}
@@ -1,14 +1,27 @@
-use chrono::{DateTime, Local, Utc};
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, Local, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 use crate::BoxError;
 
 use super::{
   gear,
-  schedules::{self, RawCoopNormalSchedule},
-  GearType, PvpMode, PvpRule,
+  schedules::{self, RawCoopNormalSchedule, RawCoopWeapon},
+  source::{DataKind, DataSource},
+  CoopMode, FestState, GearType, KingSalmonid, PvpMode, PvpRule,
 };
 
-#[derive(Debug)]
+// random weapon slots are reported by these ids instead of their splatoon3.ink ids
+pub const COOP_WEAPON_RANDOM: &str = "random";
+pub const COOP_WEAPON_GRIZZCO: &str = "grizzco";
+
+// splatoon3.ink names both green and golden question marks "Random"
+const COOP_WEAPON_GRIZZCO_ILLUST: &str =
+  "9d7272733ae2f2282938da17d69f13419a935eef42239132a02fcf37d8678f10";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GearSpiderItem {
   pub sale_end_time: DateTime<Utc>,
   pub id: String,
@@ -18,9 +31,10 @@ pub struct GearSpiderItem {
   pub price: i32,
   pub primary_gear_power: String,
   pub additional_gear_powers: i32,
+  pub pickup: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PvpSpiderItem {
   pub start_time: DateTime<Utc>,
   pub end_time: DateTime<Utc>,
@@ -29,16 +43,156 @@ pub struct PvpSpiderItem {
   pub mode: PvpMode,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventSpiderItem {
+  pub id: String,
+  pub event_id: String,
+  pub name: String,
+  pub desc: String,
+  pub regulation: String,
+  pub rule: PvpRule,
+  pub stages: Vec<u32>,
+  pub time_periods: Vec<(DateTime<Utc>, DateTime<Utc>)>,
+}
+
+impl EventSpiderItem {
+  // none for messages restored without any period
+  pub fn start_time(&self) -> Option<DateTime<Utc>> {
+    self.time_periods.first().map(|e| e.0)
+  }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FestTeam {
+  pub id: String,
+  // rgba in [0, 1]
+  pub color: (f32, f32, f32, f32),
+}
+
+impl FestTeam {
+  pub fn hex_color(&self) -> String {
+    let (r, g, b, _) = self.color;
+    let f = |e: f32| (e.clamp(0.0, 1.0) * 255.0).round() as u8;
+    format!("#{:02x}{:02x}{:02x}", f(r), f(g), f(b))
+  }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FestSpiderItem {
+  pub id: String,
+  pub title: String,
+  pub state: FestState,
+  pub start_time: DateTime<Utc>,
+  pub midterm_time: DateTime<Utc>,
+  pub end_time: DateTime<Utc>,
+  pub teams: Vec<FestTeam>,
+  pub tricolor_stage: Option<u32>,
+}
+
+impl FestSpiderItem {
+  // when the current state takes effect, monotonic within a fest
+  pub fn state_time(&self) -> DateTime<Utc> {
+    match self.state {
+      FestState::FirstHalf => self.start_time,
+      FestState::SecondHalf => self.midterm_time,
+      FestState::Closed => self.end_time,
+      // announcements precede the start
+      _ => self.start_time - Duration::seconds(1),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CoopSpiderItem {
   pub start_time: DateTime<Utc>,
   pub end_time: DateTime<Utc>,
-  stage: String,
-  weapons: String,
-  king_salmonid_guess: String,
+  pub mode: CoopMode,
+  pub stage: u32,
+  pub weapons: Vec<String>,
+  pub king_salmonid: KingSalmonid,
+}
+
+fn parse_pvp_rule(id: &str) -> PvpRule {
+  match id {
+    "VnNSdWxlLTA=" => PvpRule::Regular,
+    "VnNSdWxlLTE=" => PvpRule::Area,
+    "VnNSdWxlLTI=" => PvpRule::Yagura,
+    "VnNSdWxlLTM=" => PvpRule::Hoko,
+    "VnNSdWxlLTQ=" => PvpRule::Asari,
+    _ => PvpRule::Unknown,
+  }
+}
+
+fn parse_fest_state(state: &str) -> FestState {
+  match state {
+    "SCHEDULED" => FestState::Scheduled,
+    "FIRST_HALF" => FestState::FirstHalf,
+    "SECOND_HALF" => FestState::SecondHalf,
+    "CLOSED" => FestState::Closed,
+    _ => FestState::Unknown,
+  }
+}
+
+fn parse_vs_stage_id(id: &str) -> Option<u32> {
+  // "VnNTdGFnZS04" -> "VsStage-8"
+  base64::decode(id)
+    .ok()
+    .and_then(|e| String::from_utf8(e).ok())
+    .and_then(|e| e.strip_prefix("VsStage-").and_then(|e| e.parse().ok()))
+}
+
+fn parse_coop_stage_id(id: &str) -> u32 {
+  // "Q29vcFN0YWdlLTI=" -> "CoopStage-2"
+  base64::decode(id)
+    .ok()
+    .and_then(|e| String::from_utf8(e).ok())
+    .and_then(|e| e.strip_prefix("CoopStage-").and_then(|e| e.parse().ok()))
+    .unwrap_or_else(|| {
+      log::warn!("unknown coop stage id: [{}]", id);
+      0
+    })
+}
+
+fn parse_coop_weapon_id(weapon: RawCoopWeapon) -> String {
+  if weapon.image.url.contains(COOP_WEAPON_GRIZZCO_ILLUST) {
+    COOP_WEAPON_GRIZZCO.into()
+  } else if weapon.name == "Random" {
+    COOP_WEAPON_RANDOM.into()
+  } else {
+    weapon.splatoon3ink_id
+  }
+}
+
+fn parse_king_salmonid(guess: &str) -> KingSalmonid {
+  match guess {
+    "Cohozuna" => KingSalmonid::Cohozuna,
+    "Horrorboros" => KingSalmonid::Horrorboros,
+    "Megalodontia" => KingSalmonid::Megalodontia,
+    "Triumvirate" => KingSalmonid::Triumvirate,
+    _ => KingSalmonid::Unknown,
+  }
+}
+
+fn payload_hash(body: &[u8]) -> String {
+  format!("{:x}", Sha256::digest(body))
+}
+
+// message kinds whose items are guarded by the cursor
+fn cursor_kinds(name: &str) -> &'static [&'static str] {
+  match name {
+    "gear_pickup_brand" | "gear_limited" | "gear_hash" => &["gear"],
+    "pvp_event" => &["event"],
+    "fest" => &["fest"],
+    // unchanged payloads are skipped before any item is collected
+    "schedules_hash" => &["pvp", "coop", "event", "fest"],
+    _ if name.starts_with("pvp_") => &["pvp"],
+    _ if name.starts_with("coop_") => &["coop"],
+    _ => &[],
+  }
 }
 
 pub struct Spider {
+  source: Box<dyn DataSource>,
   gear_pickup_brand: DateTime<Utc>,
   gear_limited: DateTime<Utc>,
   pvp_regular: DateTime<Utc>,
@@ -49,12 +203,15 @@ pub struct Spider {
   coop_normal: DateTime<Utc>,
   coop_big_run: DateTime<Utc>,
   coop_team_contest: DateTime<Utc>,
+  fest: Option<(String, FestState)>,
+  gear_hash: String,
+  schedules_hash: String,
 }
 
 impl Spider {
-  pub fn new() -> Self {
-    // TODO: support suspend state
+  pub fn new(source: Box<dyn DataSource>) -> Self {
     Spider {
+      source,
       gear_pickup_brand: DateTime::<Utc>::MIN_UTC,
       gear_limited: DateTime::<Utc>::MIN_UTC,
       pvp_regular: DateTime::<Utc>::MIN_UTC,
@@ -65,25 +222,145 @@ impl Spider {
       coop_normal: DateTime::<Utc>::MIN_UTC,
       coop_big_run: DateTime::<Utc>::MIN_UTC,
       coop_team_contest: DateTime::<Utc>::MIN_UTC,
+      fest: None,
+      gear_hash: String::new(),
+      schedules_hash: String::new(),
+    }
+  }
+
+  pub fn cursors(&self) -> Vec<(&'static str, String)> {
+    let mut li: Vec<_> = [
+      ("gear_pickup_brand", &self.gear_pickup_brand),
+      ("gear_limited", &self.gear_limited),
+      ("pvp_regular", &self.pvp_regular),
+      ("pvp_bankara", &self.pvp_bankara),
+      ("pvp_x_match", &self.pvp_x_match),
+      ("pvp_event", &self.pvp_event),
+      ("pvp_fest", &self.pvp_fest),
+      ("coop_normal", &self.coop_normal),
+      ("coop_big_run", &self.coop_big_run),
+      ("coop_team_contest", &self.coop_team_contest),
+    ]
+    .into_iter()
+    // MIN_UTC is not representable in rfc3339
+    .filter(|(_, t)| **t != DateTime::<Utc>::MIN_UTC)
+    .map(|(name, t)| (name, t.to_rfc3339()))
+    .collect();
+    if let Some((id, state)) = &self.fest {
+      li.push(("fest", format!("{}/{}", id, state)));
+    }
+    li.push(("gear_hash", self.gear_hash.clone()));
+    li.push(("schedules_hash", self.schedules_hash.clone()));
+    li
+  }
+
+  fn time_cursor(&mut self, name: &str) -> Option<&mut DateTime<Utc>> {
+    match name {
+      "gear_pickup_brand" => Some(&mut self.gear_pickup_brand),
+      "gear_limited" => Some(&mut self.gear_limited),
+      "pvp_regular" => Some(&mut self.pvp_regular),
+      "pvp_bankara" => Some(&mut self.pvp_bankara),
+      "pvp_x_match" => Some(&mut self.pvp_x_match),
+      "pvp_event" => Some(&mut self.pvp_event),
+      "pvp_fest" => Some(&mut self.pvp_fest),
+      "coop_normal" => Some(&mut self.coop_normal),
+      "coop_big_run" => Some(&mut self.coop_big_run),
+      "coop_team_contest" => Some(&mut self.coop_team_contest),
+      _ => None,
+    }
+  }
+
+  pub fn restore(&mut self, name: &str, value: &str) -> Result<(), BoxError> {
+    if let Some(cursor) = self.time_cursor(name) {
+      *cursor = DateTime::parse_from_rfc3339(value)?.into();
+      return Ok(());
+    }
+    match name {
+      "fest" => {
+        let (id, state) = value
+          .rsplit_once('/')
+          .ok_or_else(|| format!("invalid fest cursor: [{}]", value))?;
+        self.fest = Some((id.into(), FestState::from_str(state)?));
+      }
+      "gear_hash" => self.gear_hash = value.into(),
+      "schedules_hash" => self.schedules_hash = value.into(),
+      _ => return Err(format!("unknown cursor: [{}]", name).into()),
+    }
+    Ok(())
+  }
+
+  // moves the cursors of the given message kinds back to `prev`, a snapshot
+  // of `cursors`, so that their items are collected again by the next update
+  pub fn rewind(
+    &mut self,
+    prev: &[(&'static str, String)],
+    kinds: &[&str],
+  ) -> Result<(), BoxError> {
+    let rewound = |name: &str| cursor_kinds(name).iter().any(|e| kinds.contains(e));
+    for (name, _) in self.cursors() {
+      if !rewound(name) {
+        continue;
+      }
+      match self.time_cursor(name) {
+        Some(cursor) => *cursor = DateTime::<Utc>::MIN_UTC,
+        None => match name {
+          "fest" => self.fest = None,
+          "gear_hash" => self.gear_hash.clear(),
+          _ => self.schedules_hash.clear(),
+        },
+      }
+    }
+    for (name, value) in prev.iter() {
+      if rewound(name) {
+        self.restore(name, value)?;
+      }
     }
+    Ok(())
+  }
+
+  // lets the source skip the payloads of `kind` handled so far
+  pub fn commit(&self, kind: DataKind) {
+    self.source.commit(kind);
   }
 
   pub async fn update_gear(&mut self) -> Result<Vec<GearSpiderItem>, BoxError> {
-    let url = "https://splatoon3.ink/data/gear.json";
-    let response = reqwest::get(url).await?;
-    log::debug!("GET [{}] -> {}", url, response.status().as_u16());
-    let json: gear::RawGearResponse = response.json().await?;
-    self.do_update_gear(json).await
+    let Some(body) = self.source.fetch(DataKind::Gear).await? else {
+      return Ok(vec![]);
+    };
+    // skip parsing when the payload is unchanged
+    let hash = payload_hash(&body);
+    if hash == self.gear_hash {
+      return Ok(vec![]);
+    }
+    let json: gear::RawGearResponse = serde_json::from_slice(&body)?;
+    let gears = self.do_update_gear(json).await?;
+    self.gear_hash = hash;
+    Ok(gears)
   }
 
   pub async fn update_schedules(
     &mut self,
-  ) -> Result<(Vec<PvpSpiderItem>, Vec<CoopSpiderItem>), BoxError> {
-    let url = "https://splatoon3.ink/data/schedules.json";
-    let response = reqwest::get(url).await?;
-    log::debug!("GET [{}] -> {}", url, response.status().as_u16());
-    let json: schedules::RawSchedulesResponse = response.json().await?;
-    self.do_update_schedules(json).await
+  ) -> Result<
+    (
+      Vec<PvpSpiderItem>,
+      Vec<CoopSpiderItem>,
+      Vec<EventSpiderItem>,
+      Vec<FestSpiderItem>,
+    ),
+    BoxError,
+  > {
+    let Some(body) = self.source.fetch(DataKind::Schedules).await? else {
+      return Ok((vec![], vec![], vec![], vec![]));
+    };
+    // skip parsing when the payload is unchanged
+    let hash = payload_hash(&body);
+    if hash == self.schedules_hash {
+      return Ok((vec![], vec![], vec![], vec![]));
+    }
+    let json: schedules::RawSchedulesResponse = serde_json::from_slice(&body)?;
+    let items = self.do_update_schedules(json).await?;
+    self.schedules_hash = hash;
+    Ok(items)
   }
 
   async fn do_update_gear(
@@ -96,7 +373,7 @@ impl Spider {
     } = response.data.gesotown;
 
     let mut gears = vec![];
-    let mut collect = |e: gear::RawGear| {
+    let mut collect = |e: gear::RawGear, pickup: bool| {
       gears.push(GearSpiderItem {
         sale_end_time: e.sale_end_time,
         id: e.id,
@@ -111,6 +388,7 @@ impl Spider {
         price: e.price,
         primary_gear_power: e.gear.primary_gear_power.splatoon3ink_id,
         additional_gear_powers: e.gear.additional_gear_powers.len() as i32,
+        pickup,
       });
     };
 
@@ -124,7 +402,7 @@ impl Spider {
         self.gear_pickup_brand.with_timezone(&Local)
       );
       for g in pickup_brand.brand_gears.into_iter() {
-        collect(g);
+        collect(g, true);
       }
     }
 
@@ -140,7 +418,7 @@ impl Spider {
         );
         for g in limited_gears.into_iter() {
           if g.sale_end_time > t {
-            collect(g);
+            collect(g, false);
           }
         }
       }
@@ -152,32 +430,33 @@ impl Spider {
   async fn do_update_schedules(
     &mut self,
     response: schedules::RawSchedulesResponse,
-  ) -> Result<(Vec<PvpSpiderItem>, Vec<CoopSpiderItem>), BoxError> {
+  ) -> Result<
+    (
+      Vec<PvpSpiderItem>,
+      Vec<CoopSpiderItem>,
+      Vec<EventSpiderItem>,
+      Vec<FestSpiderItem>,
+    ),
+    BoxError,
+  > {
     let schedules::RawSchedulesData {
       regular_schedules,
       bankara_schedules,
       x_schedules,
+      event_schedules,
       fest_schedules,
       coop_grouping_schedule,
-      ..
+      current_fest,
     } = response.data;
 
     let mut pvp = vec![];
     let mut collect_pvp = |mode: PvpMode,
                            time_period: schedules::RawTimePeriod,
                            setting: schedules::RawPvpMatchSetting| {
-      let rule = match setting.pvp_rule.id.as_str() {
-        "VnNSdWxlLTA=" => PvpRule::Regular,
-        "VnNSdWxlLTE=" => PvpRule::Area,
-        "VnNSdWxlLTI=" => PvpRule::Yagura,
-        "VnNSdWxlLTM=" => PvpRule::Hoko,
-        "VnNSdWxlLTQ=" => PvpRule::Asari,
-        _ => PvpRule::Unknown,
-      };
       pvp.push(PvpSpiderItem {
         start_time: time_period.start_time,
         end_time: time_period.end_time,
-        rule,
+        rule: parse_pvp_rule(&setting.pvp_rule.id),
         stages: setting
           .pvp_stages
           .into_iter()
@@ -187,19 +466,46 @@ impl Spider {
       });
     };
 
+    let mut event = vec![];
+    let mut collect_event = |schedule: schedules::RawEventSchedule| {
+      let schedules::RawLeagueMatchSetting {
+        league_match_event,
+        pvp_match_setting,
+      } = schedule.league_match_setting;
+      event.push(EventSpiderItem {
+        id: league_match_event.id,
+        event_id: league_match_event.league_match_event_id,
+        name: league_match_event.name,
+        desc: league_match_event.desc,
+        regulation: league_match_event.regulation,
+        rule: parse_pvp_rule(&pvp_match_setting.pvp_rule.id),
+        stages: pvp_match_setting
+          .pvp_stages
+          .into_iter()
+          .map(|e| e.pvp_stage_id)
+          .collect(),
+        time_periods: schedule
+          .time_periods
+          .into_iter()
+          .map(|e| (e.start_time, e.end_time))
+          .collect(),
+      });
+    };
+
     let mut coop = vec![];
-    let mut collect_coop = |schedule: RawCoopNormalSchedule| {
+    let mut collect_coop = |mode: CoopMode, schedule: RawCoopNormalSchedule| {
       coop.push(CoopSpiderItem {
         start_time: schedule.time_period.start_time,
         end_time: schedule.time_period.end_time,
-        stage: schedule.setting.coop_stage.id,
+        mode,
+        stage: parse_coop_stage_id(&schedule.setting.coop_stage.id),
         weapons: schedule
           .setting
           .weapons
           .into_iter()
-          .map(|e| e.splatoon3ink_id)
+          .map(parse_coop_weapon_id)
           .collect(),
-        king_salmonid_guess: schedule.splatoon3ink_king_salmonid_guess,
+        king_salmonid: parse_king_salmonid(&schedule.splatoon3ink_king_salmonid_guess),
       });
     };
 
@@ -264,6 +570,28 @@ impl Spider {
       }
     }
 
+    if let Some(p) = event_schedules
+      .nodes
+      .last()
+      .and_then(|s| s.time_periods.first())
+    {
+      let mut t = p.start_time;
+      if t > self.pvp_event {
+        // find new challenge
+        std::mem::swap(&mut self.pvp_event, &mut t);
+        log::debug!(
+          "cursor.pvp_event [{}] -> [{}]",
+          t.with_timezone(&Local),
+          self.pvp_event.with_timezone(&Local)
+        );
+        for s in event_schedules.nodes.into_iter() {
+          if matches!(s.time_periods.first(), Some(p) if p.start_time > t) {
+            collect_event(s);
+          }
+        }
+      }
+    }
+
     if let Some(s) = fest_schedules.nodes.last() {
       let mut t = s.time_period.start_time;
       if t > self.pvp_fest {
@@ -296,12 +624,114 @@ impl Spider {
         );
         for s in coop_grouping_schedule.regular_schedules.nodes.into_iter() {
           if s.time_period.start_time > t {
-            collect_coop(s);
+            collect_coop(CoopMode::Regular, s);
           }
         }
       }
     }
 
-    Ok((pvp, coop))
+    if let Some(s) = coop_grouping_schedule.big_run_schedules.nodes.last() {
+      let mut t = s.time_period.start_time;
+      if t > self.coop_big_run {
+        // find new big run schedule
+        std::mem::swap(&mut self.coop_big_run, &mut t);
+        log::debug!(
+          "cursor.coop_big_run [{}] -> [{}]",
+          t.with_timezone(&Local),
+          self.coop_big_run.with_timezone(&Local)
+        );
+        for s in coop_grouping_schedule.big_run_schedules.nodes.into_iter() {
+          if s.time_period.start_time > t {
+            collect_coop(CoopMode::BigRun, s);
+          }
+        }
+      }
+    }
+
+    if let Some(s) = coop_grouping_schedule.team_contest_schedules.nodes.last() {
+      let mut t = s.time_period.start_time;
+      if t > self.coop_team_contest {
+        // find new eggstra work schedule
+        std::mem::swap(&mut self.coop_team_contest, &mut t);
+        log::debug!(
+          "cursor.coop_team_contest [{}] -> [{}]",
+          t.with_timezone(&Local),
+          self.coop_team_contest.with_timezone(&Local)
+        );
+        for s in coop_grouping_schedule
+          .team_contest_schedules
+          .nodes
+          .into_iter()
+        {
+          if s.time_period.start_time > t {
+            collect_coop(CoopMode::TeamContest, s);
+          }
+        }
+      }
+    }
+
+    let mut fest = vec![];
+    if let Some(f) = current_fest {
+      let state = parse_fest_state(&f.state);
+      let cursor = Some((f.id.clone(), state));
+      if cursor != self.fest {
+        // find new fest or fest state transition
+        log::debug!("cursor.fest [{:?}] -> [{:?}]", self.fest, cursor);
+        self.fest = cursor;
+        fest.push(FestSpiderItem {
+          id: f.id,
+          title: f.title,
+          state,
+          start_time: f.start_time,
+          midterm_time: f.midterm_time,
+          end_time: f.end_time,
+          teams: f
+            .teams
+            .into_iter()
+            .map(|e| FestTeam {
+              id: e.id,
+              color: (e.color.r, e.color.g, e.color.b, e.color.a),
+            })
+            .collect(),
+          tricolor_stage: f.tricolor_stage.and_then(|e| parse_vs_stage_id(&e.id)),
+        });
+      }
+    }
+
+    Ok((pvp, coop, event, fest))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::Path;
+
+  use crate::splatnet::source::ReplayDataSource;
+
+  use super::*;
+
+  #[tokio::test]
+  async fn test_replay_schedules() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("resources/test");
+    let mut spider = Spider::new(Box::new(ReplayDataSource::new(dir)));
+
+    // splat3ink_schedules_fest_response.json
+    let (pvp, coop, _, fest) = spider.update_schedules().await.unwrap();
+    assert!(!pvp.is_empty());
+    assert!(!coop.is_empty());
+    assert_eq!(fest.len(), 1);
+    assert_eq!(fest[0].state, FestState::Scheduled);
+    assert_eq!(fest[0].tricolor_stage, Some(8));
+
+    // splat3ink_schedules_response.json is older, cursors don't move
+    let (pvp, coop, _, _) = spider.update_schedules().await.unwrap();
+    assert!(pvp.is_empty());
+    assert!(coop.is_empty());
+
+    let mut restored = Spider::new(Box::new(ReplayDataSource::new("")));
+    for (name, value) in spider.cursors() {
+      restored.restore(name, &value).unwrap();
+    }
+    assert_eq!(restored.cursors(), spider.cursors());
   }
 }